@@ -3,17 +3,31 @@
 //! Provides tools to query and control i3 via IPC.
 
 use rmcp::{
-    handler::server::{router::tool::ToolRouter, ServerHandler, wrapper::Parameters},
+    handler::server::{
+        router::tool::ToolRouter, tool::ToolCallContext, wrapper::Parameters, ServerHandler,
+    },
     model::*,
-    ErrorData as McpError,
+    service::{NotificationContext, Peer, RequestContext},
+    ErrorData as McpError, RoleServer,
 };
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, mpsc, Notify};
 use tokio_i3ipc::{
-    reply::{Node, Workspace},
-    I3,
+    event::{Event, Subscribe, WindowChange, WorkspaceChange},
+    msg::Msg,
+    reply::{
+        BindingModes, Floating, Marks, Node, NodeLayout, NodeType, Output, Rect, Success, Version,
+        Workspace,
+    },
+    MsgResponse, I3,
 };
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
 // ============================================================================
 // Server Struct
@@ -24,21 +38,396 @@ use tracing::{debug, error, info};
 pub struct I3Server {
     /// Tool router for MCP tool dispatch
     pub tool_router: ToolRouter<Self>,
+    /// Signalled by the shutdown_server tool to stop the service
+    shutdown: Arc<Notify>,
+    /// State tracked from the i3 event stream (see spawn_event_listener)
+    events: Arc<Mutex<EventState>>,
+    /// Shared IPC connection, opened lazily (see connect)
+    conn: Arc<tokio::sync::Mutex<Option<I3>>>,
+    /// Payloads of tick events seen on the event stream
+    ticks: broadcast::Sender<String>,
+    /// Counter for generated tick payloads
+    tick_counter: AtomicU64,
+    /// Limits how quickly the exec tool can launch processes
+    exec_limiter: Mutex<RateLimiter>,
+    /// Layouts to restore for containers switched to tabbed by toggle_tabbed, by con_id
+    tabbed_layouts: Mutex<HashMap<usize, String>>,
+    /// Placement to restore for windows presented by spotlight, by con_id
+    spotlights: Mutex<HashMap<usize, SpotlightState>>,
+    /// Workspace stash_window moves windows to (see stash_workspace)
+    stash_workspace: String,
+    /// Origin workspaces of windows moved aside by stash_window, by con_id
+    stashes: Mutex<HashMap<usize, String>>,
+    /// Per-tool invocation counts and latency (see call_tool)
+    stats: Mutex<HashMap<String, ToolStats>>,
+    /// Windows bookmarked by tag_and_remember, by mark
+    tags: Mutex<BTreeMap<String, TaggedWindow>>,
+    /// The last COMMAND_HISTORY_LEN commands sent to i3, oldest first
+    command_history: Mutex<VecDeque<CommandRecord>>,
+    /// The connected client, set once it has initialized, for notifications
+    peer: Arc<Mutex<Option<Peer<RoleServer>>>>,
+    /// Whether tree-change notifications are sent (off if the client raises
+    /// its logging level above info)
+    notify_tree_changes: Arc<AtomicBool>,
+    /// Instructions returned by get_info (see server_instructions)
+    instructions: String,
 }
 
 impl I3Server {
     /// Create a new i3 MCP server
     pub fn new() -> Self {
+        let mut tool_router = Self::tool_router();
+
+        // shutdown_server is opt-in so clients can't stop the server unexpectedly
+        if !env_flag("RMCP_I3_ALLOW_SHUTDOWN") {
+            tool_router.remove_route("shutdown_server");
+        }
+
+        // Read-only preset: drop every tool that isn't known to be a pure query
+        if env_flag("RMCP_I3_READONLY") {
+            remove_mutating_tools(&mut tool_router);
+            info!(
+                "Read-only mode: {} query tools enabled",
+                tool_router.list_all().len()
+            );
+        }
+
         Self {
-            tool_router: Self::tool_router(),
+            tool_router,
+            shutdown: Arc::new(Notify::new()),
+            events: Arc::new(Mutex::new(EventState::default())),
+            conn: Arc::new(tokio::sync::Mutex::new(None)),
+            ticks: broadcast::channel(16).0,
+            tick_counter: AtomicU64::new(0),
+            exec_limiter: Mutex::new(exec_rate_limiter()),
+            tabbed_layouts: Mutex::new(HashMap::new()),
+            spotlights: Mutex::new(HashMap::new()),
+            stash_workspace: stash_workspace(),
+            stashes: Mutex::new(HashMap::new()),
+            stats: Mutex::new(HashMap::new()),
+            tags: Mutex::new(BTreeMap::new()),
+            command_history: Mutex::new(VecDeque::new()),
+            peer: Arc::new(Mutex::new(None)),
+            notify_tree_changes: Arc::new(AtomicBool::new(true)),
+            instructions: server_instructions(),
         }
     }
 
-    /// Connect to i3 IPC socket
-    async fn connect(&self) -> Result<I3, McpError> {
-        I3::connect()
-            .await
-            .map_err(|e| McpError::internal_error(format!("Failed to connect to i3: {}", e), None))
+    /// Start the background task that subscribes to i3 events and keeps
+    /// EventState up to date. Reconnects if i3 restarts or isn't running yet.
+    /// Bursts of window/workspace events are also coalesced into a single
+    /// tree-change notification to the client.
+    pub fn spawn_event_listener(&self) -> tokio::task::JoinHandle<()> {
+        let events = self.events.clone();
+        let ticks = self.ticks.clone();
+        let peer = self.peer.clone();
+        let enabled = self.notify_tree_changes.clone();
+        let (changes_tx, mut changes_rx) = mpsc::unbounded_channel();
+
+        let notify = async move {
+            debounce_tree_changes(&mut changes_rx, TREE_CHANGE_DEBOUNCE, |changes| {
+                let peer = peer.lock().unwrap_or_else(PoisonError::into_inner).clone();
+                let enabled = enabled.load(Ordering::Relaxed);
+                async move {
+                    let Some(peer) = peer.filter(|_| enabled) else {
+                        return;
+                    };
+                    if let Err(e) = peer.notify_logging_message(changes.notification()).await {
+                        debug!("Failed to send tree-change notification: {}", e);
+                    }
+                }
+            })
+            .await;
+        };
+
+        let listen = async move {
+            let mut warned = false;
+            loop {
+                let result = listen_for_events(&events, &ticks, &changes_tx).await;
+                events
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .subscribed = false;
+                if let Err(e) = result {
+                    // Warn once, then keep retrying quietly until i3 is back
+                    if warned {
+                        debug!("i3 event stream unavailable: {}", e);
+                    } else {
+                        warn!("i3 event stream unavailable, retrying: {}", e);
+                        warned = true;
+                    }
+                }
+                tokio::time::sleep(EVENT_RECONNECT_DELAY).await;
+            }
+        };
+
+        tokio::spawn(async move {
+            tokio::join!(listen, notify);
+        })
+    }
+
+    /// Lock the event state (event handlers never panic mid-update, so a
+    /// poisoned lock still holds consistent data)
+    fn events(&self) -> MutexGuard<'_, EventState> {
+        self.events.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Take an exec launch token, or return the error result to send back if
+    /// launches are over the rate limit
+    fn check_exec_rate(&self, what: &str) -> Option<CallToolResult> {
+        let mut limiter = self
+            .exec_limiter
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if limiter.try_acquire(Instant::now()) {
+            return None;
+        }
+        warn!("exec rate limit exceeded, rejecting '{}'", what);
+        Some(ToolResponse::error(format!(
+            "Rate limit exceeded: at most {} launches per {}s. Slow down and try again shortly.",
+            limiter.capacity,
+            limiter.window.as_secs()
+        )))
+    }
+
+    /// Record one finished tool call in the per-tool stats
+    fn record_call(&self, tool: &str, latency: Duration, failed: bool) {
+        let mut stats = self.stats.lock().unwrap_or_else(PoisonError::into_inner);
+        stats
+            .entry(tool.to_string())
+            .or_default()
+            .record(latency, failed);
+    }
+
+    /// Handle notified when a client requests shutdown via shutdown_server
+    pub fn shutdown_signal(&self) -> Arc<Notify> {
+        self.shutdown.clone()
+    }
+
+    /// Get exclusive use of the shared i3 connection, connecting if needed.
+    ///
+    /// The connection is reused across tool calls. Holding the returned guard
+    /// serializes IPC, so concurrent tool calls are processed one at a time and
+    /// can never interleave requests and replies on the socket.
+    async fn connect(&self) -> Result<I3Conn<'_>, McpError> {
+        let mut guard = self.conn.lock().await;
+        if guard.is_none() {
+            debug!("Opening i3 IPC connection");
+            let conn = I3::connect().await.map_err(|e| {
+                McpError::internal_error(format!("Failed to connect to i3: {}", e), None)
+            })?;
+            *guard = Some(conn);
+        }
+        Ok(I3Conn { guard })
+    }
+
+    /// Replace the shared connection with a freshly opened one, returning it
+    /// and whether an old connection was dropped. On failure the old
+    /// connection is gone either way, so the next call retries from scratch.
+    async fn reconnect_conn(&self) -> Result<(I3Conn<'_>, bool), McpError> {
+        let mut guard = self.conn.lock().await;
+        let replaced = guard.take().is_some();
+        debug!("Reopening i3 IPC connection (had one: {})", replaced);
+        let conn = I3::connect().await.map_err(|e| {
+            McpError::internal_error(format!("Failed to connect to i3: {}", e), None)
+        })?;
+        *guard = Some(conn);
+        Ok((I3Conn { guard }, replaced))
+    }
+
+    /// Run a command on an open connection, mapping IPC failures to an McpError
+    /// described by `action` (e.g. "move window")
+    async fn send_command(
+        &self,
+        conn: &mut I3Conn<'_>,
+        command: &str,
+        action: &str,
+    ) -> Result<Vec<Success>, McpError> {
+        debug!("Sending i3 command: {}", command);
+        let result = conn.ipc().run_command(command).await;
+
+        let record = match &result {
+            Ok(results) => CommandRecord::new(command, failure_message(results)),
+            Err(e) => CommandRecord::new(command, Some(e.to_string())),
+        };
+        let mut history = self
+            .command_history
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        push_bounded(&mut history, record, COMMAND_HISTORY_LEN);
+        drop(history);
+
+        result.map_err(|e| conn.ipc_error(action, e))
+    }
+
+    /// Fetch the window tree on an open connection
+    async fn fetch_tree(&self, conn: &mut I3Conn<'_>) -> Result<Node, McpError> {
+        let result = conn.ipc().get_tree().await;
+        result.map_err(|e| conn.ipc_error("get tree", e))
+    }
+
+    /// i3 reports success even when criteria match nothing, so check the tree
+    /// first and return the error result to send back if no window matches.
+    /// Criteria beyond what criteria_matches understands are left to i3.
+    async fn check_criteria_match(
+        &self,
+        conn: &mut I3Conn<'_>,
+        criteria: &str,
+    ) -> Result<Option<CallToolResult>, McpError> {
+        let parsed = match parse_criteria(criteria) {
+            Ok(parsed) if criteria_checkable(&parsed) => parsed,
+            _ => return Ok(None),
+        };
+        let tree = self.fetch_tree(conn).await?;
+        if find_matching_nodes(&tree, &parsed).is_empty() {
+            return Ok(Some(ToolResponse::error(format!(
+                "No window matched {}",
+                criteria.trim()
+            ))));
+        }
+        Ok(None)
+    }
+
+    /// Fetch all workspaces on an open connection
+    async fn fetch_workspaces(&self, conn: &mut I3Conn<'_>) -> Result<Vec<Workspace>, McpError> {
+        let result = conn.ipc().get_workspaces().await;
+        result.map_err(|e| conn.ipc_error("get workspaces", e))
+    }
+
+    /// Fetch all outputs on an open connection
+    async fn fetch_outputs(&self, conn: &mut I3Conn<'_>) -> Result<Vec<Output>, McpError> {
+        let result = conn.ipc().get_outputs().await;
+        result.map_err(|e| conn.ipc_error("get outputs", e))
+    }
+
+    /// Fetch all marks on an open connection
+    async fn fetch_marks(&self, conn: &mut I3Conn<'_>) -> Result<Marks, McpError> {
+        let result = conn.ipc().get_marks().await;
+        result.map_err(|e| conn.ipc_error("get marks", e))
+    }
+
+    /// Fetch the ids of all configured bars on an open connection
+    async fn fetch_bar_ids(&self, conn: &mut I3Conn<'_>) -> Result<Vec<String>, McpError> {
+        let result = conn.ipc().get_bar_ids().await;
+        result
+            .map(|ids| ids.0)
+            .map_err(|e| conn.ipc_error("get bar ids", e))
+    }
+
+    /// Fetch all binding modes on an open connection
+    async fn fetch_binding_modes(&self, conn: &mut I3Conn<'_>) -> Result<BindingModes, McpError> {
+        let result = conn.ipc().get_binding_modes().await;
+        result.map_err(|e| conn.ipc_error("get binding modes", e))
+    }
+
+    /// Fetch the raw GET_VERSION reply, including fields tokio-i3ipc doesn't model
+    async fn fetch_raw_version(
+        &self,
+        conn: &mut I3Conn<'_>,
+    ) -> Result<serde_json::Value, McpError> {
+        let result = async {
+            conn.ipc().send_msg(Msg::Version).await?;
+            let reply: MsgResponse<serde_json::Value> = conn.ipc().read_msg().await?;
+            Ok::<_, std::io::Error>(reply.body)
+        }
+        .await;
+        result.map_err(|e| conn.ipc_error("get version", e))
+    }
+
+    /// Fetch the tree as raw JSON, keeping fields Node doesn't model (e.g. Sway's pid)
+    async fn fetch_raw_tree(&self, conn: &mut I3Conn<'_>) -> Result<serde_json::Value, McpError> {
+        let result = async {
+            conn.ipc().send_msg(Msg::Tree).await?;
+            let reply: MsgResponse<serde_json::Value> = conn.ipc().read_msg().await?;
+            Ok::<_, std::io::Error>(reply.body)
+        }
+        .await;
+        result.map_err(|e| conn.ipc_error("get tree", e))
+    }
+
+    /// Broadcast a tick with the given payload to all tick subscribers
+    async fn broadcast_tick(
+        &self,
+        conn: &mut I3Conn<'_>,
+        payload: &str,
+    ) -> Result<Success, McpError> {
+        let result = async {
+            conn.ipc().send_msg_body(Msg::Tick, payload).await?;
+            let reply: MsgResponse<Success> = conn.ipc().read_msg().await?;
+            Ok::<_, std::io::Error>(reply.body)
+        }
+        .await;
+        result.map_err(|e| conn.ipc_error("send tick", e))
+    }
+
+    /// Broadcast a tick and wait up to `timeout` for it to come back on the
+    /// event stream
+    async fn tick_round_trip(
+        &self,
+        payload: &str,
+        timeout: Duration,
+    ) -> Result<TickEcho, McpError> {
+        // Subscribe before sending so the echo can't be missed
+        let mut ticks = self.ticks.subscribe();
+        let subscribed = self.events().subscribed;
+
+        let mut conn = self.connect().await?;
+        let started = Instant::now();
+        let reply = self.broadcast_tick(&mut conn, payload).await?;
+        drop(conn);
+
+        if !reply.success {
+            return Ok(TickEcho::Rejected(reply.error.unwrap_or_default()));
+        }
+        if !subscribed {
+            return Ok(TickEcho::Unconfirmed);
+        }
+
+        let observed = tokio::time::timeout(timeout, async {
+            loop {
+                match ticks.recv().await {
+                    Ok(p) if p == payload => return true,
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return false,
+                }
+            }
+        })
+        .await
+        .unwrap_or(false);
+
+        Ok(if observed {
+            TickEcho::Received(started.elapsed())
+        } else {
+            TickEcho::TimedOut
+        })
+    }
+
+    /// Fetch i3 version info on an open connection
+    async fn fetch_version(&self, conn: &mut I3Conn<'_>) -> Result<Version, McpError> {
+        let result = conn.ipc().get_version().await;
+        result.map_err(|e| conn.ipc_error("get version", e))
+    }
+}
+
+/// Exclusive access to the shared i3 connection for the duration of a tool call
+struct I3Conn<'a> {
+    guard: tokio::sync::MutexGuard<'a, Option<I3>>,
+}
+
+impl I3Conn<'_> {
+    /// The underlying connection (always present while the guard is held)
+    fn ipc(&mut self) -> &mut I3 {
+        self.guard
+            .as_mut()
+            .expect("I3Conn is only constructed around a live connection")
+    }
+
+    /// Log an IPC failure and drop the connection so the next call reconnects
+    /// (an i3 restart, for example, leaves the old socket dead)
+    fn ipc_error(&mut self, action: &str, e: std::io::Error) -> McpError {
+        error!("Failed to {}: {}", action, e);
+        *self.guard = None;
+        McpError::internal_error(format!("Failed to {}: {}", action, e), None)
     }
 }
 
@@ -49,382 +438,7274 @@ impl Default for I3Server {
 }
 
 // ============================================================================
-// Tool Parameters
+// Event State
 // ============================================================================
 
-/// Parameters for switch_workspace tool
-#[derive(Debug, Serialize, Deserialize, JsonSchema)]
-pub struct SwitchWorkspaceParams {
-    /// Workspace to switch to (number or name, e.g. "1", "web", "music")
-    #[schemars(description = "Workspace to switch to (number or name)")]
-    pub workspace: String,
+/// Number of focus changes kept in the focus history
+const FOCUS_HISTORY_LEN: usize = 20;
+
+/// How long send_tick waits to see its tick come back on the event stream
+const TICK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Longest timeout sync_with_i3 accepts, in milliseconds
+const MAX_SYNC_TIMEOUT_MS: u64 = 10_000;
+
+/// What became of a tick sent by tick_round_trip
+#[derive(Debug)]
+enum TickEcho {
+    /// i3 refused the tick, with its error
+    Rejected(String),
+    /// Sent, but the event stream isn't subscribed so the echo can't be seen
+    Unconfirmed,
+    /// Seen on the event stream after this long
+    Received(Duration),
+    /// Not seen within the timeout
+    TimedOut,
 }
 
-/// Parameters for focus_window tool
-#[derive(Debug, Serialize, Deserialize, JsonSchema)]
-pub struct FocusWindowParams {
-    /// i3 criteria to match window (e.g. "[class=\"Firefox\"]", "[title=\"vim\"]")
-    #[schemars(description = "i3 criteria to match window, e.g. [class=\"Firefox\"] or [title=\"vim\"]")]
-    pub criteria: String,
+/// Delay before re-subscribing after the event connection drops
+const EVENT_RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// Quiet period after the last window/workspace event before the burst is
+/// reported as one tree-change notification
+const TREE_CHANGE_DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// A window focus change seen on the event stream
+#[derive(Debug, Clone)]
+struct FocusEntry {
+    con_id: usize,
+    /// Seconds since the Unix epoch
+    timestamp: u64,
 }
 
-/// Parameters for move_to_workspace tool
-#[derive(Debug, Serialize, Deserialize, JsonSchema)]
-pub struct MoveToWorkspaceParams {
-    /// Workspace to move the focused window to
-    #[schemars(description = "Workspace to move the focused window to")]
-    pub workspace: String,
+/// Session state derived from i3 events
+#[derive(Debug, Default)]
+struct EventState {
+    /// Whether the event listener currently holds a live subscription
+    subscribed: bool,
+    /// Recently focused windows, oldest first
+    focus_history: VecDeque<FocusEntry>,
+    /// The most recently created window, cleared when it closes
+    newest_window: Option<usize>,
+    /// The workspace focused before the current one (back_and_forth's target)
+    previous_workspace: Option<String>,
+    /// The binding mode from the last mode event; None means "default"
+    mode: Option<String>,
 }
 
-/// Parameters for run_command tool
-#[derive(Debug, Serialize, Deserialize, JsonSchema)]
-pub struct RunCommandParams {
-    /// i3 command to execute (see i3 user guide for full command list)
-    #[schemars(description = "i3 command to execute (e.g. 'split h', 'layout tabbed', 'kill')")]
-    pub command: String,
+impl EventState {
+    /// Update state from a single i3 event
+    fn handle_event(&mut self, event: &Event) {
+        match event {
+            Event::Window(data) => {
+                let con_id = data.container.id;
+                match data.change {
+                    WindowChange::Focus => self.record_focus(con_id, unix_now()),
+                    WindowChange::New => self.newest_window = Some(con_id),
+                    WindowChange::Close if self.newest_window == Some(con_id) => {
+                        self.newest_window = None;
+                    }
+                    _ => {}
+                }
+            }
+            Event::Workspace(data) if data.change == WorkspaceChange::Focus => {
+                let old = data.old.as_ref().and_then(|ws| ws.name.clone());
+                let current = data.current.as_ref().and_then(|ws| ws.name.clone());
+                if old.is_some() && old != current {
+                    self.previous_workspace = old;
+                }
+            }
+            Event::Mode(data) => self.mode = Some(data.change.clone()),
+            _ => {}
+        }
+    }
+
+    /// Record a window gaining focus, keeping the newest FOCUS_HISTORY_LEN entries
+    fn record_focus(&mut self, con_id: usize, timestamp: u64) {
+        // Refocusing the current window (e.g. clicking it again) isn't a change
+        if self
+            .focus_history
+            .back()
+            .is_some_and(|e| e.con_id == con_id)
+        {
+            return;
+        }
+        self.focus_history
+            .push_back(FocusEntry { con_id, timestamp });
+        while self.focus_history.len() > FOCUS_HISTORY_LEN {
+            self.focus_history.pop_front();
+        }
+    }
 }
 
-/// Parameters for exec tool
-#[derive(Debug, Serialize, Deserialize, JsonSchema)]
-pub struct ExecParams {
-    /// Command to execute (application to launch)
-    #[schemars(description = "Command to execute, e.g. 'firefox', 'kitty', 'emacs'")]
-    pub command: String,
+/// A burst of window/workspace events, coalesced into one notification
+#[derive(Debug, Default, PartialEq, Serialize)]
+struct TreeChanges {
+    /// Number of events in the burst
+    events: usize,
+    /// Distinct event kinds seen, e.g. "window::new"
+    kinds: BTreeSet<String>,
 }
 
-/// Parameters for kill_window tool
-#[derive(Debug, Serialize, Deserialize, JsonSchema)]
-pub struct KillWindowParams {
-    /// i3 criteria to match window to kill (e.g. "[class=\"Firefox\"]", "[title=\"vim\"]")
-    #[schemars(description = "i3 criteria to match window to kill, e.g. [class=\"Firefox\"] or [title=\"~\"]")]
-    pub criteria: String,
+impl TreeChanges {
+    fn add(&mut self, kind: String) {
+        self.events += 1;
+        self.kinds.insert(kind);
+    }
+
+    /// The MCP logging notification announcing this burst
+    fn notification(&self) -> LoggingMessageNotificationParam {
+        LoggingMessageNotificationParam {
+            level: LoggingLevel::Info,
+            logger: Some("i3".to_string()),
+            data: serde_json::json!({
+                "type": "tree_changed",
+                "events": self.events,
+                "kinds": self.kinds,
+            }),
+        }
+    }
+}
+
+/// The `type::change` name of a window or workspace event, e.g.
+/// "workspace::focus" (None for other events)
+fn tree_change_kind(event: &Event) -> Option<String> {
+    let (kind, change) = match event {
+        Event::Window(data) => ("window", serde_json::to_value(data.change)),
+        Event::Workspace(data) => ("workspace", serde_json::to_value(data.change)),
+        _ => return None,
+    };
+    Some(format!("{}::{}", kind, change.ok()?.as_str()?))
+}
+
+/// Group incoming change kinds into bursts separated by at least `quiet`, and
+/// pass each finished burst to `emit`. Runs until the sender is dropped.
+async fn debounce_tree_changes<F, Fut>(
+    changes: &mut mpsc::UnboundedReceiver<String>,
+    quiet: Duration,
+    mut emit: F,
+) where
+    F: FnMut(TreeChanges) -> Fut,
+    Fut: std::future::Future<Output = ()>,
+{
+    while let Some(first) = changes.recv().await {
+        let mut burst = TreeChanges::default();
+        burst.add(first);
+        while let Ok(Some(kind)) = tokio::time::timeout(quiet, changes.recv()).await {
+            burst.add(kind);
+        }
+        emit(burst).await;
+    }
+}
+
+/// Subscribe to i3 events and feed them into `events` until the connection fails
+async fn listen_for_events(
+    events: &Mutex<EventState>,
+    ticks: &broadcast::Sender<String>,
+    changes: &mpsc::UnboundedSender<String>,
+) -> std::io::Result<()> {
+    let mut conn = I3::connect().await?;
+    conn.subscribe([
+        Subscribe::Window,
+        Subscribe::Workspace,
+        Subscribe::Mode,
+        Subscribe::Tick,
+    ])
+    .await?;
+    info!("Subscribed to i3 events");
+    events
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .subscribed = true;
+
+    loop {
+        let event = conn.read_event().await?;
+        if let Event::Tick(tick) = &event {
+            // The first tick just acknowledges the subscription
+            if !tick.first {
+                // No receivers just means nobody is waiting on a tick
+                let _ = ticks.send(tick.payload.clone());
+            }
+        }
+        if let Some(kind) = tree_change_kind(&event) {
+            // The debouncer only stops when the listener task is aborted
+            let _ = changes.send(kind);
+        }
+        events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .handle_event(&event);
+    }
+}
+
+/// Current time in seconds since the Unix epoch
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
 }
 
 // ============================================================================
-// Tool Implementations
+// Tool Metrics
 // ============================================================================
 
-#[rmcp::tool_router]
-impl I3Server {
-    /// Get a list of all workspaces with their properties
-    #[rmcp::tool(description = "List all i3 workspaces with their properties (number, name, visible, focused, urgent, output)")]
-    pub async fn get_workspaces(&self) -> Result<CallToolResult, McpError> {
-        info!("Getting workspaces");
-        let mut conn = self.connect().await?;
+/// Invocation counters for a single tool
+#[derive(Debug, Default, Clone)]
+struct ToolStats {
+    calls: u64,
+    /// Calls that returned an error or an `is_error` result
+    errors: u64,
+    total_latency: Duration,
+}
 
-        let workspaces: Vec<Workspace> = conn.get_workspaces().await.map_err(|e| {
-            error!("Failed to get workspaces: {}", e);
-            McpError::internal_error(format!("Failed to get workspaces: {}", e), None)
-        })?;
+impl ToolStats {
+    fn record(&mut self, latency: Duration, failed: bool) {
+        self.calls += 1;
+        if failed {
+            self.errors += 1;
+        }
+        self.total_latency += latency;
+    }
+}
 
-        let json = serde_json::to_string_pretty(&workspaces).map_err(|e| {
-            McpError::internal_error(format!("Failed to serialize workspaces: {}", e), None)
-        })?;
+/// Per-tool summary returned by get_stats
+#[derive(Debug, Serialize)]
+struct ToolStatsSummary {
+    calls: u64,
+    errors: u64,
+    avg_latency_ms: f64,
+}
 
-        debug!("Found {} workspaces", workspaces.len());
-        Ok(CallToolResult::success(vec![Content::text(json)]))
+impl From<&ToolStats> for ToolStatsSummary {
+    fn from(stats: &ToolStats) -> Self {
+        let avg_latency_ms = if stats.calls == 0 {
+            0.0
+        } else {
+            stats.total_latency.as_secs_f64() * 1000.0 / stats.calls as f64
+        };
+        Self {
+            calls: stats.calls,
+            errors: stats.errors,
+            avg_latency_ms,
+        }
     }
+}
 
-    /// Get the full i3 window tree
-    #[rmcp::tool(description = "Get the full i3 window tree (all containers, windows, and their layout)")]
-    pub async fn get_tree(&self) -> Result<CallToolResult, McpError> {
-        info!("Getting window tree");
-        let mut conn = self.connect().await?;
+/// Number of commands kept in the command history
+const COMMAND_HISTORY_LEN: usize = 50;
 
-        let tree: Node = conn.get_tree().await.map_err(|e| {
-            error!("Failed to get tree: {}", e);
-            McpError::internal_error(format!("Failed to get tree: {}", e), None)
-        })?;
+/// One command sent to i3, as returned by get_command_history
+#[derive(Debug, Clone, Serialize)]
+struct CommandRecord {
+    command: String,
+    /// Seconds since the Unix epoch
+    timestamp: u64,
+    success: bool,
+    error: Option<String>,
+}
 
-        let json = serde_json::to_string_pretty(&tree).map_err(|e| {
-            McpError::internal_error(format!("Failed to serialize tree: {}", e), None)
-        })?;
+impl CommandRecord {
+    fn new(command: &str, error: Option<String>) -> Self {
+        Self {
+            command: command.to_string(),
+            timestamp: unix_now(),
+            success: error.is_none(),
+            error,
+        }
+    }
+}
 
-        Ok(CallToolResult::success(vec![Content::text(json)]))
+/// Append to a ring buffer, dropping the oldest entries beyond `capacity`
+fn push_bounded<T>(buffer: &mut VecDeque<T>, item: T, capacity: usize) {
+    buffer.push_back(item);
+    while buffer.len() > capacity {
+        buffer.pop_front();
     }
+}
 
-    /// Switch to a specific workspace
-    #[rmcp::tool(description = "Switch to a specific workspace by number or name")]
-    pub async fn switch_workspace(
-        &self,
-        Parameters(params): Parameters<SwitchWorkspaceParams>,
-    ) -> Result<CallToolResult, McpError> {
-        info!("Switching to workspace: {}", params.workspace);
-        let mut conn = self.connect().await?;
+// ============================================================================
+// Tool Response
+// ============================================================================
 
-        let command = format!("workspace {}", params.workspace);
-        let results = conn.run_command(&command).await.map_err(|e| {
-            error!("Failed to switch workspace: {}", e);
-            McpError::internal_error(format!("Failed to switch workspace: {}", e), None)
-        })?;
+/// Uniform JSON envelope every tool returns: whether the action succeeded, a
+/// human-readable message, and any structured data
+#[derive(Debug, Serialize)]
+struct ToolResponse {
+    ok: bool,
+    message: String,
+    data: Option<serde_json::Value>,
+}
 
-        // Check if command succeeded
-        let success = results.iter().all(|r| r.success);
-        if success {
-            Ok(CallToolResult::success(vec![Content::text(format!(
-                "Switched to workspace '{}'",
-                params.workspace
-            ))]))
-        } else {
-            let errors: Vec<String> = results
-                .iter()
-                .filter_map(|r| r.error.clone())
-                .collect();
-            Ok(CallToolResult::success(vec![Content::text(format!(
-                "Failed to switch workspace: {}",
-                errors.join(", ")
-            ))]))
-        }
+impl ToolResponse {
+    /// The action succeeded
+    fn success(message: impl Into<String>) -> CallToolResult {
+        Self::new(true, message, None).into_result(false)
     }
 
-    /// Focus a window by i3 criteria
-    #[rmcp::tool(description = "Focus a window matching i3 criteria (e.g. [class=\"Firefox\"], [title=\"vim\"])")]
-    pub async fn focus_window(
-        &self,
-        Parameters(params): Parameters<FocusWindowParams>,
+    /// The tool ran, but i3 (or a precondition) reported a failure
+    fn failure(message: impl Into<String>) -> CallToolResult {
+        Self::new(false, message, None).into_result(false)
+    }
+
+    /// The call was rejected or aborted part way, flagged as a tool error
+    fn error(message: impl Into<String>) -> CallToolResult {
+        Self::new(false, message, None).into_result(true)
+    }
+
+    /// The action succeeded and produced structured data
+    fn data(message: impl Into<String>, data: &impl Serialize) -> Result<CallToolResult, McpError> {
+        Ok(Self::new(true, message, Some(to_json_value(data)?)).into_result(false))
+    }
+
+    /// The tool ran but reported a failure, with details in `data`
+    fn failure_data(
+        message: impl Into<String>,
+        data: &impl Serialize,
     ) -> Result<CallToolResult, McpError> {
-        info!("Focusing window: {}", params.criteria);
-        let mut conn = self.connect().await?;
+        Ok(Self::new(false, message, Some(to_json_value(data)?)).into_result(false))
+    }
 
-        let command = format!("{} focus", params.criteria);
-        let results = conn.run_command(&command).await.map_err(|e| {
-            error!("Failed to focus window: {}", e);
-            McpError::internal_error(format!("Failed to focus window: {}", e), None)
-        })?;
+    fn new(ok: bool, message: impl Into<String>, data: Option<serde_json::Value>) -> Self {
+        Self {
+            ok,
+            message: message.into(),
+            data,
+        }
+    }
 
-        let success = results.iter().all(|r| r.success);
-        if success {
-            Ok(CallToolResult::success(vec![Content::text(format!(
-                "Focused window matching '{}'",
-                params.criteria
-            ))]))
+    /// The envelope as it is sent to the client
+    fn render(&self) -> String {
+        render_json(self, compact_json())
+    }
+
+    fn into_result(self, is_error: bool) -> CallToolResult {
+        let content = vec![Content::text(self.render())];
+        if is_error {
+            CallToolResult::error(content)
         } else {
-            let errors: Vec<String> = results
-                .iter()
-                .filter_map(|r| r.error.clone())
-                .collect();
-            Ok(CallToolResult::success(vec![Content::text(format!(
-                "Failed to focus window: {}",
-                errors.join(", ")
-            ))]))
+            CallToolResult::success(content)
         }
     }
+}
 
-    /// Move the focused window to a workspace
-    #[rmcp::tool(description = "Move the currently focused window to a specific workspace")]
-    pub async fn move_to_workspace(
-        &self,
-        Parameters(params): Parameters<MoveToWorkspaceParams>,
-    ) -> Result<CallToolResult, McpError> {
-        info!("Moving window to workspace: {}", params.workspace);
-        let mut conn = self.connect().await?;
+/// Whether responses are sent as compact JSON (RMCP_I3_JSON_COMPACT), read once
+fn compact_json() -> bool {
+    static COMPACT: OnceLock<bool> = OnceLock::new();
+    *COMPACT.get_or_init(|| env_flag("RMCP_I3_JSON_COMPACT"))
+}
 
-        let command = format!("move container to workspace {}", params.workspace);
-        let results = conn.run_command(&command).await.map_err(|e| {
-            error!("Failed to move window: {}", e);
-            McpError::internal_error(format!("Failed to move window: {}", e), None)
-        })?;
+/// Serialize a response, pretty-printed unless `compact`. Values built from
+/// plain fields and serde_json::Value always serialize.
+fn render_json(value: &impl Serialize, compact: bool) -> String {
+    let rendered = if compact {
+        serde_json::to_string(value)
+    } else {
+        serde_json::to_string_pretty(value)
+    };
+    rendered.unwrap_or_default()
+}
 
-        let success = results.iter().all(|r| r.success);
-        if success {
-            Ok(CallToolResult::success(vec![Content::text(format!(
-                "Moved window to workspace '{}'",
-                params.workspace
-            ))]))
+/// Convert tool data to JSON, mapping failures to an McpError
+fn to_json_value(data: &impl Serialize) -> Result<serde_json::Value, McpError> {
+    serde_json::to_value(data)
+        .map_err(|e| McpError::internal_error(format!("Failed to serialize response: {}", e), None))
+}
+
+// ============================================================================
+// Helpers
+// ============================================================================
+
+/// Tools that never change i3's state, the only ones registered under
+/// RMCP_I3_READONLY. Tools with an optional mutating mode (e.g.
+/// workspace_layout, list_empty_workspaces) are deliberately left out.
+const READ_ONLY_TOOLS: &[&str] = &[
+    "get_workspaces",
+    "get_bar_state",
+    "get_tree",
+    "window_overview",
+    "list_urgent_windows",
+    "list_windows",
+    "snapshot_layout",
+    "windows_by_recency",
+    "describe_state",
+    "get_window_processes",
+    "get_layout_rects",
+    "get_focus_history",
+    "get_previous_workspace",
+    "get_container",
+    "get_focus_order",
+    "get_next_free_workspace",
+    "get_focus_layer",
+    "get_focus_depth",
+    "get_outputs",
+    "get_output_layout",
+    "get_primary_output",
+    "get_marks",
+    "is_running",
+    "find_elsewhere",
+    "get_window_marks",
+    "get_tagged",
+    "get_binding_modes",
+    "get_current_mode",
+    "get_version",
+    "get_config_info",
+    "get_scratchpad",
+    "audit_scratchpad",
+    "find_offscreen_windows",
+    "build_criteria",
+    "validate_command",
+    "get_debuglog",
+    "get_socket_info",
+    "get_stats",
+    "get_command_history",
+    "get_tool_metadata",
+    "list_presets",
+];
+
+/// Whether a tool may change i3's state: every tool not in READ_ONLY_TOOLS
+fn is_mutating(tool: &str) -> bool {
+    !READ_ONLY_TOOLS.contains(&tool)
+}
+
+/// Unregister every mutating tool, leaving the RMCP_I3_READONLY tool set
+fn remove_mutating_tools(tool_router: &mut ToolRouter<I3Server>) {
+    for tool in tool_router.list_all() {
+        if is_mutating(&tool.name) {
+            tool_router.remove_route(&tool.name);
+        }
+    }
+}
+
+/// Check whether a boolean environment variable is enabled ("1" or "true")
+fn env_flag(name: &str) -> bool {
+    std::env::var(name)
+        .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+        .unwrap_or(false)
+}
+
+/// Default exec rate: launches allowed per window
+const DEFAULT_EXEC_RATE: (u32, u64) = (5, 10);
+
+/// Token bucket allowing `capacity` actions per `window`, refilled continuously
+#[derive(Debug)]
+struct RateLimiter {
+    capacity: u32,
+    window: Duration,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    fn new(capacity: u32, window: Duration) -> Self {
+        Self {
+            capacity,
+            window,
+            tokens: f64::from(capacity),
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Take a token if one is available at `now`
+    fn try_acquire(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        let refill = elapsed.as_secs_f64() / self.window.as_secs_f64() * f64::from(self.capacity);
+        self.tokens = (self.tokens + refill).min(f64::from(self.capacity));
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Instructions sent to clients in get_info unless overridden
+const DEFAULT_INSTRUCTIONS: &str = "MCP server for controlling the i3 window manager. \
+     Use get_workspaces to list workspaces, get_tree for window layout, \
+     switch_workspace/focus_window/move_to_workspace for navigation, \
+     and run_command for arbitrary i3 commands.";
+
+/// Pick the server instructions: the inline override, then the override file's
+/// contents, then the default. Blank overrides count as unset.
+fn select_instructions(inline: Option<String>, file: Option<std::io::Result<String>>) -> String {
+    let nonblank = |text: String| Some(text.trim().to_string()).filter(|t| !t.is_empty());
+    if let Some(text) = inline.and_then(nonblank) {
+        return text;
+    }
+    match file {
+        Some(Ok(text)) => nonblank(text).unwrap_or_else(|| DEFAULT_INSTRUCTIONS.to_string()),
+        Some(Err(e)) => {
+            warn!(
+                "Failed to read RMCP_I3_INSTRUCTIONS_FILE ({}); using default instructions",
+                e
+            );
+            DEFAULT_INSTRUCTIONS.to_string()
+        }
+        None => DEFAULT_INSTRUCTIONS.to_string(),
+    }
+}
+
+/// Load the server instructions from RMCP_I3_INSTRUCTIONS or
+/// RMCP_I3_INSTRUCTIONS_FILE, falling back to the default
+fn server_instructions() -> String {
+    let inline = std::env::var("RMCP_I3_INSTRUCTIONS").ok();
+    let file = std::env::var("RMCP_I3_INSTRUCTIONS_FILE")
+        .ok()
+        .filter(|path| !path.is_empty())
+        .map(std::fs::read_to_string);
+    select_instructions(inline, file)
+}
+
+/// Parse an exec rate of the form "COUNT/SECONDS" (e.g. "5/10")
+fn parse_rate(value: &str) -> Option<(u32, u64)> {
+    let (count, secs) = value.split_once('/')?;
+    let count: u32 = count.trim().parse().ok()?;
+    let secs: u64 = secs.trim().trim_end_matches('s').parse().ok()?;
+    (count > 0 && secs > 0).then_some((count, secs))
+}
+
+/// Build the exec rate limiter from RMCP_I3_EXEC_RATE, falling back to the default
+fn exec_rate_limiter() -> RateLimiter {
+    let (count, secs) = match std::env::var("RMCP_I3_EXEC_RATE") {
+        Ok(value) => parse_rate(&value).unwrap_or_else(|| {
+            warn!(
+                "Invalid RMCP_I3_EXEC_RATE '{}', expected COUNT/SECONDS; using default",
+                value
+            );
+            DEFAULT_EXEC_RATE
+        }),
+        Err(_) => DEFAULT_EXEC_RATE,
+    };
+    RateLimiter::new(count, Duration::from_secs(secs))
+}
+
+/// Default workspace stash_window moves windows to
+const DEFAULT_STASH_WORKSPACE: &str = "stash";
+
+/// Read the stash workspace name from RMCP_I3_STASH_WS, falling back to the default
+fn stash_workspace() -> String {
+    match std::env::var("RMCP_I3_STASH_WS") {
+        Ok(value) => match validate_workspace_name(&value) {
+            Ok(name) => name.to_string(),
+            Err(_) => {
+                warn!(
+                    "Invalid RMCP_I3_STASH_WS '{}'; using '{}'",
+                    value, DEFAULT_STASH_WORKSPACE
+                );
+                DEFAULT_STASH_WORKSPACE.to_string()
+            }
+        },
+        Err(_) => DEFAULT_STASH_WORKSPACE.to_string(),
+    }
+}
+
+/// Collect the error messages from a command reply, or None if every command succeeded
+fn failure_message(results: &[Success]) -> Option<String> {
+    if results.iter().all(|r| r.success) {
+        return None;
+    }
+    let errors: Vec<String> = results.iter().filter_map(|r| r.error.clone()).collect();
+    Some(errors.join(", "))
+}
+
+/// Outcome of one command in a multi-step tool
+#[derive(Debug)]
+struct StepOutcome {
+    command: String,
+    error: Option<String>,
+}
+
+impl StepOutcome {
+    fn from_results(command: impl Into<String>, results: &[Success]) -> Self {
+        Self {
+            command: command.into(),
+            error: failure_message(results),
+        }
+    }
+}
+
+/// Render step outcomes as a numbered list, one line per command
+fn format_steps(steps: &[StepOutcome]) -> String {
+    steps
+        .iter()
+        .enumerate()
+        .map(|(i, step)| match &step.error {
+            None => format!("{}. {}: ok", i + 1, step.command),
+            Some(err) => format!("{}. {}: failed ({})", i + 1, step.command, err),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Visit every node in the tree depth first (tiling children, then floating
+/// children), passing each node along with its ancestors from the root
+fn walk_tree<'a>(
+    node: &'a Node,
+    ancestors: &mut Vec<&'a Node>,
+    visit: &mut dyn FnMut(&'a Node, &[&'a Node]),
+) {
+    visit(node, ancestors);
+    ancestors.push(node);
+    for child in node.nodes.iter().chain(node.floating_nodes.iter()) {
+        walk_tree(child, ancestors, visit);
+    }
+    ancestors.pop();
+}
+
+/// Find a node by con_id, returning it together with its ancestors (root first)
+fn find_node_with_ancestors(root: &Node, con_id: usize) -> Option<(&Node, Vec<&Node>)> {
+    let mut found = None;
+    walk_tree(root, &mut Vec::new(), &mut |node, ancestors| {
+        if found.is_none() && node.id == con_id {
+            found = Some((node, ancestors.to_vec()));
+        }
+    });
+    found
+}
+
+/// All regular workspace nodes in the tree (skips i3-internal ones like __i3_scratch)
+fn workspace_nodes(root: &Node) -> Vec<&Node> {
+    let mut workspaces = Vec::new();
+    walk_tree(root, &mut Vec::new(), &mut |node, _| {
+        let internal = node.name.as_deref().is_some_and(|n| n.starts_with("__i3"));
+        if node.node_type == NodeType::Workspace && !internal {
+            workspaces.push(node);
+        }
+    });
+    workspaces
+}
+
+/// Number of windows (nodes with an X11 window) in a subtree
+fn count_windows(node: &Node) -> usize {
+    let mut count = 0;
+    walk_tree(node, &mut Vec::new(), &mut |n, _| {
+        if n.window.is_some() {
+            count += 1;
+        }
+    });
+    count
+}
+
+/// Names of workspaces (from get_workspaces) that contain no windows in the tree
+fn empty_workspace_names(workspaces: &[Workspace], tree: &Node) -> Vec<String> {
+    let nodes = workspace_nodes(tree);
+    workspaces
+        .iter()
+        .filter(|ws| {
+            nodes
+                .iter()
+                .find(|n| n.name.as_deref() == Some(ws.name.as_str()))
+                .is_none_or(|n| count_windows(n) == 0)
+        })
+        .map(|ws| ws.name.clone())
+        .collect()
+}
+
+/// The command visiting each empty workspace and finishing back on `current`
+/// (i3 removes an empty workspace once focus leaves it)
+fn cleanup_workspaces_command(empty: &[String], current: &str) -> String {
+    let mut commands: Vec<String> = empty
+        .iter()
+        .filter(|name| *name != current)
+        .map(|name| format!("workspace \"{}\"", escape_criteria_value(name)))
+        .collect();
+    commands.push(format!("workspace \"{}\"", escape_criteria_value(current)));
+    commands.join("; ")
+}
+
+/// con_ids of every window on the named workspace, or None if no such workspace
+/// is in the tree
+fn workspace_window_ids(root: &Node, name: &str) -> Option<Vec<usize>> {
+    let workspace = workspace_nodes(root)
+        .into_iter()
+        .find(|ws| ws.name.as_deref() == Some(name))?;
+    let mut ids = Vec::new();
+    walk_tree(workspace, &mut Vec::new(), &mut |node, _| {
+        if node.window.is_some() {
+            ids.push(node.id);
+        }
+    });
+    Some(ids)
+}
+
+/// One chained command moving each container to `workspace`
+fn evacuate_command(con_ids: &[usize], workspace: &str) -> String {
+    con_ids
+        .iter()
+        .map(|id| {
+            format!(
+                "[con_id={}] move container to workspace \"{}\"",
+                id,
+                escape_criteria_value(workspace)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Flattened view of a single window, as returned by window_overview
+#[derive(Debug, Clone, Serialize)]
+struct WindowInfo {
+    con_id: usize,
+    class: Option<String>,
+    title: Option<String>,
+    workspace: Option<String>,
+    output: Option<String>,
+    focused: bool,
+    urgent: bool,
+    /// Label set with label_container, if any
+    label: Option<String>,
+    /// Window geometry in root coordinates unless translated with relative_to_output
+    rect: Rect,
+    #[serde(skip)]
+    workspace_num: Option<i32>,
+}
+
+impl WindowInfo {
+    /// Build from a window node and its ancestors (root first)
+    fn from_node(node: &Node, ancestors: &[&Node]) -> Self {
+        let workspace = ancestors
+            .iter()
+            .rev()
+            .find(|a| a.node_type == NodeType::Workspace);
+        let output = ancestors
+            .iter()
+            .rev()
+            .find(|a| a.node_type == NodeType::Output);
+        let props = node.window_properties.as_ref();
+
+        Self {
+            con_id: node.id,
+            class: props.and_then(|p| p.class.clone()),
+            title: props
+                .and_then(|p| p.title.clone())
+                .or_else(|| node.name.clone()),
+            workspace: workspace.and_then(|w| w.name.clone()),
+            output: output.and_then(|o| o.name.clone()),
+            focused: node.focused,
+            urgent: node.urgent,
+            label: container_label(node).map(str::to_string),
+            rect: node.rect.clone(),
+            workspace_num: workspace.and_then(|w| w.num),
+        }
+    }
+}
+
+/// Every window in the tree, sorted by workspace (number, then name), then
+/// top-to-bottom and left-to-right
+fn collect_windows(root: &Node) -> Vec<WindowInfo> {
+    let mut windows = Vec::new();
+    walk_tree(root, &mut Vec::new(), &mut |node, ancestors| {
+        if node.window.is_some() {
+            windows.push(WindowInfo::from_node(node, ancestors));
+        }
+    });
+
+    // Numbered workspaces first in numeric order; unnumbered (-1/None) last
+    windows.sort_by(|a, b| {
+        let num = |w: &WindowInfo| w.workspace_num.filter(|n| *n >= 0).unwrap_or(i32::MAX);
+        num(a)
+            .cmp(&num(b))
+            .then_with(|| a.workspace.cmp(&b.workspace))
+            .then_with(|| a.rect.y.cmp(&b.rect.y))
+            .then_with(|| a.rect.x.cmp(&b.rect.x))
+    });
+    windows
+}
+
+/// Values accepted by focus_by_size
+const SIZE_EXTREMES: &[&str] = &["largest", "smallest"];
+
+/// The window with the largest (or smallest) rect area; ties go to the
+/// lowest con_id so the pick is deterministic
+fn window_by_size(windows: &[WindowInfo], largest: bool) -> Option<&WindowInfo> {
+    let area = |w: &WindowInfo| w.rect.width.max(0) as u64 * w.rect.height.max(0) as u64;
+    if largest {
+        windows
+            .iter()
+            .min_by_key(|w| (std::cmp::Reverse(area(w)), w.con_id))
+    } else {
+        windows.iter().min_by_key(|w| (area(w), w.con_id))
+    }
+}
+
+/// A window with the last time it gained focus, as returned by windows_by_recency
+#[derive(Debug, Serialize)]
+struct RecentWindow {
+    #[serde(flatten)]
+    window: WindowInfo,
+    /// Seconds since the Unix epoch; None if not focused since the server started
+    last_focused: Option<u64>,
+}
+
+/// Order windows most recently focused first, using the focus history
+/// (oldest first). The focused window always leads, even if its focus predates
+/// the history; windows never seen gaining focus keep their order at the end.
+fn windows_by_recency(windows: Vec<WindowInfo>, history: &[FocusEntry]) -> Vec<RecentWindow> {
+    // Later entries win, so each window ends up with its latest position
+    let seen: HashMap<usize, (usize, u64)> = history
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| (entry.con_id, (i, entry.timestamp)))
+        .collect();
+
+    let mut ranked: Vec<(Option<usize>, RecentWindow)> = windows
+        .into_iter()
+        .map(|window| {
+            let entry = seen.get(&window.con_id);
+            let rank = if window.focused {
+                Some(usize::MAX)
+            } else {
+                entry.map(|(i, _)| *i)
+            };
+            let last_focused = entry.map(|(_, timestamp)| *timestamp);
+            let recent = RecentWindow {
+                window,
+                last_focused,
+            };
+            (rank, recent)
+        })
+        .collect();
+    // Stable sort: unranked windows stay in collect_windows order
+    ranked.sort_by_key(|(rank, _)| std::cmp::Reverse(*rank));
+    ranked.into_iter().map(|(_, window)| window).collect()
+}
+
+/// A window as recorded in a layout snapshot
+#[derive(Debug, Serialize)]
+struct SnapshotWindow {
+    con_id: usize,
+    class: Option<String>,
+    instance: Option<String>,
+    title: Option<String>,
+    marks: Vec<String>,
+    floating: bool,
+}
+
+impl SnapshotWindow {
+    fn from_node(node: &Node) -> Self {
+        let props = node.window_properties.as_ref();
+        Self {
+            con_id: node.id,
+            class: props.and_then(|p| p.class.clone()),
+            instance: props.and_then(|p| p.instance.clone()),
+            title: props
+                .and_then(|p| p.title.clone())
+                .or_else(|| node.name.clone()),
+            marks: node.marks.as_ref().map(|m| m.0.clone()).unwrap_or_default(),
+            floating: is_floating(node),
+        }
+    }
+}
+
+/// A workspace and its windows in a layout snapshot
+#[derive(Debug, Serialize)]
+struct SnapshotWorkspace {
+    name: String,
+    num: i32,
+    output: String,
+    focused: bool,
+    layout: String,
+    windows: Vec<SnapshotWindow>,
+}
+
+/// A self-contained description of the session, as returned by snapshot_layout
+#[derive(Debug, Serialize)]
+struct LayoutSnapshot {
+    /// Seconds since the Unix epoch
+    taken_at: u64,
+    /// Active outputs: name, primary flag, and geometry
+    outputs: Vec<serde_json::Value>,
+    workspaces: Vec<SnapshotWorkspace>,
+    /// Windows in the scratchpad, which belong to no workspace
+    scratchpad: Vec<SnapshotWindow>,
+}
+
+/// Capture every workspace (in get_workspaces order) with its windows in
+/// reading order, plus the outputs and the scratchpad
+fn layout_snapshot(
+    root: &Node,
+    workspaces: &[Workspace],
+    outputs: &[Output],
+    taken_at: u64,
+) -> LayoutSnapshot {
+    let windows_in = |node: &Node| {
+        let mut windows = Vec::new();
+        walk_tree(node, &mut Vec::new(), &mut |n, _| {
+            if n.window.is_some() {
+                windows.push(n);
+            }
+        });
+        windows.sort_by_key(|n| (n.rect.y, n.rect.x));
+        windows.into_iter().map(SnapshotWindow::from_node).collect()
+    };
+
+    let nodes = workspace_nodes(root);
+    let snapshot_workspaces = workspaces
+        .iter()
+        .map(|ws| {
+            let node = nodes
+                .iter()
+                .find(|n| n.name.as_deref() == Some(ws.name.as_str()));
+            SnapshotWorkspace {
+                name: ws.name.clone(),
+                num: ws.num,
+                output: ws.output.clone(),
+                focused: ws.focused,
+                layout: node.map(|n| layout_name(n.layout)).unwrap_or_default(),
+                windows: node.map(|n| windows_in(n)).unwrap_or_default(),
+            }
+        })
+        .collect();
+
+    let mut scratchpad = Vec::new();
+    walk_tree(root, &mut Vec::new(), &mut |node, _| {
+        if node.node_type == NodeType::Workspace && node.name.as_deref() == Some("__i3_scratch") {
+            scratchpad = windows_in(node);
+        }
+    });
+
+    LayoutSnapshot {
+        taken_at,
+        outputs: outputs
+            .iter()
+            .filter(|o| o.active)
+            .map(|o| serde_json::json!({ "name": o.name, "primary": o.primary, "rect": o.rect }))
+            .collect(),
+        workspaces: snapshot_workspaces,
+        scratchpad,
+    }
+}
+
+/// Every window's title keyed by con_id, for list_windows
+fn window_titles(root: &Node) -> BTreeMap<String, String> {
+    let mut titles = BTreeMap::new();
+    walk_tree(root, &mut Vec::new(), &mut |node, _| {
+        if node.window.is_some() {
+            let title = node
+                .window_properties
+                .as_ref()
+                .and_then(|p| p.title.clone())
+                .or_else(|| node.name.clone())
+                .unwrap_or_default();
+            titles.insert(node.id.to_string(), title);
+        }
+    });
+    titles
+}
+
+/// How describe_state names a window: its class and title, when known,
+/// after its label_container label if it has one
+fn window_label(window: &WindowInfo) -> String {
+    let name = match (&window.class, &window.title) {
+        (Some(class), Some(title)) => format!("{} \"{}\"", class, title),
+        (Some(label), None) | (None, Some(label)) => label.clone(),
+        (None, None) => format!("con_id {}", window.con_id),
+    };
+    match &window.label {
+        Some(label) => format!("[{}] {}", label, name),
+        None => name,
+    }
+}
+
+/// A short prose summary of the session: focused workspace, output, and
+/// window, overall counts, and urgent windows
+fn describe_state(workspaces: &[Workspace], outputs: &[Output], windows: &[WindowInfo]) -> String {
+    let on_workspace = |w: &&WindowInfo| !w.workspace.as_deref().unwrap_or("").starts_with("__");
+    let visible: Vec<&WindowInfo> = windows.iter().filter(on_workspace).collect();
+    let scratchpad = windows.len() - visible.len();
+    let mut sentences = Vec::new();
+
+    match workspaces.iter().find(|ws| ws.focused) {
+        Some(ws) => {
+            let count = visible
+                .iter()
+                .filter(|w| w.workspace.as_deref() == Some(ws.name.as_str()))
+                .count();
+            sentences.push(format!(
+                "Focused workspace is '{}' on output {} with {} window(s).",
+                ws.name, ws.output, count
+            ));
+        }
+        None => sentences.push("No workspace is focused.".to_string()),
+    }
+
+    match windows.iter().find(|w| w.focused) {
+        Some(window) => sentences.push(format!("Focused window is {}.", window_label(window))),
+        None => sentences.push("No window is focused.".to_string()),
+    }
+
+    let active_outputs = outputs.iter().filter(|o| o.active).count();
+    let mut totals = format!(
+        "There are {} workspace(s) and {} window(s) across {} active output(s)",
+        workspaces.len(),
+        visible.len(),
+        active_outputs
+    );
+    if scratchpad > 0 {
+        totals.push_str(&format!(", plus {} in the scratchpad", scratchpad));
+    }
+    sentences.push(totals + ".");
+
+    let urgent: Vec<String> = windows
+        .iter()
+        .filter(|w| w.urgent)
+        .map(|w| match &w.workspace {
+            Some(workspace) => format!("{} on '{}'", window_label(w), workspace),
+            None => window_label(w),
+        })
+        .collect();
+    if urgent.is_empty() {
+        sentences.push("No windows are urgent.".to_string());
+    } else {
+        sentences.push(format!("Urgent: {}.", urgent.join("; ")));
+    }
+
+    sentences.join(" ")
+}
+
+/// Translate window rects from X root coordinates to coordinates relative to
+/// the origin of each window's output. Windows on unknown outputs are left as-is.
+fn translate_to_output(windows: &mut [WindowInfo], outputs: &[Output]) {
+    for window in windows {
+        let Some(output) = outputs
+            .iter()
+            .find(|o| Some(o.name.as_str()) == window.output.as_deref())
+        else {
+            continue;
+        };
+        window.rect.x -= output.rect.x;
+        window.rect.y -= output.rect.y;
+    }
+}
+
+/// Geometry-only view of a container and its children, as returned by get_layout_rects
+#[derive(Debug, Serialize)]
+struct LayoutRect {
+    con_id: usize,
+    #[serde(rename = "type")]
+    node_type: NodeType,
+    layout: NodeLayout,
+    rect: Rect,
+    /// Tiling children first, then floating ones
+    children: Vec<LayoutRect>,
+}
+
+impl LayoutRect {
+    /// Strip a subtree down to ids, types, layouts, and rects
+    fn from_node(node: &Node) -> Self {
+        Self {
+            con_id: node.id,
+            node_type: node.node_type,
+            layout: node.layout,
+            rect: node.rect.clone(),
+            children: node
+                .nodes
+                .iter()
+                .chain(node.floating_nodes.iter())
+                .map(Self::from_node)
+                .collect(),
+        }
+    }
+}
+
+/// A window bookmarked by tag_and_remember, as it was when tagged
+#[derive(Debug, Clone, Serialize)]
+struct TaggedWindow {
+    con_id: usize,
+    class: Option<String>,
+    title: Option<String>,
+}
+
+/// Resolve tagged windows against the current tree. Windows that have closed
+/// keep the class and title they were tagged with and are flagged closed.
+fn resolve_tags(tags: &BTreeMap<String, TaggedWindow>, tree: &Node) -> Vec<serde_json::Value> {
+    tags.iter()
+        .map(|(mark, tagged)| {
+            let Some((node, ancestors)) = find_node_with_ancestors(tree, tagged.con_id) else {
+                return serde_json::json!({
+                    "mark": mark,
+                    "con_id": tagged.con_id,
+                    "class": tagged.class,
+                    "title": tagged.title,
+                    "closed": true,
+                });
+            };
+            let window = WindowInfo::from_node(node, &ancestors);
+            serde_json::json!({
+                "mark": mark,
+                "con_id": tagged.con_id,
+                "class": window.class,
+                "title": window.title,
+                "workspace": window.workspace,
+                "marked": node.marks.iter().any(|m| m.0.contains(mark)),
+                "closed": false,
+            })
+        })
+        .collect()
+}
+
+/// A container's children in the order of its `focus` array (most recently
+/// focused first, so the first entry is what `focus child` would pick)
+fn focus_order(node: &Node) -> Vec<serde_json::Value> {
+    node.focus
+        .iter()
+        .enumerate()
+        .map(|(position, con_id)| {
+            let child = node
+                .nodes
+                .iter()
+                .chain(node.floating_nodes.iter())
+                .find(|child| child.id == *con_id);
+            let Some(child) = child else {
+                return serde_json::json!({ "position": position, "con_id": con_id });
+            };
+            let props = child.window_properties.as_ref();
+            serde_json::json!({
+                "position": position,
+                "con_id": con_id,
+                "type": child.node_type,
+                "name": child.name,
+                "class": props.and_then(|p| p.class.as_deref()),
+                "floating": is_floating(child) || child.node_type == NodeType::FloatingCon,
+            })
+        })
+        .collect()
+}
+
+/// A window and the process behind it, as returned by get_window_processes
+#[derive(Debug, Serialize)]
+struct WindowProcess {
+    con_id: usize,
+    class: Option<String>,
+    pid: Option<u32>,
+    command: Option<String>,
+    rss_kb: Option<u64>,
+    #[serde(skip)]
+    window: Option<u64>,
+}
+
+/// Windows in a raw tree reply. Sway reports each view's `pid`; i3 doesn't,
+/// so for X11 windows without one the caller looks up _NET_WM_PID.
+fn raw_tree_windows(node: &serde_json::Value, out: &mut Vec<WindowProcess>) {
+    let field = |key: &str| node.get(key).filter(|v| !v.is_null());
+    let window = field("window").and_then(|w| w.as_u64());
+    let pid = field("pid")
+        .and_then(|p| p.as_u64())
+        .and_then(|p| u32::try_from(p).ok());
+    let children = ["nodes", "floating_nodes"]
+        .iter()
+        .filter_map(|key| node.get(*key).and_then(|n| n.as_array()))
+        .flatten()
+        .collect::<Vec<_>>();
+
+    if children.is_empty() && (window.is_some() || pid.is_some()) {
+        let class = node
+            .pointer("/window_properties/class")
+            .or_else(|| field("app_id"))
+            .and_then(|c| c.as_str())
+            .map(str::to_string);
+        out.push(WindowProcess {
+            con_id: field("id").and_then(|id| id.as_u64()).unwrap_or(0) as usize,
+            class,
+            pid,
+            command: None,
+            rss_kb: None,
+            window,
+        });
+    }
+    for child in children {
+        raw_tree_windows(child, out);
+    }
+}
+
+/// The pid in `xprop -id <window> _NET_WM_PID` output, e.g.
+/// "_NET_WM_PID(CARDINAL) = 1234"
+fn parse_xprop_pid(output: &str) -> Option<u32> {
+    output.split_once('=')?.1.trim().parse().ok()
+}
+
+/// A process's command name and resident set size from
+/// `<proc_root>/<pid>/comm` and `status`. Unreadable entries yield None.
+fn proc_command_and_rss(proc_root: &Path, pid: u32) -> (Option<String>, Option<u64>) {
+    let dir = proc_root.join(pid.to_string());
+    let command = std::fs::read_to_string(dir.join("comm"))
+        .ok()
+        .map(|c| c.trim_end().to_string());
+    let rss_kb = std::fs::read_to_string(dir.join("status"))
+        .ok()
+        .and_then(|status| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix("VmRSS:"))
+                .and_then(|v| v.trim().trim_end_matches("kB").trim().parse().ok())
+        });
+    (command, rss_kb)
+}
+
+/// Remove nodes named `__i3*` (the internal output holding the scratchpad)
+/// from a serialized tree, recursively
+fn prune_internal_nodes(value: &mut serde_json::Value) {
+    for key in ["nodes", "floating_nodes"] {
+        let Some(children) = value.get_mut(key).and_then(|c| c.as_array_mut()) else {
+            continue;
+        };
+        children.retain(|child| {
+            !child
+                .get("name")
+                .and_then(|n| n.as_str())
+                .is_some_and(|n| n.starts_with("__i3"))
+        });
+        for child in children {
+            prune_internal_nodes(child);
+        }
+    }
+}
+
+/// Find the focused node, returning it together with its ancestors (root first)
+fn find_focused(root: &Node) -> Option<(&Node, Vec<&Node>)> {
+    let mut found = None;
+    walk_tree(root, &mut Vec::new(), &mut |node, ancestors| {
+        if found.is_none() && node.focused {
+            found = Some((node, ancestors.to_vec()));
+        }
+    });
+    found
+}
+
+/// The workspace containing the focused node (or the focused workspace itself)
+fn focused_workspace(root: &Node) -> Option<&Node> {
+    let (node, ancestors) = find_focused(root)?;
+    if node.node_type == NodeType::Workspace {
+        return Some(node);
+    }
+    ancestors
+        .into_iter()
+        .rev()
+        .find(|a| a.node_type == NodeType::Workspace)
+}
+
+/// The command moving window `con_id` aside to the stash workspace
+fn stash_command(con_id: usize, stash: &str) -> String {
+    format!(
+        "[con_id={}] move container to workspace \"{}\"",
+        con_id,
+        escape_criteria_value(stash)
+    )
+}
+
+/// The command returning a stashed window to its origin workspace and focusing it
+fn unstash_command(con_id: usize, origin: &str) -> String {
+    format!(
+        "[con_id={id}] move container to workspace \"{}\"; [con_id={id}] focus",
+        escape_criteria_value(origin),
+        id = con_id
+    )
+}
+
+/// Record that window `con_id` was stashed from `origin`, returning the origin
+/// to restore. Stashing a window again (say, after moving it within the stash
+/// workspace) keeps the origin from the first time.
+fn record_stash(stashes: &mut HashMap<usize, String>, con_id: usize, origin: &str) -> String {
+    stashes
+        .entry(con_id)
+        .or_insert_with(|| origin.to_string())
+        .clone()
+}
+
+/// The workspace containing a container (or the container itself if it is one)
+fn workspace_of(root: &Node, con_id: usize) -> Option<&Node> {
+    let (node, ancestors) = find_node_with_ancestors(root, con_id)?;
+    if node.node_type == NodeType::Workspace {
+        return Some(node);
+    }
+    ancestors
+        .into_iter()
+        .rev()
+        .find(|a| a.node_type == NodeType::Workspace)
+}
+
+/// Depth of a tree JSON value (a node without children has depth 0)
+fn tree_depth(value: &serde_json::Value) -> usize {
+    ["nodes", "floating_nodes"]
+        .iter()
+        .filter_map(|key| value.get(key).and_then(|c| c.as_array()))
+        .flatten()
+        .map(|child| tree_depth(child) + 1)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Drop children below `depth` levels, recording how many were removed in
+/// `truncated_children` on the node that lost them
+fn limit_tree_depth(value: &mut serde_json::Value, depth: usize) {
+    let mut truncated = 0;
+    for key in ["nodes", "floating_nodes"] {
+        let Some(children) = value.get_mut(key).and_then(|c| c.as_array_mut()) else {
+            continue;
+        };
+        if depth == 0 {
+            truncated += children.len();
+            children.clear();
+        } else {
+            for child in children {
+                limit_tree_depth(child, depth - 1);
+            }
+        }
+    }
+    if truncated > 0 {
+        value["truncated_children"] = truncated.into();
+    }
+}
+
+/// Reduce a tree JSON value's depth until `fits` accepts it, returning the
+/// tree and the depth limit applied (None if it fit unchanged). Returns None
+/// if even the root alone doesn't fit.
+fn shrink_tree_to_fit(
+    value: &serde_json::Value,
+    fits: impl Fn(&serde_json::Value, Option<usize>) -> bool,
+) -> Option<(serde_json::Value, Option<usize>)> {
+    if fits(value, None) {
+        return Some((value.clone(), None));
+    }
+
+    (0..tree_depth(value)).rev().find_map(|depth| {
+        let mut limited = value.clone();
+        limit_tree_depth(&mut limited, depth);
+        fits(&limited, Some(depth)).then_some((limited, Some(depth)))
+    })
+}
+
+/// The con_id of every node in the tree
+fn tree_con_ids(root: &Node) -> HashSet<usize> {
+    let mut ids = HashSet::new();
+    walk_tree(root, &mut Vec::new(), &mut |node, _| {
+        ids.insert(node.id);
+    });
+    ids
+}
+
+/// Whether a container is floating (enabled automatically or by the user)
+fn is_floating(node: &Node) -> bool {
+    matches!(node.floating, Some(Floating::AutoOn | Floating::UserOn))
+}
+
+/// Which layer a focused node is in: "floating" if it or an ancestor is a
+/// floating container, otherwise "tiling"
+fn focus_layer(node: &Node, ancestors: &[&Node]) -> &'static str {
+    let floating = is_floating(node)
+        || std::iter::once(&node)
+            .chain(ancestors)
+            .any(|n| n.node_type == NodeType::FloatingCon);
+    if floating {
+        "floating"
+    } else {
+        "tiling"
+    }
+}
+
+/// The layer of whatever has focus in the tree, or "none" if no window or
+/// container is focused (e.g. an empty workspace)
+fn focused_layer(root: &Node) -> &'static str {
+    match find_focused(root) {
+        Some((node, ancestors))
+            if matches!(node.node_type, NodeType::Con | NodeType::FloatingCon) =>
+        {
+            focus_layer(node, &ancestors)
+        }
+        _ => "none",
+    }
+}
+
+/// One level of the path from the root to the focused node, for get_focus_depth
+#[derive(Debug, Serialize)]
+struct FocusLevel {
+    con_id: usize,
+    /// e.g. "output", "workspace:splith", "con:tabbed", "window"
+    level: String,
+}
+
+/// The path from the output down to the focused node, and how many
+/// containers sit between it and its workspace (how many `focus parent` steps
+/// stay below the workspace). The root and each output's internal content
+/// container are skipped.
+fn focus_path(root: &Node) -> Option<(Vec<FocusLevel>, usize)> {
+    let (node, ancestors) = find_focused(root)?;
+    let mut path = Vec::new();
+    for (i, level) in ancestors.iter().copied().chain([node]).enumerate() {
+        let parent = i.checked_sub(1).map(|p| ancestors[p]);
+        let internal = level.node_type == NodeType::Root
+            || parent.is_some_and(|p| p.node_type == NodeType::Output);
+        if internal {
+            continue;
+        }
+
+        let kind = serde_json::to_value(level.node_type)
+            .ok()
+            .and_then(|v| v.as_str().map(str::to_string))
+            .unwrap_or_default();
+        let label = if level.window.is_some() {
+            "window".to_string()
+        } else if level.node_type == NodeType::Output {
+            kind
+        } else {
+            format!("{}:{}", kind, layout_name(level.layout))
+        };
+        path.push(FocusLevel {
+            con_id: level.id,
+            level: label,
+        });
+    }
+
+    let below_workspace = ancestors
+        .iter()
+        .rposition(|a| a.node_type == NodeType::Workspace)
+        .map_or(0, |ws| ancestors.len() - ws - 1);
+    Some((path, below_workspace))
+}
+
+/// Tiled windows on a workspace, top-to-bottom then left-to-right
+fn tiled_windows(workspace: &Node) -> Vec<&Node> {
+    let mut windows = Vec::new();
+    for child in &workspace.nodes {
+        walk_tree(child, &mut Vec::new(), &mut |node, _| {
+            if node.window.is_some() {
+                windows.push(node);
+            }
+        });
+    }
+    windows.sort_by_key(|node| (node.rect.y, node.rect.x));
+    windows
+}
+
+/// Temporary workspace quad_tile parks windows on to flatten their nesting
+const QUAD_TILE_WORKSPACE: &str = "rmcp-i3-quad";
+
+/// Temporary mark quad_tile moves windows next to
+const QUAD_TILE_MARK: &str = "rmcp-i3-quad";
+
+/// The command that arranges 1-4 windows on `workspace` into a grid: one
+/// window alone, two side by side, three as one beside a stacked pair, and
+/// four as two columns of two. Windows are first parked on a temporary
+/// workspace so any existing nesting is flattened, then rebuilt around a
+/// temporary mark. Windows are given in reading order.
+fn quad_tile_command(workspace: &str, con_ids: &[usize]) -> Result<String, String> {
+    let (first, rest) = match con_ids {
+        [] => return Err("The workspace has no tiled windows".to_string()),
+        ids if ids.len() > 4 => {
+            return Err(format!(
+                "quad_tile arranges at most 4 windows, the workspace has {}",
+                ids.len()
+            ))
+        }
+        [first, rest @ ..] => (*first, rest),
+    };
+
+    let with = |id: usize, command: &str| format!("[con_id={}] {}", id, command);
+    let park = format!("move container to workspace {}", QUAD_TILE_WORKSPACE);
+    let unpark = format!(
+        "move container to workspace \"{}\"",
+        escape_criteria_value(workspace)
+    );
+    let mark = format!("mark --add {}", QUAD_TILE_MARK);
+    let to_mark = format!("move container to mark {}", QUAD_TILE_MARK);
+    // Put `id` next to `anchor` (optionally splitting the anchor vertically first)
+    let beside = |anchor: usize, id: usize, split: bool| {
+        let mut steps = Vec::new();
+        if split {
+            steps.push(with(anchor, "split v"));
+        }
+        steps.push(with(anchor, &mark));
+        steps.push(with(id, &to_mark));
+        steps
+    };
+
+    let mut steps: Vec<String> = con_ids.iter().map(|id| with(*id, &park)).collect();
+    steps.push(with(first, &unpark));
+    steps.push(with(first, "layout splith"));
+    match *rest {
+        [] => {}
+        [b] => steps.extend(beside(first, b, false)),
+        [b, c] => {
+            steps.extend(beside(first, b, false));
+            steps.extend(beside(b, c, true));
+        }
+        [b, c, d] => {
+            steps.extend(beside(first, b, false));
+            steps.extend(beside(first, c, true));
+            steps.extend(beside(b, d, true));
+        }
+        _ => unreachable!("at most four windows"),
+    }
+    steps.push(format!("unmark {}", QUAD_TILE_MARK));
+    Ok(steps.join("; "))
+}
+
+/// The part of an output not covered by docked bars: the output rect minus
+/// the heights of its top and bottom dock areas
+fn work_area(output: &Node) -> Rect {
+    let dock_height = |name: &str| -> isize {
+        output
+            .nodes
+            .iter()
+            .filter(|n| n.node_type == NodeType::Dockarea && n.name.as_deref() == Some(name))
+            .map(|n| n.rect.height)
+            .sum()
+    };
+    let top = dock_height("topdock");
+    let bottom = dock_height("bottomdock");
+
+    Rect {
+        x: output.rect.x,
+        y: output.rect.y + top,
+        width: output.rect.width,
+        height: (output.rect.height - top - bottom).max(0),
+    }
+}
+
+/// How a window was placed before spotlight floated it, for unspotlight
+#[derive(Debug, Clone)]
+struct SpotlightState {
+    /// Whether the window was already floating
+    was_floating: bool,
+    /// Its floating container's geometry (only used if it was floating)
+    rect: Rect,
+}
+
+impl SpotlightState {
+    /// Capture the placement of window `node`, given its ancestors (root first)
+    fn capture(node: &Node, ancestors: &[&Node]) -> Self {
+        let container = ancestors
+            .iter()
+            .rev()
+            .find(|a| a.node_type == NodeType::FloatingCon);
+        Self {
+            was_floating: is_floating(node),
+            rect: container.map_or(&node.rect, |c| &c.rect).clone(),
+        }
+    }
+}
+
+/// The command presenting a window prominently: focus (switching to its
+/// workspace and raising it), float it, and center it on its output
+fn spotlight_command(con_id: usize) -> String {
+    format!(
+        "[con_id={id}] focus; [con_id={id}] floating enable; [con_id={id}] move position center",
+        id = con_id
+    )
+}
+
+/// The command putting a spotlighted window back: back into the tiling layout,
+/// or to its old floating position and size
+fn unspotlight_command(con_id: usize, state: &SpotlightState) -> String {
+    if state.was_floating {
+        format!(
+            "[con_id={id}] resize set {} px {} px; [con_id={id}] move position {} px {} px",
+            state.rect.width,
+            state.rect.height,
+            state.rect.x,
+            state.rect.y,
+            id = con_id
+        )
+    } else {
+        format!("[con_id={}] floating disable", con_id)
+    }
+}
+
+/// Default picture-in-picture size, as a percentage of the output's work area
+const DEFAULT_PIP_PPT: u32 = 25;
+
+/// Geometry for a picture-in-picture window: `width_ppt`/`height_ppt` percent
+/// of the work area, in its bottom-right corner
+fn pip_geometry(area: &Rect, width_ppt: u32, height_ppt: u32) -> Rect {
+    let width = area.width * width_ppt as isize / 100;
+    let height = area.height * height_ppt as isize / 100;
+    Rect {
+        x: area.x + area.width - width,
+        y: area.y + area.height - height,
+        width,
+        height,
+    }
+}
+
+/// Commands that float a window, make it sticky, and place it at `geometry`
+fn pip_commands(con_id: usize, geometry: &Rect) -> Vec<String> {
+    vec![
+        format!("[con_id={}] floating enable", con_id),
+        format!("[con_id={}] sticky enable", con_id),
+        format!(
+            "[con_id={}] resize set {} px {} px",
+            con_id, geometry.width, geometry.height
+        ),
+        format!(
+            "[con_id={}] move position {} px {} px",
+            con_id, geometry.x, geometry.y
+        ),
+    ]
+}
+
+/// Validate a percentage parameter, defaulting when unset
+fn validate_ppt(name: &str, value: Option<u32>, default: u32) -> Result<u32, McpError> {
+    match value.unwrap_or(default) {
+        ppt @ 1..=100 => Ok(ppt),
+        ppt => Err(McpError::invalid_params(
+            format!("{} must be between 1 and 100, got {}", name, ppt),
+            None,
+        )),
+    }
+}
+
+/// Reject opacities outside 0.0 (transparent) to 1.0 (opaque)
+fn validate_opacity(opacity: f32) -> Result<f32, McpError> {
+    if !(0.0..=1.0).contains(&opacity) {
+        return Err(McpError::invalid_params(
+            format!("opacity must be between 0.0 and 1.0, got {}", opacity),
+            None,
+        ));
+    }
+    Ok(opacity)
+}
+
+/// Sway's `opacity` command, applied to the criteria match or the focused window
+fn opacity_command(criteria: Option<&str>, opacity: f32) -> String {
+    match criteria.map(str::trim).filter(|c| !c.is_empty()) {
+        Some(criteria) => format!("{} opacity {}", bracketed_criteria(criteria), opacity),
+        None => format!("opacity {}", opacity),
+    }
+}
+
+/// Values accepted by `bar mode`
+const BAR_MODES: &[&str] = &["dock", "hide", "invisible"];
+
+/// Values accepted by `bar hidden_state`
+const BAR_HIDDEN_STATES: &[&str] = &["hide", "show"];
+
+/// Validate a value for a `bar` setting against the values it accepts
+fn validate_bar_value<'a>(
+    setting: &str,
+    value: &'a str,
+    allowed: &[&str],
+) -> Result<&'a str, McpError> {
+    let value = value.trim();
+    if allowed.contains(&value) {
+        Ok(value)
+    } else {
+        Err(McpError::invalid_params(
+            format!(
+                "Invalid bar {} '{}'. Expected one of: {}",
+                setting,
+                value,
+                allowed.join(", ")
+            ),
+            None,
+        ))
+    }
+}
+
+/// The `bar <setting> <value> [<bar_id>]` command. Without a bar id it
+/// applies to every bar; a given id must be one of `bar_ids`.
+fn bar_command(
+    setting: &str,
+    value: &str,
+    bar_id: Option<&str>,
+    bar_ids: &[String],
+) -> Result<String, String> {
+    if bar_ids.is_empty() {
+        return Err("No bars are configured".to_string());
+    }
+    match bar_id.map(str::trim).filter(|id| !id.is_empty()) {
+        Some(id) if bar_ids.iter().any(|b| b == id) => {
+            Ok(format!("bar {} {} {}", setting, value, id))
+        }
+        Some(id) => Err(format!(
+            "Unknown bar id '{}'. Configured bars: {}",
+            id,
+            bar_ids.join(", ")
+        )),
+        None => Ok(format!("bar {} {}", setting, value)),
+    }
+}
+
+/// Validate a split ratio: the fraction of its parent split a window should fill
+fn validate_split_ratio(ratio: f32) -> Result<f32, McpError> {
+    if !(0.1..=0.9).contains(&ratio) {
+        return Err(McpError::invalid_params(
+            format!("ratio must be between 0.1 and 0.9, got {}", ratio),
+            None,
+        ));
+    }
+    Ok(ratio)
+}
+
+/// The resize that makes `node` fill `ratio` of its parent split, plus the
+/// percentage it fills now and the target percentage. i3's ppt sizes are
+/// relative to the parent, so the target is just the ratio in percent; the
+/// rects only tell where it starts.
+fn split_ratio_command(
+    node: &Node,
+    parent: &Node,
+    ratio: f32,
+) -> Result<(String, u32, u32), String> {
+    let (dimension, size, total) = match parent.layout {
+        NodeLayout::SplitH => ("width", node.rect.width, parent.rect.width),
+        NodeLayout::SplitV => ("height", node.rect.height, parent.rect.height),
+        other => {
+            return Err(format!(
+                "The focused window's parent is {}, not a horizontal or vertical split",
+                layout_name(other)
+            ))
+        }
+    };
+    if parent.nodes.len() < 2 {
+        return Err("The focused window is alone in its split".to_string());
+    }
+    if total <= 0 {
+        return Err("The parent split has no size".to_string());
+    }
+
+    let current = (size as f32 * 100.0 / total as f32).round() as u32;
+    let ppt = (ratio * 100.0).round() as u32;
+    let command = format!("resize set {} {} ppt", dimension, ppt);
+    Ok((command, current, ppt))
+}
+
+/// The resizes giving every tiled child of each split under `container` an
+/// equal share, outermost splits first. All but the last child are set to
+/// 100/n ppt; each resize takes from the next sibling, so the last ends up
+/// with the remainder. Tabbed and stacked containers are skipped, but splits
+/// nested inside them are still equalized.
+fn equalize_commands(container: &Node) -> Vec<String> {
+    let mut commands = Vec::new();
+    let dimension = match container.layout {
+        NodeLayout::SplitH => Some("width"),
+        NodeLayout::SplitV => Some("height"),
+        _ => None,
+    };
+    if let Some(dimension) = dimension.filter(|_| container.nodes.len() >= 2) {
+        let share = 100 / container.nodes.len();
+        for child in &container.nodes[..container.nodes.len() - 1] {
+            commands.push(format!(
+                "[con_id={}] resize set {} {} ppt",
+                child.id, dimension, share
+            ));
+        }
+    }
+    // Floating containers belong to no split, so only tiling children count
+    for child in &container.nodes {
+        commands.extend(equalize_commands(child));
+    }
+    commands
+}
+
+/// i3's name for a layout value, as used in tree JSON and `layout` commands
+fn layout_name(layout: NodeLayout) -> String {
+    serde_json::to_value(layout)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+/// Layouts accepted by the `layout` command
+const LAYOUTS: &[&str] = &["default", "splith", "splitv", "stacking", "tabbed"];
+
+/// Validate a layout value for the `layout` command
+fn validate_layout(layout: &str) -> Result<&str, McpError> {
+    let layout = layout.trim();
+    if LAYOUTS.contains(&layout) {
+        Ok(layout)
+    } else {
+        Err(McpError::invalid_params(
+            format!(
+                "Invalid layout '{}'. Expected one of: {}",
+                layout,
+                LAYOUTS.join(", ")
+            ),
+            None,
+        ))
+    }
+}
+
+/// Convert a client-supplied con_id into i3's id type
+fn parse_con_id(con_id: i64) -> Result<usize, McpError> {
+    usize::try_from(con_id).map_err(|_| {
+        McpError::invalid_params(
+            format!("Invalid con_id {}: must be non-negative", con_id),
+            None,
+        )
+    })
+}
+
+/// The command focusing the window with X11 id `window_id` (i3's `id` criterion)
+fn window_id_focus_command(window_id: i64) -> Result<String, McpError> {
+    if window_id <= 0 {
+        return Err(McpError::invalid_params(
+            format!("Invalid window_id {}: must be positive", window_id),
+            None,
+        ));
+    }
+    Ok(format!("[id={}] focus", window_id))
+}
+
+/// i3 commands that end or replace the running session; refused wherever a
+/// tool forwards a client-supplied command inside a larger one
+const DANGEROUS_COMMANDS: &[&str] = &["exit", "restart"];
+
+/// Split a command on any of `separators`, ignoring separators inside
+/// `[...]` criteria or double-quoted strings
+fn split_top_level<'a>(command: &'a str, separators: &[char]) -> Vec<&'a str> {
+    let mut parts = Vec::new();
+    let mut depth = 0usize;
+    let mut in_quotes = false;
+    let mut escaped = false;
+    let mut start = 0;
+
+    for (i, c) in command.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            '[' if !in_quotes => depth += 1,
+            ']' if !in_quotes => depth = depth.saturating_sub(1),
+            c if !in_quotes && depth == 0 && separators.contains(&c) => {
+                parts.push(&command[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&command[start..]);
+    parts
+}
+
+/// Split a leading `[...]` criteria block off a command, returning the
+/// criteria (brackets included) and the remaining command text
+fn split_criteria(command: &str) -> (Option<&str>, &str) {
+    let command = command.trim_start();
+    if !command.starts_with('[') {
+        return (None, command);
+    }
+
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for (i, c) in command.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            ']' if !in_quotes => return (Some(&command[..=i]), command[i + 1..].trim_start()),
+            _ => {}
+        }
+    }
+    // Unterminated criteria: treat everything as criteria
+    (Some(command), "")
+}
+
+/// Join commands into one `;`-separated IPC payload, dropping blank entries
+fn join_commands(commands: &[String]) -> String {
+    commands
+        .iter()
+        .map(|c| c.trim())
+        .filter(|c| !c.is_empty())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Append a command refocusing `focused` (if anything was focused) to a payload
+fn restore_focus_command(command: &str, focused: Option<usize>) -> String {
+    match focused {
+        Some(con_id) => format!("{}; [con_id={}] focus", command, con_id),
+        None => command.to_string(),
+    }
+}
+
+/// Result of one sub-command, aligned with the command text that produced it
+#[derive(Debug, Serialize)]
+struct CommandResult {
+    command: Option<String>,
+    success: bool,
+    error: Option<String>,
+}
+
+/// Pair each entry of an i3 reply with the sub-command that produced it.
+///
+/// i3 returns one result per top-level `;`-separated command. If the counts
+/// don't line up (e.g. `,`-chained commands report separately), results are
+/// returned without the command text rather than guessed at.
+fn align_results(command: &str, results: &[Success]) -> Vec<CommandResult> {
+    let parts: Vec<&str> = split_top_level(command, &[';'])
+        .into_iter()
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .collect();
+    let aligned = parts.len() == results.len();
+
+    results
+        .iter()
+        .enumerate()
+        .map(|(i, r)| CommandResult {
+            command: aligned.then(|| parts[i].to_string()),
+            success: r.success,
+            error: r.error.clone(),
+        })
+        .collect()
+}
+
+/// Envelope for run_command/run_commands: ok only if every command succeeded
+fn command_results_response(results: &[CommandResult]) -> Result<CallToolResult, McpError> {
+    let failed = results.iter().filter(|r| !r.success).count();
+    if failed == 0 {
+        ToolResponse::data(format!("{} command(s) succeeded", results.len()), &results)
+    } else {
+        ToolResponse::failure_data(
+            format!("{} of {} command(s) failed", failed, results.len()),
+            &results,
+        )
+    }
+}
+
+/// Leading keyword of a single command, skipping any `[...]` criteria
+fn command_keyword(command: &str) -> &str {
+    let (_, rest) = split_criteria(command);
+    rest.split_whitespace().next().unwrap_or("")
+}
+
+/// Leading keywords of i3 commands (plus Sway's common additions), for
+/// validate_command
+const COMMAND_KEYWORDS: &[&str] = &[
+    "append_layout",
+    "bar",
+    "bindsym",
+    "border",
+    "debuglog",
+    "exec",
+    "exit",
+    "floating",
+    "floating_maximum_size",
+    "floating_minimum_size",
+    "focus",
+    "fullscreen",
+    "gaps",
+    "input",
+    "kill",
+    "layout",
+    "mark",
+    "mode",
+    "move",
+    "nop",
+    "opacity",
+    "open",
+    "output",
+    "reload",
+    "rename",
+    "resize",
+    "restart",
+    "scratchpad",
+    "seat",
+    "shmlog",
+    "split",
+    "sticky",
+    "swap",
+    "title_format",
+    "title_window_icon",
+    "unbindsym",
+    "unmark",
+    "workspace",
+];
+
+/// Commands that do nothing useful (or fail) without an argument
+const COMMANDS_WITH_ARGS: &[&str] = &[
+    "append_layout",
+    "bar",
+    "bindsym",
+    "border",
+    "exec",
+    "floating_maximum_size",
+    "floating_minimum_size",
+    "layout",
+    "mark",
+    "mode",
+    "move",
+    "rename",
+    "resize",
+    "split",
+    "swap",
+    "title_format",
+    "unbindsym",
+    "workspace",
+];
+
+/// Every criteria key i3 (or Sway) accepts, a superset of CRITERIA_KEYS.
+/// parse_criteria accepts all of them.
+const ALL_CRITERIA_KEYS: &[&str] = &[
+    "all",
+    "app_id",
+    "class",
+    "con_id",
+    "con_mark",
+    "floating",
+    "floating_from",
+    "id",
+    "instance",
+    "machine",
+    "pid",
+    "shell",
+    "tiling",
+    "tiling_from",
+    "title",
+    "urgent",
+    "window_role",
+    "window_type",
+    "workspace",
+];
+
+/// Problems with a `[...]` criteria block: no terms, or keys i3 doesn't know
+fn criteria_issues(criteria: &str) -> Vec<String> {
+    let body = criteria
+        .trim()
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let keys: Vec<&str> = split_top_level(body, &[' ', '\t'])
+        .into_iter()
+        .map(str::trim)
+        .filter(|term| !term.is_empty())
+        .map(|term| term.split_once('=').map_or(term, |(key, _)| key.trim()))
+        .collect();
+
+    if keys.is_empty() {
+        return vec![format!("Criteria {} are empty", criteria)];
+    }
+    keys.into_iter()
+        .filter(|key| !ALL_CRITERIA_KEYS.contains(key))
+        .map(|key| format!("Unknown criteria key '{}' in {}", key, criteria))
+        .collect()
+}
+
+/// Client-side syntax check of an i3 command: balanced quotes and criteria
+/// brackets, parseable criteria, and known keywords with their arguments.
+/// Returns the problems found; an empty list means nothing obviously wrong.
+fn command_issues(command: &str) -> Vec<String> {
+    let mut issues = Vec::new();
+    if command.trim().is_empty() {
+        issues.push("Command is empty".to_string());
+        return issues;
+    }
+
+    let mut depth = 0i32;
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for c in command.chars() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            '[' if !in_quotes => depth += 1,
+            ']' if !in_quotes => {
+                depth -= 1;
+                if depth < 0 {
+                    issues.push("']' without a matching '['".to_string());
+                    depth = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+    if in_quotes {
+        issues.push("Unterminated double quote".to_string());
+    }
+    if depth > 0 {
+        issues.push("'[' criteria block is never closed".to_string());
+    }
+    if !issues.is_empty() {
+        // Splitting unbalanced input would only produce follow-on noise
+        return issues;
+    }
+
+    let parts = split_top_level(command, &[';', ',']);
+    for (i, part) in parts.iter().map(|p| p.trim()).enumerate() {
+        if part.is_empty() {
+            if i + 1 < parts.len() {
+                issues.push(format!("Empty command before separator {}", i + 1));
+            }
+            continue;
+        }
+
+        let (criteria, rest) = split_criteria(part);
+        if let Some(criteria) = criteria {
+            issues.extend(criteria_issues(criteria));
+            if rest.is_empty() {
+                issues.push(format!(
+                    "Criteria {} are not followed by a command",
+                    criteria
+                ));
+                continue;
+            }
+        }
+
+        let mut words = rest.split_whitespace();
+        let keyword = words.next().unwrap_or("");
+        if !COMMAND_KEYWORDS.contains(&keyword) {
+            issues.push(format!("Unknown command '{}' in '{}'", keyword, part));
+        } else if COMMANDS_WITH_ARGS.contains(&keyword) && words.next().is_none() {
+            issues.push(format!("'{}' needs an argument", keyword));
+        }
+    }
+    issues
+}
+
+/// Refuse commands containing a dangerous sub-command (see DANGEROUS_COMMANDS)
+fn check_dangerous_command(command: &str) -> Result<(), McpError> {
+    for part in split_top_level(command, &[';', ',']) {
+        let keyword = command_keyword(part);
+        if DANGEROUS_COMMANDS.contains(&keyword) {
+            return Err(McpError::invalid_params(
+                format!("Refusing dangerous command '{}'", part.trim()),
+                None,
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// `criteria` with the brackets i3 requires, which parse_criteria lets callers
+/// leave off
+fn bracketed_criteria(criteria: &str) -> String {
+    let criteria = criteria.trim();
+    if criteria.starts_with('[') {
+        criteria.to_string()
+    } else {
+        format!("[{}]", criteria)
+    }
+}
+
+/// Build `[criteria] cmd1, cmd2, ...`. i3 applies `,`-chained commands to the
+/// windows matched by the leading criteria, which are evaluated only once.
+fn chain_commands(criteria: &str, commands: &[String]) -> Result<String, McpError> {
+    parse_criteria(criteria)?;
+    let criteria = bracketed_criteria(criteria);
+
+    let mut chained = Vec::new();
+    for command in commands.iter().map(|c| c.trim()).filter(|c| !c.is_empty()) {
+        if split_top_level(command, &[';']).len() > 1 {
+            return Err(McpError::invalid_params(
+                format!(
+                    "'{}' contains ';', which would drop the criteria for later commands",
+                    command
+                ),
+                None,
+            ));
+        }
+        if split_criteria(command).0.is_some() {
+            return Err(McpError::invalid_params(
+                format!("'{}' has its own criteria; use a separate call", command),
+                None,
+            ));
+        }
+        check_dangerous_command(command)?;
+        chained.push(command);
+    }
+
+    if chained.is_empty() {
+        return Err(McpError::invalid_params("commands must not be empty", None));
+    }
+    Ok(format!("{} {}", criteria, chained.join(", ")))
+}
+
+/// Build `[criteria] action` for run_command_on, checking each part on its
+/// own. Without criteria the action runs on the focused window and may not
+/// carry criteria of its own.
+fn command_on(criteria: Option<&str>, action: &str) -> Result<String, McpError> {
+    let action = action.trim();
+    if action.is_empty() {
+        return Err(McpError::invalid_params("action must not be empty", None));
+    }
+    match criteria.map(str::trim).filter(|c| !c.is_empty()) {
+        Some(criteria) => chain_commands(criteria, &[action.to_string()]),
+        None => {
+            if split_criteria(action).0.is_some() {
+                return Err(McpError::invalid_params(
+                    format!("'{}' has its own criteria; pass them as criteria", action),
+                    None,
+                ));
+            }
+            check_dangerous_command(action)?;
+            Ok(action.to_string())
+        }
+    }
+}
+
+/// Build a runtime `bindsym` command, validating the key combo and bound command
+fn bind_key_command(keys: &str, command: &str, release: bool) -> Result<String, McpError> {
+    let keys = validate_keys(keys)?;
+    if command.trim().is_empty() {
+        return Err(McpError::invalid_params("command must not be empty", None));
+    }
+    check_dangerous_command(command)?;
+
+    let release = if release { "--release " } else { "" };
+    Ok(format!("bindsym {}{} {}", release, keys, command.trim()))
+}
+
+/// Build a runtime `unbindsym` command
+fn unbind_key_command(keys: &str) -> Result<String, McpError> {
+    Ok(format!("unbindsym {}", validate_keys(keys)?))
+}
+
+/// Key combos must be a single non-empty token like "$mod+Shift+x"
+fn validate_keys(keys: &str) -> Result<&str, McpError> {
+    let keys = keys.trim();
+    if keys.is_empty() {
+        return Err(McpError::invalid_params("keys must not be empty", None));
+    }
+    if keys.contains(char::is_whitespace) {
+        return Err(McpError::invalid_params(
+            format!("keys must not contain whitespace: '{}'", keys),
+            None,
+        ));
+    }
+    Ok(keys)
+}
+
+/// Escape a value for use inside a double-quoted i3 criteria value
+fn escape_criteria_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Build a criteria string like `[class="Firefox" title="GitHub"]` from
+/// (key, value) pairs, skipping unset or empty values
+fn build_criteria(pairs: &[(&str, Option<&str>)]) -> Result<String, McpError> {
+    let parts: Vec<String> = pairs
+        .iter()
+        .filter_map(|(key, value)| {
+            value
+                .filter(|v| !v.is_empty())
+                .map(|v| format!("{}=\"{}\"", key, escape_criteria_value(v)))
+        })
+        .collect();
+
+    if parts.is_empty() {
+        return Err(McpError::invalid_params(
+            "At least one criteria field must be set",
+            None,
+        ));
+    }
+    Ok(format!("[{}]", parts.join(" ")))
+}
+
+/// Criteria keys understood by the client-side matcher (see criteria_matches)
+const CRITERIA_KEYS: &[&str] = &[
+    "class",
+    "instance",
+    "title",
+    "window_role",
+    "id",
+    "con_id",
+    "con_mark",
+    "workspace",
+    "urgent",
+    "floating",
+    "tiling",
+];
+
+/// One `key="value"` (or bare `key`, e.g. `floating`) term of a criteria string
+#[derive(Debug, Clone, PartialEq)]
+struct Criterion {
+    key: String,
+    value: Option<String>,
+}
+
+/// Parse and validate a criteria string like `[class="Firefox" title="^GitHub"]`
+/// (brackets optional) into its terms
+fn parse_criteria(criteria: &str) -> Result<Vec<Criterion>, McpError> {
+    let trimmed = criteria.trim();
+    let body = match trimmed.strip_prefix('[') {
+        Some(rest) => rest.strip_suffix(']').ok_or_else(|| {
+            McpError::invalid_params(format!("Unterminated criteria: {}", trimmed), None)
+        })?,
+        None => trimmed,
+    };
+
+    let mut terms = Vec::new();
+    for term in split_top_level(body, &[' ', '\t']) {
+        let term = term.trim();
+        if term.is_empty() {
+            continue;
+        }
+        let (key, value) = match term.split_once('=') {
+            Some((key, value)) => (key.trim(), Some(unquote_criteria_value(value.trim()))),
+            None => (term, None),
+        };
+        if !ALL_CRITERIA_KEYS.contains(&key) {
+            return Err(McpError::invalid_params(
+                format!(
+                    "Unsupported criteria key '{}'. Supported keys: {}",
+                    key,
+                    ALL_CRITERIA_KEYS.join(", ")
+                ),
+                None,
+            ));
+        }
+        if value.as_deref() == Some("__focused__") && key != "con_id" {
+            return Err(McpError::invalid_params(
+                "__focused__ is only supported for con_id",
+                None,
+            ));
+        }
+        terms.push(Criterion {
+            key: key.to_string(),
+            value,
+        });
+    }
+
+    if terms.is_empty() {
+        return Err(McpError::invalid_params("criteria must not be empty", None));
+    }
+    Ok(terms)
+}
+
+/// Strip the quotes from a criteria value, unescaping `\"` the way i3 does
+/// (other backslashes are left for the pattern, see criteria_value_matches)
+fn unquote_criteria_value(value: &str) -> String {
+    match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(inner) => inner.replace("\\\"", "\""),
+        None => value.to_string(),
+    }
+}
+
+/// Match a criteria value against text. i3 uses PCRE; this supports the
+/// common subset: an optional `(?i)` prefix, `^`/`$` anchors, and
+/// backslash-escaped literals. Everything else is matched literally, and like
+/// i3 the match is unanchored by default.
+fn criteria_value_matches(pattern: &str, text: &str) -> bool {
+    let (pattern, ignore_case) = match pattern.strip_prefix("(?i)") {
+        Some(rest) => (rest, true),
+        None => (pattern, false),
+    };
+    let (pattern, anchor_start) = match pattern.strip_prefix('^') {
+        Some(rest) => (rest, true),
+        None => (pattern, false),
+    };
+    let anchor_end = pattern.ends_with('$') && !pattern.ends_with("\\$");
+    let pattern = if anchor_end {
+        &pattern[..pattern.len() - 1]
+    } else {
+        pattern
+    };
+
+    let mut literal = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => literal.extend(chars.next()),
+            c => literal.push(c),
+        }
+    }
+
+    let (literal, text) = if ignore_case {
+        (literal.to_lowercase(), text.to_lowercase())
+    } else {
+        (literal, text.to_string())
+    };
+    match (anchor_start, anchor_end) {
+        (true, true) => text == literal,
+        (true, false) => text.starts_with(&literal),
+        (false, true) => text.ends_with(&literal),
+        (false, false) => text.contains(&literal),
+    }
+}
+
+/// What goto_window does for the first window matching the criteria: switch
+/// to its workspace and focus it, or show it from the scratchpad. Returns the
+/// command and the workspace name (None for the scratchpad).
+fn goto_command(root: &Node, criteria: &[Criterion]) -> Option<(String, Option<String>)> {
+    let window = *find_matching_nodes(root, criteria).first()?;
+    let workspace = workspace_of(root, window.id)
+        .and_then(|ws| ws.name.clone())
+        .filter(|name| !name.starts_with("__"));
+
+    let command = match &workspace {
+        Some(name) => format!(
+            "workspace \"{}\"; [con_id={}] focus",
+            escape_criteria_value(name),
+            window.id
+        ),
+        None => format!("[con_id={}] scratchpad show", window.id),
+    };
+    Some((command, workspace))
+}
+
+/// One top-level container in the scratchpad, as returned by audit_scratchpad
+#[derive(Debug, Serialize)]
+struct ScratchpadEntry {
+    con_id: usize,
+    /// Windows inside the container (usually exactly one)
+    windows: Vec<WindowInfo>,
+    /// Command that brings the entry back; None for a dead placeholder
+    recall: Option<String>,
+    /// The container holds no window any more
+    placeholder: bool,
+}
+
+/// The entries of i3's scratchpad (the children of the __i3_scratch
+/// workspace), with the command to recall each one
+fn scratchpad_entries(root: &Node) -> Vec<ScratchpadEntry> {
+    let mut scratch = None;
+    walk_tree(root, &mut Vec::new(), &mut |node, _| {
+        if node.node_type == NodeType::Workspace && node.name.as_deref() == Some("__i3_scratch") {
+            scratch = Some(node);
+        }
+    });
+    let Some(scratch) = scratch else {
+        return Vec::new();
+    };
+
+    scratch
+        .floating_nodes
+        .iter()
+        .chain(scratch.nodes.iter())
+        .map(|entry| {
+            let mut windows = Vec::new();
+            walk_tree(entry, &mut Vec::new(), &mut |node, _| {
+                if node.window.is_some() {
+                    windows.push(WindowInfo::from_node(node, &[]));
+                }
+            });
+            // Showing any window of the container shows the whole container
+            let recall = windows
+                .first()
+                .map(|w| format!("[con_id={}] scratchpad show", w.con_id));
+            ScratchpadEntry {
+                con_id: entry.id,
+                placeholder: windows.is_empty(),
+                windows,
+                recall,
+            }
+        })
+        .collect()
+}
+
+/// A floating window outside the active outputs, as returned by
+/// find_offscreen_windows
+#[derive(Debug, Serialize)]
+struct OffscreenWindow {
+    #[serde(flatten)]
+    window: WindowInfo,
+    /// "full" if no part is on an active output, otherwise "partial"
+    offscreen: &'static str,
+    /// Percentage of the window's area that is on an active output
+    visible_ppt: u32,
+    /// Command that brings it back to the middle of its output
+    recall: String,
+}
+
+/// Area of the overlap of two rects (0 if they don't intersect)
+fn overlap_area(a: &Rect, b: &Rect) -> i64 {
+    let width = (a.x + a.width).min(b.x + b.width) - a.x.max(b.x);
+    let height = (a.y + a.height).min(b.y + b.height) - a.y.max(b.y);
+    if width <= 0 || height <= 0 {
+        0
+    } else {
+        width as i64 * height as i64
+    }
+}
+
+/// Percentage of `rect` covered by `screens`, or None if it is fully covered.
+/// Identical screen rects (mirrored outputs) are counted once.
+fn offscreen_visible_ppt(rect: &Rect, screens: &[Rect]) -> Option<u32> {
+    let area = rect.width.max(0) as i64 * rect.height.max(0) as i64;
+    if area == 0 {
+        return None;
+    }
+    let mut distinct: Vec<&Rect> = Vec::new();
+    for screen in screens {
+        if !distinct.contains(&screen) {
+            distinct.push(screen);
+        }
+    }
+    let covered: i64 = distinct.iter().map(|s| overlap_area(rect, s)).sum();
+    (covered < area).then(|| (covered.min(area) * 100 / area) as u32)
+}
+
+/// Floating windows (outside the scratchpad) whose floating container isn't
+/// entirely on the active outputs with rects `screens`
+fn offscreen_windows(root: &Node, screens: &[Rect]) -> Vec<OffscreenWindow> {
+    let mut found = Vec::new();
+    walk_tree(root, &mut Vec::new(), &mut |node, ancestors| {
+        if node.window.is_none() || !is_floating(node) {
+            return;
+        }
+        let window = WindowInfo::from_node(node, ancestors);
+        if window
+            .workspace
+            .as_deref()
+            .is_some_and(|ws| ws.starts_with("__"))
+        {
+            return;
+        }
+        let container = ancestors
+            .iter()
+            .rev()
+            .find(|a| a.node_type == NodeType::FloatingCon);
+        let rect = container.map_or(&node.rect, |c| &c.rect);
+        if let Some(visible_ppt) = offscreen_visible_ppt(rect, screens) {
+            found.push(OffscreenWindow {
+                offscreen: if visible_ppt == 0 { "full" } else { "partial" },
+                visible_ppt,
+                recall: format!("[con_id={}] move position center", node.id),
+                window,
+            });
+        }
+    });
+    found
+}
+
+/// Prefix of the marks label_container uses as container labels
+const LABEL_MARK_PREFIX: &str = "label:";
+
+/// The label_container label on a node (its `label:` mark), if any
+fn container_label(node: &Node) -> Option<&str> {
+    node.marks
+        .as_ref()?
+        .0
+        .iter()
+        .find_map(|mark| mark.strip_prefix(LABEL_MARK_PREFIX))
+}
+
+/// The mark for a container label: whitespace collapsed to single spaces,
+/// and quotes, backslashes, and control characters (which would break the
+/// quoted mark argument) dropped
+fn label_mark(label: &str) -> Result<String, McpError> {
+    let cleaned: String = label
+        .chars()
+        .filter(|c| !matches!(c, '"' | '\\') && (c.is_whitespace() || !c.is_control()))
+        .collect();
+    let cleaned = cleaned.split_whitespace().collect::<Vec<_>>().join(" ");
+    if cleaned.is_empty() {
+        return Err(McpError::invalid_params("label must not be empty", None));
+    }
+    Ok(format!("{}{}", LABEL_MARK_PREFIX, cleaned))
+}
+
+/// The command labelling container `node` with `mark` (from label_mark),
+/// replacing any label it already has but keeping its other marks
+fn label_command(node: &Node, mark: &str) -> String {
+    let mut steps: Vec<String> = node
+        .marks
+        .as_ref()
+        .map(|m| m.0.as_slice())
+        .unwrap_or_default()
+        .iter()
+        .filter(|m| m.starts_with(LABEL_MARK_PREFIX) && *m != mark)
+        .map(|m| {
+            format!(
+                "[con_id={}] unmark \"{}\"",
+                node.id,
+                escape_criteria_value(m)
+            )
+        })
+        .collect();
+    steps.push(format!("[con_id={}] mark --add \"{}\"", node.id, mark));
+    steps.join("; ")
+}
+
+/// Default number of windows kill_all closes without `confirm`
+const DEFAULT_KILL_ALL_MAX: u32 = 3;
+
+/// Whether kill_all may go ahead: refuse (with the reason) when more than
+/// `max` windows match, or the count is unknown (`None`, criteria the server
+/// can't evaluate), unless the caller confirmed
+fn kill_all_guard(matches: Option<usize>, max: u32, confirm: bool) -> Result<(), String> {
+    if confirm {
+        return Ok(());
+    }
+    match matches {
+        Some(count) if count <= max as usize => Ok(()),
+        Some(count) => Err(format!(
+            "{} windows match, more than the limit of {}. Call again with confirm: true \
+             to close them all.",
+            count, max
+        )),
+        None => Err(
+            "These criteria use regex syntax or keys the server can't evaluate, so the \
+             number of matching windows is unknown. Call again with confirm: true to close them anyway."
+                .to_string(),
+        ),
+    }
+}
+
+/// Whether criteria_matches can judge the criteria exactly: every key is one
+/// of CRITERIA_KEYS, and no value uses regex syntax beyond `(?i)`, anchors,
+/// and escapes. Other criteria can only be judged by i3.
+fn criteria_checkable(criteria: &[Criterion]) -> bool {
+    criteria.iter().all(|criterion| {
+        if !CRITERIA_KEYS.contains(&criterion.key.as_str()) {
+            return false;
+        }
+        let Some(value) = criterion.value.as_deref() else {
+            return true;
+        };
+        let value = value.strip_prefix("(?i)").unwrap_or(value);
+        let mut chars = value.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    chars.next();
+                }
+                '.' | '*' | '+' | '?' | '|' | '(' | ')' | '[' | ']' | '{' | '}' => return false,
+                _ => {}
+            }
+        }
+        true
+    })
+}
+
+/// Parse a numeric criteria value, accepting decimal or 0x-prefixed hex
+fn parse_criteria_number(value: &str) -> Option<usize> {
+    match value.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// Whether a node (with its ancestors, root first) satisfies every criterion
+fn criteria_matches(node: &Node, ancestors: &[&Node], criteria: &[Criterion]) -> bool {
+    let props = node.window_properties.as_ref();
+    let floating = is_floating(node);
+
+    criteria.iter().all(|criterion| {
+        let value = criterion.value.as_deref();
+        let text_matches = |text: Option<&str>| match (value, text) {
+            (Some(pattern), Some(text)) => criteria_value_matches(pattern, text),
+            _ => false,
+        };
+        match criterion.key.as_str() {
+            "class" => text_matches(props.and_then(|p| p.class.as_deref())),
+            "instance" => text_matches(props.and_then(|p| p.instance.as_deref())),
+            "title" => text_matches(
+                props
+                    .and_then(|p| p.title.as_deref())
+                    .or(node.name.as_deref()),
+            ),
+            "window_role" => text_matches(props.and_then(|p| p.window_role.as_deref())),
+            "id" => node.window.is_some() && value.and_then(parse_criteria_number) == node.window,
+            "con_id" if value == Some("__focused__") => node.focused,
+            "con_id" => value.and_then(parse_criteria_number) == Some(node.id),
+            "con_mark" => node
+                .marks
+                .iter()
+                .flat_map(|marks| marks.0.iter())
+                .any(|mark| text_matches(Some(mark))),
+            "workspace" => text_matches(
+                ancestors
+                    .iter()
+                    .rev()
+                    .find(|a| a.node_type == NodeType::Workspace)
+                    .and_then(|w| w.name.as_deref()),
+            ),
+            "urgent" => node.urgent,
+            "floating" => floating,
+            "tiling" => !floating,
+            _ => false,
+        }
+    })
+}
+
+/// Nodes matching parsed criteria. Like i3, only windows are candidates unless
+/// the criteria select containers by con_id or con_mark. Leaf containers count
+/// as windows too: Sway reports native Wayland views with no X11 `window` id.
+fn find_matching_nodes<'a>(root: &'a Node, criteria: &[Criterion]) -> Vec<&'a Node> {
+    let containers = criteria
+        .iter()
+        .any(|c| c.key == "con_id" || c.key == "con_mark");
+    let mut matches = Vec::new();
+    walk_tree(root, &mut Vec::new(), &mut |node, ancestors| {
+        let container = matches!(node.node_type, NodeType::Con | NodeType::FloatingCon);
+        let leaf = node.nodes.is_empty() && node.floating_nodes.is_empty();
+        let candidate = node.window.is_some() || (container && (leaf || containers));
+        if candidate && criteria_matches(node, ancestors, criteria) {
+            matches.push(node);
+        }
+    });
+    matches
+}
+
+/// Criteria terms for the given (key, value) pairs, skipping absent values
+fn window_criteria(pairs: &[(&str, Option<&str>)]) -> Vec<Criterion> {
+    pairs
+        .iter()
+        .filter_map(|(key, value)| {
+            value.filter(|v| !v.is_empty()).map(|v| Criterion {
+                key: key.to_string(),
+                value: Some(v.to_string()),
+            })
+        })
+        .collect()
+}
+
+/// Number of windows matching the criteria, and the workspaces they are on
+/// (in tree order, without duplicates)
+fn matching_window_workspaces(root: &Node, criteria: &[Criterion]) -> (usize, Vec<String>) {
+    let mut count = 0;
+    let mut workspaces: Vec<String> = Vec::new();
+    walk_tree(root, &mut Vec::new(), &mut |node, ancestors| {
+        if node.window.is_none() || !criteria_matches(node, ancestors, criteria) {
+            return;
+        }
+        count += 1;
+        let workspace = ancestors
+            .iter()
+            .rev()
+            .find(|a| a.node_type == NodeType::Workspace)
+            .and_then(|w| w.name.clone());
+        if let Some(name) = workspace.filter(|name| !workspaces.contains(name)) {
+            workspaces.push(name);
+        }
+    });
+    (count, workspaces)
+}
+
+/// Windows matching the criteria on any workspace except the focused one
+fn matches_elsewhere(root: &Node, criteria: &[Criterion]) -> Vec<WindowInfo> {
+    let current = focused_workspace(root).map(|ws| ws.id);
+    let mut windows = Vec::new();
+    walk_tree(root, &mut Vec::new(), &mut |node, ancestors| {
+        if node.window.is_none() || !criteria_matches(node, ancestors, criteria) {
+            return;
+        }
+        let workspace = ancestors
+            .iter()
+            .rev()
+            .find(|a| a.node_type == NodeType::Workspace);
+        if workspace.map(|ws| ws.id) != current {
+            windows.push(WindowInfo::from_node(node, ancestors));
+        }
+    });
+    windows
+}
+
+/// Quote a string for POSIX sh using single quotes
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Build an `exec` command, optionally running it from `cwd`. i3 hands exec
+/// arguments to `sh -c`, so a `cd` prefix is enough to set the directory.
+fn exec_command(command: &str, cwd: Option<&str>) -> Result<String, McpError> {
+    let Some(cwd) = cwd else {
+        return Ok(format!("exec {}", command));
+    };
+    if !std::path::Path::new(cwd).is_dir() {
+        return Err(McpError::invalid_params(
+            format!("Working directory '{}' does not exist", cwd),
+            None,
+        ));
+    }
+    Ok(format!("exec cd {} && {}", shell_quote(cwd), command))
+}
+
+/// Directories searched for desktop entries: $XDG_DATA_HOME, then $XDG_DATA_DIRS
+/// (with the XDG defaults when unset)
+fn xdg_data_dirs() -> Vec<PathBuf> {
+    let data_home = std::env::var_os("XDG_DATA_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")));
+    let data_dirs = std::env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+
+    data_home
+        .into_iter()
+        .chain(
+            data_dirs
+                .split(':')
+                .filter(|d| !d.is_empty())
+                .map(PathBuf::from),
+        )
+        .collect()
+}
+
+/// Locate `<app_id>.desktop` in the applications directory of the first of
+/// `data_dirs` (see xdg_data_dirs) that has one
+fn find_desktop_entry(app_id: &str, data_dirs: &[PathBuf]) -> Result<PathBuf, McpError> {
+    let app_id = app_id.trim().trim_end_matches(".desktop");
+    if app_id.is_empty() || app_id.contains('/') || app_id.starts_with('.') {
+        return Err(McpError::invalid_params(
+            format!("Invalid desktop entry id '{}'", app_id),
+            None,
+        ));
+    }
+
+    let file_name = format!("{}.desktop", app_id);
+    data_dirs
+        .iter()
+        .map(|dir| dir.join("applications").join(&file_name))
+        .find(|path| path.is_file())
+        .ok_or_else(|| {
+            McpError::invalid_params(format!("No desktop entry found for '{}'", app_id), None)
+        })
+}
+
+/// Directory holding layout presets: $XDG_CONFIG_HOME/rmcp-i3/presets
+/// (~/.config when unset)
+fn presets_dir() -> Option<PathBuf> {
+    resolve_presets_dir(
+        std::env::var_os("XDG_CONFIG_HOME"),
+        std::env::var_os("HOME"),
+    )
+}
+
+/// The presets directory for the given XDG_CONFIG_HOME and HOME values
+fn resolve_presets_dir(
+    xdg_config_home: Option<std::ffi::OsString>,
+    home: Option<std::ffi::OsString>,
+) -> Option<PathBuf> {
+    xdg_config_home
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| home.map(|home| Path::new(&home).join(".config")))
+        .map(|config| config.join("rmcp-i3").join("presets"))
+}
+
+/// Validate a preset name, which must name a file directly in the presets
+/// directory
+fn validate_preset_name(name: &str) -> Result<&str, McpError> {
+    let name = name.trim().trim_end_matches(".json");
+    if name.is_empty() || name.contains('/') || name.starts_with('.') {
+        return Err(McpError::invalid_params(
+            format!("Invalid preset name '{}'", name),
+            None,
+        ));
+    }
+    Ok(name)
+}
+
+/// Names of the presets (`*.json` files) in `dir`, sorted
+fn preset_names(dir: &Path) -> std::io::Result<Vec<String>> {
+    let mut names: Vec<String> = std::fs::read_dir(dir)?
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().is_file())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let stem = name.strip_suffix(".json")?;
+            (!stem.is_empty() && !stem.starts_with('.')).then(|| stem.to_string())
+        })
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/// Check that `contents` is a layout i3 can append: one or more JSON
+/// container objects (or arrays of them) in a row, as i3-save-tree writes
+/// them, with `//` comment lines allowed. Returns the number of top-level
+/// containers.
+fn check_layout_json(contents: &str) -> Result<usize, String> {
+    let json: String = contents
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("//"))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let mut containers = 0;
+    for value in serde_json::Deserializer::from_str(&json).into_iter::<serde_json::Value>() {
+        let value = value.map_err(|e| format!("not valid JSON: {}", e))?;
+        let items = match &value {
+            serde_json::Value::Array(items) => items.iter().collect(),
+            other => vec![other],
+        };
+        for item in items {
+            if !item.is_object() {
+                return Err(format!("expected container objects, found {}", item));
+            }
+            containers += 1;
+        }
+    }
+    if containers == 0 {
+        return Err("no containers in layout".to_string());
+    }
+    Ok(containers)
+}
+
+/// The Exec= value of the [Desktop Entry] group, if present
+fn desktop_entry_exec(contents: &str) -> Option<&str> {
+    let mut in_entry = false;
+    for line in contents.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_entry = line == "[Desktop Entry]";
+        } else if in_entry {
+            if let Some(value) = line.strip_prefix("Exec").map(str::trim_start) {
+                if let Some(value) = value.strip_prefix('=') {
+                    return Some(value.trim()).filter(|v| !v.is_empty());
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Remove desktop entry field codes (%f, %U, ...) from an Exec value,
+/// turning %% back into a literal %
+fn strip_field_codes(exec: &str) -> String {
+    let mut out = String::with_capacity(exec.len());
+    let mut chars = exec.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        if let Some('%') = chars.next() {
+            out.push('%');
+        }
+    }
+    out.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Find a workspace by exact name, falling back to its number ("3" matches "3:web").
+/// None if nothing matches; an error if several do (e.g. "3" with both "3:web"
+/// and "3:mail"), rather than silently picking one.
+fn find_workspace<'a>(
+    workspaces: &'a [Workspace],
+    name: &str,
+) -> Result<Option<&'a Workspace>, McpError> {
+    let mut matches: Vec<&Workspace> = workspaces.iter().filter(|ws| ws.name == name).collect();
+    if matches.is_empty() {
+        if let Ok(num) = name.parse::<i32>() {
+            matches = workspaces.iter().filter(|ws| ws.num == num).collect();
+        }
+    }
+
+    match matches.as_slice() {
+        [] => Ok(None),
+        [ws] => Ok(Some(ws)),
+        several => Err(McpError::invalid_params(
+            format!(
+                "Workspace '{}' is ambiguous, it matches: {}",
+                name,
+                several
+                    .iter()
+                    .map(|ws| format!("'{}' on {}", ws.name, ws.output))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            None,
+        )),
+    }
+}
+
+/// Like find_workspace, but a workspace that doesn't exist is an error too
+fn match_workspace<'a>(name: &str, workspaces: &'a [Workspace]) -> Result<&'a Workspace, McpError> {
+    find_workspace(workspaces, name)?
+        .ok_or_else(|| McpError::invalid_params(format!("No workspace named '{}'", name), None))
+}
+
+/// Reject workspace names i3 would misparse or that name internal workspaces
+fn validate_workspace_name(name: &str) -> Result<&str, McpError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(McpError::invalid_params("Workspace name is empty", None));
+    }
+    if name.starts_with("__") || name.contains([';', ',', '"', '\n']) {
+        return Err(McpError::invalid_params(
+            format!("Invalid workspace name '{}'", name),
+            None,
+        ));
+    }
+    Ok(name)
+}
+
+/// The command that focuses `name` on `output`, and whether it moves the
+/// workspace from another output
+fn focus_on_output_command(
+    workspaces: &[Workspace],
+    name: &str,
+    output: &str,
+) -> Result<(String, bool), McpError> {
+    let command = match find_workspace(workspaces, name)? {
+        // Already on the right output: just focus it
+        Some(ws) if ws.output == output => (
+            format!("workspace \"{}\"", escape_criteria_value(&ws.name)),
+            false,
+        ),
+        // Exists elsewhere: focus it, then move it over
+        Some(ws) => (
+            format!(
+                "workspace \"{}\"; move workspace to output {}",
+                escape_criteria_value(&ws.name),
+                output
+            ),
+            true,
+        ),
+        // Doesn't exist yet: focusing the output first creates it there
+        None => (
+            format!(
+                "focus output {}; workspace \"{}\"",
+                output,
+                escape_criteria_value(name)
+            ),
+            false,
+        ),
+    };
+    Ok(command)
+}
+
+/// The command that sends window `con_id` to workspace `name`, and whether the
+/// workspace had to move between outputs. With an `output`, the workspace is
+/// first focused there (moved or created as needed, see
+/// focus_on_output_command) and focus follows the window; without one the
+/// window just moves and focus stays put.
+fn send_window_command(
+    workspaces: &[Workspace],
+    con_id: usize,
+    name: &str,
+    output: Option<&str>,
+) -> Result<(String, bool), McpError> {
+    // "3" should land on an existing "3:web" rather than create "3"
+    let target = find_workspace(workspaces, name)?.map_or(name, |ws| ws.name.as_str());
+    let move_window = format!(
+        "[con_id={}] move container to workspace \"{}\"",
+        con_id,
+        escape_criteria_value(target)
+    );
+
+    match output {
+        Some(output) => {
+            let (focus, moved) = focus_on_output_command(workspaces, name, output)?;
+            let command = format!("{}; {}; [con_id={}] focus", focus, move_window, con_id);
+            Ok((command, moved))
+        }
+        None => Ok((move_window, false)),
+    }
+}
+
+/// The command moving `window` into the container carrying `mark`, checked
+/// against the marks i3 knows about (`marks`) and the window's own
+fn move_to_mark_command(marks: &[String], window: &Node, mark: &str) -> Result<String, McpError> {
+    let mark = mark.trim();
+    if mark.is_empty() {
+        return Err(McpError::invalid_params("mark must not be empty", None));
+    }
+    if !marks.iter().any(|m| m == mark) {
+        return Err(McpError::invalid_params(
+            format!("No container is marked '{}'", mark),
+            None,
+        ));
+    }
+    if window
+        .marks
+        .as_ref()
+        .is_some_and(|m| m.0.iter().any(|m| m == mark))
+    {
+        return Err(McpError::invalid_params(
+            format!("The focused window itself carries mark '{}'", mark),
+            None,
+        ));
+    }
+    Ok(format!(
+        "[con_id={}] move window to mark \"{}\"",
+        window.id,
+        escape_criteria_value(mark)
+    ))
+}
+
+/// Orientations accepted by the `split` command
+const SPLIT_ORIENTATIONS: &[&str] = &["horizontal", "vertical", "toggle"];
+
+/// The command moving window `con_id` to workspace `name`, following it
+/// there, and splitting it in `orientation` so the next window opens beside
+/// or below it. Existing workspaces are matched like send_window does.
+fn move_and_split_command(
+    workspaces: &[Workspace],
+    con_id: usize,
+    name: &str,
+    orientation: &str,
+) -> Result<String, McpError> {
+    let orientation = orientation.trim();
+    if !SPLIT_ORIENTATIONS.contains(&orientation) {
+        return Err(McpError::invalid_params(
+            format!(
+                "Invalid orientation '{}'. Expected one of: {}",
+                orientation,
+                SPLIT_ORIENTATIONS.join(", ")
+            ),
+            None,
+        ));
+    }
+    let target = find_workspace(workspaces, name)?.map_or(name, |ws| ws.name.as_str());
+    Ok(format!(
+        "[con_id={id}] move container to workspace \"{ws}\"; workspace \"{ws}\"; [con_id={id}] split {}",
+        orientation,
+        id = con_id,
+        ws = escape_criteria_value(target)
+    ))
+}
+
+/// Directions accepted by move_to_relative_workspace
+const RELATIVE_WORKSPACES: &[&str] = &["next", "prev"];
+
+/// Validate a relative workspace direction (next or prev)
+fn validate_relative_workspace(direction: &str) -> Result<&str, McpError> {
+    let direction = direction.trim();
+    if RELATIVE_WORKSPACES.contains(&direction) {
+        Ok(direction)
+    } else {
+        Err(McpError::invalid_params(
+            format!(
+                "Invalid direction '{}'. Expected one of: {}",
+                direction,
+                RELATIVE_WORKSPACES.join(", ")
+            ),
+            None,
+        ))
+    }
+}
+
+/// The command moving a window to the next or previous workspace. With
+/// `follow`, focus goes back to the window, which switches to its new
+/// workspace.
+fn relative_move_command(con_id: usize, direction: &str, follow: bool) -> String {
+    let move_window = format!(
+        "[con_id={}] move container to workspace {}",
+        con_id, direction
+    );
+    if follow {
+        format!("{}; [con_id={}] focus", move_window, con_id)
+    } else {
+        move_window
+    }
+}
+
+/// The number prefix of a workspace name ("1:web" -> 1, "web" -> None)
+fn parse_workspace_number(name: &str) -> Option<i32> {
+    let end = name
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(name.len());
+    name[..end].parse().ok()
+}
+
+/// The lowest positive workspace number not used by any of the given names
+fn next_free_workspace_number<'a>(names: impl IntoIterator<Item = &'a str>) -> i32 {
+    let used: HashSet<i32> = names
+        .into_iter()
+        .filter_map(parse_workspace_number)
+        .collect();
+    (1..).find(|n| !used.contains(n)).unwrap_or(1)
+}
+
+/// A workspace name with its number prefix replaced ("3:web", 5 -> "5:web").
+/// None if the name has no number prefix.
+fn renumber_workspace(name: &str, num: i32) -> Option<String> {
+    parse_workspace_number(name)?;
+    let rest = name.trim_start_matches(|c: char| c.is_ascii_digit());
+    Some(format!("{}{}", num, rest))
+}
+
+/// Temporary name used while swapping two workspaces, since i3 refuses to
+/// rename a workspace to a name that is still taken
+const SWAP_WORKSPACE_PLACEHOLDER: &str = "rmcp-i3-swap";
+
+/// The command that swaps the number prefixes of workspaces `a` and `b`, and
+/// their new names. Both must exist and be numbered.
+fn swap_numbers_command(
+    names: &[&str],
+    a: &str,
+    b: &str,
+) -> Result<(String, String, String), String> {
+    for name in [a, b] {
+        if !names.contains(&name) {
+            return Err(format!("Workspace '{}' not found", name));
+        }
+    }
+    if a == b {
+        return Err(format!("Cannot swap workspace '{}' with itself", a));
+    }
+    let (Some(num_a), Some(num_b)) = (parse_workspace_number(a), parse_workspace_number(b)) else {
+        return Err(format!(
+            "Both workspaces need a number prefix to swap ('{}', '{}')",
+            a, b
+        ));
+    };
+
+    let new_a = renumber_workspace(a, num_b).unwrap_or_default();
+    let new_b = renumber_workspace(b, num_a).unwrap_or_default();
+    let rename = |from: &str, to: &str| {
+        format!(
+            "rename workspace \"{}\" to \"{}\"",
+            escape_criteria_value(from),
+            escape_criteria_value(to)
+        )
+    };
+    let command = [
+        rename(a, SWAP_WORKSPACE_PLACEHOLDER),
+        rename(b, &new_b),
+        rename(SWAP_WORKSPACE_PLACEHOLDER, &new_a),
+    ]
+    .join("; ");
+    Ok((command, new_a, new_b))
+}
+
+/// Built-in workspace labels for common window classes (matched case-insensitively)
+const CLASS_LABELS: &[(&str, &str)] = &[
+    ("firefox", "web"),
+    ("chromium", "web"),
+    ("google-chrome", "web"),
+    ("brave-browser", "web"),
+    ("alacritty", "term"),
+    ("kitty", "term"),
+    ("foot", "term"),
+    ("xterm", "term"),
+    ("urxvt", "term"),
+    ("gnome-terminal-server", "term"),
+    ("code", "code"),
+    ("emacs", "code"),
+    ("jetbrains-idea", "code"),
+    ("thunderbird", "mail"),
+    ("slack", "chat"),
+    ("discord", "chat"),
+    ("signal", "chat"),
+    ("telegramdesktop", "chat"),
+    ("spotify", "music"),
+    ("mpv", "video"),
+    ("vlc", "video"),
+    ("thunar", "files"),
+    ("org.gnome.nautilus", "files"),
+    ("gimp", "gfx"),
+    ("steam", "games"),
+];
+
+/// The most common window class on a workspace (ties go to the first seen)
+fn dominant_class(workspace: &Node) -> Option<String> {
+    let mut counts: Vec<(String, usize)> = Vec::new();
+    walk_tree(workspace, &mut Vec::new(), &mut |node, _| {
+        let class = node
+            .window_properties
+            .as_ref()
+            .and_then(|p| p.class.as_deref());
+        let Some(class) = class.filter(|_| node.window.is_some()) else {
+            return;
+        };
+        match counts.iter_mut().find(|(c, _)| c == class) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((class.to_string(), 1)),
+        }
+    });
+    // max_by_key keeps the last maximum, so scan in reverse to favour the first
+    counts
+        .into_iter()
+        .rev()
+        .max_by_key(|(_, count)| *count)
+        .map(|(class, _)| class)
+}
+
+/// Label for a window class: the client's mapping first, then CLASS_LABELS,
+/// then the class itself
+fn class_label(class: &str, mapping: Option<&HashMap<String, String>>) -> String {
+    let custom = mapping.and_then(|m| {
+        m.get(class).or_else(|| {
+            m.iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(class))
+                .map(|(_, v)| v)
+        })
+    });
+    if let Some(label) = custom {
+        return label.clone();
+    }
+    CLASS_LABELS
+        .iter()
+        .find(|(c, _)| c.eq_ignore_ascii_case(class))
+        .map(|(_, label)| label.to_string())
+        .unwrap_or_else(|| class.to_string())
+}
+
+/// A new name for a workspace, keeping its number prefix ("3:old" -> "3:web")
+fn relabel_workspace(name: &str, label: &str) -> String {
+    match parse_workspace_number(name) {
+        Some(num) => format!("{}:{}", num, label),
+        None => label.to_string(),
+    }
+}
+
+/// The urgent workspace to jump to: the lowest-numbered one if several are
+/// urgent, with unnumbered workspaces last (then by name)
+fn most_urgent_workspace(workspaces: &[Workspace]) -> Option<&Workspace> {
+    workspaces
+        .iter()
+        .filter(|ws| ws.urgent)
+        .min_by_key(|ws| (if ws.num >= 0 { ws.num } else { i32::MAX }, &ws.name))
+}
+
+/// A workspace button on a bar, as returned by get_bar_state
+#[derive(Debug, Serialize)]
+struct BarWorkspace {
+    name: String,
+    focused: bool,
+    visible: bool,
+    urgent: bool,
+}
+
+/// One active output's workspaces, in bar order
+#[derive(Debug, Serialize)]
+struct BarOutput {
+    output: String,
+    workspaces: Vec<BarWorkspace>,
+}
+
+/// Workspaces grouped by active output, the outputs left to right (then top
+/// to bottom) and the workspaces by number, unnumbered ones last in i3's order
+fn bar_state(workspaces: &[Workspace], outputs: &[Output]) -> Vec<BarOutput> {
+    let mut active: Vec<&Output> = outputs.iter().filter(|o| o.active).collect();
+    active.sort_by_key(|o| (o.rect.x, o.rect.y));
+
+    active
+        .into_iter()
+        .map(|output| {
+            let mut on_output: Vec<&Workspace> = workspaces
+                .iter()
+                .filter(|ws| ws.output == output.name)
+                .collect();
+            on_output.sort_by_key(|ws| if ws.num >= 0 { ws.num } else { i32::MAX });
+            BarOutput {
+                output: output.name.clone(),
+                workspaces: on_output
+                    .into_iter()
+                    .map(|ws| BarWorkspace {
+                        name: ws.name.clone(),
+                        focused: ws.focused,
+                        visible: ws.visible,
+                        urgent: ws.urgent,
+                    })
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+/// An active output with the workspaces on it, as returned by get_output_layout
+#[derive(Debug, Serialize)]
+struct OutputLayout {
+    name: String,
+    primary: bool,
+    rect: Rect,
+    /// The workspace currently shown on the output
+    current_workspace: Option<String>,
+    /// Every workspace on the output, in bar order
+    workspaces: Vec<String>,
+}
+
+/// Join outputs and workspaces: each active output (ordered as in bar_state)
+/// with its geometry and workspaces
+fn output_layout(workspaces: &[Workspace], outputs: &[Output]) -> Vec<OutputLayout> {
+    bar_state(workspaces, outputs)
+        .into_iter()
+        .filter_map(|bar| {
+            let output = outputs.iter().find(|o| o.name == bar.output)?;
+            Some(OutputLayout {
+                name: bar.output,
+                primary: output.primary,
+                rect: output.rect.clone(),
+                current_workspace: output.current_workspace.clone(),
+                workspaces: bar.workspaces.into_iter().map(|ws| ws.name).collect(),
+            })
+        })
+        .collect()
+}
+
+/// The command that focuses each urgent window in turn (focusing clears the
+/// hint), then returns to the focused window, or to `workspace` if only an
+/// empty workspace had focus
+fn clear_urgency_command(urgent: &[usize], focused: Option<usize>, workspace: &str) -> String {
+    let mut steps: Vec<String> = urgent
+        .iter()
+        .map(|id| format!("[con_id={}] focus", id))
+        .collect();
+    steps.push(match focused {
+        Some(con_id) => format!("[con_id={}] focus", con_id),
+        None => format!("workspace \"{}\"", escape_criteria_value(workspace)),
+    });
+    steps.join("; ")
+}
+
+/// Whether the workspace `workspace <name>` would switch to is already focused.
+/// Matches by exact name, like the command does.
+fn is_current_workspace(workspaces: &[Workspace], name: &str) -> bool {
+    workspaces.iter().any(|ws| ws.focused && ws.name == name)
+}
+
+/// The workspace toggle_workspaces switches to: `a` when `b` is focused,
+/// otherwise `b`. Existing workspaces are matched like send_window does ("3"
+/// finds "3:web") and the real name is returned.
+fn toggle_target(workspaces: &[Workspace], a: &str, b: &str) -> Result<String, McpError> {
+    let on_b = find_workspace(workspaces, b)?.is_some_and(|ws| ws.focused);
+    let target = if on_b { a } else { b };
+    Ok(find_workspace(workspaces, target)?
+        .map_or(target, |ws| ws.name.as_str())
+        .to_string())
+}
+
+/// The active output marked primary, or the first active output if none is
+fn primary_output(outputs: &[Output]) -> Option<&Output> {
+    outputs
+        .iter()
+        .find(|o| o.active && o.primary)
+        .or_else(|| outputs.iter().find(|o| o.active))
+}
+
+/// The output to use for `requested`: the requested output if it is active,
+/// otherwise (with `fallback_primary`) the primary output. Returns the chosen
+/// name and whether the fallback was used.
+fn resolve_output<'a>(
+    outputs: &'a [Output],
+    requested: &str,
+    fallback_primary: bool,
+) -> Result<(&'a str, bool), McpError> {
+    if let Some(output) = outputs.iter().find(|o| o.active && o.name == requested) {
+        return Ok((output.name.as_str(), false));
+    }
+    if fallback_primary {
+        if let Some(primary) = primary_output(outputs) {
+            return Ok((primary.name.as_str(), true));
+        }
+    }
+    Err(McpError::invalid_params(
+        format!(
+            "Unknown or inactive output '{}'. Active outputs: {}",
+            requested,
+            active_output_names(outputs).join(", ")
+        ),
+        None,
+    ))
+}
+
+/// Whether a raw GET_VERSION reply came from Sway rather than i3
+fn is_sway(version: &serde_json::Value) -> bool {
+    let field = |key: &str| {
+        version
+            .get(key)
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase()
+    };
+    field("variant") == "sway" || field("human_readable").contains("sway")
+}
+
+/// Size and modification time of the loaded config file, as returned by get_config_info
+#[derive(Debug, Serialize)]
+struct ConfigFileInfo {
+    path: String,
+    size_bytes: Option<u64>,
+    /// Seconds since the Unix epoch
+    modified: Option<u64>,
+    /// Why the file couldn't be inspected, if it couldn't
+    error: Option<String>,
+}
+
+impl ConfigFileInfo {
+    /// Stat the file at `path`; failures are recorded rather than returned
+    fn stat(path: &str) -> Self {
+        let mut info = Self {
+            path: path.to_string(),
+            size_bytes: None,
+            modified: None,
+            error: None,
+        };
+        match std::fs::metadata(path) {
+            Ok(meta) => {
+                info.size_bytes = Some(meta.len());
+                info.modified = meta
+                    .modified()
+                    .ok()
+                    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                    .map(|d| d.as_secs());
+            }
+            Err(e) => info.error = Some(e.to_string()),
+        }
+        info
+    }
+}
+
+/// The workspace named by a `workspace <name> output <outputs...>` config
+/// line (quoted or bare name), or None for any other line
+fn workspace_assignment_name(line: &str) -> Option<&str> {
+    let rest = line.trim().strip_prefix("workspace")?;
+    if !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let rest = rest.trim_start();
+    let (name, after) = match rest.strip_prefix('"') {
+        Some(quoted) => quoted.split_once('"')?,
+        None => rest.split_once(char::is_whitespace)?,
+    };
+    let after = after.trim_start().strip_prefix("output")?;
+    after.starts_with(char::is_whitespace).then_some(name)
+}
+
+/// Replace the config file at `path` with `contents`, keeping the previous
+/// version as `<file>.bak`. The new contents go to a temporary file beside
+/// the config and are renamed over it, so a failed write can't leave a
+/// truncated config behind. A symlinked config is written through the link.
+/// Returns the backup's path.
+fn write_config_atomically(path: &str, contents: &str) -> std::io::Result<PathBuf> {
+    let target = std::fs::canonicalize(path)?;
+    let sibling = |suffix: &str| {
+        let mut name = target.file_name().unwrap_or_default().to_os_string();
+        name.push(suffix);
+        target.with_file_name(name)
+    };
+    let backup = sibling(".bak");
+    let temp = sibling(".rmcp-i3.tmp");
+
+    let permissions = std::fs::metadata(&target)?.permissions();
+    std::fs::copy(&target, &backup)?;
+    let written = std::fs::write(&temp, contents)
+        .and_then(|()| std::fs::set_permissions(&temp, permissions))
+        .and_then(|()| std::fs::rename(&temp, &target));
+    if written.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    written.map(|()| backup)
+}
+
+/// Pin workspace `name` to `output` in config file contents: replace its
+/// existing `workspace ... output` line (dropping any duplicates) or append
+/// one. Returns the new contents, the line, and whether a line was replaced.
+fn pin_workspace_in_config(config: &str, name: &str, output: &str) -> (String, String, bool) {
+    let quoted = if name.contains(char::is_whitespace) {
+        format!("\"{}\"", name)
+    } else {
+        name.to_string()
+    };
+    let assignment = format!("workspace {} output {}", quoted, output);
+
+    let mut replaced = false;
+    let mut lines = Vec::new();
+    for line in config.lines() {
+        if workspace_assignment_name(line) != Some(name) {
+            lines.push(line);
+        } else if !replaced {
+            lines.push(&assignment);
+            replaced = true;
+        }
+    }
+    if !replaced {
+        lines.push(&assignment);
+    }
+
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    (contents, assignment, replaced)
+}
+
+/// The `floating_<bound>_size <w> x <h>` directive, `bound` being "minimum"
+/// or "maximum"
+fn floating_size_line(bound: &str, width: u32, height: u32) -> String {
+    format!("floating_{}_size {} x {}", bound, width, height)
+}
+
+/// Set a single-line config directive in config file contents: replace the
+/// first line starting with `keyword` or append `line`. Later lines for the
+/// same keyword are left as they are. Returns the new contents and whether a
+/// line was replaced.
+fn set_directive_in_config(config: &str, keyword: &str, line: &str) -> (String, bool) {
+    let mut replaced = false;
+    let mut lines = Vec::new();
+    for existing in config.lines() {
+        if !replaced && existing.split_whitespace().next() == Some(keyword) {
+            lines.push(line);
+            replaced = true;
+        } else {
+            lines.push(existing);
+        }
+    }
+    if !replaced {
+        lines.push(line);
+    }
+
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    (contents, replaced)
+}
+
+/// Resolve the IPC socket path the way I3::connect does: `$I3SOCK` if set,
+/// otherwise the output of `i3 --get-socketpath`. Returns the path and where
+/// it came from. The env value and the i3 lookup are passed in so callers
+/// decide where they come from.
+fn resolve_socket_path(
+    i3sock: Option<&str>,
+    get_socketpath: impl FnOnce() -> std::io::Result<String>,
+) -> std::io::Result<(String, &'static str)> {
+    match i3sock {
+        Some(path) => Ok((path.to_string(), "I3SOCK")),
+        None => get_socketpath().map(|path| (path, "i3 --get-socketpath")),
+    }
+}
+
+/// Ask the i3 binary for its socket path, as i3ipc's socket_path() does
+fn i3_get_socketpath() -> std::io::Result<String> {
+    let out = std::process::Command::new("i3")
+        .arg("--get-socketpath")
+        .output()?;
+    if !out.status.success() {
+        return Err(std::io::Error::other("i3 --get-socketpath failed"));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).trim_end().to_string())
+}
+
+/// Sway's GET_INPUTS message type, which tokio-i3ipc's Msg can't express
+const SWAY_GET_INPUTS: u32 = 100;
+
+/// Send a message type tokio-i3ipc doesn't know over a fresh IPC connection
+/// and return the JSON reply. Uses the same socket as I3::connect.
+async fn raw_ipc_request(msg_type: u32) -> std::io::Result<serde_json::Value> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let i3sock = std::env::var("I3SOCK").ok();
+    let (path, _) = resolve_socket_path(i3sock.as_deref(), i3_get_socketpath)?;
+    let mut stream = tokio::net::UnixStream::connect(path).await?;
+
+    // i3-ipc framing: magic, payload length, message type, payload (empty here)
+    let mut request = b"i3-ipc".to_vec();
+    request.extend_from_slice(&0u32.to_ne_bytes());
+    request.extend_from_slice(&msg_type.to_ne_bytes());
+    stream.write_all(&request).await?;
+
+    let mut header = [0u8; 14];
+    stream.read_exact(&mut header).await?;
+    if &header[..6] != b"i3-ipc" {
+        return Err(std::io::Error::other("Invalid IPC reply header"));
+    }
+    let len = u32::from_ne_bytes([header[6], header[7], header[8], header[9]]);
+    let mut payload = vec![0u8; len as usize];
+    stream.read_exact(&mut payload).await?;
+    Ok(serde_json::from_slice(&payload)?)
+}
+
+/// Whether a Sway input identifier names at least one device in a GET_INPUTS
+/// reply. Accepts `*` and `type:<type>` as well as exact identifiers.
+fn input_exists(inputs: &serde_json::Value, identifier: &str) -> bool {
+    let devices = inputs.as_array().map(Vec::as_slice).unwrap_or_default();
+    let field = |device: &serde_json::Value, key: &str| {
+        device
+            .get(key)
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_string()
+    };
+    if identifier == "*" {
+        return !devices.is_empty();
+    }
+    match identifier.strip_prefix("type:") {
+        Some(kind) => devices.iter().any(|d| field(d, "type") == kind),
+        None => devices.iter().any(|d| field(d, "identifier") == identifier),
+    }
+}
+
+/// The identifiers in a GET_INPUTS reply, for error messages
+fn input_identifiers(inputs: &serde_json::Value) -> Vec<String> {
+    inputs
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|d| d.get("identifier")?.as_str().map(str::to_string))
+        .collect()
+}
+
+/// Sway's `input "<identifier>" <setting> <value>` command. The setting must
+/// be a single word; a value containing a comma (e.g. xkb_layout "us,de") is
+/// quoted so Sway doesn't split the command there.
+fn input_command(identifier: &str, setting: &str, value: &str) -> Result<String, McpError> {
+    let (identifier, setting, value) = (identifier.trim(), setting.trim(), value.trim());
+    let invalid = |what: &str, v: &str| {
+        McpError::invalid_params(format!("Invalid input {} '{}'", what, v), None)
+    };
+    if identifier.is_empty() || identifier.contains(['"', ';', '\n']) {
+        return Err(invalid("identifier", identifier));
+    }
+    let word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    if setting.is_empty() || !setting.chars().all(word) {
+        return Err(invalid("setting", setting));
+    }
+    if value.is_empty() || value.contains(['"', ';', '\n']) {
+        return Err(invalid("value", value));
+    }
+
+    let value = if value.contains(',') {
+        format!("\"{}\"", value)
+    } else {
+        value.to_string()
+    };
+    Ok(format!("input \"{}\" {} {}", identifier, setting, value))
+}
+
+/// Names of all active outputs, for validation messages
+fn active_output_names(outputs: &[Output]) -> Vec<&str> {
+    outputs
+        .iter()
+        .filter(|o| o.active)
+        .map(|o| o.name.as_str())
+        .collect()
+}
+
+// ============================================================================
+// Tool Parameters
+// ============================================================================
+
+/// A value clients may send either as a JSON string or a bare number
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StringOrNumber {
+    String(String),
+    Number(serde_json::Number),
+}
+
+/// Deserialize a workspace name, accepting `1` as well as `"1"`
+fn string_or_number<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(match StringOrNumber::deserialize(deserializer)? {
+        StringOrNumber::String(s) => s,
+        StringOrNumber::Number(n) => n.to_string(),
+    })
+}
+
+/// Parameters for get_tree tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct GetTreeParams {
+    /// Drop i3-internal containers whose name starts with "__i3"
+    #[schemars(description = "Drop i3-internal containers (names starting with __i3, e.g. the scratchpad) from the tree (default false)")]
+    pub exclude_internal: Option<bool>,
+    /// Upper bound on the size of the returned text, in bytes
+    #[schemars(description = "Maximum response size in bytes. If the tree is larger, deeper levels are dropped until it fits and a note says which depth was kept.")]
+    pub max_bytes: Option<usize>,
+}
+
+/// Parameters for toggle_workspaces tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ToggleWorkspacesParams {
+    /// Workspace to switch to when on `b`
+    #[schemars(description = "Workspace to switch to when currently on b (number or name)")]
+    #[serde(deserialize_with = "string_or_number")]
+    pub a: String,
+    /// Workspace to switch to from anywhere else
+    #[schemars(description = "Workspace to switch to when on any other workspace (number or name)")]
+    #[serde(deserialize_with = "string_or_number")]
+    pub b: String,
+}
+
+/// Parameters for switch_workspace tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SwitchWorkspaceParams {
+    /// Workspace to switch to (number or name, e.g. "1", "web", "music")
+    #[schemars(description = "Workspace to switch to (number or name)")]
+    #[serde(deserialize_with = "string_or_number")]
+    pub workspace: String,
+    /// Do nothing if the workspace is already focused
+    #[schemars(description = "If the workspace is already focused, return without sending a command, avoiding workspace_auto_back_and_forth (default false)")]
+    pub skip_if_current: Option<bool>,
+}
+
+/// Parameters for focus_window tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct FocusWindowParams {
+    /// i3 criteria to match window (e.g. "[class=\"Firefox\"]", "[title=\"vim\"]")
+    #[schemars(description = "i3 criteria to match window, e.g. [class=\"Firefox\"] or [title=\"vim\"]")]
+    pub criteria: String,
+}
+
+/// Parameters for unspotlight tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct UnspotlightParams {
+    /// con_id returned by spotlight
+    #[schemars(description = "The con_id spotlight returned for the window to put back")]
+    pub con_id: i64,
+}
+
+/// Parameters for unstash_window tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct UnstashWindowParams {
+    /// con_id returned by stash_window
+    #[schemars(description = "The con_id stash_window returned for the window to bring back")]
+    pub con_id: i64,
+}
+
+/// Parameters for focus_by_window_id tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct FocusByWindowIdParams {
+    /// X11 window id, e.g. from xdotool
+    #[schemars(description = "X11 window id (the tree's 'window' field, or e.g. from xdotool search), not a con_id")]
+    pub window_id: i64,
+}
+
+/// Parameters for pip_window tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct PipWindowParams {
+    /// i3 criteria selecting the window; the focused window if omitted
+    #[schemars(description = "i3 criteria selecting the window to focus first, e.g. [class=\"mpv\"] (default: the focused window)")]
+    pub criteria: Option<String>,
+    /// Width as a percentage of the output
+    #[schemars(description = "Width as a percentage of the output's work area, 1-100 (default 25)")]
+    pub width_ppt: Option<u32>,
+    /// Height as a percentage of the output
+    #[schemars(description = "Height as a percentage of the output's work area, 1-100 (default 25)")]
+    pub height_ppt: Option<u32>,
+}
+
+/// Parameters for set_opacity tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SetOpacityParams {
+    /// Opacity from 0.0 (transparent) to 1.0 (opaque)
+    #[schemars(description = "Opacity from 0.0 (fully transparent) to 1.0 (opaque)")]
+    pub opacity: f32,
+    /// Criteria selecting the windows; the focused window if omitted
+    #[schemars(description = "Criteria selecting the windows, e.g. [app_id=\"foot\"] (default: the focused window)")]
+    pub criteria: Option<String>,
+}
+
+/// Parameters for validate_command tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ValidateCommandParams {
+    /// i3 command to check
+    #[schemars(description = "i3 command to check without running it, e.g. '[class=\"Firefox\"] focus; layout tabbed'")]
+    pub command: String,
+}
+
+/// Parameters for set_split_ratio tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SetSplitRatioParams {
+    /// Fraction of the parent split to fill, 0.1-0.9
+    #[schemars(description = "Fraction of its parent split the focused window should fill, from 0.1 to 0.9 (e.g. 0.66 for two thirds)")]
+    pub ratio: f32,
+}
+
+/// Parameters for configure_input tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ConfigureInputParams {
+    /// Input identifier from Sway's get_inputs, `type:<type>`, or `*`
+    #[schemars(description = "Input identifier, e.g. '1739:52619:SYNA8004:00_06CB:CD8B', or 'type:touchpad', 'type:keyboard', or '*' for all devices")]
+    pub identifier: String,
+    /// Setting name, e.g. tap or xkb_layout
+    #[schemars(description = "Input setting, e.g. 'tap', 'natural_scroll', 'xkb_layout', 'pointer_accel'")]
+    pub setting: String,
+    /// Value for the setting
+    #[schemars(description = "Value for the setting, e.g. 'enabled', 'disabled', 'us,de', '0.5'")]
+    pub value: String,
+}
+
+/// Parameters for set_bar_mode tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SetBarModeParams {
+    /// Bar to change; every bar if omitted
+    #[schemars(description = "Bar id to change, e.g. 'bar-0' (default: every bar)")]
+    pub bar_id: Option<String>,
+    /// dock, hide, or invisible
+    #[schemars(description = "Bar mode: 'dock' (always shown), 'hide' (shown while the modifier is held), or 'invisible' (never shown)")]
+    pub mode: String,
+}
+
+/// Parameters for set_bar_hidden_state tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SetBarHiddenStateParams {
+    /// Bar to change; every bar if omitted
+    #[schemars(description = "Bar id to change, e.g. 'bar-0' (default: every bar)")]
+    pub bar_id: Option<String>,
+    /// hide or show
+    #[schemars(description = "'show' to reveal a bar in hide mode, 'hide' to hide it again")]
+    pub state: String,
+}
+
+/// Parameters for move_to_workspace tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct MoveToWorkspaceParams {
+    /// Workspace to move the focused window to
+    #[schemars(description = "Workspace to move the focused window to")]
+    #[serde(deserialize_with = "string_or_number")]
+    pub workspace: String,
+}
+
+/// Parameters for get_previous_workspace tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct GetPreviousWorkspaceParams {
+    /// Forget the tracked workspace after returning it
+    #[schemars(description = "Forget the tracked previous workspace after returning it (default false)")]
+    pub clear: Option<bool>,
+}
+
+/// Parameters for send_window tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SendWindowParams {
+    /// Workspace to send the focused window to
+    #[schemars(description = "Workspace to send the focused window to (number or name)")]
+    #[serde(deserialize_with = "string_or_number")]
+    pub workspace: String,
+    /// Output the workspace should be on; moved there first if needed
+    #[schemars(description = "Optional output the workspace should live on, e.g. 'HDMI-1'; the workspace is moved or created there first")]
+    pub output: Option<String>,
+}
+
+/// Parameters for move_into_marked tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct MoveIntoMarkedParams {
+    /// Mark of the destination container
+    #[schemars(description = "Mark of the container to move the focused window into, e.g. 'left' (see get_marks)")]
+    pub mark: String,
+}
+
+/// Parameters for move_and_split tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct MoveAndSplitParams {
+    /// Workspace to move the focused window to
+    #[schemars(description = "Workspace to move the focused window to (number or name)")]
+    #[serde(deserialize_with = "string_or_number")]
+    pub workspace: String,
+    /// horizontal, vertical, or toggle
+    #[schemars(description = "Split to apply to the window there: 'horizontal' (next window opens beside it), 'vertical' (below it), or 'toggle'")]
+    pub orientation: String,
+}
+
+/// Parameters for move_to_relative_workspace tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct MoveToRelativeWorkspaceParams {
+    /// next or prev
+    #[schemars(description = "'next' or 'prev': the workspace after or before the current one")]
+    pub direction: String,
+    /// Whether to switch to the workspace along with the window
+    #[schemars(description = "Also switch to the workspace the window lands on (default false)")]
+    pub follow: Option<bool>,
+}
+
+/// Parameters for evacuate_workspace tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct EvacuateWorkspaceParams {
+    /// Workspace to move every window off
+    #[schemars(description = "Workspace to empty (name, or number like '3')")]
+    #[serde(deserialize_with = "string_or_number")]
+    pub from: String,
+    /// Workspace to move the windows to
+    #[schemars(description = "Workspace to move the windows to (created if it doesn't exist)")]
+    #[serde(deserialize_with = "string_or_number")]
+    pub to: String,
+}
+
+/// Parameters for run_command tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct RunCommandParams {
+    /// i3 command to execute (see i3 user guide for full command list)
+    #[schemars(description = "i3 command to execute (e.g. 'split h', 'layout tabbed', 'kill')")]
+    pub command: String,
+}
+
+/// Parameters for exec tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ExecParams {
+    /// Command to execute (application to launch)
+    #[schemars(description = "Command to execute, e.g. 'firefox', 'kitty', 'emacs'")]
+    pub command: String,
+    /// Working directory to launch the command in
+    #[schemars(description = "Working directory to launch the command in (must exist)")]
+    pub cwd: Option<String>,
+}
+
+/// Parameters for launch_app tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct LaunchAppParams {
+    /// Desktop entry id, e.g. "firefox" or "org.gnome.Nautilus"
+    #[schemars(description = "Desktop entry id (the .desktop file name without extension), e.g. 'firefox' or 'org.gnome.Nautilus'")]
+    pub app_id: String,
+}
+
+/// Parameters for apply_preset tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ApplyPresetParams {
+    /// Preset name (file name in the presets directory, without .json)
+    #[schemars(description = "Preset name: the file name under ~/.config/rmcp-i3/presets without '.json', as listed by list_presets")]
+    pub name: String,
+}
+
+/// Parameters for set_floating_minimum_size and set_floating_maximum_size
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct FloatingSizeParams {
+    /// Width in pixels
+    #[schemars(description = "Width in pixels (0 restores i3's default)")]
+    pub width: u32,
+    /// Height in pixels
+    #[schemars(description = "Height in pixels (0 restores i3's default)")]
+    pub height: u32,
+}
+
+/// Parameters for kill_window tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct KillWindowParams {
+    /// i3 criteria to match window to kill (e.g. "[class=\"Firefox\"]", "[title=\"vim\"]")
+    #[schemars(description = "i3 criteria to match window to kill, e.g. [class=\"Firefox\"] or [title=\"~\"]")]
+    pub criteria: String,
+}
+
+/// Parameters for kill_all tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct KillAllParams {
+    /// i3 criteria selecting the windows to close
+    #[schemars(description = "i3 criteria selecting every window to close, e.g. [class=\"Firefox\"]")]
+    pub criteria: String,
+    /// Most windows to close without confirm (default 3)
+    #[schemars(description = "Most windows to close without confirm (default 3)")]
+    pub max: Option<u32>,
+    /// Close even if more than max windows match
+    #[schemars(description = "Set to true to close all matches even when more than max match")]
+    pub confirm: Option<bool>,
+}
+
+/// Parameters for move_window_to_position_on_output tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct MoveWindowToPositionOnOutputParams {
+    /// Output (monitor) name to move the focused window to, e.g. "HDMI-1"
+    #[schemars(description = "Output name to move the window to, e.g. 'HDMI-1' (see get_outputs)")]
+    pub output: String,
+    /// X position in pixels
+    #[schemars(description = "X position in pixels")]
+    pub x: i32,
+    /// Y position in pixels
+    #[schemars(description = "Y position in pixels")]
+    pub y: i32,
+}
+
+/// Parameters for move_window_to_output tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct MoveWindowToOutputParams {
+    /// Output (monitor) name to move the focused window to, e.g. "HDMI-1"
+    #[schemars(description = "Output name to move the window to, e.g. 'HDMI-1' (see get_outputs)")]
+    pub output: String,
+    /// Fall back to the primary output if the requested one isn't active
+    #[schemars(description = "If the output isn't active (e.g. unplugged), move to the primary output instead of failing (default false)")]
+    pub fallback_primary: Option<bool>,
+}
+
+/// Parameters for set_output_power tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SetOutputPowerParams {
+    /// Output (monitor) name, e.g. "HDMI-1"
+    #[schemars(description = "Output name, e.g. 'HDMI-1' (see get_outputs)")]
+    pub output: String,
+    /// Whether to power the output on (true) or off (false)
+    #[schemars(description = "true to power the output on, false to turn it off")]
+    pub enable: bool,
+}
+
+/// Parameters for get_container and get_focus_order tools
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct GetContainerParams {
+    /// i3 container id (the `id` field in the tree, as used by [con_id=...] criteria)
+    #[schemars(description = "Container id (con_id) to look up")]
+    pub con_id: i64,
+}
+
+/// Parameters for focus_workspace_on_output tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct FocusWorkspaceOnOutputParams {
+    /// Workspace to focus (number or name)
+    #[schemars(description = "Workspace to focus (number or name)")]
+    #[serde(deserialize_with = "string_or_number")]
+    pub workspace: String,
+    /// Output the workspace should appear on
+    #[schemars(description = "Output the workspace should appear on, e.g. 'HDMI-1' (see get_outputs)")]
+    pub output: String,
+}
+
+/// Parameters for pull_workspace_here tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct PullWorkspaceHereParams {
+    /// Workspace to bring to the focused output (number or name)
+    #[schemars(description = "Workspace to bring to the focused output (number or name)")]
+    #[serde(deserialize_with = "string_or_number")]
+    pub workspace: String,
+}
+
+/// Parameters for pin_workspace_to_output tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct PinWorkspaceToOutputParams {
+    /// Workspace to pin (number or name, as written in the config)
+    #[schemars(description = "Workspace to pin, as it should appear in the config (number or name)")]
+    #[serde(deserialize_with = "string_or_number")]
+    pub workspace: String,
+    /// Output to pin it to
+    #[schemars(description = "Active output to pin the workspace to, e.g. 'HDMI-1'")]
+    pub output: String,
+}
+
+/// Parameters for bind_key tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BindKeyParams {
+    /// Key combo to bind, e.g. "$mod+Shift+b"
+    #[schemars(description = "Key combo to bind, e.g. 'Mod4+Shift+b'")]
+    pub keys: String,
+    /// i3 command to run when the keys are pressed
+    #[schemars(description = "i3 command to run when pressed, e.g. 'exec firefox'")]
+    pub command: String,
+    /// Trigger on key release instead of press
+    #[schemars(description = "Trigger on key release instead of press (default false)")]
+    pub release: Option<bool>,
+}
+
+/// Parameters for unbind_key tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct UnbindKeyParams {
+    /// Key combo to unbind, e.g. "$mod+Shift+b"
+    #[schemars(description = "Key combo to unbind, e.g. 'Mod4+Shift+b'")]
+    pub keys: String,
+}
+
+/// Parameters for list_empty_workspaces tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ListEmptyWorkspacesParams {
+    /// Briefly visit each empty workspace and return, so i3 removes them
+    #[schemars(description = "Visit each empty workspace and come back so i3 cleans them up (default false)")]
+    pub focus_then_leave: Option<bool>,
+}
+
+/// Parameters for auto_name_workspace tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct AutoNameWorkspaceParams {
+    /// Window class -> label overrides, e.g. {"Firefox": "browse"}
+    #[schemars(description = "Optional window class to label overrides, e.g. {\"Firefox\": \"browse\"}, checked before the built-in map")]
+    pub mapping: Option<HashMap<String, String>>,
+}
+
+/// Parameters for swap_workspace_numbers tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SwapWorkspaceNumbersParams {
+    /// First workspace name
+    #[schemars(description = "Exact name of the first workspace, e.g. '1:web'")]
+    pub a: String,
+    /// Second workspace name
+    #[schemars(description = "Exact name of the second workspace, e.g. '3:mail'")]
+    pub b: String,
+}
+
+/// Parameters for run_commands tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct RunCommandsParams {
+    /// i3 commands to execute in order, in a single IPC call
+    #[schemars(description = "i3 commands to execute in order, e.g. ['workspace 2', 'layout tabbed']")]
+    pub commands: Vec<String>,
+    /// Refocus the originally focused container afterwards
+    #[schemars(description = "Refocus whatever was focused before the commands once they finish (default false)")]
+    pub restore_focus: Option<bool>,
+}
+
+/// Parameters for with_window tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct WithWindowParams {
+    /// i3 criteria selecting the windows, e.g. `[class="mpv"]`
+    #[schemars(description = "i3 criteria selecting the windows, e.g. [class=\"mpv\"] (brackets optional)")]
+    pub criteria: String,
+    /// Commands to apply to the matched windows, in order
+    #[schemars(description = "Commands to apply to the matched windows, e.g. ['floating enable', 'move position center']")]
+    pub commands: Vec<String>,
+}
+
+/// Parameters for run_command_on tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct RunCommandOnParams {
+    /// i3 criteria selecting the windows; the focused window when unset
+    #[schemars(description = "Optional i3 criteria selecting the windows, e.g. [class=\"mpv\"] (brackets optional). Without it the focused window is used.")]
+    pub criteria: Option<String>,
+    /// Command to run on the matched windows
+    #[schemars(description = "Command to run on the matched windows, without criteria, e.g. 'floating enable' or 'move to workspace 3'")]
+    pub action: String,
+}
+
+/// Parameters for build_criteria tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BuildCriteriaParams {
+    /// Window class (WM_CLASS class), e.g. "Firefox"
+    #[schemars(description = "Window class, e.g. 'Firefox'")]
+    pub class: Option<String>,
+    /// Window instance (WM_CLASS instance), e.g. "Navigator"
+    #[schemars(description = "Window instance, e.g. 'Navigator'")]
+    pub instance: Option<String>,
+    /// Window title
+    #[schemars(description = "Window title (i3 treats it as a regular expression)")]
+    pub title: Option<String>,
+    /// Container mark
+    #[schemars(description = "Container mark")]
+    pub con_mark: Option<String>,
+    /// Window role (WM_WINDOW_ROLE)
+    #[schemars(description = "Window role, e.g. 'browser'")]
+    pub window_role: Option<String>,
+}
+
+/// Parameters for get_window_marks tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct GetWindowMarksParams {
+    /// Criteria selecting the windows, e.g. `[class="Firefox"]`
+    #[schemars(description = "i3 criteria selecting windows, e.g. '[class=\"Firefox\"]' or 'con_id=94' (brackets optional). Values support ^/$ anchors and (?i), otherwise match as substrings. Other regex syntax and keys like app_id are rejected.")]
+    pub criteria: String,
+}
+
+/// Parameters for tag_and_remember tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TagAndRememberParams {
+    /// Mark to put on the focused window
+    #[schemars(description = "Mark to put on the focused window and remember it by, e.g. 'editor'")]
+    pub mark: String,
+}
+
+/// Parameters for label_container tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct LabelContainerParams {
+    /// Label text, e.g. "build logs"
+    #[schemars(description = "Label for the focused container, e.g. 'build logs'. Stored as the mark 'label:<text>'.")]
+    pub label: String,
+}
+
+/// Parameters for is_running tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct IsRunningParams {
+    /// Window class (WM_CLASS class), e.g. "Firefox"
+    #[schemars(description = "Window class, e.g. 'Firefox' (supports ^/$ anchors and a (?i) prefix)")]
+    pub class: Option<String>,
+    /// Window instance (WM_CLASS instance), e.g. "Navigator"
+    #[schemars(description = "Window instance, e.g. 'Navigator'")]
+    pub instance: Option<String>,
+    /// Window title
+    #[schemars(description = "Window title (substring, supports ^/$ anchors and a (?i) prefix)")]
+    pub title: Option<String>,
+}
+
+/// Parameters for find_elsewhere tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct FindElsewhereParams {
+    /// Criteria selecting the windows, e.g. `[class="Firefox"]`
+    #[schemars(description = "i3 criteria selecting windows, e.g. '[class=\"Firefox\"]' (brackets optional). Values support ^/$ anchors and (?i), otherwise match as substrings. Other regex syntax and keys like app_id are rejected.")]
+    pub criteria: String,
+}
+
+/// Parameters for window_overview tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct WindowOverviewParams {
+    /// Report rects relative to each window's output instead of the X root
+    #[schemars(description = "Report window rects relative to their output's top-left corner instead of the X root (default false)")]
+    pub relative_to_output: Option<bool>,
+}
+
+/// Parameters for workspace_layout tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct WorkspaceLayoutParams {
+    /// New layout for the focused workspace; omit to just report the current one
+    #[schemars(description = "New layout for the focused workspace: default, splith, splitv, stacking or tabbed. Omit to only query.")]
+    pub layout: Option<String>,
+}
+
+/// Parameters for set_debug_logging tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SetDebugLoggingParams {
+    /// Turn i3's debug logging on or off
+    #[schemars(description = "true to enable i3 debug logging (debuglog + shmlog), false to disable")]
+    pub enable: bool,
+}
+
+/// Parameters for send_tick tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SendTickParams {
+    /// Payload to broadcast with the tick; a unique one is generated if omitted
+    #[schemars(description = "Payload to broadcast with the tick (a unique one is generated if omitted)")]
+    pub payload: Option<String>,
+}
+
+/// Parameters for sync_with_i3 tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SyncWithI3Params {
+    /// How long to wait for the tick, in milliseconds
+    #[schemars(description = "How long to wait for the tick to come back, in milliseconds (default 1000, max 10000)")]
+    pub timeout_ms: Option<u64>,
+}
+
+/// Parameters for focus_by_size tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct FocusBySizeParams {
+    /// largest or smallest
+    #[schemars(description = "'largest' or 'smallest': which window to focus, by on-screen area")]
+    pub which: String,
+}
+
+/// Parameters for move_to_container tool
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct MoveToContainerParams {
+    /// Container whose workspace the focused window should move to
+    #[schemars(description = "con_id of a window/container; the focused window moves to that container's workspace")]
+    pub con_id: i64,
+}
+
+// ============================================================================
+// Tool Implementations
+// ============================================================================
+
+#[rmcp::tool_router]
+impl I3Server {
+    /// Get a list of all workspaces with their properties
+    #[rmcp::tool(description = "List all i3 workspaces with their properties (number, name, visible, focused, urgent, output)")]
+    pub async fn get_workspaces(&self) -> Result<CallToolResult, McpError> {
+        info!("Getting workspaces");
+        let mut conn = self.connect().await?;
+
+        let workspaces = self.fetch_workspaces(&mut conn).await?;
+
+        debug!("Found {} workspaces", workspaces.len());
+        ToolResponse::data(format!("{} workspaces", workspaces.len()), &workspaces)
+    }
+
+    /// Get the per-output workspace list a status bar draws
+    #[rmcp::tool(description = "Get what a status bar needs: for each active output (left to right), its workspaces in bar order with just name, focused, visible, and urgent")]
+    pub async fn get_bar_state(&self) -> Result<CallToolResult, McpError> {
+        info!("Getting bar state");
+        let mut conn = self.connect().await?;
+
+        let workspaces = self.fetch_workspaces(&mut conn).await?;
+        let outputs = self.fetch_outputs(&mut conn).await?;
+        let state = bar_state(&workspaces, &outputs);
+
+        ToolResponse::data(
+            format!("Workspaces on {} active output(s)", state.len()),
+            &state,
+        )
+    }
+
+    /// Get the full i3 window tree
+    #[rmcp::tool(description = "Get the full i3 window tree (all containers, windows, and their layout). Set exclude_internal to drop i3's __i3 containers (scratchpad and friends), and max_bytes to cap the response size.")]
+    pub async fn get_tree(
+        &self,
+        Parameters(params): Parameters<GetTreeParams>,
+    ) -> Result<CallToolResult, McpError> {
+        info!("Getting window tree");
+        let mut conn = self.connect().await?;
+
+        let tree = self.fetch_tree(&mut conn).await?;
+
+        let mut value = serde_json::to_value(&tree).map_err(|e| {
+            McpError::internal_error(format!("Failed to serialize tree: {}", e), None)
+        })?;
+        if params.exclude_internal.unwrap_or(false) {
+            prune_internal_nodes(&mut value);
+        }
+
+        let Some(max_bytes) = params.max_bytes else {
+            return ToolResponse::data("Window tree", &value);
+        };
+
+        let message = |depth: Option<usize>| match depth {
+            Some(depth) => format!(
+                "Window tree, limited to depth {} to fit max_bytes={}",
+                depth, max_bytes
+            ),
+            None => "Window tree".to_string(),
+        };
+        let fits = |tree: &serde_json::Value, depth: Option<usize>| {
+            let response = ToolResponse::new(true, message(depth), Some(tree.clone()));
+            response.render().len() <= max_bytes
+        };
+
+        match shrink_tree_to_fit(&value, fits) {
+            Some((tree, depth)) => ToolResponse::data(message(depth), &tree),
+            None => Ok(ToolResponse::error(format!(
+                "Tree does not fit in max_bytes={} even at depth 0",
+                max_bytes
+            ))),
+        }
+    }
+
+    /// Flat list of every window across all workspaces
+    #[rmcp::tool(description = "List every window across all workspaces as a flat list (con_id, class, title, workspace, output, focused, urgent, rect), sorted by workspace then position")]
+    pub async fn window_overview(
+        &self,
+        Parameters(params): Parameters<WindowOverviewParams>,
+    ) -> Result<CallToolResult, McpError> {
+        info!("Getting window overview");
+        let mut conn = self.connect().await?;
+
+        let tree = self.fetch_tree(&mut conn).await?;
+        let mut windows = collect_windows(&tree);
+
+        if params.relative_to_output.unwrap_or(false) {
+            let outputs = self.fetch_outputs(&mut conn).await?;
+            translate_to_output(&mut windows, &outputs);
+        }
+
+        debug!("Found {} windows", windows.len());
+        ToolResponse::data(format!("{} windows", windows.len()), &windows)
+    }
+
+    /// Minimal window inventory: con_id -> title
+    #[rmcp::tool(description = "List every window as a flat {con_id: title} object and nothing else. The smallest possible inventory; use window_overview or get_container for details.")]
+    pub async fn list_windows(&self) -> Result<CallToolResult, McpError> {
+        info!("Listing windows");
+        let mut conn = self.connect().await?;
+
+        let tree = self.fetch_tree(&mut conn).await?;
+        let titles = window_titles(&tree);
+
+        ToolResponse::data(format!("{} windows", titles.len()), &titles)
+    }
+
+    /// Export the whole session as a shareable JSON document
+    #[rmcp::tool(description = "Snapshot the whole session as one self-contained JSON document: active outputs, every workspace with its output and layout, each workspace's windows (class, instance, title, marks, floating), and the scratchpad. Meant for documenting or sharing a setup so it can be recreated approximately.")]
+    pub async fn snapshot_layout(&self) -> Result<CallToolResult, McpError> {
+        info!("Taking layout snapshot");
+        let mut conn = self.connect().await?;
+
+        let workspaces = self.fetch_workspaces(&mut conn).await?;
+        let outputs = self.fetch_outputs(&mut conn).await?;
+        let tree = self.fetch_tree(&mut conn).await?;
+        let snapshot = layout_snapshot(&tree, &workspaces, &outputs, unix_now());
+
+        let windows: usize = snapshot.workspaces.iter().map(|ws| ws.windows.len()).sum();
+        ToolResponse::data(
+            format!(
+                "Snapshot of {} workspace(s) and {} window(s)",
+                snapshot.workspaces.len(),
+                windows
+            ),
+            &snapshot,
+        )
+    }
+
+    /// Windows in most-recently-focused order
+    #[rmcp::tool(description = "List every window ordered by how recently it was focused (most recent first, the focused window leading), with last_focused timestamps. Windows not focused since the server started come last. For MRU window switching.")]
+    pub async fn windows_by_recency(&self) -> Result<CallToolResult, McpError> {
+        info!("Listing windows by recency");
+        let history: Vec<FocusEntry> = self.events().focus_history.iter().cloned().collect();
+        let mut conn = self.connect().await?;
+
+        let tree = self.fetch_tree(&mut conn).await?;
+        let windows = windows_by_recency(collect_windows(&tree), &history);
+
+        ToolResponse::data(format!("{} windows", windows.len()), &windows)
+    }
+
+    /// One-paragraph summary of the current session
+    #[rmcp::tool(description = "Describe the current state in one short paragraph of prose: focused workspace, output, and window, how many workspaces and windows exist, and any urgent windows. The cheapest way to get oriented.")]
+    pub async fn describe_state(&self) -> Result<CallToolResult, McpError> {
+        info!("Describing current state");
+        let mut conn = self.connect().await?;
+
+        let workspaces = self.fetch_workspaces(&mut conn).await?;
+        let outputs = self.fetch_outputs(&mut conn).await?;
+        let tree = self.fetch_tree(&mut conn).await?;
+        let windows = collect_windows(&tree);
+
+        Ok(ToolResponse::success(describe_state(
+            &workspaces,
+            &outputs,
+            &windows,
+        )))
+    }
+
+    /// Nested rectangles for the focused workspace
+    #[rmcp::tool(description = "Get the focused workspace as nested rectangles: {con_id, type, layout, rect, children} only, without window properties. A light geometric view for spatial reasoning.")]
+    pub async fn get_layout_rects(&self) -> Result<CallToolResult, McpError> {
+        info!("Getting layout rects");
+        let mut conn = self.connect().await?;
+
+        let tree = self.fetch_tree(&mut conn).await?;
+        let Some(workspace) = focused_workspace(&tree) else {
+            return Ok(ToolResponse::failure("No focused workspace found"));
+        };
+
+        let rects = LayoutRect::from_node(workspace);
+        ToolResponse::data(
+            format!(
+                "Layout of workspace '{}'",
+                workspace.name.as_deref().unwrap_or_default()
+            ),
+            &rects,
+        )
+    }
+
+    /// Recently focused windows, most recent first
+    #[rmcp::tool(description = "Get the windows focused recently during this session (most recent first), with class/title resolved from the current tree and closed windows flagged")]
+    pub async fn get_focus_history(&self) -> Result<CallToolResult, McpError> {
+        info!("Getting focus history");
+        let history: Vec<FocusEntry> = self.events().focus_history.iter().cloned().collect();
+        if history.is_empty() {
+            return Ok(ToolResponse::success("No focus changes recorded yet"));
+        }
+
+        let mut conn = self.connect().await?;
+        let tree = self.fetch_tree(&mut conn).await?;
+
+        let entries: Vec<serde_json::Value> = history
+            .iter()
+            .rev()
+            .map(|entry| {
+                let Some((node, ancestors)) = find_node_with_ancestors(&tree, entry.con_id) else {
+                    return serde_json::json!({
+                        "con_id": entry.con_id,
+                        "timestamp": entry.timestamp,
+                        "closed": true,
+                    });
+                };
+                let window = WindowInfo::from_node(node, &ancestors);
+                serde_json::json!({
+                    "con_id": entry.con_id,
+                    "timestamp": entry.timestamp,
+                    "class": window.class,
+                    "title": window.title,
+                    "workspace": window.workspace,
+                    "closed": false,
+                })
+            })
+            .collect();
+
+        ToolResponse::data(format!("{} recent focus changes", entries.len()), &entries)
+    }
+
+    /// Focus the biggest or smallest visible window
+    #[rmcp::tool(description = "Focus the largest or smallest window on the visible workspaces, by rect area (ties go to the lowest con_id)")]
+    pub async fn focus_by_size(
+        &self,
+        Parameters(params): Parameters<FocusBySizeParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let which = params.which.trim();
+        if !SIZE_EXTREMES.contains(&which) {
+            return Err(McpError::invalid_params(
+                format!(
+                    "Invalid which '{}'. Expected one of: {}",
+                    which,
+                    SIZE_EXTREMES.join(", ")
+                ),
+                None,
+            ));
+        }
+        info!("Focusing {} window", which);
+        let mut conn = self.connect().await?;
+
+        let workspaces = self.fetch_workspaces(&mut conn).await?;
+        let tree = self.fetch_tree(&mut conn).await?;
+        let windows: Vec<WindowInfo> = collect_windows(&tree)
+            .into_iter()
+            .filter(|w| {
+                workspaces
+                    .iter()
+                    .any(|ws| ws.visible && w.workspace.as_deref() == Some(ws.name.as_str()))
+            })
+            .collect();
+        let Some(target) = window_by_size(&windows, which == "largest") else {
+            return Ok(ToolResponse::failure(
+                "No windows on the visible workspaces",
+            ));
+        };
+
+        let command = format!("[con_id={}] focus", target.con_id);
+        let results = self
+            .send_command(&mut conn, &command, "focus window")
+            .await?;
+
+        match failure_message(&results) {
+            None => ToolResponse::data(
+                format!(
+                    "Focused the {} window ({}x{}, con_id {})",
+                    which, target.rect.width, target.rect.height, target.con_id
+                ),
+                target,
+            ),
+            Some(errors) => Ok(ToolResponse::failure(format!(
+                "Failed to focus window: {}",
+                errors
+            ))),
+        }
+    }
+
+    /// Focus the window that was focused before the current one
+    #[rmcp::tool(description = "Focus the previously focused window (alt-tab style), skipping windows that have closed since")]
+    pub async fn focus_previous(&self) -> Result<CallToolResult, McpError> {
+        info!("Focusing previous window");
+        let history: Vec<usize> = self
+            .events()
+            .focus_history
+            .iter()
+            .map(|e| e.con_id)
+            .collect();
+        if history.len() < 2 {
+            return Ok(ToolResponse::failure(
+                "Not enough focus history yet to go back",
+            ));
+        }
+
+        let mut conn = self.connect().await?;
+        let tree = self.fetch_tree(&mut conn).await?;
+
+        // The newest entry is the current window; walk back from the one before it
+        let current = history[history.len() - 1];
+        let Some(target) = history
+            .iter()
+            .rev()
+            .skip(1)
+            .copied()
+            .find(|id| *id != current && find_node_with_ancestors(&tree, *id).is_some())
+        else {
+            return Ok(ToolResponse::failure(
+                "All previously focused windows have closed",
+            ));
+        };
+
+        let command = format!("[con_id={}] focus", target);
+        let results = self
+            .send_command(&mut conn, &command, "focus window")
+            .await?;
+
+        match failure_message(&results) {
+            None => Ok(ToolResponse::success(format!(
+                "Focused previous window (con_id {})",
+                target
+            ))),
+            Some(errors) => Ok(ToolResponse::failure(format!(
+                "Failed to focus window: {}",
+                errors
+            ))),
+        }
+    }
+
+    /// The workspace `workspace back_and_forth` would return to
+    #[rmcp::tool(description = "Get the previously focused workspace, the one 'workspace back_and_forth' returns to, tracked from i3 workspace events. Pass clear: true to forget it afterwards.")]
+    pub async fn get_previous_workspace(
+        &self,
+        Parameters(params): Parameters<GetPreviousWorkspaceParams>,
+    ) -> Result<CallToolResult, McpError> {
+        info!("Getting previous workspace");
+        let previous = if params.clear.unwrap_or(false) {
+            self.events().previous_workspace.take()
+        } else {
+            self.events().previous_workspace.clone()
+        };
+
+        match previous {
+            Some(name) => ToolResponse::data(format!("Previous workspace: {}", name), &name),
+            None => Ok(ToolResponse::success(
+                "No workspace switch has been seen since the server started",
+            )),
+        }
+    }
+
+    /// Focus the most recently created window
+    #[rmcp::tool(description = "Focus the most recently created window (e.g. right after launching an app), tracked from i3 window events")]
+    pub async fn focus_newest(&self) -> Result<CallToolResult, McpError> {
+        info!("Focusing newest window");
+        let Some(con_id) = self.events().newest_window else {
+            return Ok(ToolResponse::failure(
+                "No window has been created since the server started (or it has closed)",
+            ));
+        };
+
+        let mut conn = self.connect().await?;
+        let command = format!("[con_id={}] focus", con_id);
+        let results = self
+            .send_command(&mut conn, &command, "focus window")
+            .await?;
+
+        match failure_message(&results) {
+            None => Ok(ToolResponse::success(format!(
+                "Focused newest window (con_id {})",
+                con_id
+            ))),
+            Some(errors) => Ok(ToolResponse::failure(format!(
+                "Failed to focus window: {}",
+                errors
+            ))),
+        }
+    }
+
+    /// Look up a single container by con_id
+    #[rmcp::tool(description = "Get a single container by con_id, with its full properties and the chain of ancestors (root → output → workspace → ...) leading to it")]
+    pub async fn get_container(
+        &self,
+        Parameters(params): Parameters<GetContainerParams>,
+    ) -> Result<CallToolResult, McpError> {
+        info!("Getting container: {}", params.con_id);
+        let con_id = parse_con_id(params.con_id)?;
+        let mut conn = self.connect().await?;
+
+        let tree = self.fetch_tree(&mut conn).await?;
+
+        let Some((node, ancestors)) = find_node_with_ancestors(&tree, con_id) else {
+            return Ok(ToolResponse::failure(format!(
+                "No container with con_id {}",
+                con_id
+            )));
+        };
+
+        let ancestors: Vec<serde_json::Value> = ancestors
+            .iter()
+            .map(|a| {
+                serde_json::json!({
+                    "id": a.id,
+                    "name": a.name,
+                    "type": a.node_type,
+                })
+            })
+            .collect();
+
+        ToolResponse::data(
+            format!("Container {}", con_id),
+            &serde_json::json!({
+                "ancestors": ancestors,
+                "node": node,
+            }),
+        )
+    }
+
+    /// A container's children in focus order
+    #[rmcp::tool(description = "Get a container's children in focus order (its 'focus' array resolved to names and classes). The first entry is the child 'focus child' would select.")]
+    pub async fn get_focus_order(
+        &self,
+        Parameters(params): Parameters<GetContainerParams>,
+    ) -> Result<CallToolResult, McpError> {
+        info!("Getting focus order of {}", params.con_id);
+        let con_id = parse_con_id(params.con_id)?;
+        let mut conn = self.connect().await?;
+
+        let tree = self.fetch_tree(&mut conn).await?;
+        let Some((node, _)) = find_node_with_ancestors(&tree, con_id) else {
+            return Ok(ToolResponse::failure(format!(
+                "No container with con_id {}",
+                con_id
+            )));
+        };
+
+        let order = focus_order(node);
+        ToolResponse::data(
+            format!(
+                "Focus order of container {} ({} children)",
+                con_id,
+                order.len()
+            ),
+            &order,
+        )
+    }
+
+    /// List workspaces that contain no windows
+    #[rmcp::tool(description = "List workspaces that contain no windows. Optionally visit and leave each one so i3 removes it.")]
+    pub async fn list_empty_workspaces(
+        &self,
+        Parameters(params): Parameters<ListEmptyWorkspacesParams>,
+    ) -> Result<CallToolResult, McpError> {
+        info!("Listing empty workspaces");
+        let mut conn = self.connect().await?;
+
+        let workspaces = self.fetch_workspaces(&mut conn).await?;
+        let tree = self.fetch_tree(&mut conn).await?;
+        let empty = empty_workspace_names(&workspaces, &tree);
+
+        debug!("Found {} empty workspaces", empty.len());
+        if empty.is_empty() {
+            return Ok(ToolResponse::success("No empty workspaces"));
+        }
+
+        if !params.focus_then_leave.unwrap_or(false) {
+            return ToolResponse::data(format!("{} empty workspaces", empty.len()), &empty);
+        }
+
+        // i3 removes an empty workspace when focus leaves it, so visit each
+        // one in turn and finish back where we started
+        let Some(current) = workspaces.iter().find(|ws| ws.focused) else {
+            return Ok(ToolResponse::failure(
+                "No focused workspace to return to; skipping cleanup",
+            ));
+        };
+        let command = cleanup_workspaces_command(&empty, &current.name);
+
+        let results = self
+            .send_command(&mut conn, &command, "clean up workspaces")
+            .await?;
+
+        match failure_message(&results) {
+            None => Ok(ToolResponse::success(format!(
+                "Visited {} empty workspace(s) to clean them up: {}",
+                empty.len(),
+                empty.join(", ")
+            ))),
+            Some(errors) => Ok(ToolResponse::failure(format!(
+                "Failed to clean up workspaces: {}",
+                errors
+            ))),
+        }
+    }
+
+    /// Get the lowest unused workspace number
+    #[rmcp::tool(description = "Get the lowest positive workspace number not used by any current workspace, for creating a fresh numbered workspace without collisions")]
+    pub async fn get_next_free_workspace(&self) -> Result<CallToolResult, McpError> {
+        info!("Getting next free workspace number");
+        let mut conn = self.connect().await?;
+
+        let workspaces = self.fetch_workspaces(&mut conn).await?;
+        let next = next_free_workspace_number(workspaces.iter().map(|ws| ws.name.as_str()));
+
+        debug!("Next free workspace number is {}", next);
+        ToolResponse::data(format!("Next free workspace: {}", next), &next)
+    }
+
+    /// Swap the number prefixes of two workspaces
+    #[rmcp::tool(description = "Swap the number prefixes of two workspaces (e.g. '1:web' and '3:mail' become '3:web' and '1:mail'), exchanging their positions in the bar. Both must exist and be numbered.")]
+    pub async fn swap_workspace_numbers(
+        &self,
+        Parameters(params): Parameters<SwapWorkspaceNumbersParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let a = validate_workspace_name(&params.a)?;
+        let b = validate_workspace_name(&params.b)?;
+        info!("Swapping workspace numbers of '{}' and '{}'", a, b);
+        let mut conn = self.connect().await?;
+
+        let workspaces = self.fetch_workspaces(&mut conn).await?;
+        let names: Vec<&str> = workspaces.iter().map(|ws| ws.name.as_str()).collect();
+        let (command, new_a, new_b) = match swap_numbers_command(&names, a, b) {
+            Ok(swap) => swap,
+            Err(message) => return Ok(ToolResponse::failure(message)),
+        };
+
+        let results = self
+            .send_command(&mut conn, &command, "swap workspace numbers")
+            .await?;
+
+        match failure_message(&results) {
+            None => ToolResponse::data(
+                format!("Renamed '{}' to '{}' and '{}' to '{}'", a, new_a, b, new_b),
+                &serde_json::json!({ "a": new_a, "b": new_b }),
+            ),
+            Some(errors) => Ok(ToolResponse::failure(format!(
+                "Failed to swap workspace numbers: {}",
+                errors
+            ))),
+        }
+    }
+
+    /// Name the focused workspace after its most common window class
+    #[rmcp::tool(description = "Rename the focused workspace after its dominant window class (e.g. mostly Firefox -> '2:web'), keeping its number prefix. Accepts an optional class -> label mapping.")]
+    pub async fn auto_name_workspace(
+        &self,
+        Parameters(params): Parameters<AutoNameWorkspaceParams>,
+    ) -> Result<CallToolResult, McpError> {
+        info!("Auto-naming focused workspace");
+        let mut conn = self.connect().await?;
+
+        let tree = self.fetch_tree(&mut conn).await?;
+        let Some(workspace) = focused_workspace(&tree) else {
+            return Ok(ToolResponse::failure("No focused workspace found"));
+        };
+        let name = workspace.name.clone().unwrap_or_default();
+        let Some(class) = dominant_class(workspace) else {
+            return Ok(ToolResponse::failure(format!(
+                "Workspace '{}' has no windows to name it after",
+                name
+            )));
+        };
+
+        let label = class_label(&class, params.mapping.as_ref());
+        let new_name = relabel_workspace(&name, &label);
+        validate_workspace_name(&new_name)?;
+        if new_name == name {
+            return ToolResponse::data(format!("Workspace is already named '{}'", name), &name);
+        }
+
+        let command = format!(
+            "rename workspace to \"{}\"",
+            escape_criteria_value(&new_name)
+        );
+        let results = self
+            .send_command(&mut conn, &command, "rename workspace")
+            .await?;
+
+        match failure_message(&results) {
+            None => ToolResponse::data(
+                format!("Renamed workspace '{}' to '{}'", name, new_name),
+                &new_name,
+            ),
+            Some(errors) => Ok(ToolResponse::failure(format!(
+                "Failed to rename workspace: {}",
+                errors
+            ))),
+        }
+    }
+
+    /// Query or set the top-level layout of the focused workspace
+    #[rmcp::tool(description = "Get the top-level layout of the focused workspace, or set it by passing layout (applies to the workspace container, not the focused window's container)")]
+    pub async fn workspace_layout(
+        &self,
+        Parameters(params): Parameters<WorkspaceLayoutParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let layout = params.layout.as_deref().map(validate_layout).transpose()?;
+        info!("Workspace layout: {:?}", layout);
+        let mut conn = self.connect().await?;
+
+        let tree = self.fetch_tree(&mut conn).await?;
+        let Some(workspace) = focused_workspace(&tree) else {
+            return Ok(ToolResponse::failure("No focused workspace found"));
+        };
+        let name = workspace.name.clone().unwrap_or_default();
+        let current = layout_name(workspace.layout);
+
+        let Some(layout) = layout else {
+            return Ok(ToolResponse::success(format!(
+                "Workspace '{}' layout: {}",
+                name, current
+            )));
+        };
+
+        // Focus the workspace container so `layout` applies to it, then
+        // hand focus back to whatever had it before
+        let mut command = format!("[con_id={}] focus; layout {}", workspace.id, layout);
+        if let Some((focused, _)) = find_focused(&tree) {
+            if focused.id != workspace.id {
+                command.push_str(&format!("; [con_id={}] focus", focused.id));
+            }
+        }
+
+        let results = self
+            .send_command(&mut conn, &command, "set workspace layout")
+            .await?;
+
+        match failure_message(&results) {
+            None => Ok(ToolResponse::success(format!(
+                "Workspace '{}' layout: {} -> {}",
+                name, current, layout
+            ))),
+            Some(errors) => Ok(ToolResponse::failure(format!(
+                "Failed to set workspace layout: {}",
+                errors
+            ))),
+        }
+    }
+
+    /// Arrange the focused workspace's windows in a 2x2 grid
+    #[rmcp::tool(description = "Arrange the tiled windows (up to 4) on the focused workspace into a grid: 1 fills the workspace, 2 side by side, 3 as one plus a stacked pair, 4 as a 2x2 grid. Floating windows are left alone.")]
+    pub async fn quad_tile(&self) -> Result<CallToolResult, McpError> {
+        info!("Quad-tiling focused workspace");
+        let mut conn = self.connect().await?;
+
+        let tree = self.fetch_tree(&mut conn).await?;
+        let Some(workspace) = focused_workspace(&tree) else {
+            return Ok(ToolResponse::failure("No focused workspace found"));
+        };
+        let name = workspace.name.clone().unwrap_or_default();
+        let con_ids: Vec<usize> = tiled_windows(workspace).iter().map(|n| n.id).collect();
+        let command = match quad_tile_command(&name, &con_ids) {
+            Ok(command) => command,
+            Err(message) => return Ok(ToolResponse::failure(message)),
+        };
+        let focused = find_focused(&tree).map(|(node, _)| node.id);
+        let command = restore_focus_command(&command, focused);
+
+        let results = self.send_command(&mut conn, &command, "quad tile").await?;
+
+        match failure_message(&results) {
+            None => ToolResponse::data(
+                format!("Arranged {} window(s) on '{}'", con_ids.len(), name),
+                &con_ids,
+            ),
+            Some(errors) => Ok(ToolResponse::failure(format!(
+                "Failed to arrange windows: {}",
+                errors
+            ))),
+        }
+    }
+
+    /// List the layout presets in the presets directory
+    #[rmcp::tool(description = "List the named layout presets (i3 layout JSON files in $XDG_CONFIG_HOME/rmcp-i3/presets) that apply_preset can load")]
+    pub async fn list_presets(&self) -> Result<CallToolResult, McpError> {
+        info!("Listing layout presets");
+        let Some(dir) = presets_dir() else {
+            return Ok(ToolResponse::failure(
+                "Neither XDG_CONFIG_HOME nor HOME is set",
+            ));
+        };
+
+        let names = match preset_names(&dir) {
+            Ok(names) => names,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                return Err(McpError::internal_error(
+                    format!("Failed to read {}: {}", dir.display(), e),
+                    None,
+                ))
+            }
+        };
+        ToolResponse::data(
+            format!("{} preset(s) in {}", names.len(), dir.display()),
+            &serde_json::json!({ "directory": dir, "presets": names }),
+        )
+    }
+
+    /// Append a named layout preset to the focused workspace
+    #[rmcp::tool(description = "Apply a named layout preset to the focused workspace via append_layout. The preset's placeholder containers swallow matching windows as they open. See list_presets.")]
+    pub async fn apply_preset(
+        &self,
+        Parameters(params): Parameters<ApplyPresetParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let name = validate_preset_name(&params.name)?;
+        info!("Applying layout preset: {}", name);
+        let dir = presets_dir().ok_or_else(|| {
+            McpError::internal_error("Neither XDG_CONFIG_HOME nor HOME is set", None)
+        })?;
+
+        let path = dir.join(format!("{}.json", name));
+        let contents = std::fs::read_to_string(&path).map_err(|e| {
+            McpError::invalid_params(
+                format!("Cannot read preset '{}' ({}): {}", name, path.display(), e),
+                None,
+            )
+        })?;
+        let containers = check_layout_json(&contents).map_err(|e| {
+            McpError::invalid_params(
+                format!("Preset '{}' is not a valid layout: {}", name, e),
+                None,
+            )
+        })?;
+        let mut conn = self.connect().await?;
+
+        let command = format!(
+            "append_layout \"{}\"",
+            escape_criteria_value(&path.to_string_lossy())
+        );
+        let results = self
+            .send_command(&mut conn, &command, "apply preset")
+            .await?;
+
+        match failure_message(&results) {
+            None => ToolResponse::data(
+                format!("Applied preset '{}' to the focused workspace", name),
+                &serde_json::json!({ "preset": name, "path": path, "containers": containers }),
+            ),
+            Some(errors) => Ok(ToolResponse::failure(format!(
+                "Failed to apply preset '{}': {}",
+                name, errors
+            ))),
+        }
+    }
+
+    /// Switch the focused container to tabbed, or back to its previous layout
+    #[rmcp::tool(description = "Toggle the focused window's container between tabbed and the layout it had before (call once to peek as tabs, again to go back)")]
+    pub async fn toggle_tabbed(&self) -> Result<CallToolResult, McpError> {
+        info!("Toggling tabbed layout");
+        let mut conn = self.connect().await?;
+
+        let tree = self.fetch_tree(&mut conn).await?;
+        let Some((focused, ancestors)) = find_focused(&tree) else {
+            return Ok(ToolResponse::failure("No focused window"));
+        };
+        let parent = match ancestors.last() {
+            Some(parent) if focused.window.is_some() => parent,
+            _ => {
+                return Ok(ToolResponse::failure("Focused container is not a window"));
+            }
+        };
+        let current = layout_name(parent.layout);
+
+        let remembered = {
+            let mut layouts = self
+                .tabbed_layouts
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            // Forget containers that have since been closed
+            let live = tree_con_ids(&tree);
+            layouts.retain(|con_id, _| live.contains(con_id));
+            layouts.remove(&parent.id)
+        };
+
+        // Restore only if the container is still tabbed; if the layout was
+        // changed by hand since, start a fresh toggle instead
+        let (target, restoring) = match remembered {
+            Some(previous) if current == "tabbed" => (previous, true),
+            _ if current == "tabbed" => {
+                return Ok(ToolResponse::failure(
+                    "Container is already tabbed and has no remembered layout",
+                ));
+            }
+            _ => ("tabbed".to_string(), false),
+        };
+
+        let command = format!("[con_id={}] layout {}", focused.id, target);
+        let results = self
+            .send_command(&mut conn, &command, "toggle tabbed")
+            .await?;
+
+        if let Some(errors) = failure_message(&results) {
+            return Ok(ToolResponse::failure(format!(
+                "Failed to toggle tabbed: {}",
+                errors
+            )));
+        }
+
+        if restoring {
+            Ok(ToolResponse::success(format!("Restored layout {}", target)))
+        } else {
+            self.tabbed_layouts
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(parent.id, current.clone());
+            Ok(ToolResponse::success(format!(
+                "Switched {} -> tabbed; call again to restore",
+                current
+            )))
+        }
+    }
+
+    /// Switch to a specific workspace
+    #[rmcp::tool(description = "Switch to a specific workspace by number or name")]
+    pub async fn switch_workspace(
+        &self,
+        Parameters(params): Parameters<SwitchWorkspaceParams>,
+    ) -> Result<CallToolResult, McpError> {
+        info!("Switching to workspace: {}", params.workspace);
+        let mut conn = self.connect().await?;
+
+        // "3" should land on an existing "3:web" rather than create "3"
+        let workspaces = self.fetch_workspaces(&mut conn).await?;
+        let name = params.workspace.trim();
+        let target = find_workspace(&workspaces, name)?.map_or(name, |ws| ws.name.as_str());
+        if params.skip_if_current.unwrap_or(false) && is_current_workspace(&workspaces, target) {
+            return Ok(ToolResponse::success(format!(
+                "Already on workspace '{}'",
+                target
+            )));
+        }
+
+        let command = format!("workspace \"{}\"", escape_criteria_value(target));
+        let results = self
+            .send_command(&mut conn, &command, "switch workspace")
+            .await?;
+
+        // Check if command succeeded
+        let success = results.iter().all(|r| r.success);
+        if success {
+            Ok(ToolResponse::success(format!(
+                "Switched to workspace '{}'",
+                target
+            )))
+        } else {
+            let errors: Vec<String> = results.iter().filter_map(|r| r.error.clone()).collect();
+            Ok(ToolResponse::failure(format!(
+                "Failed to switch workspace: {}",
+                errors.join(", ")
+            )))
+        }
+    }
+
+    /// Bounce between two given workspaces
+    #[rmcp::tool(description = "Toggle between two workspaces: switch to a if currently on b, otherwise to b. Unlike back_and_forth this ignores focus history. Returns the workspace switched to.")]
+    pub async fn toggle_workspaces(
+        &self,
+        Parameters(params): Parameters<ToggleWorkspacesParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let a = validate_workspace_name(&params.a)?;
+        let b = validate_workspace_name(&params.b)?;
+        if a == b {
+            return Err(McpError::invalid_params(
+                format!("Workspaces a and b are both '{}'", a),
+                None,
+            ));
+        }
+        info!("Toggling between workspaces {} and {}", a, b);
+        let mut conn = self.connect().await?;
+
+        let workspaces = self.fetch_workspaces(&mut conn).await?;
+        let target = toggle_target(&workspaces, a, b)?;
+        let command = format!("workspace \"{}\"", escape_criteria_value(&target));
+        let results = self
+            .send_command(&mut conn, &command, "switch workspace")
+            .await?;
+
+        match failure_message(&results) {
+            None => ToolResponse::data(
+                format!("Switched to workspace '{}'", target),
+                &serde_json::json!({ "workspace": target }),
+            ),
+            Some(errors) => Ok(ToolResponse::failure(format!(
+                "Failed to switch workspace: {}",
+                errors
+            ))),
+        }
+    }
+
+    /// Switch to the urgent workspace
+    #[rmcp::tool(description = "Switch to the workspace i3 marks urgent (the lowest-numbered one if several are). Returns the chosen workspace, or says nothing is urgent without switching.")]
+    pub async fn focus_urgent_workspace(&self) -> Result<CallToolResult, McpError> {
+        info!("Focusing urgent workspace");
+        let mut conn = self.connect().await?;
+
+        let workspaces = self.fetch_workspaces(&mut conn).await?;
+        let Some(workspace) = most_urgent_workspace(&workspaces) else {
+            return Ok(ToolResponse::success("No workspace is urgent"));
+        };
+        let name = workspace.name.clone();
+
+        let command = format!("workspace \"{}\"", escape_criteria_value(&name));
+        let results = self
+            .send_command(&mut conn, &command, "focus urgent workspace")
+            .await?;
+
+        match failure_message(&results) {
+            None => ToolResponse::data(format!("Switched to urgent workspace '{}'", name), &name),
+            Some(errors) => Ok(ToolResponse::failure(format!(
+                "Failed to switch workspace: {}",
+                errors
+            ))),
+        }
+    }
+
+    /// Every window with the urgency hint set
+    #[rmcp::tool(description = "List all urgent windows (con_id, class, title, workspace, output, ...), e.g. to see everything that is blinking for attention")]
+    pub async fn list_urgent_windows(&self) -> Result<CallToolResult, McpError> {
+        info!("Listing urgent windows");
+        let mut conn = self.connect().await?;
+
+        let tree = self.fetch_tree(&mut conn).await?;
+        let urgent: Vec<WindowInfo> = collect_windows(&tree)
+            .into_iter()
+            .filter(|w| w.urgent)
+            .collect();
+
+        ToolResponse::data(format!("{} urgent windows", urgent.len()), &urgent)
+    }
+
+    /// Clear every urgency hint by visiting each urgent window
+    #[rmcp::tool(description = "Clear the urgency hint on every urgent window by focusing each in turn, then return focus to where it was. Returns the con_ids visited.")]
+    pub async fn clear_all_urgency(&self) -> Result<CallToolResult, McpError> {
+        info!("Clearing all urgency hints");
+        let mut conn = self.connect().await?;
+
+        let tree = self.fetch_tree(&mut conn).await?;
+        let urgent: Vec<usize> = collect_windows(&tree)
+            .iter()
+            .filter(|w| w.urgent)
+            .map(|w| w.con_id)
+            .collect();
+        if urgent.is_empty() {
+            return Ok(ToolResponse::success("No windows are urgent"));
+        }
+        let focused = find_focused(&tree)
+            .map(|(node, _)| node)
+            .filter(|node| node.node_type != NodeType::Workspace)
+            .map(|node| node.id);
+        let workspace = focused_workspace(&tree)
+            .and_then(|ws| ws.name.clone())
+            .unwrap_or_default();
+
+        let command = clear_urgency_command(&urgent, focused, &workspace);
+        let results = self
+            .send_command(&mut conn, &command, "clear urgency")
+            .await?;
+
+        match failure_message(&results) {
+            None => ToolResponse::data(
+                format!("Cleared urgency on {} window(s)", urgent.len()),
+                &urgent,
+            ),
+            Some(errors) => Ok(ToolResponse::failure(format!(
+                "Failed to clear urgency: {}",
+                errors
+            ))),
+        }
+    }
+
+    /// Focus a workspace, making sure it is shown on a specific output
+    #[rmcp::tool(description = "Focus a workspace on a specific output, moving the workspace to that output first if it lives elsewhere")]
+    pub async fn focus_workspace_on_output(
+        &self,
+        Parameters(params): Parameters<FocusWorkspaceOnOutputParams>,
+    ) -> Result<CallToolResult, McpError> {
+        info!(
+            "Focusing workspace {} on output {}",
+            params.workspace, params.output
+        );
+        let mut conn = self.connect().await?;
+
+        let outputs = self.fetch_outputs(&mut conn).await?;
+        let active = active_output_names(&outputs);
+        if !active.contains(&params.output.as_str()) {
+            return Err(McpError::invalid_params(
+                format!(
+                    "Unknown or inactive output '{}'. Active outputs: {}",
+                    params.output,
+                    active.join(", ")
+                ),
+                None,
+            ));
+        }
+
+        let workspaces = self.fetch_workspaces(&mut conn).await?;
+        let (command, moved) =
+            focus_on_output_command(&workspaces, &params.workspace, &params.output)?;
+
+        let results = self
+            .send_command(&mut conn, &command, "focus workspace")
+            .await?;
+
+        if let Some(errors) = failure_message(&results) {
+            return Ok(ToolResponse::failure(format!(
+                "Failed to focus workspace: {}",
+                errors
+            )));
+        }
+
+        let note = if moved {
+            "moved it from another output"
+        } else {
+            "no move needed"
+        };
+        Ok(ToolResponse::success(format!(
+            "Focused workspace '{}' on output '{}' ({})",
+            params.workspace, params.output, note
+        )))
+    }
+
+    /// Bring a workspace to the output that currently has focus
+    #[rmcp::tool(description = "Bring a workspace to the monitor you're looking at: focus it and move it to the currently focused output if it lives elsewhere")]
+    pub async fn pull_workspace_here(
+        &self,
+        Parameters(params): Parameters<PullWorkspaceHereParams>,
+    ) -> Result<CallToolResult, McpError> {
+        info!("Pulling workspace {} to focused output", params.workspace);
+        let mut conn = self.connect().await?;
+
+        let workspaces = self.fetch_workspaces(&mut conn).await?;
+        let Some(current) = workspaces.iter().find(|ws| ws.focused) else {
+            return Ok(ToolResponse::failure("No focused workspace found"));
+        };
+        let output = current.output.clone();
+        let (command, moved) = focus_on_output_command(&workspaces, &params.workspace, &output)?;
+
+        let results = self
+            .send_command(&mut conn, &command, "pull workspace")
+            .await?;
+        if let Some(errors) = failure_message(&results) {
+            return Ok(ToolResponse::failure(format!(
+                "Failed to pull workspace: {}",
+                errors
+            )));
+        }
+
+        // Report where the workspace actually ended up
+        let workspaces = self.fetch_workspaces(&mut conn).await?;
+        let landed = find_workspace(&workspaces, &params.workspace)
+            .ok()
+            .flatten()
+            .map(|ws| ws.output.clone())
+            .unwrap_or(output);
+        let note = if moved {
+            "moved from another output"
+        } else {
+            "no move needed"
+        };
+        ToolResponse::data(
+            format!(
+                "Workspace '{}' is on output '{}' ({})",
+                params.workspace, landed, note
+            ),
+            &serde_json::json!({ "workspace": params.workspace, "output": landed, "moved": moved }),
+        )
+    }
+
+    /// Move a workspace to an output and keep it there across restarts
+    #[rmcp::tool(description = "Pin a workspace to an output persistently: writes (or replaces) a 'workspace <name> output <output>' line in the loaded i3 config file, moves the workspace there now if it exists, and reloads i3. Edits your config file.")]
+    pub async fn pin_workspace_to_output(
+        &self,
+        Parameters(params): Parameters<PinWorkspaceToOutputParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let workspace = validate_workspace_name(&params.workspace)?;
+        info!("Pinning workspace {} to {}", workspace, params.output);
+        let mut conn = self.connect().await?;
+
+        let outputs = self.fetch_outputs(&mut conn).await?;
+        let (output, _) = resolve_output(&outputs, &params.output, false)?;
+        let output = output.to_string();
+
+        let version = self.fetch_version(&mut conn).await?;
+        let path = version.loaded_config_file_name;
+        if path.is_empty() {
+            return Ok(ToolResponse::failure(
+                "i3 did not report a loaded config file",
+            ));
+        }
+        let config = std::fs::read_to_string(&path).map_err(|e| {
+            McpError::internal_error(format!("Failed to read {}: {}", path, e), None)
+        })?;
+        let (contents, line, replaced) = pin_workspace_in_config(&config, workspace, &output);
+        let backup = if contents != config {
+            let backup = write_config_atomically(&path, &contents).map_err(|e| {
+                McpError::internal_error(format!("Failed to write {}: {}", path, e), None)
+            })?;
+            Some(backup)
+        } else {
+            None
+        };
+
+        // Only move a workspace that exists; the assignment covers creating it
+        let workspaces = self.fetch_workspaces(&mut conn).await?;
+        let mut command = String::new();
+        let mut moved = false;
+        if find_workspace(&workspaces, workspace)?.is_some() {
+            let (focus, needs_move) = focus_on_output_command(&workspaces, workspace, &output)?;
+            if needs_move {
+                command = format!("{}; ", focus);
+                moved = true;
+            }
+        }
+        command.push_str("reload");
+        let results = self
+            .send_command(&mut conn, &command, "pin workspace")
+            .await?;
+
+        let summary = serde_json::json!({
+            "config": path,
+            "backup": backup,
+            "line": line,
+            "replaced": replaced,
+            "moved": moved,
+        });
+        match failure_message(&results) {
+            None => ToolResponse::data(
+                format!("Pinned workspace '{}' to {} in {}", workspace, output, path),
+                &summary,
+            ),
+            Some(errors) => ToolResponse::failure_data(
+                format!("Config updated, but i3 reported: {}", errors),
+                &summary,
+            ),
+        }
+    }
+
+    /// Focus a window by i3 criteria
+    #[rmcp::tool(description = "Focus a window matching i3 criteria (e.g. [class=\"Firefox\"], [title=\"vim\"])")]
+    pub async fn focus_window(
+        &self,
+        Parameters(params): Parameters<FocusWindowParams>,
+    ) -> Result<CallToolResult, McpError> {
+        info!("Focusing window: {}", params.criteria);
+        let mut conn = self.connect().await?;
+
+        if let Some(unmatched) = self
+            .check_criteria_match(&mut conn, &params.criteria)
+            .await?
+        {
+            return Ok(unmatched);
+        }
+
+        let command = format!("{} focus", bracketed_criteria(&params.criteria));
+        let results = self
+            .send_command(&mut conn, &command, "focus window")
+            .await?;
+
+        let success = results.iter().all(|r| r.success);
+        if success {
+            Ok(ToolResponse::success(format!(
+                "Focused window matching '{}'",
+                params.criteria
+            )))
+        } else {
+            let errors: Vec<String> = results.iter().filter_map(|r| r.error.clone()).collect();
+            Ok(ToolResponse::failure(format!(
+                "Failed to focus window: {}",
+                errors.join(", ")
+            )))
+        }
+    }
+
+    /// Focus a window by its X11 window id
+    #[rmcp::tool(description = "Focus a window by its X11 window id ([id=N] focus), e.g. one obtained from xdotool. Not a con_id. Reports an error result if no window has that id.")]
+    pub async fn focus_by_window_id(
+        &self,
+        Parameters(params): Parameters<FocusByWindowIdParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let command = window_id_focus_command(params.window_id)?;
+        info!("Focusing X11 window {}", params.window_id);
+        let mut conn = self.connect().await?;
+
+        // i3 reports success even when no window has the id, so check first
+        let tree = self.fetch_tree(&mut conn).await?;
+        let mut matched = None;
+        walk_tree(&tree, &mut Vec::new(), &mut |node, ancestors| {
+            if matched.is_none() && node.window == usize::try_from(params.window_id).ok() {
+                matched = Some(WindowInfo::from_node(node, ancestors));
+            }
+        });
+        let Some(window) = matched else {
+            return Ok(ToolResponse::error(format!(
+                "No window has X11 id {}",
+                params.window_id
+            )));
+        };
+
+        let results = self
+            .send_command(&mut conn, &command, "focus window")
+            .await?;
+        match failure_message(&results) {
+            None => ToolResponse::data(
+                format!(
+                    "Focused X11 window {} (con_id {})",
+                    params.window_id, window.con_id
+                ),
+                &window,
+            ),
+            Some(errors) => Ok(ToolResponse::failure(format!(
+                "Failed to focus window: {}",
+                errors
+            ))),
+        }
+    }
+
+    /// Switch to the workspace holding a window, then focus it
+    #[rmcp::tool(description = "Jump to a window matching i3 criteria: switch to its workspace and focus it in one call, or show it if it is in the scratchpad. Returns the workspace it landed on.")]
+    pub async fn goto_window(
+        &self,
+        Parameters(params): Parameters<FocusWindowParams>,
+    ) -> Result<CallToolResult, McpError> {
+        info!("Going to window: {}", params.criteria);
+        let mut conn = self.connect().await?;
+
+        // Patterns the server can't evaluate are left to i3's own focus,
+        // which also switches workspaces
+        let parsed = parse_criteria(&params.criteria)?;
+        let (command, workspace) = if criteria_checkable(&parsed) {
+            let tree = self.fetch_tree(&mut conn).await?;
+            match goto_command(&tree, &parsed) {
+                Some(target) => target,
+                None => {
+                    return Ok(ToolResponse::error(format!(
+                        "No window matched {}",
+                        params.criteria.trim()
+                    )))
+                }
+            }
+        } else {
+            (
+                format!("{} focus", bracketed_criteria(&params.criteria)),
+                None,
+            )
+        };
+
+        let results = self
+            .send_command(&mut conn, &command, "go to window")
+            .await?;
+
+        match failure_message(&results) {
+            None => ToolResponse::data(
+                match &workspace {
+                    Some(name) => format!("Focused window on workspace '{}'", name),
+                    None => format!("Focused window matching '{}'", params.criteria),
+                },
+                &serde_json::json!({ "command": command, "workspace": workspace }),
+            ),
+            Some(errors) => Ok(ToolResponse::failure(format!(
+                "Failed to go to window: {}",
+                errors
+            ))),
+        }
+    }
+
+    /// Move the focused window to a workspace
+    #[rmcp::tool(description = "Move the currently focused window to a specific workspace")]
+    pub async fn move_to_workspace(
+        &self,
+        Parameters(params): Parameters<MoveToWorkspaceParams>,
+    ) -> Result<CallToolResult, McpError> {
+        info!("Moving window to workspace: {}", params.workspace);
+        let mut conn = self.connect().await?;
+
+        let workspaces = self.fetch_workspaces(&mut conn).await?;
+        let name = params.workspace.trim();
+        let target = find_workspace(&workspaces, name)?.map_or(name, |ws| ws.name.as_str());
+        let command = format!(
+            "move container to workspace \"{}\"",
+            escape_criteria_value(target)
+        );
+        let results = self
+            .send_command(&mut conn, &command, "move window")
+            .await?;
+
+        let success = results.iter().all(|r| r.success);
+        if success {
+            Ok(ToolResponse::success(format!(
+                "Moved window to workspace '{}'",
+                target
+            )))
+        } else {
+            let errors: Vec<String> = results.iter().filter_map(|r| r.error.clone()).collect();
+            Ok(ToolResponse::failure(format!(
+                "Failed to move window: {}",
+                errors.join(", ")
+            )))
+        }
+    }
+
+    /// Move the focused window to the workspace of another container
+    #[rmcp::tool(description = "Move the focused window to the workspace containing the given con_id (put this window next to that one)")]
+    pub async fn move_to_container(
+        &self,
+        Parameters(params): Parameters<MoveToContainerParams>,
+    ) -> Result<CallToolResult, McpError> {
+        info!("Moving window to workspace of con_id {}", params.con_id);
+        let con_id = parse_con_id(params.con_id)?;
+        let mut conn = self.connect().await?;
+
+        let tree = self.fetch_tree(&mut conn).await?;
+        let workspace = workspace_of(&tree, con_id)
+            .and_then(|ws| ws.name.clone())
+            .ok_or_else(|| {
+                McpError::invalid_params(
+                    format!("No container with con_id {} on any workspace", con_id),
+                    None,
+                )
+            })?;
+
+        let command = format!(
+            "move container to workspace \"{}\"",
+            escape_criteria_value(&workspace)
+        );
+        let results = self
+            .send_command(&mut conn, &command, "move window")
+            .await?;
+
+        match failure_message(&results) {
+            None => Ok(ToolResponse::success(format!(
+                "Moved window to workspace '{}' (where con_id {} is)",
+                workspace, con_id
+            ))),
+            Some(errors) => Ok(ToolResponse::failure(format!(
+                "Failed to move window: {}",
+                errors
+            ))),
+        }
+    }
+
+    /// Move the focused window into the container carrying a mark
+    #[rmcp::tool(description = "Move the focused window into the container carrying a mark (move window to mark), next to or inside it, for placing windows precisely when building a layout. The mark must exist (see get_marks).")]
+    pub async fn move_into_marked(
+        &self,
+        Parameters(params): Parameters<MoveIntoMarkedParams>,
+    ) -> Result<CallToolResult, McpError> {
+        info!("Moving window to mark {}", params.mark);
+        let mut conn = self.connect().await?;
+
+        let tree = self.fetch_tree(&mut conn).await?;
+        let window = find_focused(&tree)
+            .map(|(node, _)| node)
+            .filter(|node| node.window.is_some());
+        let Some(window) = window else {
+            return Ok(ToolResponse::failure("No focused window to move"));
+        };
+
+        let marks = self.fetch_marks(&mut conn).await?;
+        let command = move_to_mark_command(&marks.0, window, &params.mark)?;
+        let results = self
+            .send_command(&mut conn, &command, "move window")
+            .await?;
+
+        match failure_message(&results) {
+            None => Ok(ToolResponse::success(format!(
+                "Moved window {} to mark '{}'",
+                window.id,
+                params.mark.trim()
+            ))),
+            Some(errors) => Ok(ToolResponse::failure(format!(
+                "Failed to move window: {}",
+                errors
+            ))),
+        }
+    }
+
+    /// Move the focused window to a workspace, optionally on a given output
+    #[rmcp::tool(description = "Send the focused window to a workspace. With output, the workspace is first moved to (or created on) that output and focus follows the window, e.g. 'workspace 3 on my left monitor'.")]
+    pub async fn send_window(
+        &self,
+        Parameters(params): Parameters<SendWindowParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let workspace = validate_workspace_name(&params.workspace)?;
+        info!(
+            "Sending window to workspace {} on {:?}",
+            workspace, params.output
+        );
+        let mut conn = self.connect().await?;
+
+        let output = match &params.output {
+            Some(requested) => {
+                let outputs = self.fetch_outputs(&mut conn).await?;
+                Some(resolve_output(&outputs, requested, false)?.0.to_string())
+            }
+            None => None,
+        };
+
+        let tree = self.fetch_tree(&mut conn).await?;
+        let window = find_focused(&tree)
+            .map(|(node, _)| node)
+            .filter(|node| node.window.is_some());
+        let Some(window) = window else {
+            return Ok(ToolResponse::failure("No focused window to send"));
+        };
+
+        let workspaces = self.fetch_workspaces(&mut conn).await?;
+        let (command, moved) =
+            send_window_command(&workspaces, window.id, workspace, output.as_deref())?;
+        let results = self
+            .send_command(&mut conn, &command, "send window")
+            .await?;
+
+        let destination = match &output {
+            Some(output) => format!("'{}' on {}", workspace, output),
+            None => format!("'{}'", workspace),
+        };
+        match failure_message(&results) {
+            None => ToolResponse::data(
+                format!("Sent window to workspace {}", destination),
+                &serde_json::json!({
+                    "con_id": window.id,
+                    "workspace": workspace,
+                    "output": output,
+                    "moved_workspace": moved,
+                }),
+            ),
+            Some(errors) => Ok(ToolResponse::failure(format!(
+                "Failed to send window: {}",
+                errors
+            ))),
+        }
+    }
+
+    /// Move the focused window to a workspace and split it there
+    #[rmcp::tool(description = "Move the focused window to a workspace, follow it, and set its split orientation there (horizontal, vertical, or toggle), so the next window opens where you want it. For building layouts step by step.")]
+    pub async fn move_and_split(
+        &self,
+        Parameters(params): Parameters<MoveAndSplitParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let workspace = validate_workspace_name(&params.workspace)?;
+        info!(
+            "Moving window to workspace {} and splitting {}",
+            workspace, params.orientation
+        );
+        let mut conn = self.connect().await?;
+
+        let tree = self.fetch_tree(&mut conn).await?;
+        let window = find_focused(&tree)
+            .map(|(node, _)| node)
+            .filter(|node| node.window.is_some());
+        let Some(window) = window else {
+            return Ok(ToolResponse::failure("No focused window to move"));
+        };
+
+        let workspaces = self.fetch_workspaces(&mut conn).await?;
+        let command =
+            move_and_split_command(&workspaces, window.id, workspace, &params.orientation)?;
+        let results = self
+            .send_command(&mut conn, &command, "move and split")
+            .await?;
+
+        let orientation = params.orientation.trim();
+        match failure_message(&results) {
+            None => ToolResponse::data(
+                format!(
+                    "Moved window to workspace '{}' and split {}",
+                    workspace, orientation
+                ),
+                &serde_json::json!({
+                    "con_id": window.id,
+                    "workspace": workspace,
+                    "orientation": orientation,
+                    "command": command,
+                }),
+            ),
+            Some(errors) => Ok(ToolResponse::failure(format!(
+                "Failed to move and split: {}",
+                errors
+            ))),
+        }
+    }
+
+    /// Move the focused window to the next or previous workspace
+    #[rmcp::tool(description = "Move the focused window to the next or previous workspace, like the common mod+shift+arrow keybinds. Pass follow: true to switch there too. Returns the workspace the window landed on.")]
+    pub async fn move_to_relative_workspace(
+        &self,
+        Parameters(params): Parameters<MoveToRelativeWorkspaceParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let direction = validate_relative_workspace(&params.direction)?;
+        let follow = params.follow.unwrap_or(false);
+        info!(
+            "Moving window to {} workspace (follow: {})",
+            direction, follow
+        );
+        let mut conn = self.connect().await?;
+
+        let tree = self.fetch_tree(&mut conn).await?;
+        let window = find_focused(&tree)
+            .map(|(node, _)| node)
+            .filter(|node| node.window.is_some());
+        let Some(window) = window else {
+            return Ok(ToolResponse::failure("No focused window to move"));
+        };
+        let con_id = window.id;
+
+        let command = relative_move_command(con_id, direction, follow);
+        let results = self
+            .send_command(&mut conn, &command, "move window")
+            .await?;
+        if let Some(errors) = failure_message(&results) {
+            return Ok(ToolResponse::failure(format!(
+                "Failed to move window: {}",
+                errors
+            )));
+        }
+
+        let tree = self.fetch_tree(&mut conn).await?;
+        let workspace = workspace_of(&tree, con_id).and_then(|ws| ws.name.clone());
+        let summary = match &workspace {
+            Some(name) => format!("Moved window to workspace '{}'", name),
+            None => format!("Moved window to the {} workspace", direction),
+        };
+        ToolResponse::data(
+            summary,
+            &serde_json::json!({
+                "con_id": con_id,
+                "direction": direction,
+                "workspace": workspace,
+                "followed": follow,
+            }),
+        )
+    }
+
+    /// Move every window from one workspace to another
+    #[rmcp::tool(description = "Move all windows from one workspace to another in a single command (e.g. to consolidate before unplugging a monitor)")]
+    pub async fn evacuate_workspace(
+        &self,
+        Parameters(params): Parameters<EvacuateWorkspaceParams>,
+    ) -> Result<CallToolResult, McpError> {
+        info!("Evacuating workspace {} to {}", params.from, params.to);
+        let to = validate_workspace_name(&params.to)?;
+        let mut conn = self.connect().await?;
+
+        let workspaces = self.fetch_workspaces(&mut conn).await?;
+        let from = match_workspace(validate_workspace_name(&params.from)?, &workspaces)?
+            .name
+            .clone();
+        if from == to || find_workspace(&workspaces, to)?.is_some_and(|ws| ws.name == from) {
+            return Err(McpError::invalid_params(
+                format!("Source and target are both workspace '{}'", from),
+                None,
+            ));
+        }
+
+        let tree = self.fetch_tree(&mut conn).await?;
+        let con_ids = workspace_window_ids(&tree, &from).unwrap_or_default();
+        if con_ids.is_empty() {
+            return Ok(ToolResponse::success(format!(
+                "Workspace '{}' has no windows to move",
+                from
+            )));
+        }
+
+        let command = evacuate_command(&con_ids, to);
+        let results = self
+            .send_command(&mut conn, &command, "move windows")
+            .await?;
+        let moved = results.iter().filter(|r| r.success).count();
+
+        let summary = serde_json::json!({ "from": from, "to": to, "moved": moved });
+        match failure_message(&results) {
+            None => ToolResponse::data(
+                format!("Moved {} windows from '{}' to '{}'", moved, from, to),
+                &summary,
+            ),
+            Some(errors) => ToolResponse::failure_data(
+                format!(
+                    "Moved {} of {} windows from '{}' to '{}': {}",
+                    moved,
+                    con_ids.len(),
+                    from,
+                    to,
+                    errors
+                ),
+                &summary,
+            ),
+        }
+    }
+
+    /// Run an arbitrary i3 command
+    #[rmcp::tool(description = "Execute any i3 command (escape hatch for advanced operations). See i3 user guide for command list. Not rate limited: 'exec ...' sent here bypasses the exec tool's launch limit.")]
+    pub async fn run_command(
+        &self,
+        Parameters(params): Parameters<RunCommandParams>,
+    ) -> Result<CallToolResult, McpError> {
+        info!("Running i3 command: {}", params.command);
+        let mut conn = self.connect().await?;
+
+        let results = self
+            .send_command(&mut conn, &params.command, "run command")
+            .await?;
+
+        let aligned = align_results(&params.command, &results);
+        command_results_response(&aligned)
+    }
+
+    /// Run several i3 commands in a single IPC call
+    #[rmcp::tool(description = "Execute several i3 commands in one IPC call. Returns one {command, success, error} entry per command so you can see exactly which one failed.")]
+    pub async fn run_commands(
+        &self,
+        Parameters(params): Parameters<RunCommandsParams>,
+    ) -> Result<CallToolResult, McpError> {
+        if params.commands.iter().all(|c| c.trim().is_empty()) {
+            return Err(McpError::invalid_params("commands must not be empty", None));
+        }
+        let mut command = join_commands(&params.commands);
+        info!("Running i3 commands: {}", command);
+        let mut conn = self.connect().await?;
+
+        if params.restore_focus.unwrap_or(false) {
+            let tree = self.fetch_tree(&mut conn).await?;
+            let focused = find_focused(&tree).map(|(node, _)| node.id);
+            command = restore_focus_command(&command, focused);
+        }
+
+        let results = self
+            .send_command(&mut conn, &command, "run commands")
+            .await?;
+
+        let aligned = align_results(&command, &results);
+        command_results_response(&aligned)
+    }
+
+    /// Apply several commands to the windows matching one criteria
+    #[rmcp::tool(description = "Apply several commands to the windows matching criteria in one IPC call, e.g. criteria '[class=\"mpv\"]' with ['floating enable', 'move position center']. The criteria are matched once for the whole chain.")]
+    pub async fn with_window(
+        &self,
+        Parameters(params): Parameters<WithWindowParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let command = chain_commands(&params.criteria, &params.commands)?;
+        info!("Running on {}: {}", params.criteria, command);
+        let mut conn = self.connect().await?;
+
+        if let Some(unmatched) = self
+            .check_criteria_match(&mut conn, &params.criteria)
+            .await?
+        {
+            return Ok(unmatched);
+        }
+
+        let results = self
+            .send_command(&mut conn, &command, "run commands")
+            .await?;
+
+        let aligned = align_results(&command, &results);
+        command_results_response(&aligned)
+    }
+
+    /// Run one command on the windows matching criteria, given as separate fields
+    #[rmcp::tool(description = "Run an i3 command on the windows matching criteria, e.g. criteria '[class=\"mpv\"]' with action 'floating enable'. The criteria and action are validated separately before being joined, so the combined command can't come out malformed. Without criteria the action applies to the focused window. exit and restart are refused.")]
+    pub async fn run_command_on(
+        &self,
+        Parameters(params): Parameters<RunCommandOnParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let command = command_on(params.criteria.as_deref(), &params.action)?;
+        info!("Running i3 command: {}", command);
+        let mut conn = self.connect().await?;
+
+        if let Some(criteria) = params.criteria.as_deref().filter(|c| !c.trim().is_empty()) {
+            if let Some(unmatched) = self.check_criteria_match(&mut conn, criteria).await? {
+                return Ok(unmatched);
+            }
+        }
+
+        let results = self
+            .send_command(&mut conn, &command, "run command")
+            .await?;
+
+        let aligned = align_results(&command, &results);
+        command_results_response(&aligned)
+    }
+
+    /// Launch an application
+    #[rmcp::tool(description = "Launch an application (e.g. 'firefox', 'kitty', 'emacs')")]
+    pub async fn exec(
+        &self,
+        Parameters(params): Parameters<ExecParams>,
+    ) -> Result<CallToolResult, McpError> {
+        info!("Executing: {}", params.command);
+        let command = exec_command(&params.command, params.cwd.as_deref())?;
+
+        if let Some(rejected) = self.check_exec_rate(&params.command) {
+            return Ok(rejected);
+        }
+        let mut conn = self.connect().await?;
+
+        let results = self.send_command(&mut conn, &command, "exec").await?;
+
+        let success = results.iter().all(|r| r.success);
+        if success {
+            Ok(ToolResponse::success(format!(
+                "Launched '{}'",
+                params.command
+            )))
+        } else {
+            let errors: Vec<String> = results.iter().filter_map(|r| r.error.clone()).collect();
+            Ok(ToolResponse::failure(format!(
+                "Failed to launch: {}",
+                errors.join(", ")
+            )))
+        }
+    }
+
+    /// Launch an application from its XDG desktop entry
+    #[rmcp::tool(description = "Launch an application by its desktop entry id (e.g. 'firefox', 'org.gnome.Nautilus'), using the Exec line of its .desktop file")]
+    pub async fn launch_app(
+        &self,
+        Parameters(params): Parameters<LaunchAppParams>,
+    ) -> Result<CallToolResult, McpError> {
+        info!("Launching app: {}", params.app_id);
+        let path = find_desktop_entry(&params.app_id, &xdg_data_dirs())?;
+        let contents = std::fs::read_to_string(&path).map_err(|e| {
+            McpError::internal_error(format!("Failed to read {}: {}", path.display(), e), None)
+        })?;
+        let exec = desktop_entry_exec(&contents)
+            .map(strip_field_codes)
+            .filter(|exec| !exec.is_empty())
+            .ok_or_else(|| {
+                McpError::invalid_params(format!("{} has no Exec line", path.display()), None)
+            })?;
+        debug!("Resolved {} to '{}'", path.display(), exec);
+
+        if let Some(rejected) = self.check_exec_rate(&params.app_id) {
+            return Ok(rejected);
+        }
+        let mut conn = self.connect().await?;
+
+        let command = format!("exec --no-startup-id {}", exec);
+        let results = self.send_command(&mut conn, &command, "exec").await?;
+
+        match failure_message(&results) {
+            None => Ok(ToolResponse::success(format!(
+                "Launched '{}' ({})",
+                params.app_id, exec
+            ))),
+            Some(errors) => Ok(ToolResponse::failure(format!(
+                "Failed to launch: {}",
+                errors
+            ))),
+        }
+    }
+
+    /// Kill (close) the focused window
+    #[rmcp::tool(description = "Kill (close) the currently focused window")]
+    pub async fn kill(&self) -> Result<CallToolResult, McpError> {
+        info!("Killing focused window");
+        let mut conn = self.connect().await?;
+
+        let results = self
+            .send_command(&mut conn, "kill", "kill window")
+            .await?;
+
+        let success = results.iter().all(|r| r.success);
+        if success {
+            Ok(ToolResponse::success("Killed focused window"))
+        } else {
+            let errors: Vec<String> = results.iter().filter_map(|r| r.error.clone()).collect();
+            Ok(ToolResponse::failure(format!(
+                "Failed to kill window: {}",
+                errors.join(", ")
+            )))
+        }
+    }
+
+    /// Kill (close) a window matching criteria
+    #[rmcp::tool(description = "Kill (close) a window matching i3 criteria (e.g. [class=\"Firefox\"], [title=\"vim\"])")]
+    pub async fn kill_window(
+        &self,
+        Parameters(params): Parameters<KillWindowParams>,
+    ) -> Result<CallToolResult, McpError> {
+        info!("Killing window: {}", params.criteria);
+        let mut conn = self.connect().await?;
+
+        if let Some(unmatched) = self
+            .check_criteria_match(&mut conn, &params.criteria)
+            .await?
+        {
+            return Ok(unmatched);
+        }
+
+        let command = format!("{} kill", bracketed_criteria(&params.criteria));
+        let results = self
+            .send_command(&mut conn, &command, "kill window")
+            .await?;
+
+        let success = results.iter().all(|r| r.success);
+        if success {
+            Ok(ToolResponse::success(format!(
+                "Killed window matching '{}'",
+                params.criteria
+            )))
+        } else {
+            let errors: Vec<String> = results.iter().filter_map(|r| r.error.clone()).collect();
+            Ok(ToolResponse::failure(format!(
+                "Failed to kill window: {}",
+                errors.join(", ")
+            )))
+        }
+    }
+
+    /// Close every window matching criteria, with a guard against mass closes
+    #[rmcp::tool(description = "Close all windows matching i3 criteria. Counts the matches first: if more than max (default 3) match, nothing is closed and an error asks you to call again with confirm: true.")]
+    pub async fn kill_all(
+        &self,
+        Parameters(params): Parameters<KillAllParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let max = params.max.unwrap_or(DEFAULT_KILL_ALL_MAX);
+        let confirm = params.confirm.unwrap_or(false);
+        info!(
+            "Killing all windows matching {} (max {})",
+            params.criteria, max
+        );
+        let criteria = parse_criteria(&params.criteria)?;
+        let mut conn = self.connect().await?;
+
+        // Kill exactly the windows that were counted, by con_id
+        let con_ids = if criteria_checkable(&criteria) {
+            let tree = self.fetch_tree(&mut conn).await?;
+            let ids: Vec<usize> = find_matching_nodes(&tree, &criteria)
+                .iter()
+                .map(|node| node.id)
+                .collect();
+            if ids.is_empty() {
+                return Ok(ToolResponse::error(format!(
+                    "No window matched {}",
+                    params.criteria.trim()
+                )));
+            }
+            Some(ids)
+        } else {
+            None
+        };
+        if let Err(message) = kill_all_guard(con_ids.as_ref().map(Vec::len), max, confirm) {
+            return Ok(ToolResponse::error(message));
+        }
+
+        let command = match &con_ids {
+            Some(ids) => ids
+                .iter()
+                .map(|id| format!("[con_id={}] kill", id))
+                .collect::<Vec<_>>()
+                .join("; "),
+            None => format!("{} kill", bracketed_criteria(&params.criteria)),
+        };
+        let results = self
+            .send_command(&mut conn, &command, "kill windows")
+            .await?;
+
+        match failure_message(&results) {
+            None => ToolResponse::data(
+                format!("Closed windows matching '{}'", params.criteria.trim()),
+                &serde_json::json!({ "closed": con_ids }),
+            ),
+            Some(errors) => Ok(ToolResponse::failure(format!(
+                "Failed to close windows: {}",
+                errors
+            ))),
+        }
+    }
+
+    /// Toggle fullscreen for the focused window
+    #[rmcp::tool(description = "Toggle fullscreen mode for the currently focused window")]
+    pub async fn fullscreen(&self) -> Result<CallToolResult, McpError> {
+        info!("Toggling fullscreen");
+        let mut conn = self.connect().await?;
+
+        let results = self
+            .send_command(&mut conn, "fullscreen toggle", "toggle fullscreen")
+            .await?;
+
+        let success = results.iter().all(|r| r.success);
+        if success {
+            Ok(ToolResponse::success("Toggled fullscreen"))
+        } else {
+            let errors: Vec<String> = results.iter().filter_map(|r| r.error.clone()).collect();
+            Ok(ToolResponse::failure(format!(
+                "Failed to toggle fullscreen: {}",
+                errors.join(", ")
+            )))
+        }
+    }
+
+    /// Switch focus between the tiling and floating layers
+    #[rmcp::tool(description = "Switch focus between tiling and floating windows on the current workspace (focus mode_toggle), reporting which layer and window focus landed on")]
+    pub async fn toggle_focus_mode(&self) -> Result<CallToolResult, McpError> {
+        info!("Toggling focus mode");
+        let mut conn = self.connect().await?;
+
+        // Without floating windows mode_toggle has nothing to switch to
+        let tree = self.fetch_tree(&mut conn).await?;
+        if focused_workspace(&tree).is_some_and(|ws| ws.floating_nodes.is_empty()) {
+            return Ok(ToolResponse::success(
+                "No floating windows on this workspace; focus unchanged",
+            ));
+        }
+
+        let results = self
+            .send_command(&mut conn, "focus mode_toggle", "toggle focus mode")
+            .await?;
+        if let Some(errors) = failure_message(&results) {
+            return Ok(ToolResponse::failure(format!(
+                "Failed to toggle focus mode: {}",
+                errors
+            )));
+        }
+
+        let tree = self.fetch_tree(&mut conn).await?;
+        let Some((node, ancestors)) = find_focused(&tree) else {
+            return Ok(ToolResponse::success("Toggled focus mode"));
+        };
+        let window = WindowInfo::from_node(node, &ancestors);
+
+        Ok(ToolResponse::success(format!(
+            "Focus is now on a {} window: {} (con_id {})",
+            focus_layer(node, &ancestors),
+            window.title.as_deref().unwrap_or("untitled"),
+            window.con_id
+        )))
+    }
+
+    /// Report whether focus is in the floating or tiling layer
+    #[rmcp::tool(description = "Report whether focus is on a floating or tiling window: returns 'floating', 'tiling', or 'none' if nothing is focused. Check this before floating-only commands like 'move position'.")]
+    pub async fn get_focus_layer(&self) -> Result<CallToolResult, McpError> {
+        info!("Getting focus layer");
+        let mut conn = self.connect().await?;
+
+        let tree = self.fetch_tree(&mut conn).await?;
+        let layer = focused_layer(&tree);
+        ToolResponse::data(format!("Focus layer: {}", layer), &layer)
+    }
+
+    /// How deeply the focused window is nested
+    #[rmcp::tool(description = "Get the nesting path from the output down to the focused window, one entry per level with its type and layout (e.g. output, workspace:splith, con:tabbed, window), plus depth: the number of containers between the window and its workspace. Use before 'focus parent'.")]
+    pub async fn get_focus_depth(&self) -> Result<CallToolResult, McpError> {
+        info!("Getting focus depth");
+        let mut conn = self.connect().await?;
+
+        let tree = self.fetch_tree(&mut conn).await?;
+        let Some((path, depth)) = focus_path(&tree) else {
+            return Ok(ToolResponse::failure("Nothing is focused"));
+        };
+
+        ToolResponse::data(
+            format!("Focused node is {} container(s) below its workspace", depth),
+            &serde_json::json!({ "depth": depth, "path": path }),
+        )
+    }
+
+    /// Float a window as picture-in-picture in the corner of its output
+    #[rmcp::tool(description = "Picture-in-picture: float a window (focused, or selected by criteria), make it sticky, shrink it (default 25% of the output), and move it to the output's bottom-right corner")]
+    pub async fn pip_window(
+        &self,
+        Parameters(params): Parameters<PipWindowParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let width_ppt = validate_ppt("width_ppt", params.width_ppt, DEFAULT_PIP_PPT)?;
+        let height_ppt = validate_ppt("height_ppt", params.height_ppt, DEFAULT_PIP_PPT)?;
+        info!(
+            "Picture-in-picture {:?} at {}x{} ppt",
+            params.criteria, width_ppt, height_ppt
+        );
+        let mut conn = self.connect().await?;
+
+        let mut steps = Vec::new();
+
+        if let Some(criteria) = &params.criteria {
+            if let Some(unmatched) = self.check_criteria_match(&mut conn, criteria).await? {
+                return Ok(unmatched);
+            }
+            let command = format!("{} focus", bracketed_criteria(criteria));
+            let results = self
+                .send_command(&mut conn, &command, "focus window")
+                .await?;
+            steps.push(StepOutcome::from_results(command, &results));
+            if steps[0].error.is_some() {
+                return Ok(ToolResponse::error(format!(
+                    "Aborted: could not focus window matching '{}'\n{}",
+                    criteria,
+                    format_steps(&steps)
+                )));
+            }
+        }
+
+        let tree = self.fetch_tree(&mut conn).await?;
+        let target = find_focused(&tree).filter(|(node, _)| node.window.is_some());
+        let Some((node, ancestors)) = target else {
+            return Ok(ToolResponse::failure("No focused window"));
+        };
+        let Some(output) = ancestors.iter().find(|a| a.node_type == NodeType::Output) else {
+            return Ok(ToolResponse::failure(
+                "Could not determine the window's output",
+            ));
+        };
+        let geometry = pip_geometry(&work_area(output), width_ppt, height_ppt);
+
+        for command in pip_commands(node.id, &geometry) {
+            let results = self
+                .send_command(&mut conn, &command, "picture-in-picture")
+                .await?;
+            steps.push(StepOutcome::from_results(command, &results));
+            if steps.last().is_some_and(|s| s.error.is_some()) {
+                return Ok(ToolResponse::error(format!(
+                    "Aborted picture-in-picture part way\n{}",
+                    format_steps(&steps)
+                )));
+            }
+        }
+
+        Ok(ToolResponse::success(format!(
+            "Window {} is now {}x{} at ({}, {}), floating and sticky\n{}",
+            node.id,
+            geometry.width,
+            geometry.height,
+            geometry.x,
+            geometry.y,
+            format_steps(&steps)
+        )))
+    }
+
+    /// Set window opacity (Sway only)
+    #[rmcp::tool(description = "Set the opacity (0.0-1.0) of the focused window or windows matching criteria. Requires Sway; plain i3 has no opacity command.")]
+    pub async fn set_opacity(
+        &self,
+        Parameters(params): Parameters<SetOpacityParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let opacity = validate_opacity(params.opacity)?;
+        info!("Setting opacity {} for {:?}", opacity, params.criteria);
+        let mut conn = self.connect().await?;
+
+        let version = self.fetch_raw_version(&mut conn).await?;
+        if !is_sway(&version) {
+            return Ok(ToolResponse::failure(
+                "Window opacity requires a compositor. Sway supports it over IPC; on plain i3 \
+                 configure opacity in a compositor such as picom instead.",
+            ));
+        }
+
+        if let Some(criteria) = &params.criteria {
+            if let Some(unmatched) = self.check_criteria_match(&mut conn, criteria).await? {
+                return Ok(unmatched);
+            }
+        }
+
+        let command = opacity_command(params.criteria.as_deref(), opacity);
+        let results = self
+            .send_command(&mut conn, &command, "set opacity")
+            .await?;
+
+        match failure_message(&results) {
+            None => Ok(ToolResponse::success(format!("Set opacity to {}", opacity))),
+            Some(errors) => Ok(ToolResponse::failure(format!(
+                "Failed to set opacity: {}",
+                errors
+            ))),
+        }
+    }
+
+    /// Resize the focused window to a fraction of its parent split
+    #[rmcp::tool(description = "Resize the focused tiled window so it fills the given fraction (0.1-0.9) of its parent split: width in a horizontal split, height in a vertical one. Easier than grow/shrink steps.")]
+    pub async fn set_split_ratio(
+        &self,
+        Parameters(params): Parameters<SetSplitRatioParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let ratio = validate_split_ratio(params.ratio)?;
+        info!("Setting split ratio {}", ratio);
+        let mut conn = self.connect().await?;
+
+        let tree = self.fetch_tree(&mut conn).await?;
+        let Some((node, ancestors)) = find_focused(&tree) else {
+            return Ok(ToolResponse::failure("No focused window found"));
+        };
+        if node.node_type != NodeType::Con || focus_layer(node, &ancestors) == "floating" {
+            return Ok(ToolResponse::failure(
+                "The focused window is not tiled, so it has no split to resize in",
+            ));
+        }
+        let Some(parent) = ancestors.last() else {
+            return Ok(ToolResponse::failure(
+                "The focused window has no parent split",
+            ));
+        };
+        let (command, from, to) = match split_ratio_command(node, parent, ratio) {
+            Ok(resize) => resize,
+            Err(message) => return Ok(ToolResponse::failure(message)),
+        };
+
+        let results = self
+            .send_command(&mut conn, &command, "set split ratio")
+            .await?;
+
+        match failure_message(&results) {
+            None => ToolResponse::data(
+                format!("Resized from {}% to {}% of the split", from, to),
+                &serde_json::json!({ "from_ppt": from, "to_ppt": to, "command": command }),
+            ),
+            Some(errors) => Ok(ToolResponse::failure(format!(
+                "Failed to set split ratio: {}",
+                errors
+            ))),
+        }
+    }
+
+    /// Give every window on the focused workspace an equal share of its split
+    #[rmcp::tool(description = "Tidy up the focused workspace: resize every tiled container so siblings in each horizontal or vertical split get equal shares (i3 has no native balance). Nested splits are equalized too; floating windows are left alone.")]
+    pub async fn equalize(&self) -> Result<CallToolResult, McpError> {
+        info!("Equalizing focused workspace");
+        let mut conn = self.connect().await?;
+
+        let tree = self.fetch_tree(&mut conn).await?;
+        let Some(workspace) = focused_workspace(&tree) else {
+            return Ok(ToolResponse::failure("No focused workspace found"));
+        };
+        let name = workspace.name.clone().unwrap_or_default();
+        let commands = equalize_commands(workspace);
+        if commands.is_empty() {
+            return Ok(ToolResponse::success(format!(
+                "Nothing to equalize on '{}': no split has more than one container",
+                name
+            )));
+        }
+
+        let command = commands.join("; ");
+        let results = self.send_command(&mut conn, &command, "equalize").await?;
+
+        match failure_message(&results) {
+            None => ToolResponse::data(
+                format!("Equalized '{}' with {} resize(s)", name, commands.len()),
+                &commands,
+            ),
+            Some(errors) => Ok(ToolResponse::failure(format!(
+                "Failed to equalize: {}",
+                errors
+            ))),
+        }
+    }
+
+    /// Configure an input device (Sway only)
+    #[rmcp::tool(description = "Change an input device setting at runtime, e.g. tap-to-click or keyboard layout: runs input \"<identifier>\" <setting> <value>. The identifier must match a device (or be type:<type> or *). Requires Sway; i3 leaves input devices to X.")]
+    pub async fn configure_input(
+        &self,
+        Parameters(params): Parameters<ConfigureInputParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let command = input_command(&params.identifier, &params.setting, &params.value)?;
+        let identifier = params.identifier.trim();
+        info!("Configuring input: {}", command);
+        let mut conn = self.connect().await?;
+
+        let version = self.fetch_raw_version(&mut conn).await?;
+        if !is_sway(&version) {
+            return Ok(ToolResponse::failure(
+                "Input configuration requires Sway. On plain i3 input devices belong to X: \
+                 use xinput or setxkbmap instead.",
+            ));
+        }
+
+        let inputs = raw_ipc_request(SWAY_GET_INPUTS)
+            .await
+            .map_err(|e| conn.ipc_error("get inputs", e))?;
+        if !input_exists(&inputs, identifier) {
+            return Ok(ToolResponse::failure(format!(
+                "No input device matches '{}'. Known identifiers: {}",
+                identifier,
+                input_identifiers(&inputs).join(", ")
+            )));
+        }
+
+        let results = self
+            .send_command(&mut conn, &command, "configure input")
+            .await?;
+
+        match failure_message(&results) {
+            None => Ok(ToolResponse::success(format!("Ran: {}", command))),
+            Some(errors) => Ok(ToolResponse::failure(format!(
+                "Failed to configure input: {}",
+                errors
+            ))),
+        }
+    }
+
+    /// Change the display mode of i3bar
+    #[rmcp::tool(description = "Set the mode of one bar (by bar_id) or all bars: 'dock' (always visible), 'hide' (visible while the modifier is held), or 'invisible' (never visible)")]
+    pub async fn set_bar_mode(
+        &self,
+        Parameters(params): Parameters<SetBarModeParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let mode = validate_bar_value("mode", &params.mode, BAR_MODES)?;
+        info!("Setting bar mode {} for {:?}", mode, params.bar_id);
+        self.set_bar("mode", mode, params.bar_id.as_deref()).await
+    }
+
+    /// Show or hide a bar that is in hide mode
+    #[rmcp::tool(description = "Show or hide one bar (by bar_id) or all bars that are in 'hide' mode, without holding the modifier")]
+    pub async fn set_bar_hidden_state(
+        &self,
+        Parameters(params): Parameters<SetBarHiddenStateParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let state = validate_bar_value("hidden_state", &params.state, BAR_HIDDEN_STATES)?;
+        info!("Setting bar hidden_state {} for {:?}", state, params.bar_id);
+        self.set_bar("hidden_state", state, params.bar_id.as_deref())
+            .await
+    }
+
+    /// Send a `bar` command after checking the bar id against the configured bars
+    async fn set_bar(
+        &self,
+        setting: &str,
+        value: &str,
+        bar_id: Option<&str>,
+    ) -> Result<CallToolResult, McpError> {
+        let mut conn = self.connect().await?;
+
+        let bar_ids = self.fetch_bar_ids(&mut conn).await?;
+        let command = match bar_command(setting, value, bar_id, &bar_ids) {
+            Ok(command) => command,
+            Err(message) => return Ok(ToolResponse::failure(message)),
+        };
+        let results = self.send_command(&mut conn, &command, "set bar").await?;
+
+        match failure_message(&results) {
+            None => Ok(ToolResponse::success(format!("Ran: {}", command))),
+            Some(errors) => Ok(ToolResponse::failure(format!(
+                "Failed to set bar {}: {}",
+                setting, errors
+            ))),
+        }
+    }
+
+    /// Set the minimum size of floating windows
+    #[rmcp::tool(description = "Set the minimum size floating windows can be resized to (floating_minimum_size). Applied at runtime if i3 accepts it, otherwise written to the config file and reloaded.")]
+    pub async fn set_floating_minimum_size(
+        &self,
+        Parameters(params): Parameters<FloatingSizeParams>,
+    ) -> Result<CallToolResult, McpError> {
+        info!(
+            "Setting floating minimum size {}x{}",
+            params.width, params.height
+        );
+        self.set_floating_size("minimum", params.width, params.height)
+            .await
+    }
+
+    /// Set the maximum size of floating windows
+    #[rmcp::tool(description = "Set the maximum size floating windows can be resized to (floating_maximum_size). Applied at runtime if i3 accepts it, otherwise written to the config file and reloaded.")]
+    pub async fn set_floating_maximum_size(
+        &self,
+        Parameters(params): Parameters<FloatingSizeParams>,
+    ) -> Result<CallToolResult, McpError> {
+        info!(
+            "Setting floating maximum size {}x{}",
+            params.width, params.height
+        );
+        self.set_floating_size("maximum", params.width, params.height)
+            .await
+    }
+
+    /// Shared body of the floating size tools: try the directive as a runtime
+    /// command, and fall back to editing the config and reloading when
+    /// this i3 only accepts it in the config
+    async fn set_floating_size(
+        &self,
+        bound: &str,
+        width: u32,
+        height: u32,
+    ) -> Result<CallToolResult, McpError> {
+        let mut conn = self.connect().await?;
+
+        let line = floating_size_line(bound, width, height);
+        let results = self
+            .send_command(&mut conn, &line, "set floating size")
+            .await?;
+        let Some(runtime_error) = failure_message(&results) else {
+            return ToolResponse::data(
+                format!("Ran: {}", line),
+                &serde_json::json!({ "line": line, "method": "runtime" }),
+            );
+        };
+        debug!(
+            "{} rejected at runtime ({}), editing config",
+            line, runtime_error
+        );
+
+        let version = self.fetch_version(&mut conn).await?;
+        let path = version.loaded_config_file_name;
+        if path.is_empty() {
+            return Ok(ToolResponse::failure(format!(
+                "i3 rejected '{}' at runtime ({}) and did not report a loaded config file",
+                line, runtime_error
+            )));
+        }
+        let config = std::fs::read_to_string(&path).map_err(|e| {
+            McpError::internal_error(format!("Failed to read {}: {}", path, e), None)
+        })?;
+        let keyword = format!("floating_{}_size", bound);
+        let (contents, replaced) = set_directive_in_config(&config, &keyword, &line);
+        let backup = if contents != config {
+            let backup = write_config_atomically(&path, &contents).map_err(|e| {
+                McpError::internal_error(format!("Failed to write {}: {}", path, e), None)
+            })?;
+            Some(backup)
+        } else {
+            None
+        };
+
+        let results = self.send_command(&mut conn, "reload", "reload").await?;
+        let summary = serde_json::json!({
+            "line": line,
+            "method": "config",
+            "config": path,
+            "backup": backup,
+            "replaced": replaced,
+        });
+        match failure_message(&results) {
+            None => ToolResponse::data(format!("Set {} in {} and reloaded", line, path), &summary),
+            Some(errors) => ToolResponse::failure_data(
+                format!("Config updated, but i3 reported: {}", errors),
+                &summary,
+            ),
+        }
+    }
+
+    /// Float, center, and focus a window, remembering how to put it back
+    #[rmcp::tool(description = "Present a window prominently: focus the first window matching criteria, float it, and center it on its output. Returns its con_id; pass that to unspotlight to restore its previous tiling or floating placement.")]
+    pub async fn spotlight(
+        &self,
+        Parameters(params): Parameters<FocusWindowParams>,
+    ) -> Result<CallToolResult, McpError> {
+        info!("Spotlighting window: {}", params.criteria);
+        let parsed = parse_criteria(&params.criteria)?;
+        if !criteria_checkable(&parsed) {
+            return Err(McpError::invalid_params(
+                "spotlight records the window's placement, so its criteria must not use regex \
+                 syntax or keys (like app_id) the server can't evaluate",
+                None,
+            ));
+        }
+        let mut conn = self.connect().await?;
+
+        let tree = self.fetch_tree(&mut conn).await?;
+        let Some(window) = find_matching_nodes(&tree, &parsed).first().copied() else {
+            return Ok(ToolResponse::error(format!(
+                "No window matched {}",
+                params.criteria.trim()
+            )));
+        };
+        let con_id = window.id;
+        let Some((node, ancestors)) = find_node_with_ancestors(&tree, con_id) else {
+            return Ok(ToolResponse::failure(
+                "Matched window vanished from the tree",
+            ));
+        };
+        let state = SpotlightState::capture(node, &ancestors);
+
+        let command = spotlight_command(con_id);
+        let results = self.send_command(&mut conn, &command, "spotlight").await?;
+        if let Some(errors) = failure_message(&results) {
+            return Ok(ToolResponse::failure(format!(
+                "Failed to spotlight window: {}",
+                errors
+            )));
+        }
+
+        // Spotlighting twice keeps the placement from before the first call
+        let was_floating = self
+            .spotlights
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(con_id)
+            .or_insert(state)
+            .was_floating;
+        ToolResponse::data(
+            format!(
+                "Spotlighted window {}; unspotlight it to put it back",
+                con_id
+            ),
+            &serde_json::json!({ "con_id": con_id, "was_floating": was_floating }),
+        )
+    }
+
+    /// Put a spotlighted window back where it was
+    #[rmcp::tool(description = "Undo spotlight for a window: return it to the tiling layout, or to its old floating position and size if it was floating before")]
+    pub async fn unspotlight(
+        &self,
+        Parameters(params): Parameters<UnspotlightParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let con_id = parse_con_id(params.con_id)?;
+        info!("Unspotlighting window {}", con_id);
+        let state = self
+            .spotlights
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&con_id);
+        let Some(state) = state else {
+            return Ok(ToolResponse::failure(format!(
+                "Window {} is not spotlighted",
+                con_id
+            )));
+        };
+        let mut conn = self.connect().await?;
+
+        let tree = self.fetch_tree(&mut conn).await?;
+        if find_node_with_ancestors(&tree, con_id).is_none() {
+            return Ok(ToolResponse::failure(format!(
+                "Window {} has closed since it was spotlighted",
+                con_id
+            )));
+        }
+
+        let command = unspotlight_command(con_id, &state);
+        let results = self
+            .send_command(&mut conn, &command, "unspotlight")
+            .await?;
+
+        let placement = if state.was_floating {
+            "floating"
+        } else {
+            "tiled"
+        };
+        match failure_message(&results) {
+            None => Ok(ToolResponse::success(format!(
+                "Returned window {} to its {} placement",
+                con_id, placement
+            ))),
+            Some(errors) => {
+                // Keep the state so the client can retry
+                self.spotlights
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .insert(con_id, state);
+                Ok(ToolResponse::failure(format!(
+                    "Failed to restore window: {}",
+                    errors
+                )))
+            }
+        }
+    }
+
+    /// Move the focused window aside to the stash workspace
+    #[rmcp::tool(description = "Set the focused window aside: move it to the stash workspace ('stash' unless RMCP_I3_STASH_WS says otherwise), remembering the workspace it came from. Returns its con_id; pass that to unstash_window to bring it back.")]
+    pub async fn stash_window(&self) -> Result<CallToolResult, McpError> {
+        let stash = self.stash_workspace.as_str();
+        info!("Stashing focused window to {}", stash);
+        let mut conn = self.connect().await?;
+
+        let tree = self.fetch_tree(&mut conn).await?;
+        let Some((node, _)) = find_focused(&tree) else {
+            return Ok(ToolResponse::failure("No focused window"));
+        };
+        if node.node_type == NodeType::Workspace {
+            return Ok(ToolResponse::failure(
+                "The focused workspace is empty; there is no window to stash",
+            ));
+        }
+        let con_id = node.id;
+        let Some(origin) = workspace_of(&tree, con_id).and_then(|ws| ws.name.clone()) else {
+            return Ok(ToolResponse::failure(
+                "The focused window is not on a workspace",
+            ));
+        };
+        let known = self
+            .stashes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .contains_key(&con_id);
+        if origin == stash && !known {
+            return Ok(ToolResponse::failure(format!(
+                "Window {} is already on the stash workspace '{}'",
+                con_id, stash
+            )));
+        }
+
+        let command = stash_command(con_id, stash);
+        let results = self
+            .send_command(&mut conn, &command, "stash window")
+            .await?;
+        if let Some(errors) = failure_message(&results) {
+            return Ok(ToolResponse::failure(format!(
+                "Failed to stash window: {}",
+                errors
+            )));
+        }
+
+        let origin = record_stash(
+            &mut self.stashes.lock().unwrap_or_else(PoisonError::into_inner),
+            con_id,
+            &origin,
+        );
+        ToolResponse::data(
+            format!(
+                "Stashed window {} from workspace {}; unstash_window brings it back",
+                con_id, origin
+            ),
+            &serde_json::json!({ "con_id": con_id, "origin": origin, "stash": stash }),
+        )
+    }
+
+    /// Move a stashed window back to the workspace it came from
+    #[rmcp::tool(description = "Undo stash_window for a window: move it back to the workspace it was stashed from and focus it")]
+    pub async fn unstash_window(
+        &self,
+        Parameters(params): Parameters<UnstashWindowParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let con_id = parse_con_id(params.con_id)?;
+        info!("Unstashing window {}", con_id);
+        let origin = self
+            .stashes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&con_id);
+        let Some(origin) = origin else {
+            return Ok(ToolResponse::failure(format!(
+                "Window {} is not stashed",
+                con_id
+            )));
+        };
+        let mut conn = self.connect().await?;
+
+        let tree = self.fetch_tree(&mut conn).await?;
+        if find_node_with_ancestors(&tree, con_id).is_none() {
+            return Ok(ToolResponse::failure(format!(
+                "Window {} has closed since it was stashed",
+                con_id
+            )));
+        }
+
+        let command = unstash_command(con_id, &origin);
+        let results = self
+            .send_command(&mut conn, &command, "unstash window")
+            .await?;
+
+        match failure_message(&results) {
+            None => Ok(ToolResponse::success(format!(
+                "Returned window {} to workspace {}",
+                con_id, origin
+            ))),
+            Some(errors) => {
+                // Keep the origin so the client can retry
+                self.stashes
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .insert(con_id, origin);
+                Ok(ToolResponse::failure(format!(
+                    "Failed to unstash window: {}",
+                    errors
+                )))
+            }
+        }
+    }
+
+    /// Maximize the focused floating window within its output's work area
+    #[rmcp::tool(description = "Resize and move the focused floating window to fill its output, leaving docked bars visible (maximize without fullscreen)")]
+    pub async fn maximize_floating(&self) -> Result<CallToolResult, McpError> {
+        info!("Maximizing floating window");
+        let mut conn = self.connect().await?;
+
+        let tree = self.fetch_tree(&mut conn).await?;
+        let Some((node, ancestors)) = find_focused(&tree) else {
+            return Ok(ToolResponse::failure("No focused window"));
+        };
+        if node.window.is_none() || !is_floating(node) {
+            return Ok(ToolResponse::failure(
+                "Focused window is not floating; use fullscreen or toggle floating first",
+            ));
+        }
+        let Some(output) = ancestors.iter().find(|a| a.node_type == NodeType::Output) else {
+            return Ok(ToolResponse::failure(
+                "Could not determine the focused window's output",
+            ));
+        };
+
+        let area = work_area(output);
+        let command = format!(
+            "[con_id={id}] resize set {} px {} px; [con_id={id}] move position {} px {} px",
+            area.width,
+            area.height,
+            area.x,
+            area.y,
+            id = node.id
+        );
+        let results = self
+            .send_command(&mut conn, &command, "maximize window")
+            .await?;
+
+        match failure_message(&results) {
+            None => Ok(ToolResponse::success(format!(
+                "Maximized window to {}x{} at ({}, {}) on {}",
+                area.width,
+                area.height,
+                area.x,
+                area.y,
+                output.name.as_deref().unwrap_or("unknown output")
+            ))),
+            Some(errors) => Ok(ToolResponse::failure(format!(
+                "Failed to maximize window: {}",
+                errors
+            ))),
+        }
+    }
+
+    /// Get all outputs (monitors)
+    #[rmcp::tool(description = "Get all outputs/monitors with their properties (name, resolution, position, active status)")]
+    pub async fn get_outputs(&self) -> Result<CallToolResult, McpError> {
+        info!("Getting outputs");
+        let mut conn = self.connect().await?;
+
+        let outputs = self.fetch_outputs(&mut conn).await?;
+
+        debug!("Found {} outputs", outputs.len());
+        ToolResponse::data(format!("{} outputs", outputs.len()), &outputs)
+    }
+
+    /// Get every active output with the workspaces on it
+    #[rmcp::tool(description = "Get the multi-monitor picture in one call: each active output (left to right) with its geometry, primary flag, the workspace it currently shows, and every workspace on it")]
+    pub async fn get_output_layout(&self) -> Result<CallToolResult, McpError> {
+        info!("Getting output layout");
+        let mut conn = self.connect().await?;
+
+        let outputs = self.fetch_outputs(&mut conn).await?;
+        let workspaces = self.fetch_workspaces(&mut conn).await?;
+        let layout = output_layout(&workspaces, &outputs);
+
+        ToolResponse::data(format!("{} active output(s)", layout.len()), &layout)
+    }
+
+    /// Get the primary output
+    #[rmcp::tool(description = "Get the primary output/monitor (or the first active one if none is marked primary), with its current workspace and geometry")]
+    pub async fn get_primary_output(&self) -> Result<CallToolResult, McpError> {
+        info!("Getting primary output");
+        let mut conn = self.connect().await?;
+
+        let outputs = self.fetch_outputs(&mut conn).await?;
+        let Some(output) = primary_output(&outputs) else {
+            return Ok(ToolResponse::failure("No active outputs"));
+        };
+
+        ToolResponse::data(format!("Primary output: {}", output.name), output)
+    }
+
+    /// Power an output on or off
+    #[rmcp::tool(description = "Turn an output/monitor's power on or off (DPMS). Supported on Sway; plain i3 doesn't expose DPMS over IPC.")]
+    pub async fn set_output_power(
+        &self,
+        Parameters(params): Parameters<SetOutputPowerParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let state = if params.enable { "on" } else { "off" };
+        info!("Setting output {} power {}", params.output, state);
+        let mut conn = self.connect().await?;
+
+        // A powered-off output may no longer be reported as active, so accept any known output
+        let outputs = self.fetch_outputs(&mut conn).await?;
+        if !outputs.iter().any(|o| o.name == params.output) {
+            let names: Vec<&str> = outputs.iter().map(|o| o.name.as_str()).collect();
+            return Err(McpError::invalid_params(
+                format!(
+                    "Unknown output '{}'. Outputs: {}",
+                    params.output,
+                    names.join(", ")
+                ),
+                None,
+            ));
+        }
+
+        let version = self.fetch_raw_version(&mut conn).await?;
+        if !is_sway(&version) {
+            return Ok(ToolResponse::failure(format!(
+                "i3 doesn't support output power control over IPC. On plain i3 use xset instead, \
+                 e.g. exec `xset dpms force {}` (this affects all X11 outputs).",
+                state
+            )));
+        }
+
+        let command = format!("output {} dpms {}", params.output, state);
+        let results = self
+            .send_command(&mut conn, &command, "set output power")
+            .await?;
+
+        match failure_message(&results) {
+            None => Ok(ToolResponse::success(format!(
+                "Turned output {} {}",
+                params.output, state
+            ))),
+            Some(errors) => Ok(ToolResponse::failure(format!(
+                "Failed to set output power: {}",
+                errors
+            ))),
+        }
+    }
+
+    /// Get all marks
+    #[rmcp::tool(description = "Get all window marks (user-assigned labels for windows)")]
+    pub async fn get_marks(&self) -> Result<CallToolResult, McpError> {
+        info!("Getting marks");
+        let mut conn = self.connect().await?;
+
+        let marks = self.fetch_marks(&mut conn).await?;
+
+        if marks.0.is_empty() {
+            Ok(ToolResponse::success("No marks defined"))
         } else {
-            let errors: Vec<String> = results
-                .iter()
-                .filter_map(|r| r.error.clone())
-                .collect();
-            Ok(CallToolResult::success(vec![Content::text(format!(
-                "Failed to move window: {}",
-                errors.join(", ")
-            ))]))
+            Ok(ToolResponse::success(format!(
+                "Marks:\n{}",
+                marks.0.join("\n")
+            )))
         }
     }
 
-    /// Run an arbitrary i3 command
-    #[rmcp::tool(description = "Execute any i3 command (escape hatch for advanced operations). See i3 user guide for command list.")]
-    pub async fn run_command(
+    /// Check whether any window matches the given class, instance, or title
+    #[rmcp::tool(description = "Check whether an app is running: returns running, the number of matching windows, and the workspaces they're on. Match by class, instance, and/or title.")]
+    pub async fn is_running(
         &self,
-        Parameters(params): Parameters<RunCommandParams>,
+        Parameters(params): Parameters<IsRunningParams>,
     ) -> Result<CallToolResult, McpError> {
-        info!("Running i3 command: {}", params.command);
+        let criteria = window_criteria(&[
+            ("class", params.class.as_deref()),
+            ("instance", params.instance.as_deref()),
+            ("title", params.title.as_deref()),
+        ]);
+        if criteria.is_empty() {
+            return Err(McpError::invalid_params(
+                "Provide at least one of class, instance, or title",
+                None,
+            ));
+        }
+        info!("Checking for running windows matching {:?}", criteria);
         let mut conn = self.connect().await?;
 
-        let results = conn.run_command(&params.command).await.map_err(|e| {
-            error!("Failed to run command: {}", e);
-            McpError::internal_error(format!("Failed to run command: {}", e), None)
-        })?;
-
-        let json = serde_json::to_string_pretty(&results).map_err(|e| {
-            McpError::internal_error(format!("Failed to serialize results: {}", e), None)
-        })?;
+        let tree = self.fetch_tree(&mut conn).await?;
+        let (count, workspaces) = matching_window_workspaces(&tree, &criteria);
 
-        Ok(CallToolResult::success(vec![Content::text(json)]))
+        let message = match count {
+            0 => "No matching windows".to_string(),
+            _ => format!("{} matching windows on {}", count, workspaces.join(", ")),
+        };
+        ToolResponse::data(
+            message,
+            &serde_json::json!({
+                "running": count > 0,
+                "count": count,
+                "workspaces": workspaces,
+            }),
+        )
     }
 
-    /// Launch an application
-    #[rmcp::tool(description = "Launch an application (e.g. 'firefox', 'kitty', 'emacs')")]
-    pub async fn exec(
+    /// Find matching windows on workspaces other than the focused one
+    #[rmcp::tool(description = "Find windows matching criteria on workspaces other than the focused one (e.g. to jump to an existing instance instead of launching another). Returns each match's con_id and workspace.")]
+    pub async fn find_elsewhere(
         &self,
-        Parameters(params): Parameters<ExecParams>,
+        Parameters(params): Parameters<FindElsewhereParams>,
     ) -> Result<CallToolResult, McpError> {
-        info!("Executing: {}", params.command);
+        info!("Finding {} on other workspaces", params.criteria);
+        let criteria = parse_criteria(&params.criteria)?;
+        if !criteria_checkable(&criteria) {
+            return Err(McpError::invalid_params(
+                "find_elsewhere matches windows itself, so its criteria must not use regex \
+                 syntax or keys (like app_id) the server can't evaluate",
+                None,
+            ));
+        }
         let mut conn = self.connect().await?;
 
-        let command = format!("exec {}", params.command);
-        let results = conn.run_command(&command).await.map_err(|e| {
-            error!("Failed to exec: {}", e);
-            McpError::internal_error(format!("Failed to exec: {}", e), None)
-        })?;
+        let tree = self.fetch_tree(&mut conn).await?;
+        let windows = matches_elsewhere(&tree, &criteria);
 
-        let success = results.iter().all(|r| r.success);
-        if success {
-            Ok(CallToolResult::success(vec![Content::text(format!(
-                "Launched '{}'",
-                params.command
-            ))]))
-        } else {
-            let errors: Vec<String> = results
-                .iter()
-                .filter_map(|r| r.error.clone())
-                .collect();
-            Ok(CallToolResult::success(vec![Content::text(format!(
-                "Failed to launch: {}",
-                errors.join(", ")
-            ))]))
-        }
+        ToolResponse::data(
+            format!("{} matching windows on other workspaces", windows.len()),
+            &windows,
+        )
     }
 
-    /// Kill (close) the focused window
-    #[rmcp::tool(description = "Kill (close) the currently focused window")]
-    pub async fn kill(&self) -> Result<CallToolResult, McpError> {
-        info!("Killing focused window");
+    /// Get the marks on the windows matching a criteria string
+    #[rmcp::tool(description = "Get the marks on specific windows selected by i3 criteria (e.g. '[class=\"Firefox\"]'). Returns the marks array for a single match, or marks keyed by con_id if several windows match.")]
+    pub async fn get_window_marks(
+        &self,
+        Parameters(params): Parameters<GetWindowMarksParams>,
+    ) -> Result<CallToolResult, McpError> {
+        info!("Getting marks for {}", params.criteria);
+        let criteria = parse_criteria(&params.criteria)?;
+        if !criteria_checkable(&criteria) {
+            return Err(McpError::invalid_params(
+                "get_window_marks matches windows itself, so its criteria must not use regex \
+                 syntax or keys (like app_id) the server can't evaluate",
+                None,
+            ));
+        }
         let mut conn = self.connect().await?;
 
-        let results = conn.run_command("kill").await.map_err(|e| {
-            error!("Failed to kill window: {}", e);
-            McpError::internal_error(format!("Failed to kill window: {}", e), None)
-        })?;
+        let tree = self.fetch_tree(&mut conn).await?;
+        let matches = find_matching_nodes(&tree, &criteria);
+        let marks = |node: &Node| node.marks.as_ref().map(|m| m.0.clone()).unwrap_or_default();
 
-        let success = results.iter().all(|r| r.success);
-        if success {
-            Ok(CallToolResult::success(vec![Content::text(
-                "Killed focused window".to_string(),
-            )]))
-        } else {
-            let errors: Vec<String> = results
+        let value = match matches.as_slice() {
+            [] => {
+                return Ok(ToolResponse::failure(format!(
+                    "No windows match {}",
+                    params.criteria
+                )));
+            }
+            [node] => serde_json::json!(marks(node)),
+            nodes => serde_json::json!(nodes
                 .iter()
-                .filter_map(|r| r.error.clone())
-                .collect();
-            Ok(CallToolResult::success(vec![Content::text(format!(
-                "Failed to kill window: {}",
-                errors.join(", ")
-            ))]))
-        }
+                .map(|n| (n.id, marks(n)))
+                .collect::<BTreeMap<_, _>>()),
+        };
+
+        ToolResponse::data(
+            format!("Marks on {} matching window(s)", matches.len()),
+            &value,
+        )
     }
 
-    /// Kill (close) a window matching criteria
-    #[rmcp::tool(description = "Kill (close) a window matching i3 criteria (e.g. [class=\"Firefox\"], [title=\"vim\"])")]
-    pub async fn kill_window(
+    /// Label the focused container with a `label:` mark
+    #[rmcp::tool(description = "Give the focused container a human-readable label (i3 can't rename window titles). Stored as the mark 'label:<text>', replacing any earlier label on it; window_overview and describe_state show it.")]
+    pub async fn label_container(
         &self,
-        Parameters(params): Parameters<KillWindowParams>,
+        Parameters(params): Parameters<LabelContainerParams>,
     ) -> Result<CallToolResult, McpError> {
-        info!("Killing window: {}", params.criteria);
+        let mark = label_mark(&params.label)?;
+        info!("Labelling focused container: {}", mark);
         let mut conn = self.connect().await?;
 
-        let command = format!("{} kill", params.criteria);
-        let results = conn.run_command(&command).await.map_err(|e| {
-            error!("Failed to kill window: {}", e);
-            McpError::internal_error(format!("Failed to kill window: {}", e), None)
-        })?;
+        let tree = self.fetch_tree(&mut conn).await?;
+        let Some((node, _)) = find_focused(&tree) else {
+            return Ok(ToolResponse::failure("No focused container"));
+        };
+        if node.node_type == NodeType::Workspace {
+            return Ok(ToolResponse::failure(
+                "The focused workspace is empty; there is no container to label",
+            ));
+        }
 
-        let success = results.iter().all(|r| r.success);
-        if success {
-            Ok(CallToolResult::success(vec![Content::text(format!(
-                "Killed window matching '{}'",
-                params.criteria
-            ))]))
-        } else {
-            let errors: Vec<String> = results
-                .iter()
-                .filter_map(|r| r.error.clone())
-                .collect();
-            Ok(CallToolResult::success(vec![Content::text(format!(
-                "Failed to kill window: {}",
-                errors.join(", ")
-            ))]))
+        let command = label_command(node, &mark);
+        let results = self
+            .send_command(&mut conn, &command, "label container")
+            .await?;
+
+        let label = &mark[LABEL_MARK_PREFIX.len()..];
+        match failure_message(&results) {
+            None => ToolResponse::data(
+                format!("Labelled container {} '{}'", node.id, label),
+                &serde_json::json!({ "con_id": node.id, "label": label, "mark": mark }),
+            ),
+            Some(errors) => Ok(ToolResponse::failure(format!(
+                "Failed to label container: {}",
+                errors
+            ))),
         }
     }
 
-    /// Toggle fullscreen for the focused window
-    #[rmcp::tool(description = "Toggle fullscreen mode for the currently focused window")]
-    pub async fn fullscreen(&self) -> Result<CallToolResult, McpError> {
-        info!("Toggling fullscreen");
+    /// Mark the focused window and remember it in the tag registry
+    #[rmcp::tool(description = "Mark the focused window and remember it (con_id, class, title) under that mark, for listing later with get_tagged")]
+    pub async fn tag_and_remember(
+        &self,
+        Parameters(params): Parameters<TagAndRememberParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let mark = params.mark.trim();
+        if mark.is_empty() {
+            return Err(McpError::invalid_params("mark must not be empty", None));
+        }
+        info!("Tagging focused window as {}", mark);
         let mut conn = self.connect().await?;
 
-        let results = conn.run_command("fullscreen toggle").await.map_err(|e| {
-            error!("Failed to toggle fullscreen: {}", e);
-            McpError::internal_error(format!("Failed to toggle fullscreen: {}", e), None)
-        })?;
+        let tree = self.fetch_tree(&mut conn).await?;
+        let target = find_focused(&tree).filter(|(node, _)| node.window.is_some());
+        let Some((node, ancestors)) = target else {
+            return Ok(ToolResponse::failure("No focused window"));
+        };
+        let window = WindowInfo::from_node(node, &ancestors);
 
-        let success = results.iter().all(|r| r.success);
-        if success {
-            Ok(CallToolResult::success(vec![Content::text(
-                "Toggled fullscreen".to_string(),
-            )]))
-        } else {
-            let errors: Vec<String> = results
-                .iter()
-                .filter_map(|r| r.error.clone())
-                .collect();
-            Ok(CallToolResult::success(vec![Content::text(format!(
-                "Failed to toggle fullscreen: {}",
-                errors.join(", ")
-            ))]))
+        let command = format!(
+            "[con_id={}] mark --add \"{}\"",
+            node.id,
+            escape_criteria_value(mark)
+        );
+        let results = self
+            .send_command(&mut conn, &command, "mark window")
+            .await?;
+        if let Some(errors) = failure_message(&results) {
+            return Ok(ToolResponse::failure(format!(
+                "Failed to mark window: {}",
+                errors
+            )));
         }
-    }
 
-    /// Get all outputs (monitors)
-    #[rmcp::tool(description = "Get all outputs/monitors with their properties (name, resolution, position, active status)")]
-    pub async fn get_outputs(&self) -> Result<CallToolResult, McpError> {
-        info!("Getting outputs");
-        let mut conn = self.connect().await?;
+        let tagged = TaggedWindow {
+            con_id: node.id,
+            class: window.class,
+            title: window.title,
+        };
+        let message = format!("Tagged window {} as '{}'", node.id, mark);
+        let response = ToolResponse::data(message, &tagged);
+        self.tags
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(mark.to_string(), tagged);
+        response
+    }
 
-        let outputs = conn.get_outputs().await.map_err(|e| {
-            error!("Failed to get outputs: {}", e);
-            McpError::internal_error(format!("Failed to get outputs: {}", e), None)
-        })?;
+    /// List windows tagged with tag_and_remember
+    #[rmcp::tool(description = "List windows remembered by tag_and_remember, resolved against the current tree (workspace, current title) and flagging tags whose windows have closed")]
+    pub async fn get_tagged(&self) -> Result<CallToolResult, McpError> {
+        info!("Listing tagged windows");
+        let tags = self
+            .tags
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        if tags.is_empty() {
+            return Ok(ToolResponse::success("No windows tagged yet"));
+        }
 
-        let json = serde_json::to_string_pretty(&outputs).map_err(|e| {
-            McpError::internal_error(format!("Failed to serialize outputs: {}", e), None)
-        })?;
+        let mut conn = self.connect().await?;
+        let tree = self.fetch_tree(&mut conn).await?;
 
-        debug!("Found {} outputs", outputs.len());
-        Ok(CallToolResult::success(vec![Content::text(json)]))
+        let entries = resolve_tags(&tags, &tree);
+        ToolResponse::data(format!("{} tagged windows", entries.len()), &entries)
     }
 
-    /// Get all marks
-    #[rmcp::tool(description = "Get all window marks (user-assigned labels for windows)")]
-    pub async fn get_marks(&self) -> Result<CallToolResult, McpError> {
-        info!("Getting marks");
+    /// List each window's process with its memory use
+    #[rmcp::tool(description = "List every window with its process: pid, command name, and resident memory (RSS, KiB) from /proc. pid comes from Sway or the window's _NET_WM_PID (via xprop on i3).")]
+    pub async fn get_window_processes(&self) -> Result<CallToolResult, McpError> {
+        info!("Getting window processes");
         let mut conn = self.connect().await?;
 
-        let marks = conn.get_marks().await.map_err(|e| {
-            error!("Failed to get marks: {}", e);
-            McpError::internal_error(format!("Failed to get marks: {}", e), None)
-        })?;
+        let tree = self.fetch_raw_tree(&mut conn).await?;
+        // The xprop and /proc lookups below don't need i3, so free the connection
+        drop(conn);
+        let mut windows = Vec::new();
+        raw_tree_windows(&tree, &mut windows);
 
-        if marks.0.is_empty() {
-            Ok(CallToolResult::success(vec![Content::text(
-                "No marks defined".to_string()
-            )]))
-        } else {
-            Ok(CallToolResult::success(vec![Content::text(
-                format!("Marks:\n{}", marks.0.join("\n"))
-            )]))
+        for window in &mut windows {
+            if window.pid.is_none() {
+                if let Some(id) = window.window {
+                    let output = tokio::process::Command::new("xprop")
+                        .args(["-id", &id.to_string(), "_NET_WM_PID"])
+                        .output()
+                        .await;
+                    window.pid = output
+                        .ok()
+                        .filter(|o| o.status.success())
+                        .and_then(|o| parse_xprop_pid(&String::from_utf8_lossy(&o.stdout)));
+                }
+            }
+            if let Some(pid) = window.pid {
+                (window.command, window.rss_kb) = proc_command_and_rss(Path::new("/proc"), pid);
+            }
         }
+
+        let without_pid = windows.iter().filter(|w| w.pid.is_none()).count();
+        let message = match without_pid {
+            0 => format!("{} windows", windows.len()),
+            n => format!("{} windows ({} without a known pid)", windows.len(), n),
+        };
+        ToolResponse::data(message, &windows)
     }
 
     /// Get all binding modes
@@ -433,16 +7714,22 @@ impl I3Server {
         info!("Getting binding modes");
         let mut conn = self.connect().await?;
 
-        let modes = conn.get_binding_modes().await.map_err(|e| {
-            error!("Failed to get binding modes: {}", e);
-            McpError::internal_error(format!("Failed to get binding modes: {}", e), None)
-        })?;
+        let modes = self.fetch_binding_modes(&mut conn).await?;
 
-        let json = serde_json::to_string_pretty(&modes).map_err(|e| {
-            McpError::internal_error(format!("Failed to serialize binding modes: {}", e), None)
-        })?;
+        ToolResponse::data("Binding modes", &modes)
+    }
 
-        Ok(CallToolResult::success(vec![Content::text(json)]))
+    /// Get the binding mode that is active right now
+    #[rmcp::tool(description = "Get the active binding mode (e.g. 'default' or 'resize'), tracked from i3 mode events. Check it before sending keys or commands that depend on the mode.")]
+    pub async fn get_current_mode(&self) -> Result<CallToolResult, McpError> {
+        info!("Getting current binding mode");
+        let mode = self
+            .events()
+            .mode
+            .clone()
+            .unwrap_or_else(|| "default".to_string());
+
+        ToolResponse::data(format!("Current mode: {}", mode), &mode)
     }
 
     /// Get i3 version info
@@ -451,16 +7738,46 @@ impl I3Server {
         info!("Getting i3 version");
         let mut conn = self.connect().await?;
 
-        let version = conn.get_version().await.map_err(|e| {
-            error!("Failed to get version: {}", e);
-            McpError::internal_error(format!("Failed to get version: {}", e), None)
-        })?;
+        let version = self.fetch_version(&mut conn).await?;
 
-        let json = serde_json::to_string_pretty(&version).map_err(|e| {
-            McpError::internal_error(format!("Failed to serialize version: {}", e), None)
-        })?;
+        ToolResponse::data(format!("i3 {}", version.human_readable), &version)
+    }
+
+    /// Drop the shared IPC connection and open a new one
+    #[rmcp::tool(description = "Drop the server's i3 connection and open a fresh one, returning the i3 version now answering. Use after an i3 restart or socket change if calls keep failing. Safe to call at any time.")]
+    pub async fn reconnect(&self) -> Result<CallToolResult, McpError> {
+        info!("Reconnecting to i3");
+        let (mut conn, replaced) = self.reconnect_conn().await?;
+
+        let version = self.fetch_version(&mut conn).await?;
+        ToolResponse::data(
+            format!("Reconnected to i3 {}", version.human_readable),
+            &serde_json::json!({ "version": version.human_readable, "replaced": replaced }),
+        )
+    }
+
+    /// Get the loaded config file's path, size, and modification time
+    #[rmcp::tool(description = "Get the path, size, and modification time of the config file i3 loaded (not its contents), to cheaply detect config changes")]
+    pub async fn get_config_info(&self) -> Result<CallToolResult, McpError> {
+        info!("Getting config file info");
+        let mut conn = self.connect().await?;
+
+        let version = self.fetch_version(&mut conn).await?;
+        let path = version.loaded_config_file_name;
+        if path.is_empty() {
+            return Ok(ToolResponse::failure(
+                "i3 did not report a loaded config file",
+            ));
+        }
 
-        Ok(CallToolResult::success(vec![Content::text(json)]))
+        let info = ConfigFileInfo::stat(&path);
+        match &info.error {
+            None => ToolResponse::data(format!("Config file: {}", path), &info),
+            Some(e) => ToolResponse::failure_data(
+                format!("Config file {} can't be read: {}", path, e),
+                &info,
+            ),
+        }
     }
 
     /// Get scratchpad windows
@@ -469,10 +7786,7 @@ impl I3Server {
         info!("Getting scratchpad windows");
         let mut conn = self.connect().await?;
 
-        let tree = conn.get_tree().await.map_err(|e| {
-            error!("Failed to get tree: {}", e);
-            McpError::internal_error(format!("Failed to get tree: {}", e), None)
-        })?;
+        let tree = self.fetch_tree(&mut conn).await?;
 
         // Find scratchpad workspace and list its windows
         fn find_scratchpad_windows(node: &Node) -> Vec<String> {
@@ -508,30 +7822,586 @@ impl I3Server {
         let scratchpad_windows = find_scratchpad_windows(&tree);
 
         if scratchpad_windows.is_empty() {
-            Ok(CallToolResult::success(vec![Content::text(
-                "Scratchpad is empty".to_string()
-            )]))
+            Ok(ToolResponse::success("Scratchpad is empty"))
+        } else {
+            Ok(ToolResponse::success(format!(
+                "Scratchpad windows ({}):\n{}",
+                scratchpad_windows.len(),
+                scratchpad_windows.join("\n")
+            )))
+        }
+    }
+
+    /// List scratchpad entries with recall commands, flagging dead placeholders
+    #[rmcp::tool(description = "Audit the scratchpad: list every entry with its windows and the '[con_id=N] scratchpad show' command that recalls it, and flag placeholders that no longer hold a window. Useful for finding forgotten scratchpad windows.")]
+    pub async fn audit_scratchpad(&self) -> Result<CallToolResult, McpError> {
+        info!("Auditing scratchpad");
+        let mut conn = self.connect().await?;
+
+        let tree = self.fetch_tree(&mut conn).await?;
+        let entries = scratchpad_entries(&tree);
+        let placeholders = entries.iter().filter(|e| e.placeholder).count();
+
+        let summary = if entries.is_empty() {
+            "Scratchpad is empty".to_string()
+        } else {
+            format!(
+                "{} scratchpad entr{}, {} dead placeholder(s)",
+                entries.len(),
+                if entries.len() == 1 { "y" } else { "ies" },
+                placeholders
+            )
+        };
+        ToolResponse::data(summary, &entries)
+    }
+
+    /// Find floating windows that are partly or entirely off-screen
+    #[rmcp::tool(description = "Find floating windows lying fully or partially outside every active output (e.g. after unplugging a monitor), each with the share still visible and a '[con_id=N] move position center' command to bring it back")]
+    pub async fn find_offscreen_windows(&self) -> Result<CallToolResult, McpError> {
+        info!("Finding off-screen windows");
+        let mut conn = self.connect().await?;
+
+        let outputs = self.fetch_outputs(&mut conn).await?;
+        let screens: Vec<Rect> = outputs
+            .iter()
+            .filter(|o| o.active)
+            .map(|o| o.rect.clone())
+            .collect();
+        let tree = self.fetch_tree(&mut conn).await?;
+        let windows = offscreen_windows(&tree, &screens);
+
+        let message = if windows.is_empty() {
+            "Every floating window is on an active output".to_string()
+        } else {
+            format!("{} floating window(s) off-screen", windows.len())
+        };
+        ToolResponse::data(message, &windows)
+    }
+
+    /// Move the focused window to an output
+    #[rmcp::tool(description = "Move the focused window to an output/monitor. With fallback_primary, moves to the primary output instead if the requested one isn't active (e.g. an unplugged dock monitor).")]
+    pub async fn move_window_to_output(
+        &self,
+        Parameters(params): Parameters<MoveWindowToOutputParams>,
+    ) -> Result<CallToolResult, McpError> {
+        info!("Moving window to output {}", params.output);
+        let mut conn = self.connect().await?;
+
+        let outputs = self.fetch_outputs(&mut conn).await?;
+        let fallback_primary = params.fallback_primary.unwrap_or(false);
+        let (output, fell_back) = resolve_output(&outputs, &params.output, fallback_primary)?;
+        if fell_back {
+            debug!(
+                "Output {} inactive, falling back to {}",
+                params.output, output
+            );
+        }
+
+        let command = format!("move container to output {}", output);
+        let results = self
+            .send_command(&mut conn, &command, "move window")
+            .await?;
+
+        match failure_message(&results) {
+            None if fell_back => Ok(ToolResponse::success(format!(
+                "Output '{}' is not active; moved window to primary output '{}' instead",
+                params.output, output
+            ))),
+            None => Ok(ToolResponse::success(format!(
+                "Moved window to output '{}'",
+                output
+            ))),
+            Some(errors) => Ok(ToolResponse::failure(format!(
+                "Failed to move window: {}",
+                errors
+            ))),
+        }
+    }
+
+    /// Move the focused window to an output, then to an absolute position
+    #[rmcp::tool(description = "Move the focused (floating) window to an output and place it at an absolute pixel position")]
+    pub async fn move_window_to_position_on_output(
+        &self,
+        Parameters(params): Parameters<MoveWindowToPositionOnOutputParams>,
+    ) -> Result<CallToolResult, McpError> {
+        info!(
+            "Moving window to output {} at ({}, {})",
+            params.output, params.x, params.y
+        );
+        let mut conn = self.connect().await?;
+
+        let outputs = self.fetch_outputs(&mut conn).await?;
+        let (output, _) = resolve_output(&outputs, &params.output, false)?;
+
+        // Focus stays on the old output, so both steps name the window
+        let tree = self.fetch_tree(&mut conn).await?;
+        let Some((node, _)) = find_focused(&tree) else {
+            return Ok(ToolResponse::failure("No focused window"));
+        };
+        if node.node_type == NodeType::Workspace {
+            return Ok(ToolResponse::failure(
+                "The focused workspace is empty; there is no window to move",
+            ));
+        }
+        let con_id = node.id;
+
+        let mut steps = Vec::new();
+
+        let command = format!(
+            "[con_id={}] move container to output \"{}\"",
+            con_id,
+            escape_criteria_value(output)
+        );
+        let results = self
+            .send_command(&mut conn, &command, "move window")
+            .await?;
+        steps.push(StepOutcome::from_results(command, &results));
+        if steps[0].error.is_some() {
+            return Ok(ToolResponse::error(format!(
+                "Aborted: could not move window to output '{}'\n{}",
+                params.output,
+                format_steps(&steps)
+            )));
+        }
+
+        let command = format!(
+            "[con_id={}] move position {} px {} px",
+            con_id, params.x, params.y
+        );
+        let results = self
+            .send_command(&mut conn, &command, "move window")
+            .await?;
+        steps.push(StepOutcome::from_results(command, &results));
+
+        if steps[1].error.is_some() {
+            Ok(ToolResponse::error(format!(
+                "Moved window to output '{}' but failed to position it\n{}",
+                params.output,
+                format_steps(&steps)
+            )))
+        } else {
+            Ok(ToolResponse::success(format!(
+                "Moved window to output '{}' at ({}, {})\n{}",
+                params.output,
+                params.x,
+                params.y,
+                format_steps(&steps)
+            )))
+        }
+    }
+
+    /// Add a keybinding at runtime
+    #[rmcp::tool(description = "Bind a key combo to an i3 command at runtime (bindsym). Runtime bindings are lost on reload/restart.")]
+    pub async fn bind_key(
+        &self,
+        Parameters(params): Parameters<BindKeyParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let command = bind_key_command(
+            &params.keys,
+            &params.command,
+            params.release.unwrap_or(false),
+        )?;
+        info!("Binding key: {}", command);
+        let mut conn = self.connect().await?;
+
+        let results = self.send_command(&mut conn, &command, "bind key").await?;
+
+        match failure_message(&results) {
+            None => Ok(ToolResponse::success(format!(
+                "Bound '{}' to '{}' (until next reload)",
+                params.keys.trim(),
+                params.command.trim()
+            ))),
+            Some(errors) => Ok(ToolResponse::failure(format!(
+                "Failed to bind key: {}",
+                errors
+            ))),
+        }
+    }
+
+    /// Remove a keybinding at runtime
+    #[rmcp::tool(description = "Remove a key binding at runtime (unbindsym). Bindings from the config file return on reload.")]
+    pub async fn unbind_key(
+        &self,
+        Parameters(params): Parameters<UnbindKeyParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let command = unbind_key_command(&params.keys)?;
+        info!("Unbinding key: {}", command);
+        let mut conn = self.connect().await?;
+
+        let results = self
+            .send_command(&mut conn, &command, "unbind key")
+            .await?;
+
+        match failure_message(&results) {
+            None => Ok(ToolResponse::success(format!(
+                "Unbound '{}'",
+                params.keys.trim()
+            ))),
+            Some(errors) => Ok(ToolResponse::failure(format!(
+                "Failed to unbind key: {}",
+                errors
+            ))),
+        }
+    }
+
+    /// Build an escaped criteria string from individual fields
+    #[rmcp::tool(description = "Build a correctly quoted and escaped i3 criteria string (e.g. [class=\"Firefox\" title=\"GitHub\"]) from individual fields. Does not talk to i3.")]
+    pub async fn build_criteria(
+        &self,
+        Parameters(params): Parameters<BuildCriteriaParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let criteria = build_criteria(&[
+            ("class", params.class.as_deref()),
+            ("instance", params.instance.as_deref()),
+            ("title", params.title.as_deref()),
+            ("con_mark", params.con_mark.as_deref()),
+            ("window_role", params.window_role.as_deref()),
+        ])?;
+        debug!("Built criteria: {}", criteria);
+
+        Ok(ToolResponse::success(criteria))
+    }
+
+    /// Check an i3 command's syntax without sending it
+    #[rmcp::tool(description = "Check an i3 command for syntax mistakes without running it: unbalanced quotes or [criteria] brackets, invalid criteria, unknown command keywords, and missing arguments. Returns { valid, issues }. Does not talk to i3, so it can't catch every error.")]
+    pub async fn validate_command(
+        &self,
+        Parameters(params): Parameters<ValidateCommandParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let issues = command_issues(&params.command);
+        debug!("Validated command {:?}: {:?}", params.command, issues);
+
+        let message = if issues.is_empty() {
+            "No problems found".to_string()
+        } else {
+            format!("{} problem(s) found", issues.len())
+        };
+        ToolResponse::data(
+            message,
+            &serde_json::json!({ "valid": issues.is_empty(), "issues": issues }),
+        )
+    }
+
+    /// Toggle i3's debug log and in-memory (shm) log
+    #[rmcp::tool(description = "Enable or disable i3's debug logging (debuglog and shmlog), e.g. while troubleshooting window placement")]
+    pub async fn set_debug_logging(
+        &self,
+        Parameters(params): Parameters<SetDebugLoggingParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let state = if params.enable { "on" } else { "off" };
+        info!("Setting i3 debug logging {}", state);
+        let mut conn = self.connect().await?;
+
+        // Run separately so a build without shmlog support doesn't hide the debuglog result
+        let mut steps = Vec::new();
+        for command in [format!("debuglog {}", state), format!("shmlog {}", state)] {
+            let results = self
+                .send_command(&mut conn, &command, "set debug logging")
+                .await?;
+            steps.push(StepOutcome::from_results(command, &results));
+        }
+
+        let text = format_steps(&steps);
+        if steps.iter().all(|s| s.error.is_some()) {
+            Ok(ToolResponse::error(format!(
+                "Debug logging not available in this i3\n{}",
+                text
+            )))
         } else {
-            Ok(CallToolResult::success(vec![Content::text(
-                format!("Scratchpad windows ({}):\n{}",
-                    scratchpad_windows.len(),
-                    scratchpad_windows.join("\n"))
-            )]))
+            Ok(ToolResponse::success(format!(
+                "Debug logging {}\n{}",
+                state, text
+            )))
+        }
+    }
+
+    /// Report where i3's shm debug log can be read from
+    #[rmcp::tool(description = "Get the location of i3's in-memory debug log (shmlog), if the running i3 reports it")]
+    pub async fn get_debuglog(&self) -> Result<CallToolResult, McpError> {
+        info!("Getting debug log location");
+        let mut conn = self.connect().await?;
+
+        let version = self.fetch_raw_version(&mut conn).await?;
+        let shmlog_path = version
+            .get("shmlog_path")
+            .and_then(|p| p.as_str())
+            .filter(|p| !p.is_empty());
+
+        match shmlog_path {
+            Some(path) => Ok(ToolResponse::success(format!(
+                "i3 shmlog: {}\nRead it with: i3-dump-log",
+                path
+            ))),
+            None => Ok(ToolResponse::failure(
+                "shmlog location not available from this i3. Enable it with set_debug_logging, \
+                 then read it with `i3-dump-log`.",
+            )),
+        }
+    }
+
+    /// Report which i3 socket the server talks to
+    #[rmcp::tool(description = "Report the i3 IPC socket the server connects to, how it was resolved ($I3SOCK or i3 --get-socketpath), the $I3SOCK/$SWAYSOCK values, and the version reachable there. Useful with nested i3 (e.g. in Xephyr).")]
+    pub async fn get_socket_info(&self) -> Result<CallToolResult, McpError> {
+        info!("Getting socket info");
+        let i3sock = std::env::var("I3SOCK").ok();
+        let swaysock = std::env::var("SWAYSOCK").ok();
+        let resolved = resolve_socket_path(i3sock.as_deref(), i3_get_socketpath);
+
+        let (socket, source) = match &resolved {
+            Ok((path, source)) => (Some(path.clone()), Some(*source)),
+            Err(_) => (None, None),
+        };
+        let mut info = serde_json::json!({
+            "socket": socket,
+            "source": source,
+            "I3SOCK": i3sock,
+            "SWAYSOCK": swaysock,
+        });
+
+        if let Err(e) = &resolved {
+            info["error"] = format!("Could not resolve the socket path: {}", e).into();
+            return ToolResponse::failure_data("No i3 socket found", &info);
+        }
+
+        let version = match self.connect().await {
+            Ok(mut conn) => self.fetch_version(&mut conn).await,
+            Err(e) => Err(e),
+        };
+        match version {
+            Ok(version) => {
+                info["version"] = version.human_readable.clone().into();
+                info["loaded_config_file_name"] = version.loaded_config_file_name.into();
+                ToolResponse::data(
+                    format!(
+                        "Connected to i3 {} at {}",
+                        version.human_readable,
+                        socket.unwrap_or_default()
+                    ),
+                    &info,
+                )
+            }
+            Err(e) => {
+                info["error"] = e.message.to_string().into();
+                ToolResponse::failure_data(
+                    format!("No i3 reachable at {}", socket.unwrap_or_default()),
+                    &info,
+                )
+            }
+        }
+    }
+
+    /// Send a tick through i3's event queue
+    #[rmcp::tool(description = "Send an i3 tick event. Once the tick comes back on the event stream, every command sent before it has been processed by i3.")]
+    pub async fn send_tick(
+        &self,
+        Parameters(params): Parameters<SendTickParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let payload = params.payload.unwrap_or_else(|| {
+            let n = self.tick_counter.fetch_add(1, Ordering::Relaxed);
+            format!("rmcp-i3-tick-{}", n)
+        });
+        info!("Sending tick: {}", payload);
+
+        match self.tick_round_trip(&payload, TICK_TIMEOUT).await? {
+            TickEcho::Rejected(error) => Ok(ToolResponse::failure(format!(
+                "Failed to send tick: {}",
+                error
+            ))),
+            TickEcho::Unconfirmed => Ok(ToolResponse::success(format!(
+                "Sent tick '{}' (event stream not active, so delivery wasn't confirmed)",
+                payload
+            ))),
+            TickEcho::Received(elapsed) => Ok(ToolResponse::success(format!(
+                "Sent tick '{}' (received on event stream after {} ms)",
+                payload,
+                elapsed.as_millis()
+            ))),
+            TickEcho::TimedOut => Ok(ToolResponse::success(format!(
+                "Sent tick '{}' (not seen on event stream within {} ms)",
+                payload,
+                TICK_TIMEOUT.as_millis()
+            ))),
+        }
+    }
+
+    /// Wait until i3 has processed everything sent so far
+    #[rmcp::tool(description = "Synchronize with i3: send a tick and wait for it to come back on the event stream, so every earlier command has been processed before you continue. Returns the round-trip time, or an error result if the tick isn't seen within timeout_ms.")]
+    pub async fn sync_with_i3(
+        &self,
+        Parameters(params): Parameters<SyncWithI3Params>,
+    ) -> Result<CallToolResult, McpError> {
+        let timeout = match params.timeout_ms {
+            None => TICK_TIMEOUT,
+            Some(ms @ 1..=MAX_SYNC_TIMEOUT_MS) => Duration::from_millis(ms),
+            Some(ms) => {
+                return Err(McpError::invalid_params(
+                    format!(
+                        "timeout_ms must be between 1 and {}, got {}",
+                        MAX_SYNC_TIMEOUT_MS, ms
+                    ),
+                    None,
+                ))
+            }
+        };
+        let n = self.tick_counter.fetch_add(1, Ordering::Relaxed);
+        let payload = format!("rmcp-i3-sync-{}", n);
+        info!("Syncing with i3 ({})", payload);
+
+        match self.tick_round_trip(&payload, timeout).await? {
+            TickEcho::Rejected(error) => Ok(ToolResponse::failure(format!(
+                "Failed to send tick: {}",
+                error
+            ))),
+            TickEcho::Unconfirmed => Ok(ToolResponse::failure(
+                "The event stream is not active, so the sync can't be confirmed",
+            )),
+            TickEcho::Received(elapsed) => {
+                let ms = elapsed.as_secs_f64() * 1000.0;
+                ToolResponse::data(
+                    format!("In sync with i3 after {:.1} ms", ms),
+                    &serde_json::json!({ "payload": payload, "round_trip_ms": ms }),
+                )
+            }
+            TickEcho::TimedOut => Ok(ToolResponse::failure(format!(
+                "Tick '{}' not seen on the event stream within {} ms",
+                payload,
+                timeout.as_millis()
+            ))),
         }
     }
+
+    /// Classify the registered tools as mutating or read-only
+    #[rmcp::tool(description = "List every registered tool with whether it can change i3's state (mutating) and its description, e.g. to ask for confirmation before mutating calls. Uses the same classification as read-only mode.")]
+    pub async fn get_tool_metadata(&self) -> Result<CallToolResult, McpError> {
+        info!("Getting tool metadata");
+
+        let mut tools: Vec<serde_json::Value> = self
+            .tool_router
+            .list_all()
+            .into_iter()
+            .map(|tool| {
+                serde_json::json!({
+                    "name": tool.name,
+                    "mutating": is_mutating(&tool.name),
+                    "description": tool.description,
+                })
+            })
+            .collect();
+        tools.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+
+        let mutating = tools.iter().filter(|t| t["mutating"] == true).count();
+        ToolResponse::data(
+            format!("{} tools, {} mutating", tools.len(), mutating),
+            &tools,
+        )
+    }
+
+    /// Report per-tool usage statistics
+    #[rmcp::tool(description = "Get per-tool usage statistics since the server started: call counts, error counts, and average latency. Errors count calls that were rejected or flagged is_error, not actions i3 ran and reported as failed (ok: false)")]
+    pub async fn get_stats(&self) -> Result<CallToolResult, McpError> {
+        info!("Getting tool stats");
+
+        let summary: BTreeMap<String, ToolStatsSummary> = self
+            .stats
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|(tool, stats)| (tool.clone(), stats.into()))
+            .collect();
+
+        ToolResponse::data(format!("Stats for {} tools", summary.len()), &summary)
+    }
+
+    /// Commands recently sent to i3, oldest first
+    #[rmcp::tool(description = "Get the last 50 commands this server sent to i3 (from any tool), oldest first, with timestamps and whether each succeeded. Useful for auditing what was actually done.")]
+    pub async fn get_command_history(&self) -> Result<CallToolResult, McpError> {
+        info!("Getting command history");
+        let history: Vec<CommandRecord> = self
+            .command_history
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .cloned()
+            .collect();
+
+        ToolResponse::data(format!("{} recent commands", history.len()), &history)
+    }
+
+    /// Stop the MCP server (only registered when RMCP_I3_ALLOW_SHUTDOWN=1)
+    #[rmcp::tool(description = "Shut down the rmcp-i3 server. The connection closes shortly after this call returns.")]
+    pub async fn shutdown_server(&self) -> Result<CallToolResult, McpError> {
+        info!("Shutdown requested by client");
+
+        // notify_one stores a permit, so main picks it up even if it isn't waiting yet
+        self.shutdown.notify_one();
+
+        Ok(ToolResponse::success("Shutting down rmcp-i3 server"))
+    }
 }
 
 // ============================================================================
 // Server Handler Implementation
 // ============================================================================
 
-#[rmcp::tool_handler]
 impl ServerHandler for I3Server {
+    async fn call_tool(
+        &self,
+        request: CallToolRequestParam,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let tool = request.name.to_string();
+        let known = self.tool_router.has_route(&tool);
+        let started = Instant::now();
+
+        let tcc = ToolCallContext::new(self, request, context);
+        let result = self.tool_router.call(tcc).await;
+
+        if known {
+            let failed = result
+                .as_ref()
+                .map_or(true, |r| r.is_error.unwrap_or(false));
+            self.record_call(&tool, started.elapsed(), failed);
+        }
+        result
+    }
+
+    async fn list_tools(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListToolsResult, McpError> {
+        Ok(ListToolsResult {
+            tools: self.tool_router.list_all(),
+            meta: None,
+            next_cursor: None,
+        })
+    }
+
+    async fn on_initialized(&self, context: NotificationContext<RoleServer>) {
+        info!("Client initialized");
+        *self.peer.lock().unwrap_or_else(PoisonError::into_inner) = Some(context.peer);
+    }
+
+    async fn set_level(
+        &self,
+        request: SetLevelRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<(), McpError> {
+        // Tree-change notifications are sent at info level
+        let enabled = request.level as u8 <= LoggingLevel::Info as u8;
+        info!("Client log level {:?}", request.level);
+        self.notify_tree_changes.store(enabled, Ordering::Relaxed);
+        Ok(())
+    }
+
     fn get_info(&self) -> ServerInfo {
         InitializeResult {
             protocol_version: ProtocolVersion::default(),
             capabilities: ServerCapabilities {
                 tools: Some(ToolsCapability { list_changed: None }),
+                // Used for tree-change notifications (see spawn_event_listener)
+                logging: Some(JsonObject::new()),
                 ..Default::default()
             },
             server_info: Implementation {
@@ -541,13 +8411,10 @@ impl ServerHandler for I3Server {
                 icons: None,
                 website_url: None,
             },
-            instructions: Some(
-                "MCP server for controlling the i3 window manager. \
-                 Use get_workspaces to list workspaces, get_tree for window layout, \
-                 switch_workspace/focus_window/move_to_workspace for navigation, \
-                 and run_command for arbitrary i3 commands."
-                    .to_string(),
-            ),
+            instructions: Some(self.instructions.clone()),
         }
     }
 }
+
+#[cfg(test)]
+mod tests;
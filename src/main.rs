@@ -1,28 +1,293 @@
 //! rmcp-i3: MCP server for i3 window manager control
 //!
-//! Run with: `rmcp-i3` (serves on stdio)
+//! Run with: `rmcp-i3` (serves on stdio). Set `RMCP_I3_TRANSPORT=tcp|unix` and
+//! `RMCP_I3_LISTEN` to run as a daemon accepting several clients instead.
 
-use rmcp::ServiceExt;
+use rmcp::{transport::IntoTransport, RoleServer, ServiceExt};
 use rmcp_i3::I3Server;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tokio_i3ipc::I3;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
+/// How long the startup probe waits for i3 before giving up
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Where the server accepts MCP clients (selected by RMCP_I3_TRANSPORT)
+#[derive(Debug)]
+enum Transport {
+    /// A single client on stdin/stdout (the default)
+    Stdio,
+    /// Clients connecting to a TCP address
+    Tcp(String),
+    /// Clients connecting to a Unix socket
+    Unix(PathBuf),
+}
+
+impl Transport {
+    /// Read RMCP_I3_TRANSPORT and, for listeners, the RMCP_I3_LISTEN address
+    fn from_env() -> anyhow::Result<Self> {
+        let kind = std::env::var("RMCP_I3_TRANSPORT").unwrap_or_default();
+        let listen = || {
+            std::env::var("RMCP_I3_LISTEN")
+                .ok()
+                .filter(|v| !v.is_empty())
+                .ok_or_else(|| {
+                    anyhow::anyhow!("RMCP_I3_TRANSPORT={} requires RMCP_I3_LISTEN", kind)
+                })
+        };
+
+        match kind.to_ascii_lowercase().as_str() {
+            "" | "stdio" => Ok(Self::Stdio),
+            "tcp" => Ok(Self::Tcp(listen()?)),
+            "unix" => Ok(Self::Unix(PathBuf::from(listen()?))),
+            other => anyhow::bail!(
+                "Unknown RMCP_I3_TRANSPORT '{}': expected stdio, tcp, or unix",
+                other
+            ),
+        }
+    }
+}
+
+/// Check that i3 is reachable, logging a warning (not failing) if it isn't.
+/// Tools still work if i3 starts later, so this is purely informational.
+async fn probe_i3() {
+    let probe = async {
+        let mut conn = I3::connect().await?;
+        conn.get_version().await
+    };
+
+    match tokio::time::timeout(PROBE_TIMEOUT, probe).await {
+        Ok(Ok(version)) => tracing::info!("Connected to i3 {}", version.human_readable),
+        Ok(Err(e)) => tracing::warn!(
+            "i3 is not reachable ({}). Tools will fail until i3 is running.",
+            e
+        ),
+        Err(_) => tracing::warn!(
+            "i3 did not respond within {}s. Tools will fail until i3 is reachable.",
+            PROBE_TIMEOUT.as_secs()
+        ),
+    }
+}
+
+/// Wait for SIGINT or SIGTERM, returning the signal's name
+async fn termination_signal() -> anyhow::Result<&'static str> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let name = tokio::select! {
+        result = tokio::signal::ctrl_c() => result.map(|_| "SIGINT")?,
+        _ = sigterm.recv() => "SIGTERM",
+    };
+    Ok(name)
+}
+
+/// Resolve once the server is stopping (never, if every sender is gone)
+async fn stopped(stop: &mut watch::Receiver<bool>) {
+    if stop.wait_for(|stop| *stop).await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Serve one MCP client until it disconnects, calls shutdown_server, or the
+/// server is stopping (`stop` becomes true). Returns whether the client
+/// requested shutdown.
+async fn run_session<T, E, A>(transport: T, mut stop: watch::Receiver<bool>) -> anyhow::Result<bool>
+where
+    T: IntoTransport<RoleServer, E, A>,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let server = I3Server::new();
+    let shutdown = server.shutdown_signal();
+    let events = server.spawn_event_listener();
+    let service = tokio::select! {
+        service = server.serve(transport) => service,
+        _ = stopped(&mut stop) => {
+            events.abort();
+            return Ok(false);
+        }
+    };
+    let service = match service {
+        Ok(service) => service,
+        Err(e) => {
+            events.abort();
+            return Err(e.into());
+        }
+    };
+
+    let cancel = service.cancellation_token();
+    let waiting = service.waiting();
+    tokio::pin!(waiting);
+
+    let requested = tokio::select! {
+        result = &mut waiting => {
+            result?;
+            false
+        }
+        _ = shutdown.notified() => {
+            // The short delay lets the tool's confirmation reach the client
+            // before the transport closes
+            tracing::info!("Shutdown requested, stopping service");
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            cancel.cancel();
+            waiting.await?;
+            true
+        }
+        _ = stopped(&mut stop) => {
+            cancel.cancel();
+            waiting.await?;
+            false
+        }
+    };
+
+    events.abort();
+    Ok(requested)
+}
+
+/// Reap a finished client session, stopping the server if it requested shutdown
+fn session_finished(
+    result: Result<(String, anyhow::Result<bool>), tokio::task::JoinError>,
+    stop: &watch::Sender<bool>,
+) {
+    match result {
+        Ok((peer, Ok(requested))) => {
+            tracing::info!("Client disconnected: {}", peer);
+            if requested {
+                stop.send_replace(true);
+            }
+        }
+        Ok((peer, Err(e))) => tracing::warn!("Session {} failed: {}", peer, e),
+        Err(e) => tracing::warn!("Session task failed: {}", e),
+    }
+}
+
+/// Accept MCP clients from `accept` until `stop` becomes true, then wait for
+/// the open sessions to wind down
+async fn serve_clients<S, F, Fut>(mut accept: F, stop: &watch::Sender<bool>) -> anyhow::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = std::io::Result<(S, String)>>,
+{
+    let mut stopping = stop.subscribe();
+    let mut sessions = JoinSet::new();
+
+    let result = loop {
+        tokio::select! {
+            accepted = accept() => {
+                let (stream, peer) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => break Err(e.into()),
+                };
+                tracing::info!("Client connected: {}", peer);
+                let session = run_session(stream, stop.subscribe());
+                sessions.spawn(async move { (peer, session.await) });
+            }
+            Some(done) = sessions.join_next(), if !sessions.is_empty() => {
+                session_finished(done, stop);
+            }
+            _ = stopped(&mut stopping) => break Ok(()),
+        }
+    };
+
+    stop.send_replace(true);
+    while let Some(done) = sessions.join_next().await {
+        session_finished(done, stop);
+    }
+    result
+}
+
+/// Accept MCP clients on a TCP address
+async fn serve_tcp(addr: &str, stop: &watch::Sender<bool>) -> anyhow::Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    tracing::info!("Listening on tcp://{}", listener.local_addr()?);
+    if !listener.local_addr()?.ip().is_loopback() {
+        tracing::warn!(
+            "Listening on a non-loopback address: anyone who can reach it can control i3"
+        );
+    }
+
+    let accept = || async {
+        let (stream, peer) = listener.accept().await?;
+        Ok((stream, peer.to_string()))
+    };
+    serve_clients(accept, stop).await
+}
+
+/// Accept MCP clients on a Unix socket (owner-only)
+async fn serve_unix(path: &Path, stop: &watch::Sender<bool>) -> anyhow::Result<()> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    // Replace a stale socket from a previous run, but never an unrelated file
+    if let Ok(meta) = std::fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() {
+            anyhow::bail!("{} exists and is not a socket", path.display());
+        }
+        std::fs::remove_file(path)?;
+    }
+
+    let listener = tokio::net::UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    tracing::info!("Listening on unix://{}", path.display());
+
+    let next_id = AtomicU64::new(0);
+    let accept = || async {
+        let (stream, _) = listener.accept().await?;
+        let id = next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        Ok((stream, format!("unix#{}", id)))
+    };
+    let result = serve_clients(accept, stop).await;
+
+    let _ = std::fs::remove_file(path);
+    result
+}
+
+fn main() -> anyhow::Result<()> {
     // Initialize tracing (to stderr so it doesn't interfere with stdio transport)
     tracing_subscriber::registry()
         .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
         .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
         .init();
 
-    tracing::info!("Starting rmcp-i3 server");
+    let runtime = tokio::runtime::Runtime::new()?;
+    let result = runtime.block_on(run());
+    // A blocking stdin read never finishes on its own if the client keeps the
+    // pipe open, so don't wait for it after a signal stopped the session
+    runtime.shutdown_background();
 
-    // Create server and serve on stdio
-    let server = I3Server::new();
-    let service = server.serve(rmcp::transport::stdio()).await?;
+    tracing::info!("rmcp-i3 server stopped");
+    result
+}
 
-    // Wait for shutdown
-    service.waiting().await?;
+async fn run() -> anyhow::Result<()> {
+    let transport = Transport::from_env()?;
+    tracing::info!("Starting rmcp-i3 server");
+    probe_i3().await;
 
-    tracing::info!("rmcp-i3 server stopped");
+    // SIGINT/SIGTERM (e.g. from systemd) stop every session cleanly
+    let (stop, _) = watch::channel(false);
+    let signal_stop = stop.clone();
+    tokio::spawn(async move {
+        match termination_signal().await {
+            Ok(name) => tracing::info!("Received {}, shutting down", name),
+            Err(e) => {
+                tracing::warn!("Failed to install signal handlers: {}", e);
+                return;
+            }
+        }
+        signal_stop.send_replace(true);
+    });
+
+    match transport {
+        Transport::Stdio => {
+            run_session(rmcp::transport::stdio(), stop.subscribe()).await?;
+        }
+        Transport::Tcp(addr) => serve_tcp(&addr, &stop).await?,
+        Transport::Unix(path) => serve_unix(&path, &stop).await?,
+    }
     Ok(())
 }
@@ -0,0 +1,4865 @@
+//! Unit tests for the helpers behind the tools, run against synthetic trees
+
+use super::*;
+use tokio_i3ipc::reply::{
+    FullscreenMode, NodeBorder, NodeOrientation, ScratchpadState, WindowProperties,
+};
+
+// ============================================================================
+// Fixtures
+// ============================================================================
+
+fn rect(x: isize, y: isize, width: isize, height: isize) -> Rect {
+    Rect {
+        x,
+        y,
+        width,
+        height,
+    }
+}
+
+/// A childless node of the given type
+fn node(id: usize, node_type: NodeType) -> Node {
+    Node {
+        id,
+        name: None,
+        num: None,
+        node_type,
+        layout: NodeLayout::SplitH,
+        output: None,
+        orientation: NodeOrientation::Horizontal,
+        border: NodeBorder::Normal,
+        scratchpad_state: ScratchpadState::None,
+        percent: None,
+        rect: rect(0, 0, 0, 0),
+        window_rect: rect(0, 0, 0, 0),
+        deco_rect: rect(0, 0, 0, 0),
+        geometry: rect(0, 0, 0, 0),
+        window: None,
+        window_properties: None,
+        window_type: None,
+        current_border_width: 0,
+        urgent: false,
+        marks: None,
+        focused: false,
+        focus: Vec::new(),
+        sticky: false,
+        floating: None,
+        floating_nodes: Vec::new(),
+        fullscreen_mode: FullscreenMode::None,
+        nodes: Vec::new(),
+    }
+}
+
+/// A tiled X11 window with the given class and title (X11 id is con_id + 1000)
+fn window(id: usize, class: &str, title: &str) -> Node {
+    Node {
+        name: Some(title.to_string()),
+        window: Some(id + 1000),
+        window_properties: Some(WindowProperties {
+            title: Some(title.to_string()),
+            instance: Some(class.to_lowercase()),
+            class: Some(class.to_string()),
+            window_role: None,
+            transient_for: None,
+            machine: None,
+        }),
+        ..node(id, NodeType::Con)
+    }
+}
+
+/// A split container holding `children`
+fn split(id: usize, layout: NodeLayout, children: Vec<Node>) -> Node {
+    Node {
+        layout,
+        nodes: children,
+        ..node(id, NodeType::Con)
+    }
+}
+
+/// A workspace holding tiled `children`
+fn workspace(id: usize, name: &str, children: Vec<Node>) -> Node {
+    Node {
+        name: Some(name.to_string()),
+        num: name.split(':').next().and_then(|n| n.parse().ok()),
+        nodes: children,
+        ..node(id, NodeType::Workspace)
+    }
+}
+
+/// An output holding `workspaces`
+fn output(id: usize, name: &str, workspaces: Vec<Node>) -> Node {
+    Node {
+        name: Some(name.to_string()),
+        layout: NodeLayout::Output,
+        nodes: workspaces,
+        ..node(id, NodeType::Output)
+    }
+}
+
+/// The root of a tree holding `outputs`
+fn root(outputs: Vec<Node>) -> Node {
+    Node {
+        name: Some("root".to_string()),
+        nodes: outputs,
+        ..node(1, NodeType::Root)
+    }
+}
+
+/// The node with con_id `id`, for changing a fixture in place
+fn node_mut(node: &mut Node, id: usize) -> &mut Node {
+    fn find(node: &mut Node, id: usize) -> Option<&mut Node> {
+        if node.id == id {
+            return Some(node);
+        }
+        node.nodes
+            .iter_mut()
+            .chain(node.floating_nodes.iter_mut())
+            .find_map(|child| find(child, id))
+    }
+    find(node, id).unwrap_or_else(|| panic!("no node {}", id))
+}
+
+/// A get_workspaces entry
+fn ws_reply(name: &str, output: &str, focused: bool) -> Workspace {
+    Workspace {
+        id: 0,
+        num: name
+            .split(':')
+            .next()
+            .and_then(|n| n.parse().ok())
+            .unwrap_or(-1),
+        name: name.to_string(),
+        visible: focused,
+        focused,
+        urgent: false,
+        rect: rect(0, 0, 0, 0),
+        output: output.to_string(),
+    }
+}
+
+/// `node` with its rect set
+fn at(node: Node, x: isize, y: isize, width: isize, height: isize) -> Node {
+    Node {
+        rect: rect(x, y, width, height),
+        ..node
+    }
+}
+
+/// `window` floating in its own floating container `id`
+fn floating(id: usize, window: Node) -> Node {
+    Node {
+        rect: window.rect.clone(),
+        nodes: vec![Node {
+            floating: Some(Floating::UserOn),
+            ..window
+        }],
+        ..node(id, NodeType::FloatingCon)
+    }
+}
+
+fn success() -> Success {
+    Success {
+        success: true,
+        error: None,
+    }
+}
+
+fn failed(error: &str) -> Success {
+    Success {
+        success: false,
+        error: Some(error.to_string()),
+    }
+}
+
+/// The JSON envelope a tool result carries
+fn envelope(result: &CallToolResult) -> serde_json::Value {
+    let text = &result.content[0].as_text().expect("text content").text;
+    serde_json::from_str(text).expect("envelope is JSON")
+}
+
+/// Whether a tool result is flagged as a tool error
+fn is_error(result: &CallToolResult) -> bool {
+    result.is_error == Some(true)
+}
+
+/// An active get_outputs entry showing `current`
+fn output_reply(name: &str, rect: Rect, current: Option<&str>) -> Output {
+    Output {
+        name: name.to_string(),
+        active: true,
+        primary: false,
+        current_workspace: current.map(str::to_string),
+        rect,
+    }
+}
+
+fn window_event(change: WindowChange, con_id: usize) -> Event {
+    Event::Window(Box::new(tokio_i3ipc::event::WindowData {
+        change,
+        container: window(con_id, "kitty", "shell"),
+    }))
+}
+
+/// A workspace focus event moving from `old` to `current`
+fn workspace_focus_event(old: Option<&str>, current: &str) -> Event {
+    Event::Workspace(Box::new(tokio_i3ipc::event::WorkspaceData {
+        change: WorkspaceChange::Focus,
+        current: Some(workspace(1, current, vec![])),
+        old: old.map(|name| workspace(2, name, vec![])),
+    }))
+}
+
+/// A binding mode change event
+fn mode_event(change: &str) -> Event {
+    Event::Mode(tokio_i3ipc::event::ModeData {
+        change: change.to_string(),
+        pango_markup: false,
+    })
+}
+
+/// `node` carrying `marks`
+fn marked(node: Node, marks: &[&str]) -> Node {
+    Node {
+        marks: Some(Marks(marks.iter().map(|m| m.to_string()).collect())),
+        ..node
+    }
+}
+
+// ============================================================================
+// Tree walking
+// ============================================================================
+
+#[test]
+fn find_node_with_ancestors_returns_path_from_root() {
+    let tree = root(vec![output(
+        2,
+        "DP-1",
+        vec![workspace(
+            3,
+            "1",
+            vec![split(
+                4,
+                NodeLayout::SplitV,
+                vec![window(5, "Firefox", "web"), window(6, "kitty", "shell")],
+            )],
+        )],
+    )]);
+
+    let (node, ancestors) = find_node_with_ancestors(&tree, 6).unwrap();
+    assert_eq!(node.id, 6);
+    let path: Vec<usize> = ancestors.iter().map(|a| a.id).collect();
+    assert_eq!(path, [1, 2, 3, 4]);
+
+    let (node, ancestors) = find_node_with_ancestors(&tree, 1).unwrap();
+    assert_eq!(node.id, 1);
+    assert!(ancestors.is_empty());
+
+    assert!(find_node_with_ancestors(&tree, 99).is_none());
+}
+
+#[test]
+fn find_node_with_ancestors_searches_floating_nodes() {
+    let mut ws = workspace(3, "1", vec![window(5, "kitty", "tiled")]);
+    let mut floating = node(7, NodeType::FloatingCon);
+    floating.nodes.push(window(8, "pavucontrol", "volume"));
+    ws.floating_nodes.push(floating);
+    let tree = root(vec![output(2, "DP-1", vec![ws])]);
+
+    let (node, ancestors) = find_node_with_ancestors(&tree, 8).unwrap();
+    assert_eq!(node.id, 8);
+    let path: Vec<usize> = ancestors.iter().map(|a| a.id).collect();
+    assert_eq!(path, [1, 2, 3, 7]);
+}
+
+// ============================================================================
+// Key bindings
+// ============================================================================
+
+#[test]
+fn bind_key_command_builds_bindsym() {
+    assert_eq!(
+        bind_key_command(" $mod+Shift+x ", " kill ", false).unwrap(),
+        "bindsym $mod+Shift+x kill"
+    );
+    assert_eq!(
+        bind_key_command("$mod+d", "exec rofi -show run", true).unwrap(),
+        "bindsym --release $mod+d exec rofi -show run"
+    );
+    assert_eq!(unbind_key_command("$mod+d").unwrap(), "unbindsym $mod+d");
+}
+
+#[test]
+fn bind_key_command_rejects_bad_input() {
+    assert!(bind_key_command("", "kill", false).is_err());
+    assert!(bind_key_command("$mod+x", "  ", false).is_err());
+    assert!(bind_key_command("$mod+x y", "kill", false).is_err());
+    assert!(unbind_key_command(" ").is_err());
+}
+
+#[test]
+fn check_dangerous_command_guards_every_part() {
+    assert!(check_dangerous_command("focus left").is_ok());
+    assert!(check_dangerous_command("exec kitty").is_ok());
+    assert!(check_dangerous_command("exit").is_err());
+    assert!(check_dangerous_command("focus left; restart").is_err());
+    assert!(check_dangerous_command("[class=\"x\"] focus, exit").is_err());
+    // Separators inside quotes don't start a new command
+    assert!(check_dangerous_command("exec \"echo; exit\"").is_ok());
+
+    assert!(bind_key_command("$mod+Shift+e", "exit", false).is_err());
+    assert!(bind_key_command("$mod+r", "nop; restart", false).is_err());
+}
+
+// ============================================================================
+// Empty workspaces
+// ============================================================================
+
+#[test]
+fn empty_workspace_names_lists_windowless_workspaces() {
+    let tree = root(vec![output(
+        2,
+        "DP-1",
+        vec![
+            workspace(3, "1", vec![window(5, "kitty", "shell")]),
+            workspace(4, "2:web", vec![split(6, NodeLayout::SplitV, vec![])]),
+        ],
+    )]);
+    // "3" is in get_workspaces but not yet in the fetched tree
+    let workspaces = [
+        ws_reply("1", "DP-1", true),
+        ws_reply("2:web", "DP-1", false),
+        ws_reply("3", "DP-1", false),
+    ];
+
+    assert_eq!(empty_workspace_names(&workspaces, &tree), ["2:web", "3"]);
+}
+
+#[test]
+fn cleanup_workspaces_command_returns_to_current() {
+    let empty = [
+        "2:web".to_string(),
+        "my ws, two".to_string(),
+        "1".to_string(),
+    ];
+    assert_eq!(
+        cleanup_workspaces_command(&empty, "1"),
+        "workspace \"2:web\"; workspace \"my ws, two\"; workspace \"1\""
+    );
+}
+
+// ============================================================================
+// Parameter parsing
+// ============================================================================
+
+#[test]
+fn workspace_param_accepts_number_or_string() {
+    let number: SwitchWorkspaceParams =
+        serde_json::from_value(serde_json::json!({ "workspace": 3 })).unwrap();
+    assert_eq!(number.workspace, "3");
+
+    let string: SwitchWorkspaceParams =
+        serde_json::from_value(serde_json::json!({ "workspace": "3:web" })).unwrap();
+    assert_eq!(string.workspace, "3:web");
+    assert_eq!(string.skip_if_current, None);
+
+    let mixed: ToggleWorkspacesParams =
+        serde_json::from_value(serde_json::json!({ "a": 1, "b": "mail" })).unwrap();
+    assert_eq!((mixed.a.as_str(), mixed.b.as_str()), ("1", "mail"));
+}
+
+#[test]
+fn workspace_param_rejects_other_types() {
+    for value in [
+        serde_json::json!({ "workspace": true }),
+        serde_json::json!({ "workspace": null }),
+        serde_json::json!({ "workspace": ["1"] }),
+        serde_json::json!({}),
+    ] {
+        assert!(serde_json::from_value::<SwitchWorkspaceParams>(value).is_err());
+    }
+}
+
+// ============================================================================
+// Window overview
+// ============================================================================
+
+/// Two outputs side by side: DP-1 with workspaces "notes" and "2", HDMI-1
+/// with "1:web" holding a tiled split and a floating window
+fn two_output_tree() -> Node {
+    let mut web = workspace(
+        20,
+        "1:web",
+        vec![split(
+            21,
+            NodeLayout::SplitV,
+            vec![
+                at(window(22, "Firefox", "GitHub"), 1920, 0, 1920, 540),
+                at(window(23, "kitty", "logs"), 1920, 540, 1920, 540),
+            ],
+        )],
+    );
+    web.floating_nodes.push(floating(
+        24,
+        at(window(25, "Pavucontrol", "Volume"), 2400, 200, 600, 400),
+    ));
+    let mut focused = at(window(12, "Emacs", "init.el"), 0, 0, 1920, 1080);
+    focused.focused = true;
+    let mut urgent = at(window(14, "Slack", "ping"), 0, 0, 1920, 1080);
+    urgent.urgent = true;
+
+    root(vec![
+        at(
+            output(
+                2,
+                "DP-1",
+                vec![
+                    workspace(10, "notes", vec![urgent]),
+                    workspace(11, "2", vec![focused]),
+                ],
+            ),
+            0,
+            0,
+            1920,
+            1080,
+        ),
+        at(output(3, "HDMI-1", vec![web]), 1920, 0, 1920, 1080),
+    ])
+}
+
+/// two_output_tree with i3's internal __i3 output in front, whose
+/// __i3_scratch workspace holds the scratchpad's floating containers
+fn with_scratchpad(scratchpad: Vec<Node>) -> Node {
+    let mut tree = two_output_tree();
+    let mut scratch = workspace(91, "__i3_scratch", vec![]);
+    scratch.floating_nodes = scratchpad;
+    tree.nodes.insert(0, output(90, "__i3", vec![scratch]));
+    tree
+}
+
+#[test]
+fn collect_windows_flattens_and_sorts() {
+    let windows = collect_windows(&two_output_tree());
+    let ids: Vec<usize> = windows.iter().map(|w| w.con_id).collect();
+    // Numbered workspaces in order, unnumbered last; then top-to-bottom, left-to-right
+    assert_eq!(ids, [22, 25, 23, 12, 14]);
+}
+
+#[test]
+fn window_info_populates_fields_from_ancestors() {
+    let windows = collect_windows(&two_output_tree());
+    let by_id = |id| windows.iter().find(|w| w.con_id == id).unwrap();
+
+    let volume = by_id(25);
+    assert_eq!(volume.class.as_deref(), Some("Pavucontrol"));
+    assert_eq!(volume.title.as_deref(), Some("Volume"));
+    assert_eq!(volume.workspace.as_deref(), Some("1:web"));
+    assert_eq!(volume.output.as_deref(), Some("HDMI-1"));
+    assert_eq!(volume.rect, rect(2400, 200, 600, 400));
+    assert!(!volume.focused && !volume.urgent);
+    assert_eq!(volume.label, None);
+
+    assert!(by_id(12).focused);
+    assert!(by_id(14).urgent);
+    assert_eq!(by_id(14).output.as_deref(), Some("DP-1"));
+
+    // The title falls back to the node name when there are no window properties
+    let titled = Node {
+        window_properties: None,
+        ..window(30, "x", "from name")
+    };
+    let info = WindowInfo::from_node(&titled, &[]);
+    assert_eq!(info.title.as_deref(), Some("from name"));
+    assert_eq!(info.class, None);
+    assert_eq!(info.workspace, None);
+
+    let json = serde_json::to_value(by_id(22)).unwrap();
+    assert_eq!(json["workspace"], "1:web");
+    assert!(json.get("workspace_num").is_none());
+}
+
+// ============================================================================
+// Command results
+// ============================================================================
+
+#[test]
+fn align_results_pinpoints_a_middle_failure() {
+    let command = "focus left; [class=\"a;b\"] move scratchpad; layout tabbed";
+    let results = [success(), failed("No window matches"), success()];
+
+    let aligned = align_results(command, &results);
+    let commands: Vec<Option<&str>> = aligned.iter().map(|r| r.command.as_deref()).collect();
+    assert_eq!(
+        commands,
+        [
+            Some("focus left"),
+            Some("[class=\"a;b\"] move scratchpad"),
+            Some("layout tabbed")
+        ]
+    );
+    assert!(aligned[0].success && !aligned[1].success && aligned[2].success);
+    assert_eq!(aligned[1].error.as_deref(), Some("No window matches"));
+
+    let result = command_results_response(&aligned).unwrap();
+    assert!(!is_error(&result));
+    let body = envelope(&result);
+    assert_eq!(body["ok"], false);
+    assert_eq!(body["message"], "1 of 3 command(s) failed");
+    assert_eq!(body["data"][1]["success"], false);
+    assert_eq!(body["data"][2]["command"], "layout tabbed");
+
+    assert_eq!(
+        failure_message(&results).as_deref(),
+        Some("No window matches")
+    );
+    assert_eq!(failure_message(&[success(), success()]), None);
+}
+
+#[test]
+fn align_results_drops_commands_when_counts_differ() {
+    let aligned = align_results("focus left; focus right", &[success()]);
+    assert_eq!(aligned.len(), 1);
+    assert_eq!(aligned[0].command, None);
+}
+
+#[test]
+fn format_steps_numbers_each_outcome() {
+    let steps = [
+        StepOutcome::from_results("debuglog on", &[success()]),
+        StepOutcome::from_results("shmlog on", &[failed("not supported")]),
+    ];
+    assert_eq!(
+        format_steps(&steps),
+        "1. debuglog on: ok\n2. shmlog on: failed (not supported)"
+    );
+}
+
+// ============================================================================
+// Criteria
+// ============================================================================
+
+#[test]
+fn build_criteria_quotes_and_escapes_values() {
+    assert_eq!(
+        build_criteria(&[("class", Some("Firefox")), ("title", None)]).unwrap(),
+        "[class=\"Firefox\"]"
+    );
+    assert_eq!(
+        build_criteria(&[
+            ("class", Some("kitty")),
+            ("title", Some(r#"say "hi" \ bye"#)),
+        ])
+        .unwrap(),
+        r#"[class="kitty" title="say \"hi\" \\ bye"]"#
+    );
+    // The escaped result parses back to the original value
+    let built = build_criteria(&[("title", Some(r#"a "b" c"#))]).unwrap();
+    let parsed = parse_criteria(&built).unwrap();
+    assert_eq!(parsed[0].value.as_deref(), Some(r#"a "b" c"#));
+}
+
+#[test]
+fn build_criteria_rejects_empty_input() {
+    assert!(build_criteria(&[]).is_err());
+    assert!(build_criteria(&[("class", None), ("title", Some(""))]).is_err());
+}
+
+#[test]
+fn translate_to_output_makes_rects_output_relative() {
+    let mut windows = collect_windows(&two_output_tree());
+    let outputs = [
+        output_reply("DP-1", rect(0, 0, 1920, 1080), Some("2")),
+        output_reply("HDMI-1", rect(1920, 0, 1920, 1080), Some("1:web")),
+    ];
+    translate_to_output(&mut windows, &outputs);
+
+    let by_id = |id| &windows.iter().find(|w| w.con_id == id).unwrap().rect;
+    assert_eq!(*by_id(12), rect(0, 0, 1920, 1080));
+    assert_eq!(*by_id(23), rect(0, 540, 1920, 540));
+    assert_eq!(*by_id(25), rect(480, 200, 600, 400));
+}
+
+#[test]
+fn translate_to_output_leaves_unknown_outputs_alone() {
+    let mut windows = collect_windows(&two_output_tree());
+    translate_to_output(
+        &mut windows,
+        &[output_reply("DP-1", rect(0, 0, 1920, 1080), None)],
+    );
+    let web = windows.iter().find(|w| w.con_id == 22).unwrap();
+    assert_eq!(web.rect, rect(1920, 0, 1920, 540));
+}
+
+// ============================================================================
+// Event state
+// ============================================================================
+
+fn focus_ids(state: &EventState) -> Vec<usize> {
+    state.focus_history.iter().map(|e| e.con_id).collect()
+}
+
+#[test]
+fn focus_events_build_the_focus_history() {
+    let mut state = EventState::default();
+    for id in [5, 6, 6, 7, 5] {
+        state.handle_event(&window_event(WindowChange::Focus, id));
+    }
+    // Refocusing the current window isn't recorded twice
+    assert_eq!(focus_ids(&state), [5, 6, 7, 5]);
+
+    // Other window changes leave the history alone
+    state.handle_event(&window_event(WindowChange::Title, 8));
+    assert_eq!(focus_ids(&state), [5, 6, 7, 5]);
+}
+
+#[test]
+fn focus_history_keeps_the_newest_entries() {
+    let mut state = EventState::default();
+    for id in 0..FOCUS_HISTORY_LEN + 5 {
+        state.handle_event(&window_event(WindowChange::Focus, id));
+    }
+    let ids = focus_ids(&state);
+    assert_eq!(ids.len(), FOCUS_HISTORY_LEN);
+    assert_eq!(ids.first(), Some(&5));
+    assert_eq!(ids.last(), Some(&(FOCUS_HISTORY_LEN + 4)));
+}
+
+#[test]
+fn window_new_events_track_the_newest_window() {
+    let mut state = EventState::default();
+    state.handle_event(&window_event(WindowChange::New, 5));
+    state.handle_event(&window_event(WindowChange::New, 6));
+    assert_eq!(state.newest_window, Some(6));
+
+    // Closing some other window keeps the newest; closing it clears it
+    state.handle_event(&window_event(WindowChange::Close, 5));
+    assert_eq!(state.newest_window, Some(6));
+    state.handle_event(&window_event(WindowChange::Close, 6));
+    assert_eq!(state.newest_window, None);
+}
+
+#[test]
+fn workspace_focus_events_track_the_previous_workspace() {
+    let mut state = EventState::default();
+    // The first focus event at startup has no old workspace
+    state.handle_event(&workspace_focus_event(None, "1:web"));
+    assert_eq!(state.previous_workspace, None);
+
+    state.handle_event(&workspace_focus_event(Some("1:web"), "2"));
+    assert_eq!(state.previous_workspace.as_deref(), Some("1:web"));
+    state.handle_event(&workspace_focus_event(Some("2"), "notes"));
+    assert_eq!(state.previous_workspace.as_deref(), Some("2"));
+
+    // Refocusing the same workspace keeps the real previous one
+    state.handle_event(&workspace_focus_event(Some("notes"), "notes"));
+    assert_eq!(state.previous_workspace.as_deref(), Some("2"));
+}
+
+#[test]
+fn mode_events_set_the_current_mode() {
+    let mut state = EventState::default();
+    assert_eq!(state.mode, None);
+    state.handle_event(&mode_event("resize"));
+    assert_eq!(state.mode.as_deref(), Some("resize"));
+    state.handle_event(&mode_event("default"));
+    assert_eq!(state.mode.as_deref(), Some("default"));
+}
+
+#[tokio::test]
+async fn get_current_mode_defaults_until_a_mode_event() {
+    let mock = MockI3::start(MockState::default()).await;
+    let server = mock.server().await;
+    assert_eq!(
+        envelope(&server.get_current_mode().await.unwrap())["data"],
+        "default"
+    );
+
+    server.events().handle_event(&mode_event("resize"));
+    assert_eq!(
+        envelope(&server.get_current_mode().await.unwrap())["data"],
+        "resize"
+    );
+}
+
+#[tokio::test]
+async fn focus_newest_focuses_the_last_created_window() {
+    let mock = MockI3::start(MockState::default()).await;
+    let server = mock.server().await;
+
+    let before = server.focus_newest().await.unwrap();
+    assert_eq!(envelope(&before)["ok"], false);
+    assert!(mock.state().commands.is_empty());
+
+    server
+        .events()
+        .handle_event(&window_event(WindowChange::New, 23));
+    let result = server.focus_newest().await.unwrap();
+    assert_eq!(envelope(&result)["ok"], true);
+    assert_eq!(mock.state().commands, ["[con_id=23] focus"]);
+}
+
+#[tokio::test]
+async fn get_previous_workspace_can_clear_what_it_returns() {
+    let mock = MockI3::start(MockState::default()).await;
+    let server = mock.server().await;
+    server
+        .events()
+        .handle_event(&workspace_focus_event(Some("1:web"), "2"));
+
+    let params = |clear| Parameters(GetPreviousWorkspaceParams { clear: Some(clear) });
+    let kept = server.get_previous_workspace(params(false)).await.unwrap();
+    assert_eq!(envelope(&kept)["data"], "1:web");
+    let cleared = server.get_previous_workspace(params(true)).await.unwrap();
+    assert_eq!(envelope(&cleared)["data"], "1:web");
+    assert_eq!(server.events().previous_workspace, None);
+}
+
+// ============================================================================
+// Mock i3
+// ============================================================================
+
+/// Serializes pointing $I3SOCK at a mock, since I3::connect only reads the
+/// socket path from the environment
+static I3SOCK_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// A fresh, empty temporary directory for one test
+fn scratch_dir(name: &str) -> PathBuf {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "rmcp-i3-test-{}-{}-{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed),
+        name
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// What the mock i3 serves, and what it has been sent
+struct MockState {
+    tree: Node,
+    workspaces: Vec<Workspace>,
+    outputs: Vec<Output>,
+    marks: Vec<String>,
+    /// Every RUN_COMMAND payload received, in order
+    commands: Vec<String>,
+    /// Reply to each command with its own text as the error, so callers can
+    /// tell whose reply they got
+    echo_commands: bool,
+    /// Commands containing this text fail
+    fail_matching: Option<String>,
+    /// Applies a command's effect to the tree, the way i3 would
+    on_command: Option<fn(&mut Node, &str)>,
+    /// Identify as Sway in the GET_VERSION reply
+    sway: bool,
+    /// Sway's GET_INPUTS reply
+    inputs: Vec<serde_json::Value>,
+    /// Connections accepted so far
+    connections: usize,
+    /// loaded_config_file_name in the GET_VERSION reply
+    config_file: String,
+}
+
+impl Default for MockState {
+    fn default() -> Self {
+        Self {
+            tree: root(Vec::new()),
+            workspaces: Vec::new(),
+            outputs: Vec::new(),
+            marks: Vec::new(),
+            commands: Vec::new(),
+            echo_commands: false,
+            fail_matching: None,
+            on_command: None,
+            sway: false,
+            inputs: Vec::new(),
+            connections: 0,
+            config_file: "/dev/null".to_string(),
+        }
+    }
+}
+
+impl MockState {
+    /// The reply body for one IPC message
+    fn reply(&mut self, kind: u32, payload: &str) -> String {
+        match kind {
+            // RUN_COMMAND: one result per top-level ';' part
+            0 => {
+                self.commands.push(payload.to_string());
+                if let Some(apply) = self.on_command {
+                    apply(&mut self.tree, payload);
+                }
+                let results: Vec<serde_json::Value> = split_top_level(payload, &[';'])
+                    .into_iter()
+                    .map(str::trim)
+                    .filter(|part| !part.is_empty())
+                    .map(|part| {
+                        let fails = self
+                            .fail_matching
+                            .as_deref()
+                            .is_some_and(|text| part.contains(text));
+                        if self.echo_commands {
+                            serde_json::json!({ "success": true, "error": part })
+                        } else if fails {
+                            serde_json::json!({ "success": false, "error": "mock failure" })
+                        } else {
+                            serde_json::json!({ "success": true })
+                        }
+                    })
+                    .collect();
+                to_json(&results)
+            }
+            1 => to_json(&self.workspaces),
+            // SUBSCRIBE
+            2 => r#"{"success":true}"#.to_string(),
+            3 => to_json(&self.outputs),
+            4 => to_json(&self.tree),
+            5 => to_json(&self.marks),
+            7 if self.sway => serde_json::json!({
+                "major": 1,
+                "minor": 9,
+                "patch": 0,
+                "human_readable": "sway version 1.9 (mock)",
+                "variant": "sway",
+                "loaded_config_file_name": self.config_file,
+            })
+            .to_string(),
+            7 => serde_json::json!({
+                "major": 4,
+                "minor": 23,
+                "patch": 0,
+                "human_readable": "4.23 (mock)",
+                "loaded_config_file_name": self.config_file,
+            })
+            .to_string(),
+            // SEND_TICK, SYNC
+            10 | 11 => r#"{"success":true}"#.to_string(),
+            SWAY_GET_INPUTS => to_json(&self.inputs),
+            _ => r#"{"success":false,"error":"unsupported by mock"}"#.to_string(),
+        }
+    }
+}
+
+/// Serialize a reply body
+fn to_json(value: &impl Serialize) -> String {
+    serde_json::to_string(value).unwrap()
+}
+
+/// Encode one i3 IPC frame
+fn ipc_frame(kind: u32, body: &str) -> Vec<u8> {
+    let mut frame = b"i3-ipc".to_vec();
+    frame.extend((body.len() as u32).to_ne_bytes());
+    frame.extend(kind.to_ne_bytes());
+    frame.extend(body.as_bytes());
+    frame
+}
+
+/// An i3 IPC server on a Unix socket, answering from a MockState. Subscribed
+/// connections receive tick events for the ticks sent to it.
+struct MockI3 {
+    dir: PathBuf,
+    path: PathBuf,
+    state: Arc<Mutex<MockState>>,
+    accept: tokio::task::JoinHandle<()>,
+}
+
+impl MockI3 {
+    async fn start(state: MockState) -> Self {
+        let dir = scratch_dir("mock-i3");
+        let path = dir.join("ipc.sock");
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        let state = Arc::new(Mutex::new(state));
+        let events = broadcast::channel(64).0;
+
+        let accept = {
+            let state = state.clone();
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    state.lock().unwrap().connections += 1;
+                    tokio::spawn(serve_mock_connection(stream, state.clone(), events.clone()));
+                }
+            })
+        };
+        Self {
+            dir,
+            path,
+            state,
+            accept,
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().unwrap()
+    }
+
+    /// Point $I3SOCK at this mock for as long as the guard is held
+    async fn use_socket(&self) -> tokio::sync::MutexGuard<'static, ()> {
+        let guard = I3SOCK_LOCK.lock().await;
+        std::env::set_var("I3SOCK", &self.path);
+        guard
+    }
+
+    /// A server whose shared connection is already open to this mock
+    async fn server(&self) -> I3Server {
+        let server = I3Server::new();
+        let _socket = self.use_socket().await;
+        drop(server.connect().await.unwrap());
+        server
+    }
+}
+
+impl Drop for MockI3 {
+    fn drop(&mut self) {
+        self.accept.abort();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+async fn serve_mock_connection(
+    stream: tokio::net::UnixStream,
+    state: Arc<Mutex<MockState>>,
+    events: broadcast::Sender<(u32, String)>,
+) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let (mut reader, mut writer) = stream.into_split();
+    let (out, mut frames) = mpsc::unbounded_channel::<Vec<u8>>();
+    tokio::spawn(async move {
+        while let Some(frame) = frames.recv().await {
+            if writer.write_all(&frame).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    loop {
+        let mut header = [0u8; 14];
+        if reader.read_exact(&mut header).await.is_err() {
+            break;
+        }
+        let len = u32::from_ne_bytes(header[6..10].try_into().unwrap()) as usize;
+        let kind = u32::from_ne_bytes(header[10..14].try_into().unwrap());
+        let mut payload = vec![0u8; len];
+        if reader.read_exact(&mut payload).await.is_err() {
+            break;
+        }
+        let payload = String::from_utf8_lossy(&payload).into_owned();
+
+        if kind == 2 {
+            let mut subscription = events.subscribe();
+            let out = out.clone();
+            tokio::spawn(async move {
+                while let Ok((kind, body)) = subscription.recv().await {
+                    if out.send(ipc_frame(kind | 1 << 31, &body)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        if kind == 10 {
+            let tick = serde_json::json!({ "first": false, "payload": payload });
+            let _ = events.send((7, tick.to_string()));
+        }
+        let body = state.lock().unwrap().reply(kind, &payload);
+        if out.send(ipc_frame(kind, &body)).is_err() {
+            break;
+        }
+    }
+}
+
+// ============================================================================
+// Shared connection
+// ============================================================================
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_tool_calls_each_get_their_own_reply() {
+    let mock = MockI3::start(MockState {
+        echo_commands: true,
+        ..MockState::default()
+    })
+    .await;
+    let server = Arc::new(mock.server().await);
+
+    let calls: Vec<_> = (0..64)
+        .map(|i| {
+            let server = server.clone();
+            tokio::spawn(async move {
+                let command = format!("nop call {}", i);
+                let params = RunCommandParams {
+                    command: command.clone(),
+                };
+                let result = server.run_command(Parameters(params)).await.unwrap();
+                (command, envelope(&result))
+            })
+        })
+        .collect();
+
+    for call in calls {
+        let (command, body) = call.await.unwrap();
+        assert_eq!(body["data"][0]["command"], command.as_str());
+        assert_eq!(body["data"][0]["error"], command.as_str());
+    }
+    assert_eq!(mock.state().commands.len(), 64);
+}
+
+// ============================================================================
+// exec
+// ============================================================================
+
+#[test]
+fn exec_command_quotes_a_cwd_with_spaces() {
+    let dir = scratch_dir("exec cwd");
+    let spaced = dir.join("it's a dir");
+    std::fs::create_dir(&spaced).unwrap();
+    let cwd = spaced.to_str().unwrap();
+
+    let command = exec_command("pwd", Some(cwd)).unwrap();
+    assert_eq!(command, format!("exec cd {} && pwd", shell_quote(cwd)));
+
+    // i3 runs exec arguments with sh -c; the quoting must survive that
+    let script = command.strip_prefix("exec ").unwrap();
+    let out = std::process::Command::new("sh")
+        .args(["-c", script])
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8_lossy(&out.stdout).trim_end(), cwd);
+
+    assert_eq!(exec_command("kitty", None).unwrap(), "exec kitty");
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn exec_command_rejects_a_missing_cwd() {
+    let dir = scratch_dir("exec missing");
+    let missing = dir.join("nope");
+    let err = exec_command("pwd", Some(missing.to_str().unwrap())).unwrap_err();
+    assert!(err.message.contains("does not exist"), "{}", err.message);
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn shell_quote_escapes_single_quotes() {
+    assert_eq!(shell_quote("plain"), "'plain'");
+    assert_eq!(shell_quote("it's"), r#"'it'\''s'"#);
+}
+
+// ============================================================================
+// move_to_container
+// ============================================================================
+
+#[test]
+fn workspace_of_resolves_containers_to_their_workspace() {
+    let tree = two_output_tree();
+    let name = |id| workspace_of(&tree, id).and_then(|ws| ws.name.as_deref());
+    assert_eq!(name(23), Some("1:web"));
+    assert_eq!(name(25), Some("1:web"));
+    assert_eq!(name(21), Some("1:web"));
+    // A workspace resolves to itself
+    assert_eq!(name(10), Some("notes"));
+    // Outputs and the root aren't on a workspace
+    assert_eq!(name(2), None);
+    assert_eq!(name(1), None);
+    assert_eq!(name(999), None);
+}
+
+#[tokio::test]
+async fn move_to_container_quotes_the_resolved_workspace() {
+    let mut tree = two_output_tree();
+    tree.nodes[1].nodes[0].name = Some("1:web, mail".to_string());
+    let mock = MockI3::start(MockState {
+        tree,
+        ..MockState::default()
+    })
+    .await;
+    let server = mock.server().await;
+
+    let params = MoveToContainerParams { con_id: 23 };
+    let result = server.move_to_container(Parameters(params)).await.unwrap();
+    assert_eq!(envelope(&result)["ok"], true);
+    assert_eq!(
+        mock.state().commands,
+        ["move container to workspace \"1:web, mail\""]
+    );
+
+    let missing = MoveToContainerParams { con_id: 999 };
+    assert!(server.move_to_container(Parameters(missing)).await.is_err());
+}
+
+// ============================================================================
+// Workspace numbers
+// ============================================================================
+
+#[test]
+fn next_free_workspace_number_fills_gaps() {
+    assert_eq!(next_free_workspace_number(["1", "2", "4"]), 3);
+    assert_eq!(next_free_workspace_number(["2:web", "3:mail"]), 1);
+    assert_eq!(next_free_workspace_number(["1", "2", "3"]), 4);
+    assert_eq!(next_free_workspace_number([]), 1);
+}
+
+#[test]
+fn next_free_workspace_number_ignores_non_numeric_names() {
+    assert_eq!(next_free_workspace_number(["web", "1", "mail", "x2"]), 2);
+    assert_eq!(parse_workspace_number("web"), None);
+    assert_eq!(parse_workspace_number("x2"), None);
+    assert_eq!(parse_workspace_number("12:code"), Some(12));
+    assert_eq!(parse_workspace_number("7"), Some(7));
+}
+
+// ============================================================================
+// exec rate limit
+// ============================================================================
+
+#[test]
+fn rate_limiter_rejects_the_sixth_rapid_launch() {
+    let (count, secs) = DEFAULT_EXEC_RATE;
+    let mut limiter = RateLimiter::new(count, Duration::from_secs(secs));
+    let now = limiter.last_refill;
+
+    for i in 0..5 {
+        assert!(limiter.try_acquire(now), "launch {} should pass", i + 1);
+    }
+    assert!(!limiter.try_acquire(now));
+
+    // One token comes back after window / capacity (2s for 5 per 10s)
+    assert!(!limiter.try_acquire(now + Duration::from_millis(1900)));
+    assert!(limiter.try_acquire(now + Duration::from_millis(2100)));
+    assert!(!limiter.try_acquire(now + Duration::from_millis(2100)));
+
+    // A long pause refills to capacity, not beyond
+    let later = now + Duration::from_secs(60);
+    for _ in 0..5 {
+        assert!(limiter.try_acquire(later));
+    }
+    assert!(!limiter.try_acquire(later));
+}
+
+#[test]
+fn check_exec_rate_returns_an_error_result_when_exhausted() {
+    let server = I3Server::new();
+    for _ in 0..5 {
+        assert!(server.check_exec_rate("kitty").is_none());
+    }
+    let rejected = server
+        .check_exec_rate("kitty")
+        .expect("sixth launch rejected");
+    assert!(is_error(&rejected));
+    let body = envelope(&rejected);
+    assert_eq!(body["ok"], false);
+    assert!(body["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("Rate limit exceeded"));
+}
+
+// ============================================================================
+// get_window_marks
+// ============================================================================
+
+#[tokio::test]
+async fn get_window_marks_reads_marks_from_the_tree() {
+    let tree = root(vec![output(
+        2,
+        "DP-1",
+        vec![workspace(
+            3,
+            "1",
+            vec![
+                marked(window(5, "Firefox", "web"), &["browser", "label:docs"]),
+                marked(window(6, "kitty", "one"), &["term"]),
+                window(7, "kitty", "two"),
+            ],
+        )],
+    )]);
+    let mock = MockI3::start(MockState {
+        tree,
+        ..MockState::default()
+    })
+    .await;
+    let server = mock.server().await;
+    let marks = |criteria: &str| {
+        let params = GetWindowMarksParams {
+            criteria: criteria.to_string(),
+        };
+        server.get_window_marks(Parameters(params))
+    };
+
+    let single = envelope(&marks("[class=\"Firefox\"]").await.unwrap());
+    assert_eq!(single["data"], serde_json::json!(["browser", "label:docs"]));
+
+    let several = envelope(&marks("[class=\"kitty\"]").await.unwrap());
+    assert_eq!(
+        several["data"],
+        serde_json::json!({ "6": ["term"], "7": [] })
+    );
+
+    let none = envelope(&marks("[class=\"mpv\"]").await.unwrap());
+    assert_eq!(none["ok"], false);
+    assert!(mock.state().commands.is_empty());
+}
+
+// ============================================================================
+// Outputs
+// ============================================================================
+
+/// DP-1 (primary), HDMI-1, and a disconnected VGA-1
+fn three_outputs() -> Vec<Output> {
+    vec![
+        Output {
+            primary: true,
+            ..output_reply("DP-1", rect(0, 0, 1920, 1080), Some("1"))
+        },
+        output_reply("HDMI-1", rect(1920, 0, 1920, 1080), Some("2")),
+        Output {
+            active: false,
+            ..output_reply("VGA-1", rect(0, 0, 0, 0), None)
+        },
+    ]
+}
+
+#[test]
+fn resolve_output_picks_an_active_output() {
+    let outputs = three_outputs();
+    assert_eq!(
+        resolve_output(&outputs, "HDMI-1", false).unwrap(),
+        ("HDMI-1", false)
+    );
+    assert_eq!(
+        resolve_output(&outputs, "HDMI-1", true).unwrap(),
+        ("HDMI-1", false)
+    );
+}
+
+#[test]
+fn resolve_output_falls_back_to_the_primary() {
+    let outputs = three_outputs();
+    assert_eq!(
+        resolve_output(&outputs, "VGA-1", true).unwrap(),
+        ("DP-1", true)
+    );
+    assert_eq!(
+        resolve_output(&outputs, "eDP-1", true).unwrap(),
+        ("DP-1", true)
+    );
+
+    let err = resolve_output(&outputs, "VGA-1", false).unwrap_err();
+    assert!(
+        err.message.contains("Active outputs: DP-1, HDMI-1"),
+        "{}",
+        err.message
+    );
+
+    // Without a primary, the first active output stands in
+    let no_primary: Vec<Output> = outputs
+        .into_iter()
+        .map(|o| Output {
+            primary: false,
+            ..o
+        })
+        .collect();
+    assert_eq!(
+        resolve_output(&no_primary, "VGA-1", true).unwrap(),
+        ("DP-1", true)
+    );
+}
+
+// ============================================================================
+// Tool stats
+// ============================================================================
+
+#[tokio::test]
+async fn record_call_increments_per_tool_stats() {
+    let server = I3Server::new();
+    server.record_call("get_tree", Duration::from_millis(10), false);
+    server.record_call("get_tree", Duration::from_millis(30), true);
+    server.record_call("kill", Duration::from_millis(4), false);
+
+    let body = envelope(&server.get_stats().await.unwrap());
+    assert_eq!(
+        body["data"]["get_tree"],
+        serde_json::json!({ "calls": 2, "errors": 1, "avg_latency_ms": 20.0 })
+    );
+    assert_eq!(body["data"]["kill"]["calls"], 1);
+    assert_eq!(body["data"]["kill"]["errors"], 0);
+}
+
+#[test]
+fn tool_stats_summary_handles_no_calls() {
+    let summary = ToolStatsSummary::from(&ToolStats::default());
+    assert_eq!(summary.calls, 0);
+    assert_eq!(summary.avg_latency_ms, 0.0);
+}
+
+/// Send one JSON-RPC message to a served I3Server and, for requests, read the reply
+async fn rpc(
+    io: &mut tokio::io::BufReader<tokio::io::DuplexStream>,
+    message: serde_json::Value,
+) -> Option<serde_json::Value> {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+    let expects_reply = message.get("id").is_some();
+    io.get_mut()
+        .write_all(format!("{}\n", message).as_bytes())
+        .await
+        .unwrap();
+    if !expects_reply {
+        return None;
+    }
+    let mut line = String::new();
+    io.read_line(&mut line).await.unwrap();
+    Some(serde_json::from_str(&line).unwrap())
+}
+
+#[tokio::test]
+async fn call_tool_records_stats_for_routed_tools() {
+    use rmcp::ServiceExt;
+
+    let (client, transport) = tokio::io::duplex(1 << 20);
+    let (read, write) = tokio::io::split(transport);
+    let service = tokio::spawn(async move {
+        let running = I3Server::new().serve((read, write)).await.unwrap();
+        running.waiting().await.unwrap();
+    });
+    let mut io = tokio::io::BufReader::new(client);
+
+    let init = rpc(
+        &mut io,
+        serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "initialize",
+            "params": {
+                "protocolVersion": "2025-03-26",
+                "capabilities": {},
+                "clientInfo": { "name": "test", "version": "0" }
+            }
+        }),
+    )
+    .await
+    .unwrap();
+    assert!(init.get("result").is_some(), "{}", init);
+    rpc(
+        &mut io,
+        serde_json::json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }),
+    )
+    .await;
+
+    let calls = [
+        ("get_tool_metadata", serde_json::json!({})),
+        ("get_tool_metadata", serde_json::json!({})),
+        // Missing its required criteria, so the router rejects it
+        ("focus_window", serde_json::json!({})),
+        ("no_such_tool", serde_json::json!({})),
+    ];
+    for (id, (name, arguments)) in calls.into_iter().enumerate() {
+        rpc(
+            &mut io,
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": id + 2,
+                "method": "tools/call",
+                "params": { "name": name, "arguments": arguments }
+            }),
+        )
+        .await
+        .unwrap();
+    }
+
+    let reply = rpc(
+        &mut io,
+        serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 10,
+            "method": "tools/call",
+            "params": { "name": "get_stats", "arguments": {} }
+        }),
+    )
+    .await
+    .unwrap();
+    let text = reply["result"]["content"][0]["text"].as_str().unwrap();
+    let stats = &serde_json::from_str::<serde_json::Value>(text).unwrap()["data"];
+
+    assert_eq!(stats["get_tool_metadata"]["calls"], 2);
+    assert_eq!(stats["get_tool_metadata"]["errors"], 0);
+    assert!(
+        stats["get_tool_metadata"]["avg_latency_ms"]
+            .as_f64()
+            .unwrap()
+            >= 0.0
+    );
+    assert_eq!(stats["focus_window"]["calls"], 1);
+    assert_eq!(stats["focus_window"]["errors"], 1);
+    // Unroutable names aren't recorded, so clients can't grow the stats map
+    assert!(stats.get("no_such_tool").is_none(), "{}", stats);
+
+    drop(io);
+    service.await.unwrap();
+}
+
+// ============================================================================
+// Work area
+// ============================================================================
+
+fn dock(id: usize, name: &str, height: isize) -> Node {
+    Node {
+        name: Some(name.to_string()),
+        rect: rect(0, 0, 1920, height),
+        ..node(id, NodeType::Dockarea)
+    }
+}
+
+#[test]
+fn work_area_subtracts_docked_bars() {
+    let mut out = at(output(2, "HDMI-1", Vec::new()), 1920, 0, 2560, 1440);
+    out.nodes = vec![
+        dock(3, "topdock", 24),
+        workspace(4, "1", Vec::new()),
+        dock(5, "bottomdock", 30),
+    ];
+    assert_eq!(work_area(&out), rect(1920, 24, 2560, 1386));
+
+    // No bars: the whole output
+    let bare = at(output(6, "DP-1", Vec::new()), 0, 0, 1920, 1080);
+    assert_eq!(work_area(&bare), rect(0, 0, 1920, 1080));
+
+    // Bars taller than the output can't make the height negative
+    let mut tiny = at(output(7, "VIRTUAL-1", Vec::new()), 0, 0, 100, 20);
+    tiny.nodes = vec![dock(8, "topdock", 15), dock(9, "bottomdock", 15)];
+    assert_eq!(work_area(&tiny), rect(0, 15, 100, 0));
+}
+
+// ============================================================================
+// toggle_tabbed
+// ============================================================================
+
+/// Workspace "1" with a vertical split holding kitty 5 and focused Firefox 6
+fn focused_in_split_tree() -> Node {
+    let mut web = window(6, "Firefox", "web");
+    web.focused = true;
+    root(vec![output(
+        2,
+        "DP-1",
+        vec![workspace(
+            3,
+            "1",
+            vec![split(
+                4,
+                NodeLayout::SplitV,
+                vec![window(5, "kitty", "shell"), web],
+            )],
+        )],
+    )])
+}
+
+#[tokio::test]
+async fn toggle_tabbed_switches_and_restores() {
+    let mock = MockI3::start(MockState {
+        tree: focused_in_split_tree(),
+        ..MockState::default()
+    })
+    .await;
+    let server = mock.server().await;
+
+    let first = envelope(&server.toggle_tabbed().await.unwrap());
+    assert_eq!(
+        first["message"],
+        "Switched splitv -> tabbed; call again to restore"
+    );
+
+    // i3 applied it
+    mock.state().tree.nodes[0].nodes[0].nodes[0].layout = NodeLayout::Tabbed;
+    let second = envelope(&server.toggle_tabbed().await.unwrap());
+    assert_eq!(second["message"], "Restored layout splitv");
+    assert_eq!(
+        mock.state().commands,
+        ["[con_id=6] layout tabbed", "[con_id=6] layout splitv"]
+    );
+
+    // The remembered layout was used up; a still-tabbed container can't toggle back
+    let third = envelope(&server.toggle_tabbed().await.unwrap());
+    assert_eq!(third["ok"], false);
+    assert_eq!(mock.state().commands.len(), 2);
+}
+
+// ============================================================================
+// Tree size limits
+// ============================================================================
+
+/// 3 outputs x 8 workspaces x 20 windows, each window in its own split
+fn large_tree() -> Node {
+    let mut id = 10;
+    let mut next = || {
+        id += 1;
+        id
+    };
+    let outputs = (0..3)
+        .map(|o| {
+            let workspaces = (0..8)
+                .map(|w| {
+                    let windows = (0..20)
+                        .map(|i| {
+                            let leaf = window(next(), "kitty", &format!("shell {}", i));
+                            split(next(), NodeLayout::SplitH, vec![leaf])
+                        })
+                        .collect();
+                    workspace(next(), &format!("{}", o * 8 + w + 1), windows)
+                })
+                .collect();
+            output(next(), &format!("OUT-{}", o), workspaces)
+        })
+        .collect();
+    root(outputs)
+}
+
+#[test]
+fn limit_tree_depth_counts_dropped_children() {
+    let mut value = serde_json::to_value(two_output_tree()).unwrap();
+    assert_eq!(tree_depth(&value), 4);
+
+    // Keep root -> outputs -> workspaces; "1:web" loses a split and a
+    // floating container
+    limit_tree_depth(&mut value, 2);
+    assert_eq!(tree_depth(&value), 2);
+    let web = &value["nodes"][1]["nodes"][0];
+    assert_eq!(web["nodes"], serde_json::json!([]));
+    assert_eq!(web["floating_nodes"], serde_json::json!([]));
+    assert_eq!(web["truncated_children"], 2);
+    assert!(value.get("truncated_children").is_none());
+}
+
+#[tokio::test]
+async fn get_tree_keeps_a_large_tree_under_max_bytes() {
+    let tree = large_tree();
+    let full = serde_json::to_string_pretty(&tree).unwrap().len();
+    let mock = MockI3::start(MockState {
+        tree,
+        ..MockState::default()
+    })
+    .await;
+    let server = mock.server().await;
+
+    let max_bytes = 40_000;
+    assert!(full > max_bytes * 10);
+    let params = GetTreeParams {
+        exclude_internal: None,
+        max_bytes: Some(max_bytes),
+    };
+    let result = server.get_tree(Parameters(params)).await.unwrap();
+    let text = &result.content[0].as_text().unwrap().text;
+    assert!(text.len() <= max_bytes, "{} bytes", text.len());
+
+    let body = envelope(&result);
+    assert_eq!(body["ok"], true);
+    assert!(body["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("Window tree, limited to depth"));
+
+    // Too small for even the root
+    let params = GetTreeParams {
+        exclude_internal: None,
+        max_bytes: Some(100),
+    };
+    let result = server.get_tree(Parameters(params)).await.unwrap();
+    assert!(is_error(&result));
+}
+
+// ============================================================================
+// toggle_focus_mode
+// ============================================================================
+
+/// two_output_tree with focus on the tiled GitHub window in "1:web"
+fn focused_on_web_tree() -> Node {
+    let mut tree = two_output_tree();
+    node_mut(&mut tree, 12).focused = false;
+    node_mut(&mut tree, 22).focused = true;
+    tree
+}
+
+#[tokio::test]
+async fn toggle_focus_mode_reports_where_focus_landed() {
+    let mock = MockI3::start(MockState {
+        tree: focused_on_web_tree(),
+        on_command: Some(|tree, command| {
+            if command == "focus mode_toggle" {
+                node_mut(tree, 22).focused = false;
+                node_mut(tree, 25).focused = true;
+            }
+        }),
+        ..MockState::default()
+    })
+    .await;
+    let server = mock.server().await;
+
+    let body = envelope(&server.toggle_focus_mode().await.unwrap());
+    assert_eq!(
+        body["message"],
+        "Focus is now on a floating window: Volume (con_id 25)"
+    );
+    assert_eq!(mock.state().commands, ["focus mode_toggle"]);
+}
+
+#[tokio::test]
+async fn toggle_focus_mode_skips_workspaces_without_floating_windows() {
+    // Focus is on workspace "2", which has no floating windows
+    let mock = MockI3::start(MockState {
+        tree: two_output_tree(),
+        ..MockState::default()
+    })
+    .await;
+    let server = mock.server().await;
+
+    let body = envelope(&server.toggle_focus_mode().await.unwrap());
+    assert_eq!(body["ok"], true);
+    assert_eq!(
+        body["message"],
+        "No floating windows on this workspace; focus unchanged"
+    );
+    assert!(mock.state().commands.is_empty());
+}
+
+// ============================================================================
+// switch_workspace
+// ============================================================================
+
+fn two_workspaces() -> Vec<Workspace> {
+    vec![
+        ws_reply("1:web", "DP-1", true),
+        ws_reply("2", "DP-1", false),
+    ]
+}
+
+#[tokio::test]
+async fn switch_workspace_skips_the_current_workspace() {
+    let mock = MockI3::start(MockState {
+        workspaces: two_workspaces(),
+        ..MockState::default()
+    })
+    .await;
+    let server = mock.server().await;
+    let switch = |workspace: &str, skip| {
+        let params = SwitchWorkspaceParams {
+            workspace: workspace.to_string(),
+            skip_if_current: skip,
+        };
+        server.switch_workspace(Parameters(params))
+    };
+
+    let body = envelope(&switch("1:web", Some(true)).await.unwrap());
+    assert_eq!(body["message"], "Already on workspace '1:web'");
+    assert!(mock.state().commands.is_empty());
+
+    // "1" resolves to "1:web" first, so it is current too
+    let body = envelope(&switch("1", Some(true)).await.unwrap());
+    assert_eq!(body["message"], "Already on workspace '1:web'");
+    switch("2", Some(true)).await.unwrap();
+    assert_eq!(mock.state().commands, ["workspace \"2\""]);
+}
+
+#[tokio::test]
+async fn switch_workspace_without_skip_always_sends() {
+    let mock = MockI3::start(MockState {
+        workspaces: two_workspaces(),
+        ..MockState::default()
+    })
+    .await;
+    let server = mock.server().await;
+
+    for skip in [None, Some(false)] {
+        let params = SwitchWorkspaceParams {
+            workspace: "1:web".to_string(),
+            skip_if_current: skip,
+        };
+        let body = envelope(&server.switch_workspace(Parameters(params)).await.unwrap());
+        assert_eq!(body["message"], "Switched to workspace '1:web'");
+    }
+    assert_eq!(
+        mock.state().commands,
+        ["workspace \"1:web\"", "workspace \"1:web\""]
+    );
+}
+
+#[tokio::test]
+async fn switch_workspace_resolves_numbers_and_quotes_the_name() {
+    let mock = MockI3::start(MockState {
+        workspaces: vec![
+            ws_reply("2", "DP-1", true),
+            ws_reply("3: build", "DP-1", false),
+            ws_reply("4:mail", "DP-1", false),
+            ws_reply("4:chat", "HDMI-1", false),
+        ],
+        ..MockState::default()
+    })
+    .await;
+    let server = mock.server().await;
+    let switch = |workspace: &str| {
+        let params = SwitchWorkspaceParams {
+            workspace: workspace.to_string(),
+            skip_if_current: None,
+        };
+        server.switch_workspace(Parameters(params))
+    };
+
+    let body = envelope(&switch("3").await.unwrap());
+    assert_eq!(body["message"], "Switched to workspace '3: build'");
+    // A workspace that doesn't exist yet is created under the given name
+    switch(" music ").await.unwrap();
+    assert!(switch("4").await.is_err());
+    assert_eq!(
+        mock.state().commands,
+        ["workspace \"3: build\"", "workspace \"music\""]
+    );
+}
+
+#[tokio::test]
+async fn move_to_workspace_resolves_numbers_and_quotes_the_name() {
+    let mock = MockI3::start(MockState {
+        workspaces: vec![
+            ws_reply("2", "DP-1", true),
+            ws_reply("3: build", "DP-1", false),
+            ws_reply("4:mail", "DP-1", false),
+            ws_reply("4:chat", "HDMI-1", false),
+        ],
+        ..MockState::default()
+    })
+    .await;
+    let server = mock.server().await;
+    let move_to = |workspace: &str| {
+        let params = MoveToWorkspaceParams {
+            workspace: workspace.to_string(),
+        };
+        server.move_to_workspace(Parameters(params))
+    };
+
+    let body = envelope(&move_to("3").await.unwrap());
+    assert_eq!(body["message"], "Moved window to workspace '3: build'");
+    move_to("music").await.unwrap();
+    assert!(move_to("4").await.is_err());
+    assert_eq!(
+        mock.state().commands,
+        [
+            "move container to workspace \"3: build\"",
+            "move container to workspace \"music\"",
+        ]
+    );
+}
+
+#[test]
+fn is_current_workspace_matches_the_focused_name_exactly() {
+    let workspaces = two_workspaces();
+    assert!(is_current_workspace(&workspaces, "1:web"));
+    assert!(!is_current_workspace(&workspaces, "1"));
+    assert!(!is_current_workspace(&workspaces, "2"));
+}
+
+// ============================================================================
+// Desktop entries
+// ============================================================================
+
+const FIREFOX_DESKTOP: &str = "\
+[Desktop Entry]
+Name=Firefox
+Exec=firefox %u --class=100%%
+
+[Desktop Action new-window]
+Exec=firefox --new-window %u
+";
+
+#[test]
+fn find_desktop_entry_searches_data_dirs_in_order() {
+    let dir = scratch_dir("desktop");
+    let (home, system) = (dir.join("home"), dir.join("system"));
+    for data in [&home, &system] {
+        std::fs::create_dir_all(data.join("applications")).unwrap();
+    }
+    std::fs::write(home.join("applications/firefox.desktop"), FIREFOX_DESKTOP).unwrap();
+    std::fs::write(system.join("applications/firefox.desktop"), "").unwrap();
+    std::fs::write(system.join("applications/mpv.desktop"), "").unwrap();
+    let dirs = [home.clone(), system.clone()];
+
+    assert_eq!(
+        find_desktop_entry("firefox", &dirs).unwrap(),
+        home.join("applications/firefox.desktop")
+    );
+    assert_eq!(
+        find_desktop_entry(" mpv.desktop ", &dirs).unwrap(),
+        system.join("applications/mpv.desktop")
+    );
+    let missing = find_desktop_entry("gimp", &dirs).unwrap_err();
+    assert!(missing
+        .message
+        .contains("No desktop entry found for 'gimp'"));
+    for bad in ["", "../firefox", ".hidden"] {
+        assert!(find_desktop_entry(bad, &dirs).is_err(), "{:?}", bad);
+    }
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn desktop_entry_exec_reads_the_main_group() {
+    let exec = desktop_entry_exec(FIREFOX_DESKTOP).unwrap();
+    assert_eq!(exec, "firefox %u --class=100%%");
+    assert_eq!(strip_field_codes(exec), "firefox --class=100%");
+
+    let actions_only = "[Desktop Action x]\nExec=nope\n";
+    assert_eq!(desktop_entry_exec(actions_only), None);
+    assert_eq!(desktop_entry_exec("[Desktop Entry]\nExec=\n"), None);
+    assert_eq!(
+        desktop_entry_exec("[Desktop Entry]\nExecutable=1\nExec = mpv %F\n"),
+        Some("mpv %F")
+    );
+}
+
+// ============================================================================
+// Tool response envelope
+// ============================================================================
+
+#[test]
+fn success_and_failure_envelopes() {
+    let ok = ToolResponse::success("Switched");
+    assert!(!is_error(&ok));
+    assert_eq!(
+        envelope(&ok),
+        serde_json::json!({ "ok": true, "message": "Switched", "data": null })
+    );
+
+    let failed = ToolResponse::failure("No focused window");
+    assert!(!is_error(&failed));
+    assert_eq!(
+        envelope(&failed),
+        serde_json::json!({ "ok": false, "message": "No focused window", "data": null })
+    );
+}
+
+#[test]
+fn error_envelope_is_flagged_as_a_tool_error() {
+    let error = ToolResponse::error("Rate limit exceeded");
+    assert!(is_error(&error));
+    assert_eq!(
+        envelope(&error),
+        serde_json::json!({ "ok": false, "message": "Rate limit exceeded", "data": null })
+    );
+}
+
+#[test]
+fn data_envelopes_carry_the_payload() {
+    let data = ToolResponse::data("2 windows", &[5, 6]).unwrap();
+    assert!(!is_error(&data));
+    assert_eq!(
+        envelope(&data),
+        serde_json::json!({ "ok": true, "message": "2 windows", "data": [5, 6] })
+    );
+
+    let failure =
+        ToolResponse::failure_data("1 of 2 failed", &serde_json::json!({ "a": 1 })).unwrap();
+    assert!(!is_error(&failure));
+    assert_eq!(
+        envelope(&failure),
+        serde_json::json!({ "ok": false, "message": "1 of 2 failed", "data": { "a": 1 } })
+    );
+}
+
+#[test]
+fn envelope_always_has_the_three_keys() {
+    // `data: null` is sent explicitly rather than omitted
+    let text = ToolResponse::new(true, "x", None).render();
+    let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+    let mut keys: Vec<&str> = value
+        .as_object()
+        .unwrap()
+        .keys()
+        .map(|k| k.as_str())
+        .collect();
+    keys.sort_unstable();
+    assert_eq!(keys, ["data", "message", "ok"]);
+    assert!(value["data"].is_null());
+}
+
+// ============================================================================
+// Picture in picture
+// ============================================================================
+
+#[test]
+fn pip_geometry_sits_in_the_bottom_right_corner() {
+    let area = rect(1920, 24, 2560, 1416);
+    assert_eq!(
+        pip_geometry(&area, DEFAULT_PIP_PPT, DEFAULT_PIP_PPT),
+        rect(3840, 1086, 640, 354)
+    );
+    assert_eq!(pip_geometry(&area, 100, 100), area);
+}
+
+#[test]
+fn pip_commands_float_pin_size_and_place() {
+    assert_eq!(
+        pip_commands(42, &rect(3840, 1086, 640, 354)),
+        [
+            "[con_id=42] floating enable",
+            "[con_id=42] sticky enable",
+            "[con_id=42] resize set 640 px 354 px",
+            "[con_id=42] move position 3840 px 1086 px",
+        ]
+    );
+}
+
+#[test]
+fn validate_ppt_defaults_and_bounds() {
+    assert_eq!(validate_ppt("width_ppt", None, 25).unwrap(), 25);
+    assert_eq!(validate_ppt("width_ppt", Some(100), 25).unwrap(), 100);
+    assert!(validate_ppt("width_ppt", Some(0), 25).is_err());
+    assert!(validate_ppt("width_ppt", Some(101), 25).is_err());
+}
+
+// ============================================================================
+// evacuate_workspace
+// ============================================================================
+
+#[test]
+fn evacuate_command_moves_every_window_on_the_workspace() {
+    let tree = two_output_tree();
+    let ids = workspace_window_ids(&tree, "1:web").unwrap();
+    assert_eq!(ids, [22, 23, 25]);
+    assert_eq!(workspace_window_ids(&tree, "9"), None);
+    assert_eq!(
+        workspace_window_ids(
+            &root(vec![output(2, "DP-1", vec![workspace(3, "9", vec![])])]),
+            "9"
+        ),
+        Some(vec![])
+    );
+
+    assert_eq!(
+        evacuate_command(&ids, "2: spare room"),
+        "[con_id=22] move container to workspace \"2: spare room\"; \
+         [con_id=23] move container to workspace \"2: spare room\"; \
+         [con_id=25] move container to workspace \"2: spare room\""
+    );
+    // Quoting keeps separators from splitting the command
+    assert_eq!(
+        evacuate_command(&[7], "a;b, \"c\""),
+        "[con_id=7] move container to workspace \"a;b, \\\"c\\\"\""
+    );
+}
+
+// ============================================================================
+// is_running
+// ============================================================================
+
+#[tokio::test]
+async fn is_running_reports_matches_and_their_workspaces() {
+    let mut tree = two_output_tree();
+    node_mut(&mut tree, 11)
+        .nodes
+        .push(window(13, "kitty", "build"));
+    let mock = MockI3::start(MockState {
+        tree,
+        ..MockState::default()
+    })
+    .await;
+    let server = mock.server().await;
+    let is_running = |class: Option<&str>, title: Option<&str>| {
+        let params = IsRunningParams {
+            class: class.map(str::to_string),
+            instance: None,
+            title: title.map(str::to_string),
+        };
+        server.is_running(Parameters(params))
+    };
+
+    let kitty = envelope(&is_running(Some("kitty"), None).await.unwrap());
+    assert_eq!(kitty["data"]["running"], true);
+    assert_eq!(kitty["data"]["count"], 2);
+    assert_eq!(
+        kitty["data"]["workspaces"],
+        serde_json::json!(["2", "1:web"])
+    );
+
+    let logs = envelope(&is_running(Some("kitty"), Some("logs")).await.unwrap());
+    assert_eq!(logs["data"]["count"], 1);
+    assert_eq!(logs["data"]["workspaces"], serde_json::json!(["1:web"]));
+
+    let none = envelope(&is_running(Some("Thunderbird"), None).await.unwrap());
+    assert_eq!(none["message"], "No matching windows");
+    assert_eq!(none["data"]["running"], false);
+    assert_eq!(none["data"]["count"], 0);
+    assert_eq!(none["data"]["workspaces"], serde_json::json!([]));
+
+    assert!(is_running(None, Some("")).await.is_err());
+    assert!(mock.state().commands.is_empty());
+}
+
+// ============================================================================
+// Opacity
+// ============================================================================
+
+#[test]
+fn validate_opacity_accepts_only_zero_to_one() {
+    assert_eq!(validate_opacity(0.0).unwrap(), 0.0);
+    assert_eq!(validate_opacity(0.85).unwrap(), 0.85);
+    assert_eq!(validate_opacity(1.0).unwrap(), 1.0);
+    assert!(validate_opacity(-0.1).is_err());
+    assert!(validate_opacity(1.5).is_err());
+    assert!(validate_opacity(f32::NAN).is_err());
+}
+
+#[tokio::test]
+async fn set_opacity_needs_sway() {
+    let params = || SetOpacityParams {
+        opacity: 0.8,
+        criteria: None,
+    };
+
+    let i3 = MockI3::start(MockState::default()).await;
+    let server = i3.server().await;
+    let result = server.set_opacity(Parameters(params())).await.unwrap();
+    assert_eq!(envelope(&result)["ok"], false);
+    assert!(i3.state().commands.is_empty());
+    drop(server);
+    drop(i3);
+
+    let sway = MockI3::start(MockState {
+        sway: true,
+        ..MockState::default()
+    })
+    .await;
+    let server = sway.server().await;
+    let result = server.set_opacity(Parameters(params())).await.unwrap();
+    assert_eq!(envelope(&result)["ok"], true);
+    assert_eq!(sway.state().commands, ["opacity 0.8"]);
+}
+
+// ============================================================================
+// auto_name_workspace
+// ============================================================================
+
+#[test]
+fn dominant_class_counts_windows_and_breaks_ties_by_tree_order() {
+    let ws = workspace(
+        3,
+        "1",
+        vec![
+            window(4, "kitty", "a"),
+            split(
+                5,
+                NodeLayout::SplitV,
+                vec![window(6, "Firefox", "b"), window(7, "Firefox", "c")],
+            ),
+        ],
+    );
+    assert_eq!(dominant_class(&ws).as_deref(), Some("Firefox"));
+
+    let tie = workspace(
+        3,
+        "1",
+        vec![window(4, "kitty", "a"), window(5, "Firefox", "b")],
+    );
+    assert_eq!(dominant_class(&tie).as_deref(), Some("kitty"));
+    assert_eq!(dominant_class(&workspace(3, "1", vec![])), None);
+}
+
+#[test]
+fn class_label_prefers_the_mapping_then_the_built_in_labels() {
+    let mapping: HashMap<String, String> = [("firefox".to_string(), "browse".to_string())].into();
+    assert_eq!(class_label("Firefox", Some(&mapping)), "browse");
+    assert_eq!(class_label("Firefox", None), "web");
+    assert_eq!(class_label("kitty", Some(&mapping)), "term");
+    assert_eq!(class_label("Obsidian", None), "Obsidian");
+
+    assert_eq!(relabel_workspace("3:old", "web"), "3:web");
+    assert_eq!(relabel_workspace("3", "web"), "3:web");
+    assert_eq!(relabel_workspace("scratch", "web"), "web");
+}
+
+#[tokio::test]
+async fn auto_name_workspace_renames_after_the_dominant_class() {
+    let mock = MockI3::start(MockState {
+        tree: two_output_tree(),
+        ..MockState::default()
+    })
+    .await;
+    let server = mock.server().await;
+
+    let params = AutoNameWorkspaceParams { mapping: None };
+    let result = server
+        .auto_name_workspace(Parameters(params))
+        .await
+        .unwrap();
+    assert_eq!(envelope(&result)["data"], "2:code");
+    assert_eq!(mock.state().commands, ["rename workspace to \"2:code\""]);
+}
+
+// ============================================================================
+// Socket path
+// ============================================================================
+
+#[test]
+fn resolve_socket_path_prefers_i3sock() {
+    let from_env = resolve_socket_path(Some("/run/user/1000/i3/ipc"), || {
+        panic!("i3 --get-socketpath shouldn't run when I3SOCK is set")
+    })
+    .unwrap();
+    assert_eq!(from_env, ("/run/user/1000/i3/ipc".to_string(), "I3SOCK"));
+
+    let from_i3 = resolve_socket_path(None, || Ok("/tmp/i3-ipc.sock".to_string())).unwrap();
+    assert_eq!(
+        from_i3,
+        ("/tmp/i3-ipc.sock".to_string(), "i3 --get-socketpath")
+    );
+
+    let missing = resolve_socket_path(None, || {
+        Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+    });
+    assert_eq!(missing.unwrap_err().kind(), std::io::ErrorKind::NotFound);
+}
+
+#[tokio::test]
+async fn get_socket_info_reports_the_i3sock_instance() {
+    let mock = MockI3::start(MockState::default()).await;
+    let server = mock.server().await;
+
+    let _socket = mock.use_socket().await;
+    let result = server.get_socket_info().await.unwrap();
+    let body = envelope(&result);
+    let path = mock.path.to_str().unwrap();
+    assert_eq!(body["ok"], true);
+    assert_eq!(body["data"]["socket"], path);
+    assert_eq!(body["data"]["source"], "I3SOCK");
+    assert_eq!(body["data"]["I3SOCK"], path);
+    assert_eq!(body["data"]["version"], "4.23 (mock)");
+}
+
+// ============================================================================
+// Chained commands
+// ============================================================================
+
+fn strings(items: &[&str]) -> Vec<String> {
+    items.iter().map(|s| s.to_string()).collect()
+}
+
+#[test]
+fn chain_commands_joins_with_commas_under_one_criteria() {
+    assert_eq!(
+        chain_commands(
+            "[class=\"mpv\"]",
+            &strings(&[
+                "floating enable",
+                " ",
+                "resize set 640 px 360 px",
+                "sticky enable"
+            ])
+        )
+        .unwrap(),
+        "[class=\"mpv\"] floating enable, resize set 640 px 360 px, sticky enable"
+    );
+    // Bare criteria get their brackets
+    assert_eq!(
+        chain_commands("class=\"mpv\"", &strings(&["mark video"])).unwrap(),
+        "[class=\"mpv\"] mark video"
+    );
+    // A ';' inside a quoted argument doesn't end the chain
+    assert_eq!(
+        chain_commands(
+            "[con_id=5]",
+            &strings(&["title_format \"a;b\"", "border none"])
+        )
+        .unwrap(),
+        "[con_id=5] title_format \"a;b\", border none"
+    );
+}
+
+#[test]
+fn chain_commands_rejects_breaking_the_chain() {
+    let chain = |commands: &[&str]| chain_commands("[class=\"mpv\"]", &strings(commands));
+    assert!(chain(&["floating enable; kill"]).is_err());
+    assert!(chain(&["[class=\"kitty\"] kill"]).is_err());
+    assert!(chain(&[]).is_err());
+    assert!(chain(&["", "  "]).is_err());
+    assert!(chain_commands("", &strings(&["kill"])).is_err());
+}
+
+// ============================================================================
+// Config file info
+// ============================================================================
+
+#[test]
+fn config_file_info_stats_the_file() {
+    let dir = scratch_dir("config-info");
+    let path = dir.join("config");
+    std::fs::write(&path, "set $mod Mod4\n").unwrap();
+    let before = unix_now();
+
+    let info = ConfigFileInfo::stat(path.to_str().unwrap());
+    assert_eq!(info.path, path.to_str().unwrap());
+    assert_eq!(info.size_bytes, Some(14));
+    let modified = info.modified.unwrap();
+    assert!(modified.abs_diff(before) <= 2, "{} vs {}", modified, before);
+    assert!(info.error.is_none());
+
+    let missing = ConfigFileInfo::stat(dir.join("missing").to_str().unwrap());
+    assert_eq!(missing.size_bytes, None);
+    assert_eq!(missing.modified, None);
+    assert!(missing.error.is_some());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+// ============================================================================
+// run_commands
+// ============================================================================
+
+#[test]
+fn restore_focus_command_refocuses_the_original_window() {
+    let command = join_commands(&strings(&["workspace 2", "", "layout tabbed"]));
+    assert_eq!(command, "workspace 2; layout tabbed");
+    assert_eq!(
+        restore_focus_command(&command, Some(12)),
+        "workspace 2; layout tabbed; [con_id=12] focus"
+    );
+    assert_eq!(restore_focus_command(&command, None), command);
+}
+
+#[tokio::test]
+async fn run_commands_restores_focus_when_asked() {
+    let mock = MockI3::start(MockState {
+        tree: two_output_tree(),
+        ..MockState::default()
+    })
+    .await;
+    let server = mock.server().await;
+    let run = |restore_focus| {
+        let params = RunCommandsParams {
+            commands: strings(&["workspace 1:web", "layout tabbed"]),
+            restore_focus,
+        };
+        server.run_commands(Parameters(params))
+    };
+
+    let result = envelope(&run(Some(true)).await.unwrap());
+    assert_eq!(result["data"][2]["command"], "[con_id=12] focus");
+    run(None).await.unwrap();
+    assert_eq!(
+        mock.state().commands,
+        [
+            "workspace 1:web; layout tabbed; [con_id=12] focus",
+            "workspace 1:web; layout tabbed",
+        ]
+    );
+}
+
+// ============================================================================
+// Layout rects
+// ============================================================================
+
+#[test]
+fn layout_rects_keep_only_geometry_and_nesting() {
+    let tree = two_output_tree();
+    let (web, _) = find_node_with_ancestors(&tree, 20).unwrap();
+    let value = serde_json::to_value(LayoutRect::from_node(web)).unwrap();
+    let bounds = |x, y, w, h| serde_json::to_value(rect(x, y, w, h)).unwrap();
+    let leaf = |con_id, bounds| {
+        serde_json::json!({
+            "con_id": con_id,
+            "type": "con",
+            "layout": "splith",
+            "rect": bounds,
+            "children": [],
+        })
+    };
+    // Tiled children first, then floating ones, and no window properties
+    assert_eq!(
+        value,
+        serde_json::json!({
+            "con_id": 20,
+            "type": "workspace",
+            "layout": "splith",
+            "rect": bounds(0, 0, 0, 0),
+            "children": [
+                {
+                    "con_id": 21,
+                    "type": "con",
+                    "layout": "splitv",
+                    "rect": bounds(0, 0, 0, 0),
+                    "children": [
+                        leaf(22, bounds(1920, 0, 1920, 540)),
+                        leaf(23, bounds(1920, 540, 1920, 540)),
+                    ],
+                },
+                {
+                    "con_id": 24,
+                    "type": "floating_con",
+                    "layout": "splith",
+                    "rect": bounds(2400, 200, 600, 400),
+                    "children": [leaf(25, bounds(2400, 200, 600, 400))],
+                },
+            ],
+        })
+    );
+}
+
+// ============================================================================
+// Criteria pre-check
+// ============================================================================
+
+#[tokio::test]
+async fn zero_match_criteria_fail_without_sending_a_command() {
+    let mock = MockI3::start(MockState {
+        tree: two_output_tree(),
+        ..MockState::default()
+    })
+    .await;
+    let server = mock.server().await;
+    let focus = |criteria: &str| {
+        let params = FocusWindowParams {
+            criteria: criteria.to_string(),
+        };
+        server.focus_window(Parameters(params))
+    };
+
+    let unmatched = focus("[class=\"Thunderbird\"]").await.unwrap();
+    assert!(is_error(&unmatched));
+    assert_eq!(
+        envelope(&unmatched)["message"],
+        "No window matched [class=\"Thunderbird\"]"
+    );
+    assert!(mock.state().commands.is_empty());
+
+    // Regex criteria can't be checked here, so i3 gets to decide
+    let regex = focus("[class=\"^Thunder.*\"]").await.unwrap();
+    assert!(!is_error(&regex));
+    let matched = focus("[class=\"kitty\"]").await.unwrap();
+    assert!(!is_error(&matched));
+    assert_eq!(
+        mock.state().commands,
+        ["[class=\"^Thunder.*\"] focus", "[class=\"kitty\"] focus"]
+    );
+}
+
+#[test]
+fn parse_criteria_accepts_every_key_i3_does() {
+    for key in ALL_CRITERIA_KEYS {
+        let criteria = format!("[{}=\"x\"]", key);
+        assert!(parse_criteria(&criteria).is_ok(), "{}", criteria);
+    }
+    assert!(parse_criteria("[bogus=\"x\"]").is_err());
+}
+
+#[test]
+fn criteria_checkable_needs_keys_the_matcher_knows() {
+    let checkable = |criteria: &str| criteria_checkable(&parse_criteria(criteria).unwrap());
+    assert!(checkable("[class=\"^kitty$\" floating]"));
+    assert!(!checkable("[class=\"^kit.*\"]"));
+    assert!(!checkable("[app_id=\"foot\"]"));
+    assert!(!checkable("[class=\"kitty\" window_type=\"dialog\"]"));
+}
+
+#[tokio::test]
+async fn criteria_the_server_cant_evaluate_are_left_to_i3() {
+    let mock = MockI3::start(MockState {
+        tree: two_output_tree(),
+        ..MockState::default()
+    })
+    .await;
+    let server = mock.server().await;
+
+    let params = FocusWindowParams {
+        criteria: "[app_id=\"foot\"]".to_string(),
+    };
+    let focused = server.focus_window(Parameters(params)).await.unwrap();
+    assert!(!is_error(&focused));
+    assert_eq!(mock.state().commands, ["[app_id=\"foot\"] focus"]);
+
+    // Tools that match windows themselves refuse them instead of guessing
+    let params = GetWindowMarksParams {
+        criteria: "[app_id=\"foot\"]".to_string(),
+    };
+    assert!(server.get_window_marks(Parameters(params)).await.is_err());
+}
+
+/// A native Wayland view as Sway reports it: no X11 window id or properties
+fn wayland_view(id: usize, title: &str) -> Node {
+    Node {
+        name: Some(title.to_string()),
+        ..node(id, NodeType::Con)
+    }
+}
+
+#[tokio::test]
+async fn criteria_pre_check_matches_wayland_views() {
+    let mock = MockI3::start(MockState {
+        tree: root(vec![output(
+            2,
+            "eDP-1",
+            vec![workspace(
+                10,
+                "1",
+                vec![split(
+                    11,
+                    NodeLayout::SplitV,
+                    vec![wayland_view(12, "foot"), wayland_view(13, "Files")],
+                )],
+            )],
+        )]),
+        sway: true,
+        ..MockState::default()
+    })
+    .await;
+    let server = mock.server().await;
+    let focus = |criteria: &str| {
+        let params = FocusWindowParams {
+            criteria: criteria.to_string(),
+        };
+        server.focus_window(Parameters(params))
+    };
+
+    assert!(!is_error(&focus("[title=\"foot\"]").await.unwrap()));
+    assert!(is_error(&focus("[title=\"mail\"]").await.unwrap()));
+    assert_eq!(mock.state().commands, ["[title=\"foot\"] focus"]);
+
+    // Only the views count, not the split holding them
+    let tree = mock.state().tree.clone();
+    let parsed = parse_criteria("[tiling]").unwrap();
+    let ids: Vec<usize> = find_matching_nodes(&tree, &parsed)
+        .iter()
+        .map(|n| n.id)
+        .collect();
+    assert_eq!(ids, [12, 13]);
+}
+
+// ============================================================================
+// Tags
+// ============================================================================
+
+#[tokio::test]
+async fn tagged_windows_resolve_against_the_current_tree() {
+    let mock = MockI3::start(MockState {
+        tree: two_output_tree(),
+        ..MockState::default()
+    })
+    .await;
+    let server = mock.server().await;
+    let tag = |mark: &str| {
+        let params = TagAndRememberParams {
+            mark: mark.to_string(),
+        };
+        server.tag_and_remember(Parameters(params))
+    };
+
+    let empty = envelope(&server.get_tagged().await.unwrap());
+    assert_eq!(empty["message"], "No windows tagged yet");
+
+    let editor = envelope(&tag("editor").await.unwrap());
+    assert_eq!(editor["data"]["con_id"], 12);
+    assert_eq!(editor["data"]["class"], "Emacs");
+
+    // Focus moves to Firefox; Emacs carries the mark and a new title
+    {
+        let mut state = mock.state();
+        *node_mut(&mut state.tree, 12) = marked(window(12, "Emacs", "notes.org"), &["editor"]);
+        node_mut(&mut state.tree, 22).focused = true;
+    }
+    tag("browser").await.unwrap();
+    assert_eq!(
+        mock.state().commands,
+        [
+            "[con_id=12] mark --add \"editor\"",
+            "[con_id=22] mark --add \"browser\"",
+        ]
+    );
+
+    // Firefox closes; its tag keeps what it was tagged with
+    node_mut(&mut mock.state().tree, 21)
+        .nodes
+        .retain(|n| n.id != 22);
+    let tagged = envelope(&server.get_tagged().await.unwrap());
+    assert_eq!(
+        tagged["data"],
+        serde_json::json!([
+            {
+                "mark": "browser",
+                "con_id": 22,
+                "class": "Firefox",
+                "title": "GitHub",
+                "closed": true,
+            },
+            {
+                "mark": "editor",
+                "con_id": 12,
+                "class": "Emacs",
+                "title": "notes.org",
+                "workspace": "2",
+                "marked": true,
+                "closed": false,
+            },
+        ])
+    );
+}
+
+// ============================================================================
+// Focus layer
+// ============================================================================
+
+#[test]
+fn focused_layer_covers_tiling_floating_and_nothing() {
+    let with_focus = |id| {
+        let mut tree = two_output_tree();
+        node_mut(&mut tree, 12).focused = false;
+        node_mut(&mut tree, id).focused = true;
+        tree
+    };
+
+    assert_eq!(focused_layer(&two_output_tree()), "tiling");
+    // A tiled split container is still in the tiling layer
+    assert_eq!(focused_layer(&with_focus(21)), "tiling");
+    // The window inside a floating container, and the container itself
+    assert_eq!(focused_layer(&with_focus(25)), "floating");
+    assert_eq!(focused_layer(&with_focus(24)), "floating");
+    // Nothing but an empty workspace has focus
+    assert_eq!(focused_layer(&with_focus(10)), "none");
+
+    // A window flagged floating counts even without a floating parent
+    let flagged = Node {
+        floating: Some(Floating::AutoOn),
+        ..window(5, "Pinentry", "pin")
+    };
+    assert_eq!(focus_layer(&flagged, &[]), "floating");
+    assert_eq!(focus_layer(&window(5, "kitty", "shell"), &[]), "tiling");
+}
+
+// ============================================================================
+// Tree-change debounce
+// ============================================================================
+
+#[tokio::test(start_paused = true)]
+async fn debounce_coalesces_a_burst_into_one_notification() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let emitted = Arc::new(Mutex::new(Vec::new()));
+    let listener = {
+        let emitted = emitted.clone();
+        tokio::spawn(async move {
+            debounce_tree_changes(&mut rx, TREE_CHANGE_DEBOUNCE, |changes| {
+                emitted.lock().unwrap().push(changes);
+                async {}
+            })
+            .await;
+        })
+    };
+    let gap = TREE_CHANGE_DEBOUNCE / 4;
+
+    // A burst of events closer together than the quiet period
+    for kind in [
+        "window::new",
+        "window::focus",
+        "window::focus",
+        "workspace::focus",
+    ] {
+        tx.send(kind.to_string()).unwrap();
+        tokio::time::sleep(gap).await;
+    }
+    assert!(emitted.lock().unwrap().is_empty());
+    tokio::time::sleep(TREE_CHANGE_DEBOUNCE).await;
+    assert_eq!(emitted.lock().unwrap().len(), 1);
+
+    // A second burst after the quiet period is reported on its own
+    tx.send("window::close".to_string()).unwrap();
+    drop(tx);
+    listener.await.unwrap();
+
+    let kinds = |kinds: &[&str]| kinds.iter().map(|k| k.to_string()).collect();
+    assert_eq!(
+        *emitted.lock().unwrap(),
+        [
+            TreeChanges {
+                events: 4,
+                kinds: kinds(&["window::focus", "window::new", "workspace::focus"]),
+            },
+            TreeChanges {
+                events: 1,
+                kinds: kinds(&["window::close"]),
+            },
+        ]
+    );
+}
+
+// ============================================================================
+// find_elsewhere
+// ============================================================================
+
+#[test]
+fn matches_elsewhere_skips_the_focused_workspace() {
+    let mut tree = two_output_tree();
+    node_mut(&mut tree, 11)
+        .nodes
+        .push(window(13, "kitty", "build"));
+    let ids = |criteria: &str| -> Vec<usize> {
+        matches_elsewhere(&tree, &parse_criteria(criteria).unwrap())
+            .iter()
+            .map(|w| w.con_id)
+            .collect()
+    };
+
+    // kitty 13 shares the focused workspace "2"; kitty 23 is on 1:web
+    assert_eq!(ids("[class=\"kitty\"]"), [23]);
+    assert!(ids("[class=\"Emacs\"]").is_empty());
+    assert_eq!(ids("[class=\"(?i)^s\"]"), [14]);
+}
+
+// ============================================================================
+// JSON rendering
+// ============================================================================
+
+#[test]
+fn compact_json_has_no_newlines() {
+    let tree = two_output_tree();
+    let compact = render_json(&tree, true);
+    let pretty = render_json(&tree, false);
+    assert!(!compact.contains('\n'));
+    assert!(pretty.contains('\n'));
+    assert!(compact.len() < pretty.len());
+
+    // Both forms carry the same value
+    let parse = |json: &str| serde_json::from_str::<serde_json::Value>(json).unwrap();
+    assert_eq!(parse(&compact), parse(&pretty));
+}
+
+// ============================================================================
+// Workspaces on outputs
+// ============================================================================
+
+#[test]
+fn focus_on_output_command_focuses_moves_or_creates() {
+    let workspaces = [
+        ws_reply("1:web", "HDMI-1", false),
+        ws_reply("2", "DP-1", true),
+        ws_reply("3: mail; chat", "HDMI-1", false),
+    ];
+    let command = |name| focus_on_output_command(&workspaces, name, "DP-1").unwrap();
+
+    assert_eq!(command("2"), ("workspace \"2\"".to_string(), false));
+    assert_eq!(
+        command("1"),
+        (
+            "workspace \"1:web\"; move workspace to output DP-1".to_string(),
+            true
+        )
+    );
+    // Names i3 would otherwise split on stay one argument
+    assert_eq!(
+        command("3: mail; chat"),
+        (
+            "workspace \"3: mail; chat\"; move workspace to output DP-1".to_string(),
+            true
+        )
+    );
+    assert_eq!(
+        command("7"),
+        ("focus output DP-1; workspace \"7\"".to_string(), false)
+    );
+}
+
+#[tokio::test]
+async fn pull_workspace_here_targets_the_focused_output() {
+    let mock = MockI3::start(MockState {
+        workspaces: vec![
+            ws_reply("1:web", "HDMI-1", false),
+            ws_reply("2", "DP-1", true),
+        ],
+        ..MockState::default()
+    })
+    .await;
+    let server = mock.server().await;
+
+    let params = PullWorkspaceHereParams {
+        workspace: "1:web".to_string(),
+    };
+    let result = envelope(
+        &server
+            .pull_workspace_here(Parameters(params))
+            .await
+            .unwrap(),
+    );
+    assert_eq!(result["data"]["moved"], true);
+    assert_eq!(
+        mock.state().commands,
+        ["workspace \"1:web\"; move workspace to output DP-1"]
+    );
+}
+
+// ============================================================================
+// Focus order
+// ============================================================================
+
+#[test]
+fn focus_order_follows_the_focus_array() {
+    let mut tree = two_output_tree();
+    let web = node_mut(&mut tree, 20);
+    // The floating volume control was focused last, then the split; 99 is gone
+    web.focus = vec![24, 21, 99];
+
+    assert_eq!(
+        serde_json::Value::from(focus_order(web)),
+        serde_json::json!([
+            {
+                "position": 0,
+                "con_id": 24,
+                "type": "floating_con",
+                "name": null,
+                "class": null,
+                "floating": true,
+            },
+            {
+                "position": 1,
+                "con_id": 21,
+                "type": "con",
+                "name": null,
+                "class": null,
+                "floating": false,
+            },
+            { "position": 2, "con_id": 99 },
+        ])
+    );
+
+    let split = node_mut(&mut tree, 21);
+    split.focus = vec![23, 22];
+    let classes: Vec<_> = focus_order(split)
+        .iter()
+        .map(|e| e["class"].clone())
+        .collect();
+    assert_eq!(classes, ["kitty", "Firefox"]);
+}
+
+// ============================================================================
+// Read-only mode
+// ============================================================================
+
+#[test]
+fn kill_is_mutating_and_get_tree_is_not() {
+    assert!(is_mutating("kill"));
+    assert!(!is_mutating("get_tree"));
+}
+
+#[tokio::test]
+async fn get_tool_metadata_flags_each_tool() {
+    let server = I3Server::new();
+    let result = envelope(&server.get_tool_metadata().await.unwrap());
+    let tools = result["data"].as_array().unwrap();
+    let mutating = |name: &str| {
+        tools
+            .iter()
+            .find(|t| t["name"] == name)
+            .map(|t| t["mutating"].clone())
+    };
+    assert_eq!(mutating("kill"), Some(serde_json::json!(true)));
+    assert_eq!(mutating("get_tree"), Some(serde_json::json!(false)));
+    // Only the tools this server registered are listed
+    assert_eq!(tools.len(), server.tool_router.list_all().len());
+}
+
+#[test]
+fn read_only_router_keeps_only_query_tools() {
+    let mut tool_router = I3Server::tool_router();
+    let all = tool_router.list_all().len();
+    remove_mutating_tools(&mut tool_router);
+
+    let mut remaining: Vec<String> = tool_router
+        .list_all()
+        .into_iter()
+        .map(|tool| tool.name.to_string())
+        .collect();
+    remaining.sort_unstable();
+    assert!(remaining.len() < all);
+    for tool in &remaining {
+        assert!(
+            !is_mutating(tool),
+            "{} is registered in read-only mode",
+            tool
+        );
+    }
+
+    // Every allowlisted tool is a real one, so a typo can't hide a query tool
+    let mut expected: Vec<&str> = READ_ONLY_TOOLS.to_vec();
+    expected.sort_unstable();
+    assert_eq!(remaining, expected);
+}
+
+// ============================================================================
+// Window processes
+// ============================================================================
+
+#[test]
+fn proc_command_and_rss_reads_comm_and_status() {
+    let proc_root = scratch_dir("proc");
+    let firefox = proc_root.join("4242");
+    std::fs::create_dir(&firefox).unwrap();
+    std::fs::write(firefox.join("comm"), "firefox\n").unwrap();
+    std::fs::write(
+        firefox.join("status"),
+        "Name:\tfirefox\nVmPeak:\t 3000000 kB\nVmRSS:\t  812344 kB\nThreads:\t95\n",
+    )
+    .unwrap();
+    // A kernel thread has no VmRSS line
+    let kthread = proc_root.join("2");
+    std::fs::create_dir(&kthread).unwrap();
+    std::fs::write(kthread.join("comm"), "kthreadd\n").unwrap();
+    std::fs::write(kthread.join("status"), "Name:\tkthreadd\nThreads:\t1\n").unwrap();
+
+    assert_eq!(
+        proc_command_and_rss(&proc_root, 4242),
+        (Some("firefox".to_string()), Some(812344))
+    );
+    assert_eq!(
+        proc_command_and_rss(&proc_root, 2),
+        (Some("kthreadd".to_string()), None)
+    );
+    // The process has exited
+    assert_eq!(proc_command_and_rss(&proc_root, 9999), (None, None));
+    std::fs::remove_dir_all(proc_root).unwrap();
+}
+
+// ============================================================================
+// Command history
+// ============================================================================
+
+#[tokio::test]
+async fn command_history_records_commands_in_order() {
+    let mock = MockI3::start(MockState {
+        fail_matching: Some("bogus".to_string()),
+        ..MockState::default()
+    })
+    .await;
+    let server = mock.server().await;
+    for command in ["workspace 2", "bogus command"] {
+        let params = RunCommandParams {
+            command: command.to_string(),
+        };
+        server.run_command(Parameters(params)).await.unwrap();
+    }
+
+    let history = envelope(&server.get_command_history().await.unwrap());
+    let entries = history["data"].as_array().unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0]["command"], "workspace 2");
+    assert_eq!(entries[0]["success"], true);
+    assert!(entries[0]["error"].is_null());
+    assert_eq!(entries[1]["command"], "bogus command");
+    assert_eq!(entries[1]["success"], false);
+    assert_eq!(entries[1]["error"], "mock failure");
+    assert!(entries[0]["timestamp"].as_u64() <= entries[1]["timestamp"].as_u64());
+}
+
+#[test]
+fn push_bounded_drops_the_oldest_entries() {
+    let mut buffer = VecDeque::new();
+    for item in 1..=5 {
+        push_bounded(&mut buffer, item, 3);
+    }
+    assert_eq!(buffer, [3, 4, 5]);
+}
+
+// ============================================================================
+// Swapping workspace numbers
+// ============================================================================
+
+#[test]
+fn swap_numbers_command_renames_through_a_placeholder() {
+    let names = ["1:web", "3:mail", "notes"];
+    let (command, new_a, new_b) = swap_numbers_command(&names, "1:web", "3:mail").unwrap();
+    assert_eq!(new_a, "3:web");
+    assert_eq!(new_b, "1:mail");
+    assert_eq!(
+        command,
+        "rename workspace \"1:web\" to \"rmcp-i3-swap\"; \
+         rename workspace \"3:mail\" to \"1:mail\"; \
+         rename workspace \"rmcp-i3-swap\" to \"3:web\""
+    );
+}
+
+#[test]
+fn swap_numbers_command_rejects_unswappable_workspaces() {
+    let names = ["1:web", "3:mail", "notes"];
+    assert!(swap_numbers_command(&names, "1:web", "9").is_err());
+    assert!(swap_numbers_command(&names, "1:web", "1:web").is_err());
+    assert!(swap_numbers_command(&names, "1:web", "notes").is_err());
+
+    assert_eq!(renumber_workspace("12:chat", 4).as_deref(), Some("4:chat"));
+    assert_eq!(renumber_workspace("12", 4).as_deref(), Some("4"));
+    assert_eq!(renumber_workspace("notes", 4), None);
+}
+
+// ============================================================================
+// Bars
+// ============================================================================
+
+#[test]
+fn bar_command_targets_one_bar_or_all() {
+    let bars = strings(&["bar-0", "bar-1"]);
+    assert_eq!(
+        bar_command("mode", "hide", Some("bar-1"), &bars).unwrap(),
+        "bar mode hide bar-1"
+    );
+    assert_eq!(
+        bar_command("hidden_state", "show", None, &bars).unwrap(),
+        "bar hidden_state show"
+    );
+    assert_eq!(
+        bar_command("mode", "dock", Some("  "), &bars).unwrap(),
+        "bar mode dock"
+    );
+    assert!(bar_command("mode", "dock", Some("bar-9"), &bars).is_err());
+    assert!(bar_command("mode", "dock", None, &[]).is_err());
+}
+
+#[test]
+fn validate_bar_value_accepts_only_known_values() {
+    assert_eq!(
+        validate_bar_value("mode", " invisible ", BAR_MODES).unwrap(),
+        "invisible"
+    );
+    assert_eq!(
+        validate_bar_value("hidden_state", "hide", BAR_HIDDEN_STATES).unwrap(),
+        "hide"
+    );
+    assert!(validate_bar_value("mode", "show", BAR_MODES).is_err());
+    assert!(validate_bar_value("mode", "dock; exit", BAR_MODES).is_err());
+}
+
+// ============================================================================
+// describe_state
+// ============================================================================
+
+#[test]
+fn describe_state_mentions_the_focused_window() {
+    let workspaces = [
+        ws_reply("notes", "DP-1", false),
+        ws_reply("2", "DP-1", true),
+        ws_reply("1:web", "HDMI-1", false),
+    ];
+    let outputs = [
+        output_reply("DP-1", rect(0, 0, 1920, 1080), Some("2")),
+        output_reply("HDMI-1", rect(1920, 0, 1920, 1080), Some("1:web")),
+    ];
+    let windows = collect_windows(&two_output_tree());
+
+    assert_eq!(
+        describe_state(&workspaces, &outputs, &windows),
+        "Focused workspace is '2' on output DP-1 with 1 window(s). \
+         Focused window is Emacs \"init.el\". \
+         There are 3 workspace(s) and 5 window(s) across 2 active output(s). \
+         Urgent: Slack \"ping\" on 'notes'."
+    );
+
+    let unfocused: Vec<WindowInfo> = windows
+        .into_iter()
+        .map(|w| WindowInfo {
+            focused: false,
+            ..w
+        })
+        .collect();
+    assert!(describe_state(&workspaces, &outputs, &unfocused).contains("No window is focused."));
+}
+
+// ============================================================================
+// Input devices
+// ============================================================================
+
+#[test]
+fn input_command_quotes_the_identifier_and_comma_values() {
+    assert_eq!(
+        input_command("type:touchpad", "tap", "enabled").unwrap(),
+        "input \"type:touchpad\" tap enabled"
+    );
+    assert_eq!(
+        input_command(" type:keyboard ", "xkb_layout", "us,de").unwrap(),
+        "input \"type:keyboard\" xkb_layout \"us,de\""
+    );
+    assert!(input_command("", "tap", "enabled").is_err());
+    assert!(input_command("type:touchpad", "tap enabled", "x").is_err());
+    assert!(input_command("type:touchpad", "tap", "enabled; exit").is_err());
+    assert!(input_command("a\"b", "tap", "enabled").is_err());
+}
+
+#[test]
+fn is_sway_checks_variant_and_version_string() {
+    assert!(is_sway(&serde_json::json!({ "variant": "sway" })));
+    assert!(is_sway(
+        &serde_json::json!({ "human_readable": "sway version 1.9" })
+    ));
+    assert!(!is_sway(
+        &serde_json::json!({ "human_readable": "4.23 (2023-10-29)" })
+    ));
+    assert!(!is_sway(&serde_json::json!({})));
+}
+
+#[tokio::test]
+async fn configure_input_runs_only_on_sway() {
+    let params = || ConfigureInputParams {
+        identifier: "type:touchpad".to_string(),
+        setting: "tap".to_string(),
+        value: "enabled".to_string(),
+    };
+
+    let i3 = MockI3::start(MockState::default()).await;
+    let server = i3.server().await;
+    let result = server.configure_input(Parameters(params())).await.unwrap();
+    assert_eq!(envelope(&result)["ok"], false);
+    assert!(i3.state().commands.is_empty());
+    drop(server);
+    drop(i3);
+
+    let sway = MockI3::start(MockState {
+        sway: true,
+        inputs: vec![serde_json::json!({
+            "identifier": "1739:52619:SYNA8004:00_06CB:CD8B",
+            "type": "touchpad",
+        })],
+        ..MockState::default()
+    })
+    .await;
+    let server = sway.server().await;
+    // GET_INPUTS goes over its own connection, found through $I3SOCK
+    let _socket = sway.use_socket().await;
+    let result = server.configure_input(Parameters(params())).await.unwrap();
+    assert_eq!(envelope(&result)["ok"], true);
+    assert_eq!(
+        sway.state().commands,
+        ["input \"type:touchpad\" tap enabled"]
+    );
+}
+
+// ============================================================================
+// goto_window
+// ============================================================================
+
+#[test]
+fn goto_command_switches_workspace_or_shows_the_scratchpad() {
+    let tree = with_scratchpad(vec![floating(30, window(31, "KeePassXC", "Passwords"))]);
+    let goto = |criteria: &str| goto_command(&tree, &parse_criteria(criteria).unwrap());
+
+    assert_eq!(
+        goto("[class=\"kitty\"]"),
+        Some((
+            "workspace \"1:web\"; [con_id=23] focus".to_string(),
+            Some("1:web".to_string())
+        ))
+    );
+    // The first match in tree order wins
+    assert_eq!(
+        goto("[title=\"i\"]").map(|(command, _)| command),
+        Some("workspace \"notes\"; [con_id=14] focus".to_string())
+    );
+    assert_eq!(
+        goto("[class=\"KeePassXC\"]"),
+        Some(("[con_id=31] scratchpad show".to_string(), None))
+    );
+    assert_eq!(goto("[class=\"Thunderbird\"]"), None);
+}
+
+#[tokio::test]
+async fn goto_window_resolves_the_window_then_sends_one_command() {
+    let mock = MockI3::start(MockState {
+        tree: two_output_tree(),
+        ..MockState::default()
+    })
+    .await;
+    let server = mock.server().await;
+    let goto = |criteria: &str| {
+        let params = FocusWindowParams {
+            criteria: criteria.to_string(),
+        };
+        server.goto_window(Parameters(params))
+    };
+
+    let result = envelope(&goto("class=\"Firefox\"").await.unwrap());
+    assert_eq!(result["data"]["workspace"], "1:web");
+    assert!(is_error(&goto("[class=\"Thunderbird\"]").await.unwrap()));
+    // Regex criteria go to i3 as they are
+    goto("[class=\"^Fire.*\"]").await.unwrap();
+    assert_eq!(
+        mock.state().commands,
+        [
+            "workspace \"1:web\"; [con_id=22] focus",
+            "[class=\"^Fire.*\"] focus",
+        ]
+    );
+}
+
+// ============================================================================
+// Workspace lookup
+// ============================================================================
+
+#[test]
+fn match_workspace_by_exact_name_then_number() {
+    let workspaces = [
+        ws_reply("1:web", "DP-1", true),
+        ws_reply("3:mail", "DP-1", false),
+        ws_reply("3:chat", "HDMI-1", false),
+        ws_reply("notes", "HDMI-1", false),
+    ];
+    let name = |query| match_workspace(query, &workspaces).map(|ws| ws.name.as_str());
+
+    assert_eq!(name("notes").unwrap(), "notes");
+    assert_eq!(name("3:chat").unwrap(), "3:chat");
+    // A bare number matches the workspace with that number
+    assert_eq!(name("1").unwrap(), "1:web");
+
+    let ambiguous = name("3").unwrap_err();
+    assert!(
+        ambiguous.message.contains("'3:mail' on DP-1"),
+        "{}",
+        ambiguous.message
+    );
+    assert!(ambiguous.message.contains("'3:chat' on HDMI-1"));
+    assert!(name("7").is_err());
+    assert!(name("web").is_err());
+
+    // An exact name wins over a number match
+    let exact = [
+        ws_reply("3", "DP-1", false),
+        ws_reply("3:mail", "DP-1", false),
+    ];
+    assert_eq!(match_workspace("3", &exact).unwrap().name, "3");
+}
+
+// ============================================================================
+// Split ratio
+// ============================================================================
+
+#[test]
+fn split_ratio_command_sets_the_target_ppt() {
+    let left = at(window(5, "kitty", "left"), 0, 0, 640, 1080);
+    let right = at(window(6, "Firefox", "right"), 640, 0, 1280, 1080);
+    let row = at(
+        split(4, NodeLayout::SplitH, vec![left.clone(), right.clone()]),
+        0,
+        0,
+        1920,
+        1080,
+    );
+    assert_eq!(
+        split_ratio_command(&left, &row, 0.7).unwrap(),
+        ("resize set width 70 ppt".to_string(), 33, 70)
+    );
+    assert_eq!(
+        split_ratio_command(&right, &row, 0.25).unwrap(),
+        ("resize set width 25 ppt".to_string(), 67, 25)
+    );
+
+    let top = at(window(8, "kitty", "top"), 0, 0, 1920, 270);
+    let column = at(
+        split(
+            7,
+            NodeLayout::SplitV,
+            vec![top.clone(), window(9, "kitty", "bottom")],
+        ),
+        0,
+        0,
+        1920,
+        1080,
+    );
+    assert_eq!(
+        split_ratio_command(&top, &column, 0.5).unwrap(),
+        ("resize set height 50 ppt".to_string(), 25, 50)
+    );
+}
+
+#[test]
+fn split_ratio_command_needs_a_sized_split_with_siblings() {
+    let only = at(window(5, "kitty", "only"), 0, 0, 1920, 1080);
+    let alone = at(
+        split(4, NodeLayout::SplitH, vec![only.clone()]),
+        0,
+        0,
+        1920,
+        1080,
+    );
+    assert!(split_ratio_command(&only, &alone, 0.5).is_err());
+
+    let tabs = at(
+        split(
+            4,
+            NodeLayout::Tabbed,
+            vec![only.clone(), window(6, "kitty", "b")],
+        ),
+        0,
+        0,
+        1920,
+        1080,
+    );
+    assert!(split_ratio_command(&only, &tabs, 0.5).is_err());
+
+    let empty = split(
+        4,
+        NodeLayout::SplitH,
+        vec![only.clone(), window(6, "kitty", "b")],
+    );
+    assert!(split_ratio_command(&only, &empty, 0.5).is_err());
+
+    assert!(validate_split_ratio(0.05).is_err());
+    assert!(validate_split_ratio(0.95).is_err());
+    assert_eq!(validate_split_ratio(0.5).unwrap(), 0.5);
+}
+
+// ============================================================================
+// validate_command
+// ============================================================================
+
+#[test]
+fn command_issues_flags_malformed_commands() {
+    let issues = |command| command_issues(command);
+    assert_eq!(issues("  "), ["Command is empty"]);
+    assert_eq!(issues("workspace \"2"), ["Unterminated double quote"]);
+    assert_eq!(
+        issues("[class=\"kitty\" focus"),
+        ["'[' criteria block is never closed"]
+    );
+    assert_eq!(
+        issues("class=\"kitty\"] focus"),
+        ["']' without a matching '['"]
+    );
+    assert_eq!(issues("[] kill"), ["Criteria [] are empty"]);
+    assert_eq!(
+        issues("[colour=\"red\"] kill"),
+        ["Unknown criteria key 'colour' in [colour=\"red\"]"]
+    );
+    assert_eq!(
+        issues("[class=\"kitty\"]"),
+        ["Criteria [class=\"kitty\"] are not followed by a command"]
+    );
+    assert_eq!(
+        issues("focus left;; kill"),
+        ["Empty command before separator 2"]
+    );
+    assert_eq!(
+        issues("focuss left"),
+        ["Unknown command 'focuss' in 'focuss left'"]
+    );
+    assert_eq!(issues("workspace"), ["'workspace' needs an argument"]);
+    assert_eq!(issues("bindsym"), ["'bindsym' needs an argument"]);
+    assert_eq!(
+        issues("floating_minimum_size"),
+        ["'floating_minimum_size' needs an argument"]
+    );
+}
+
+#[test]
+fn command_issues_accepts_well_formed_commands() {
+    for command in [
+        "workspace 2; layout tabbed",
+        "[class=\"Firefox\" title=\"a;b\"] focus, floating enable",
+        "rename workspace to \"1:web\"",
+        "title_format \"<b>%title</b>\"",
+        "bindsym --release $mod+x kill",
+        "unbindsym $mod+x",
+        "floating_minimum_size 200 x 100",
+        "floating_maximum_size -1 x -1",
+        "focus parent; ",
+    ] {
+        assert_eq!(command_issues(command), Vec::<String>::new(), "{}", command);
+    }
+}
+
+/// One command from each builder the server sends through
+fn built_commands() -> Vec<String> {
+    let tree = two_output_tree();
+    let (emacs, _) = find_node_with_ancestors(&tree, 12).unwrap();
+    let (web, _) = find_node_with_ancestors(&tree, 20).unwrap();
+    let workspaces = [
+        ws_reply("1:web", "HDMI-1", false),
+        ws_reply("2", "DP-1", true),
+    ];
+    let marks = strings(&["build"]);
+    let left = at(window(5, "kitty", "left"), 0, 0, 640, 1080);
+    let row = at(
+        split(
+            4,
+            NodeLayout::SplitH,
+            vec![left.clone(), window(6, "kitty", "b")],
+        ),
+        0,
+        0,
+        1920,
+        1080,
+    );
+    let floating = SpotlightState {
+        was_floating: true,
+        rect: rect(2400, 200, 600, 400),
+    };
+    let tiled = SpotlightState {
+        was_floating: false,
+        rect: rect(0, 0, 0, 0),
+    };
+    let kitty = parse_criteria("[class=\"kitty\"]").unwrap();
+
+    let mut commands = vec![
+        cleanup_workspaces_command(&strings(&["4", "5: spare"]), "2"),
+        evacuate_command(&[22, 23], "2"),
+        stash_command(22, "stash"),
+        unstash_command(22, "1:web"),
+        quad_tile_command("1:web", &[22, 23, 25]).unwrap(),
+        spotlight_command(22),
+        unspotlight_command(25, &floating),
+        unspotlight_command(22, &tiled),
+        opacity_command(Some("[app_id=\"foot\"]"), 0.8),
+        opacity_command(None, 1.0),
+        bar_command("mode", "hide", Some("bar-0"), &strings(&["bar-0"])).unwrap(),
+        split_ratio_command(&left, &row, 0.7).unwrap().0,
+        window_id_focus_command(1022).unwrap(),
+        restore_focus_command("workspace 2", Some(12)),
+        chain_commands(
+            "[class=\"mpv\"]",
+            &strings(&["floating enable", "sticky enable"]),
+        )
+        .unwrap(),
+        command_on(Some("[class=\"mpv\"]"), "fullscreen toggle").unwrap(),
+        command_on(None, "kill").unwrap(),
+        bind_key_command("$mod+x", "kill", true).unwrap(),
+        unbind_key_command("$mod+x").unwrap(),
+        goto_command(&tree, &kitty).unwrap().0,
+        label_command(emacs, &label_mark("notes").unwrap()),
+        exec_command("firefox --new-window", None).unwrap(),
+        focus_on_output_command(&workspaces, "1:web", "DP-1")
+            .unwrap()
+            .0,
+        focus_on_output_command(&workspaces, "7", "DP-1").unwrap().0,
+        send_window_command(&workspaces, 12, "1", None).unwrap().0,
+        send_window_command(&workspaces, 12, "1", Some("DP-1"))
+            .unwrap()
+            .0,
+        move_to_mark_command(&marks, emacs, "build").unwrap(),
+        move_and_split_command(&workspaces, 12, "1", "vertical").unwrap(),
+        relative_move_command(12, "next", true),
+        relative_move_command(12, "prev", false),
+        swap_numbers_command(&["1:web", "2"], "1:web", "2")
+            .unwrap()
+            .0,
+        clear_urgency_command(&[14], Some(12), "2"),
+        clear_urgency_command(&[14], None, "2"),
+        input_command("type:keyboard", "xkb_layout", "us,de").unwrap(),
+        floating_size_line("minimum", 200, 100),
+        floating_size_line("maximum", 1920, 1080),
+    ];
+    commands.extend(pip_commands(42, &rect(3840, 1086, 640, 354)));
+    commands.extend(equalize_commands(web));
+    commands
+}
+
+#[test]
+fn command_issues_accepts_every_built_command() {
+    for command in built_commands() {
+        assert_eq!(
+            command_issues(&command),
+            Vec::<String>::new(),
+            "{}",
+            command
+        );
+    }
+}
+
+// ============================================================================
+// reconnect
+// ============================================================================
+
+#[tokio::test]
+async fn reconnect_replaces_the_shared_connection() {
+    let mock = MockI3::start(MockState::default()).await;
+    let server = mock.server().await;
+    // A round trip, so the mock has surely accepted the connection
+    server.get_version().await.unwrap();
+    assert_eq!(mock.state().connections, 1);
+
+    let result = {
+        let _socket = mock.use_socket().await;
+        envelope(&server.reconnect().await.unwrap())
+    };
+    assert_eq!(result["data"]["replaced"], true);
+    assert_eq!(result["data"]["version"], "4.23 (mock)");
+    assert_eq!(mock.state().connections, 2);
+
+    // Later calls reuse the new connection
+    server.get_version().await.unwrap();
+    assert_eq!(mock.state().connections, 2);
+
+    // Without an open connection there is nothing to replace
+    let fresh = I3Server::new();
+    let _socket = mock.use_socket().await;
+    let result = envelope(&fresh.reconnect().await.unwrap());
+    assert_eq!(result["data"]["replaced"], false);
+}
+
+// ============================================================================
+// list_windows
+// ============================================================================
+
+#[test]
+fn window_titles_lists_exactly_the_leaf_windows() {
+    let mut tree = with_scratchpad(vec![floating(30, window(31, "KeePassXC", "Passwords"))]);
+    // A split container with a name of its own is still not a window
+    node_mut(&mut tree, 21).name = Some("split".to_string());
+
+    let expected: BTreeMap<String, String> = [
+        (12, "init.el"),
+        (14, "ping"),
+        (22, "GitHub"),
+        (23, "logs"),
+        (25, "Volume"),
+        (31, "Passwords"),
+    ]
+    .into_iter()
+    .map(|(id, title)| (id.to_string(), title.to_string()))
+    .collect();
+    assert_eq!(window_titles(&tree), expected);
+}
+
+// ============================================================================
+// send_window
+// ============================================================================
+
+#[test]
+fn send_window_command_without_an_output_just_moves() {
+    let workspaces = [
+        ws_reply("3:web", "HDMI-1", false),
+        ws_reply("2", "DP-1", true),
+    ];
+    // "3" lands on the existing 3:web
+    assert_eq!(
+        send_window_command(&workspaces, 12, "3", None).unwrap(),
+        (
+            "[con_id=12] move container to workspace \"3:web\"".to_string(),
+            false
+        )
+    );
+    assert_eq!(
+        send_window_command(&workspaces, 12, "8", None).unwrap(),
+        (
+            "[con_id=12] move container to workspace \"8\"".to_string(),
+            false
+        )
+    );
+}
+
+#[test]
+fn send_window_command_with_an_output_brings_the_workspace_along() {
+    let workspaces = [
+        ws_reply("3:web", "HDMI-1", false),
+        ws_reply("2", "DP-1", true),
+    ];
+    assert_eq!(
+        send_window_command(&workspaces, 12, "3", Some("DP-1")).unwrap(),
+        (
+            "workspace \"3:web\"; move workspace to output DP-1; \
+             [con_id=12] move container to workspace \"3:web\"; [con_id=12] focus"
+                .to_string(),
+            true
+        )
+    );
+    assert_eq!(
+        send_window_command(&workspaces, 12, "3", Some("HDMI-1")).unwrap(),
+        (
+            "workspace \"3:web\"; [con_id=12] move container to workspace \"3:web\"; \
+             [con_id=12] focus"
+                .to_string(),
+            false
+        )
+    );
+    assert_eq!(
+        send_window_command(&workspaces, 12, "8", Some("HDMI-1"))
+            .unwrap()
+            .0,
+        "focus output HDMI-1; workspace \"8\"; \
+         [con_id=12] move container to workspace \"8\"; [con_id=12] focus"
+    );
+}
+
+// ============================================================================
+// Focus depth
+// ============================================================================
+
+#[test]
+fn focus_path_walks_down_to_a_nested_window() {
+    let mut focused = window(7, "kitty", "deep");
+    focused.focused = true;
+    let tabs = split(
+        6,
+        NodeLayout::Tabbed,
+        vec![window(8, "kitty", "other"), focused],
+    );
+    let column = split(
+        5,
+        NodeLayout::SplitV,
+        vec![tabs, window(9, "Emacs", "notes")],
+    );
+    // Like i3, the output keeps its workspaces in an internal "content" container
+    let content = Node {
+        name: Some("content".to_string()),
+        nodes: vec![workspace(4, "1", vec![column])],
+        ..node(3, NodeType::Con)
+    };
+    let tree = root(vec![output(2, "DP-1", vec![content])]);
+
+    let (path, depth) = focus_path(&tree).unwrap();
+    let levels: Vec<(usize, &str)> = path.iter().map(|l| (l.con_id, l.level.as_str())).collect();
+    assert_eq!(
+        levels,
+        [
+            (2, "output"),
+            (4, "workspace:splith"),
+            (5, "con:splitv"),
+            (6, "con:tabbed"),
+            (7, "window"),
+        ]
+    );
+    // Two containers between the window and its workspace
+    assert_eq!(depth, 2);
+
+    assert!(focus_path(&root(vec![])).is_none());
+}
+
+// ============================================================================
+// kill_all
+// ============================================================================
+
+#[test]
+fn kill_all_guard_enforces_the_threshold() {
+    assert!(kill_all_guard(Some(1), 3, false).is_ok());
+    assert!(kill_all_guard(Some(3), 3, false).is_ok());
+    let over = kill_all_guard(Some(4), 3, false).unwrap_err();
+    assert!(
+        over.starts_with("4 windows match, more than the limit of 3"),
+        "{}",
+        over
+    );
+    assert!(kill_all_guard(Some(4), 3, true).is_ok());
+    assert!(kill_all_guard(Some(40), 100, false).is_ok());
+
+    // An unknown count always needs confirming
+    assert!(kill_all_guard(None, 100, false).is_err());
+    assert!(kill_all_guard(None, 3, true).is_ok());
+}
+
+#[tokio::test]
+async fn kill_all_closes_the_counted_windows_by_con_id() {
+    let mut tree = two_output_tree();
+    node_mut(&mut tree, 11)
+        .nodes
+        .push(window(13, "kitty", "build"));
+    let mock = MockI3::start(MockState {
+        tree,
+        ..MockState::default()
+    })
+    .await;
+    let server = mock.server().await;
+    let kill_all = |confirm| {
+        let params = KillAllParams {
+            criteria: "[class=\"kitty\"]".to_string(),
+            max: Some(1),
+            confirm,
+        };
+        server.kill_all(Parameters(params))
+    };
+
+    assert!(is_error(&kill_all(None).await.unwrap()));
+    assert!(mock.state().commands.is_empty());
+
+    let result = envelope(&kill_all(Some(true)).await.unwrap());
+    assert_eq!(result["data"]["closed"], serde_json::json!([13, 23]));
+    assert_eq!(
+        mock.state().commands,
+        ["[con_id=13] kill; [con_id=23] kill"]
+    );
+}
+
+// ============================================================================
+// Urgent workspaces
+// ============================================================================
+
+fn urgent(workspace: Workspace) -> Workspace {
+    Workspace {
+        urgent: true,
+        ..workspace
+    }
+}
+
+#[test]
+fn most_urgent_workspace_prefers_the_lowest_number() {
+    let workspaces = [
+        ws_reply("1:web", "DP-1", true),
+        urgent(ws_reply("chat", "DP-1", false)),
+        urgent(ws_reply("5:mail", "HDMI-1", false)),
+        urgent(ws_reply("3: build, test", "HDMI-1", false)),
+        ws_reply("2", "DP-1", false),
+    ];
+    let name =
+        |workspaces: &[Workspace]| most_urgent_workspace(workspaces).map(|ws| ws.name.clone());
+    assert_eq!(name(&workspaces).as_deref(), Some("3: build, test"));
+    // Unnumbered workspaces come last
+    assert_eq!(name(&workspaces[..3]).as_deref(), Some("5:mail"));
+    assert_eq!(name(&workspaces[..2]).as_deref(), Some("chat"));
+    assert_eq!(name(&workspaces[..1]), None);
+}
+
+#[tokio::test]
+async fn focus_urgent_workspace_quotes_the_name() {
+    let mock = MockI3::start(MockState {
+        workspaces: vec![
+            ws_reply("1:web", "DP-1", true),
+            urgent(ws_reply("5:mail", "HDMI-1", false)),
+            urgent(ws_reply("3: build, test", "HDMI-1", false)),
+        ],
+        ..MockState::default()
+    })
+    .await;
+    let server = mock.server().await;
+
+    let result = envelope(&server.focus_urgent_workspace().await.unwrap());
+    assert_eq!(result["data"], "3: build, test");
+    assert_eq!(mock.state().commands, ["workspace \"3: build, test\""]);
+
+    mock.state()
+        .workspaces
+        .iter_mut()
+        .for_each(|ws| ws.urgent = false);
+    let calm = envelope(&server.focus_urgent_workspace().await.unwrap());
+    assert_eq!(calm["message"], "No workspace is urgent");
+    assert_eq!(mock.state().commands.len(), 1);
+}
+
+// ============================================================================
+// Config edits
+// ============================================================================
+
+const FIXTURE_CONFIG: &str = "\
+set $mod Mod4
+set $ws1 \"1:web\"
+workspace $ws1 output DP-1
+workspace 2 output DP-1
+bindsym $mod+Return exec kitty
+workspace 2 output HDMI-1
+";
+
+#[test]
+fn pin_workspace_in_config_replaces_the_assignment() {
+    let (contents, line, replaced) = pin_workspace_in_config(FIXTURE_CONFIG, "2", "HDMI-1");
+    assert_eq!(line, "workspace 2 output HDMI-1");
+    assert!(replaced);
+    // Replaced in place, with the later duplicate dropped
+    assert_eq!(
+        contents,
+        "set $mod Mod4\n\
+         set $ws1 \"1:web\"\n\
+         workspace $ws1 output DP-1\n\
+         workspace 2 output HDMI-1\n\
+         bindsym $mod+Return exec kitty\n"
+    );
+}
+
+#[test]
+fn pin_workspace_in_config_appends_new_assignments() {
+    let (contents, line, replaced) = pin_workspace_in_config(FIXTURE_CONFIG, "3 mail", "DP-1");
+    assert_eq!(line, "workspace \"3 mail\" output DP-1");
+    assert!(!replaced);
+    assert_eq!(contents, format!("{}{}\n", FIXTURE_CONFIG, line));
+
+    // Assignments through variables aren't recognized, so 1:web gets its own line
+    let (contents, _, replaced) = pin_workspace_in_config(FIXTURE_CONFIG, "1:web", "HDMI-1");
+    assert!(!replaced);
+    assert!(contents.contains("workspace $ws1 output DP-1\n"));
+    assert!(contents.ends_with("workspace 1:web output HDMI-1\n"));
+}
+
+#[test]
+fn write_config_atomically_keeps_a_backup() {
+    let dir = scratch_dir("config-write");
+    let config = dir.join("config");
+    std::fs::write(&config, FIXTURE_CONFIG).unwrap();
+    // Dotfile setups often symlink the config; the link must survive
+    let link = dir.join("link");
+    std::os::unix::fs::symlink(&config, &link).unwrap();
+
+    let backup = write_config_atomically(link.to_str().unwrap(), "set $mod Mod1\n").unwrap();
+    assert_eq!(
+        backup,
+        std::fs::canonicalize(dir.join("config.bak")).unwrap()
+    );
+    assert_eq!(std::fs::read_to_string(&backup).unwrap(), FIXTURE_CONFIG);
+    assert_eq!(std::fs::read_to_string(&config).unwrap(), "set $mod Mod1\n");
+    assert!(std::fs::symlink_metadata(&link)
+        .unwrap()
+        .file_type()
+        .is_symlink());
+    assert!(!dir.join("config.rmcp-i3.tmp").exists());
+
+    assert!(write_config_atomically(dir.join("missing").to_str().unwrap(), "").is_err());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn pin_workspace_to_output_edits_the_loaded_config() {
+    let dir = scratch_dir("pin-config");
+    let config = dir.join("config");
+    std::fs::write(&config, FIXTURE_CONFIG).unwrap();
+    let mock = MockI3::start(MockState {
+        workspaces: vec![ws_reply("2", "DP-1", true)],
+        outputs: vec![
+            output_reply("DP-1", rect(0, 0, 1920, 1080), Some("2")),
+            output_reply("HDMI-1", rect(1920, 0, 1920, 1080), None),
+        ],
+        config_file: config.to_str().unwrap().to_string(),
+        ..MockState::default()
+    })
+    .await;
+    let server = mock.server().await;
+
+    let params = PinWorkspaceToOutputParams {
+        workspace: "2".to_string(),
+        output: "HDMI-1".to_string(),
+    };
+    let result = envelope(
+        &server
+            .pin_workspace_to_output(Parameters(params))
+            .await
+            .unwrap(),
+    );
+    assert_eq!(result["data"]["replaced"], true);
+    assert_eq!(result["data"]["moved"], true);
+    assert_eq!(
+        result["data"]["backup"],
+        std::fs::canonicalize(dir.join("config.bak"))
+            .unwrap()
+            .to_str()
+            .unwrap()
+    );
+    assert_eq!(
+        std::fs::read_to_string(dir.join("config.bak")).unwrap(),
+        FIXTURE_CONFIG
+    );
+    assert!(std::fs::read_to_string(&config)
+        .unwrap()
+        .contains("\nworkspace 2 output HDMI-1\nbindsym"));
+    assert_eq!(
+        mock.state().commands,
+        ["workspace \"2\"; move workspace to output HDMI-1; reload"]
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+// ============================================================================
+// windows_by_recency
+// ============================================================================
+
+#[test]
+fn windows_by_recency_orders_by_latest_focus() {
+    let history: Vec<FocusEntry> = [(23, 100), (22, 110), (23, 120), (14, 130)]
+        .into_iter()
+        .map(|(con_id, timestamp)| FocusEntry { con_id, timestamp })
+        .collect();
+    let windows = windows_by_recency(collect_windows(&two_output_tree()), &history);
+
+    let order: Vec<(usize, Option<u64>)> = windows
+        .iter()
+        .map(|w| (w.window.con_id, w.last_focused))
+        .collect();
+    assert_eq!(
+        order,
+        [
+            // Focused Emacs leads though the history never saw it
+            (12, None),
+            (14, Some(130)),
+            // kitty's refocus counts, not its first focus
+            (23, Some(120)),
+            (22, Some(110)),
+            (25, None),
+        ]
+    );
+}
+
+// ============================================================================
+// quad_tile
+// ============================================================================
+
+/// quad_tile_command's steps, one per line
+fn quad_tile_steps(con_ids: &[usize]) -> Vec<String> {
+    let command = quad_tile_command("2: code", con_ids).unwrap();
+    command.split("; ").map(str::to_string).collect()
+}
+
+#[test]
+fn quad_tile_command_one_and_two_windows() {
+    assert_eq!(
+        quad_tile_steps(&[5]),
+        [
+            "[con_id=5] move container to workspace rmcp-i3-quad",
+            "[con_id=5] move container to workspace \"2: code\"",
+            "[con_id=5] layout splith",
+            "unmark rmcp-i3-quad",
+        ]
+    );
+    assert_eq!(
+        quad_tile_steps(&[5, 6]),
+        [
+            "[con_id=5] move container to workspace rmcp-i3-quad",
+            "[con_id=6] move container to workspace rmcp-i3-quad",
+            "[con_id=5] move container to workspace \"2: code\"",
+            "[con_id=5] layout splith",
+            "[con_id=5] mark --add rmcp-i3-quad",
+            "[con_id=6] move container to mark rmcp-i3-quad",
+            "unmark rmcp-i3-quad",
+        ]
+    );
+}
+
+#[test]
+fn quad_tile_command_three_windows_stack_the_second_column() {
+    assert_eq!(
+        quad_tile_steps(&[5, 6, 7])[5..],
+        [
+            "[con_id=5] mark --add rmcp-i3-quad",
+            "[con_id=6] move container to mark rmcp-i3-quad",
+            "[con_id=6] split v",
+            "[con_id=6] mark --add rmcp-i3-quad",
+            "[con_id=7] move container to mark rmcp-i3-quad",
+            "unmark rmcp-i3-quad",
+        ]
+    );
+}
+
+#[test]
+fn quad_tile_command_four_windows_make_two_columns() {
+    assert_eq!(
+        quad_tile_steps(&[5, 6, 7, 8])[5..],
+        [
+            "[con_id=5] layout splith",
+            "[con_id=5] mark --add rmcp-i3-quad",
+            "[con_id=6] move container to mark rmcp-i3-quad",
+            "[con_id=5] split v",
+            "[con_id=5] mark --add rmcp-i3-quad",
+            "[con_id=7] move container to mark rmcp-i3-quad",
+            "[con_id=6] split v",
+            "[con_id=6] mark --add rmcp-i3-quad",
+            "[con_id=8] move container to mark rmcp-i3-quad",
+            "unmark rmcp-i3-quad",
+        ]
+    );
+}
+
+#[test]
+fn quad_tile_command_needs_one_to_four_windows() {
+    assert!(quad_tile_command("2", &[]).is_err());
+    assert!(quad_tile_command("2", &[5, 6, 7, 8, 9]).is_err());
+}
+
+// ============================================================================
+// Urgency
+// ============================================================================
+
+#[test]
+fn clear_urgency_command_visits_each_window_then_returns() {
+    assert_eq!(
+        clear_urgency_command(&[14, 22], Some(12), "2"),
+        "[con_id=14] focus; [con_id=22] focus; [con_id=12] focus"
+    );
+    // With only an empty workspace focused, go back to the workspace itself
+    assert_eq!(
+        clear_urgency_command(&[14], None, "3: build"),
+        "[con_id=14] focus; workspace \"3: build\""
+    );
+}
+
+#[tokio::test]
+async fn list_and_clear_urgent_windows() {
+    let mock = MockI3::start(MockState {
+        tree: two_output_tree(),
+        ..MockState::default()
+    })
+    .await;
+    let server = mock.server().await;
+
+    let listed = envelope(&server.list_urgent_windows().await.unwrap());
+    let ids: Vec<_> = listed["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|w| w["con_id"].clone())
+        .collect();
+    assert_eq!(ids, [14]);
+
+    let cleared = envelope(&server.clear_all_urgency().await.unwrap());
+    assert_eq!(cleared["data"], serde_json::json!([14]));
+    assert_eq!(
+        mock.state().commands,
+        ["[con_id=14] focus; [con_id=12] focus"]
+    );
+}
+
+// ============================================================================
+// Server instructions
+// ============================================================================
+
+#[test]
+fn select_instructions_prefers_inline_then_file_then_default() {
+    let file = |text: &str| Some(Ok(text.to_string()));
+    assert_eq!(
+        select_instructions(Some(" inline ".to_string()), file("from file")),
+        "inline"
+    );
+    assert_eq!(select_instructions(None, file("from file\n")), "from file");
+    // Blank overrides count as unset
+    assert_eq!(
+        select_instructions(Some("  ".to_string()), file("from file")),
+        "from file"
+    );
+    assert_eq!(select_instructions(None, file("\n")), DEFAULT_INSTRUCTIONS);
+
+    let missing = Some(Err(std::io::Error::from(std::io::ErrorKind::NotFound)));
+    assert_eq!(select_instructions(None, missing), DEFAULT_INSTRUCTIONS);
+    assert_eq!(select_instructions(None, None), DEFAULT_INSTRUCTIONS);
+}
+
+#[test]
+fn get_info_returns_the_selected_instructions() {
+    let mut server = I3Server::new();
+    server.instructions = "Only touch workspace 9.".to_string();
+    assert_eq!(
+        server.get_info().instructions.as_deref(),
+        Some("Only touch workspace 9.")
+    );
+}
+
+// ============================================================================
+// Relative workspace moves
+// ============================================================================
+
+#[test]
+fn relative_move_command_with_and_without_follow() {
+    assert_eq!(
+        relative_move_command(12, "next", false),
+        "[con_id=12] move container to workspace next"
+    );
+    assert_eq!(
+        relative_move_command(12, "prev", true),
+        "[con_id=12] move container to workspace prev; [con_id=12] focus"
+    );
+}
+
+#[test]
+fn validate_relative_workspace_accepts_only_the_keywords() {
+    assert_eq!(validate_relative_workspace(" next ").unwrap(), "next");
+    assert!(validate_relative_workspace("2").is_err());
+    assert!(validate_relative_workspace("next; kill").is_err());
+}
+
+// ============================================================================
+// Layout presets
+// ============================================================================
+
+#[test]
+fn resolve_presets_dir_prefers_xdg_config_home() {
+    let os = |v: &str| Some(std::ffi::OsString::from(v));
+    assert_eq!(
+        resolve_presets_dir(os("/xdg"), os("/home/me")),
+        Some(PathBuf::from("/xdg/rmcp-i3/presets"))
+    );
+    // An empty XDG_CONFIG_HOME counts as unset
+    assert_eq!(
+        resolve_presets_dir(os(""), os("/home/me")),
+        Some(PathBuf::from("/home/me/.config/rmcp-i3/presets"))
+    );
+    assert_eq!(resolve_presets_dir(None, None), None);
+}
+
+#[test]
+fn preset_names_lists_json_files_sorted() {
+    let dir = scratch_dir("preset-names");
+    for file in [
+        "work.json",
+        "Coding.json",
+        "notes.txt",
+        ".hidden.json",
+        ".json",
+    ] {
+        std::fs::write(dir.join(file), "{}").unwrap();
+    }
+    std::fs::create_dir(dir.join("nested.json")).unwrap();
+
+    assert_eq!(preset_names(&dir).unwrap(), strings(&["Coding", "work"]));
+    assert!(preset_names(&dir.join("missing")).is_err());
+}
+
+#[test]
+fn validate_preset_name_rejects_paths() {
+    assert_eq!(validate_preset_name(" work.json ").unwrap(), "work");
+    for bad in ["", "../work", "a/b", ".hidden"] {
+        assert!(validate_preset_name(bad).is_err(), "{:?}", bad);
+    }
+}
+
+// ============================================================================
+// toggle_workspaces
+// ============================================================================
+
+#[test]
+fn toggle_target_goes_to_whichever_is_not_focused() {
+    let on_b = [
+        ws_reply("1:web", "DP-1", false),
+        ws_reply("3: build", "DP-1", true),
+    ];
+    assert_eq!(toggle_target(&on_b, "1", "3").unwrap(), "1:web");
+
+    let on_a = [
+        ws_reply("1:web", "DP-1", true),
+        ws_reply("3: build", "DP-1", false),
+    ];
+    assert_eq!(toggle_target(&on_a, "1", "3").unwrap(), "3: build");
+    // A workspace that doesn't exist yet is used as given
+    assert_eq!(toggle_target(&on_a, "1", "7").unwrap(), "7");
+}
+
+#[tokio::test]
+async fn toggle_workspaces_quotes_the_target() {
+    let mock = MockI3::start(MockState {
+        workspaces: vec![
+            ws_reply("1:web", "DP-1", true),
+            ws_reply("3: build", "DP-1", false),
+        ],
+        ..MockState::default()
+    })
+    .await;
+    let server = mock.server().await;
+
+    let params = Parameters(ToggleWorkspacesParams {
+        a: "1".to_string(),
+        b: "3".to_string(),
+    });
+    let result = envelope(&server.toggle_workspaces(params).await.unwrap());
+    assert_eq!(result["data"]["workspace"], "3: build");
+    assert_eq!(mock.state().commands, ["workspace \"3: build\""]);
+}
+
+// ============================================================================
+// run_command_on
+// ============================================================================
+
+#[test]
+fn command_on_joins_criteria_and_action() {
+    assert_eq!(
+        command_on(Some("class=\"mpv\""), " floating enable ").unwrap(),
+        "[class=\"mpv\"] floating enable"
+    );
+    // Without criteria (or with blank criteria) the action runs as given
+    assert_eq!(
+        command_on(None, "fullscreen toggle").unwrap(),
+        "fullscreen toggle"
+    );
+    assert_eq!(command_on(Some(" "), "border none").unwrap(), "border none");
+}
+
+#[test]
+fn command_on_guards_each_part() {
+    assert!(command_on(Some("[class=\"mpv\"]"), " ").is_err());
+    assert!(command_on(Some("[class=\"mpv\"]"), "floating enable; kill").is_err());
+    assert!(command_on(Some("[class=\"mpv\"]"), "[class=\"kitty\"] kill").is_err());
+    assert!(command_on(Some("[class=\"mpv\""), "kill").is_err());
+    assert!(command_on(None, "[class=\"kitty\"] kill").is_err());
+    assert!(command_on(None, "exit").is_err());
+    assert!(command_on(Some("[class=\"mpv\"]"), "restart").is_err());
+}
+
+#[tokio::test]
+async fn run_command_on_checks_the_criteria_match_first() {
+    let mock = MockI3::start(MockState {
+        tree: two_output_tree(),
+        ..MockState::default()
+    })
+    .await;
+    let server = mock.server().await;
+    let params = |criteria: &str| {
+        Parameters(RunCommandOnParams {
+            criteria: Some(criteria.to_string()),
+            action: "floating enable".to_string(),
+        })
+    };
+
+    let unmatched = server
+        .run_command_on(params("[class=\"mpv\"]"))
+        .await
+        .unwrap();
+    assert_eq!(envelope(&unmatched)["ok"], false);
+    assert!(mock.state().commands.is_empty());
+
+    let matched = server
+        .run_command_on(params("[class=\"kitty\"]"))
+        .await
+        .unwrap();
+    assert_eq!(envelope(&matched)["ok"], true);
+    assert_eq!(mock.state().commands, ["[class=\"kitty\"] floating enable"]);
+}
+
+// ============================================================================
+// Scratchpad
+// ============================================================================
+
+#[test]
+fn scratchpad_entries_lists_each_container_with_its_recall() {
+    let tree = with_scratchpad(vec![
+        floating(60, window(61, "KeePassXC", "Passwords")),
+        // A container stashed whole shows as one entry
+        floating(
+            62,
+            split(
+                63,
+                NodeLayout::SplitV,
+                vec![window(64, "kitty", "top"), window(65, "kitty", "bottom")],
+            ),
+        ),
+        node(66, NodeType::FloatingCon),
+    ]);
+
+    let entries = scratchpad_entries(&tree);
+    let summary: Vec<_> = entries
+        .iter()
+        .map(|e| {
+            let windows: Vec<usize> = e.windows.iter().map(|w| w.con_id).collect();
+            (e.con_id, windows, e.recall.as_deref(), e.placeholder)
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (60, vec![61], Some("[con_id=61] scratchpad show"), false),
+            (62, vec![64, 65], Some("[con_id=64] scratchpad show"), false),
+            (66, vec![], None, true),
+        ]
+    );
+    assert!(scratchpad_entries(&two_output_tree()).is_empty());
+}
+
+// ============================================================================
+// Floating size limits
+// ============================================================================
+
+const FLOATING_CONFIG: &str = "\
+set $mod Mod4
+floating_maximum_size 800 x 600
+bindsym $mod+Return exec kitty
+floating_maximum_size 1024 x 768
+";
+
+#[test]
+fn floating_size_line_builds_the_directive() {
+    assert_eq!(
+        floating_size_line("minimum", 300, 200),
+        "floating_minimum_size 300 x 200"
+    );
+    assert_eq!(
+        floating_size_line("maximum", 0, 0),
+        "floating_maximum_size 0 x 0"
+    );
+}
+
+#[test]
+fn set_directive_in_config_replaces_only_the_first_line() {
+    let line = "floating_maximum_size 1920 x 1080";
+    let (contents, replaced) =
+        set_directive_in_config(FLOATING_CONFIG, "floating_maximum_size", line);
+    assert!(replaced);
+    assert_eq!(
+        contents,
+        "set $mod Mod4\n\
+         floating_maximum_size 1920 x 1080\n\
+         bindsym $mod+Return exec kitty\n\
+         floating_maximum_size 1024 x 768\n"
+    );
+
+    let line = "floating_minimum_size 300 x 200";
+    let (contents, replaced) =
+        set_directive_in_config(FLOATING_CONFIG, "floating_minimum_size", line);
+    assert!(!replaced);
+    assert_eq!(contents, format!("{}{}\n", FLOATING_CONFIG, line));
+}
+
+#[tokio::test]
+async fn set_floating_size_runs_at_runtime_when_accepted() {
+    let mock = MockI3::start(MockState::default()).await;
+    let server = mock.server().await;
+
+    let result = envelope(&server.set_floating_size("minimum", 300, 200).await.unwrap());
+    assert_eq!(result["data"]["method"], "runtime");
+    assert_eq!(mock.state().commands, ["floating_minimum_size 300 x 200"]);
+}
+
+#[tokio::test]
+async fn set_floating_size_falls_back_to_the_config() {
+    let dir = scratch_dir("floating-config");
+    let config = dir.join("config");
+    std::fs::write(&config, FLOATING_CONFIG).unwrap();
+    let mock = MockI3::start(MockState {
+        fail_matching: Some("floating_".to_string()),
+        config_file: config.to_str().unwrap().to_string(),
+        ..MockState::default()
+    })
+    .await;
+    let server = mock.server().await;
+
+    let result = envelope(
+        &server
+            .set_floating_size("maximum", 1920, 1080)
+            .await
+            .unwrap(),
+    );
+    assert_eq!(result["data"]["method"], "config");
+    assert_eq!(result["data"]["replaced"], true);
+    assert!(result["data"]["backup"]
+        .as_str()
+        .unwrap()
+        .ends_with("config.bak"));
+    assert_eq!(
+        std::fs::read_to_string(dir.join("config.bak")).unwrap(),
+        FLOATING_CONFIG
+    );
+    assert!(std::fs::read_to_string(&config)
+        .unwrap()
+        .contains("\nfloating_maximum_size 1920 x 1080\nbindsym"));
+    assert_eq!(
+        mock.state().commands,
+        ["floating_maximum_size 1920 x 1080", "reload"]
+    );
+}
+
+// ============================================================================
+// move_to_mark
+// ============================================================================
+
+#[test]
+fn move_to_mark_command_quotes_the_mark() {
+    let marks = strings(&["dock", "my \"spot\""]);
+    let kitty = window(23, "kitty", "logs");
+    assert_eq!(
+        move_to_mark_command(&marks, &kitty, " dock ").unwrap(),
+        "[con_id=23] move window to mark \"dock\""
+    );
+    assert_eq!(
+        move_to_mark_command(&marks, &kitty, "my \"spot\"").unwrap(),
+        "[con_id=23] move window to mark \"my \\\"spot\\\"\""
+    );
+}
+
+#[test]
+fn move_to_mark_command_checks_the_mark() {
+    let marks = strings(&["dock"]);
+    let kitty = window(23, "kitty", "logs");
+    assert!(move_to_mark_command(&marks, &kitty, " ").is_err());
+    assert!(move_to_mark_command(&marks, &kitty, "elsewhere").is_err());
+    // Moving a window onto its own mark would go nowhere
+    let docked = marked(kitty, &["dock"]);
+    assert!(move_to_mark_command(&marks, &docked, "dock").is_err());
+}
+
+// ============================================================================
+// Bar state
+// ============================================================================
+
+#[test]
+fn bar_state_groups_workspaces_by_output_in_bar_order() {
+    let workspaces = [
+        ws_reply("notes", "DP-1", false),
+        ws_reply("10", "HDMI-1", false),
+        urgent(ws_reply("chat", "DP-1", false)),
+        ws_reply("2", "DP-1", true),
+        ws_reply("1:web", "HDMI-1", false),
+    ];
+    let outputs = [
+        output_reply("HDMI-1", rect(1920, 0, 1920, 1080), Some("1:web")),
+        output_reply("DP-1", rect(0, 0, 1920, 1080), Some("2")),
+        Output {
+            active: false,
+            ..output_reply("VGA-1", rect(0, 0, 0, 0), None)
+        },
+    ];
+
+    let bars = bar_state(&workspaces, &outputs);
+    let layout: Vec<(&str, Vec<&str>)> = bars
+        .iter()
+        .map(|bar| {
+            let names = bar.workspaces.iter().map(|ws| ws.name.as_str()).collect();
+            (bar.output.as_str(), names)
+        })
+        .collect();
+    assert_eq!(
+        layout,
+        [
+            ("DP-1", vec!["2", "notes", "chat"]),
+            ("HDMI-1", vec!["1:web", "10"]),
+        ]
+    );
+
+    let dp1 = &bars[0].workspaces;
+    assert!(dp1[0].focused && dp1[0].visible && !dp1[0].urgent);
+    assert!(!dp1[2].focused && dp1[2].urgent);
+}
+
+// ============================================================================
+// Spotlight
+// ============================================================================
+
+#[test]
+fn spotlight_state_captures_the_floating_container_geometry() {
+    let mut tree = two_output_tree();
+    // The container is taller than the window by its title bar
+    node_mut(&mut tree, 24).rect = rect(2400, 180, 600, 420);
+
+    let (volume, ancestors) = find_node_with_ancestors(&tree, 25).unwrap();
+    let floating = SpotlightState::capture(volume, &ancestors);
+    assert!(floating.was_floating);
+    assert_eq!(floating.rect, rect(2400, 180, 600, 420));
+    assert_eq!(
+        unspotlight_command(25, &floating),
+        "[con_id=25] resize set 600 px 420 px; [con_id=25] move position 2400 px 180 px"
+    );
+
+    let (logs, ancestors) = find_node_with_ancestors(&tree, 23).unwrap();
+    let tiled = SpotlightState::capture(logs, &ancestors);
+    assert!(!tiled.was_floating);
+    assert_eq!(
+        unspotlight_command(23, &tiled),
+        "[con_id=23] floating disable"
+    );
+}
+
+#[test]
+fn spotlight_command_focuses_floats_and_centers() {
+    assert_eq!(
+        spotlight_command(23),
+        "[con_id=23] focus; [con_id=23] floating enable; [con_id=23] move position center"
+    );
+}
+
+// ============================================================================
+// Ticks
+// ============================================================================
+
+#[tokio::test]
+async fn tick_round_trip_sees_its_tick_on_the_event_stream() {
+    let mock = MockI3::start(MockState::default()).await;
+    let server = mock.server().await;
+
+    // Without a subscription the echo can't be seen
+    let echo = server
+        .tick_round_trip("before", TICK_TIMEOUT)
+        .await
+        .unwrap();
+    assert!(matches!(echo, TickEcho::Unconfirmed), "{:?}", echo);
+
+    let _socket = mock.use_socket().await;
+    let listener = server.spawn_event_listener();
+    let subscribed = async {
+        while !server.events().subscribed {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(5), subscribed)
+        .await
+        .expect("listener subscribes");
+    let echo = server.tick_round_trip("probe", TICK_TIMEOUT).await.unwrap();
+    assert!(matches!(echo, TickEcho::Received(_)), "{:?}", echo);
+    listener.abort();
+}
+
+// ============================================================================
+// focus_by_size
+// ============================================================================
+
+#[test]
+fn window_by_size_picks_by_area_with_lowest_con_id_on_ties() {
+    let windows = collect_windows(&two_output_tree());
+    let pick = |windows: &[WindowInfo], largest| window_by_size(windows, largest).map(|w| w.con_id);
+    // 12 and 14 both fill an output
+    assert_eq!(pick(&windows, true), Some(12));
+    assert_eq!(pick(&windows, false), Some(25));
+
+    let halves: Vec<WindowInfo> = windows
+        .into_iter()
+        .filter(|w| [22, 23].contains(&w.con_id))
+        .rev()
+        .collect();
+    assert_eq!(pick(&halves, true), Some(22));
+    assert_eq!(pick(&halves, false), Some(22));
+    assert_eq!(pick(&[], true), None);
+}
+
+// ============================================================================
+// Layout snapshot
+// ============================================================================
+
+#[test]
+fn layout_snapshot_covers_every_workspace_and_the_scratchpad() {
+    let tree = with_scratchpad(vec![floating(60, window(61, "KeePassXC", "Passwords"))]);
+    let workspaces = [
+        ws_reply("1:web", "HDMI-1", false),
+        ws_reply("2", "DP-1", true),
+        ws_reply("notes", "DP-1", false),
+        // Listed by i3 but missing from this tree
+        ws_reply("9", "DP-1", false),
+    ];
+    let outputs = [
+        output_reply("DP-1", rect(0, 0, 1920, 1080), Some("2")),
+        Output {
+            active: false,
+            ..output_reply("VGA-1", rect(0, 0, 0, 0), None)
+        },
+    ];
+
+    let snapshot = layout_snapshot(&tree, &workspaces, &outputs, 1_700_000_000);
+    let json = serde_json::to_value(&snapshot).unwrap();
+    assert_eq!(json["taken_at"], 1_700_000_000);
+    assert_eq!(
+        json["outputs"],
+        serde_json::json!([{
+            "name": "DP-1",
+            "primary": false,
+            "rect": { "x": 0, "y": 0, "width": 1920, "height": 1080 },
+        }])
+    );
+
+    let summary: Vec<_> = snapshot
+        .workspaces
+        .iter()
+        .map(|ws| {
+            let windows: Vec<usize> = ws.windows.iter().map(|w| w.con_id).collect();
+            (ws.name.as_str(), ws.layout.as_str(), ws.focused, windows)
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            ("1:web", "splith", false, vec![22, 25, 23]),
+            ("2", "splith", true, vec![12]),
+            ("notes", "splith", false, vec![14]),
+            ("9", "", false, vec![]),
+        ]
+    );
+    assert!(snapshot.workspaces[0].windows[1].floating);
+
+    let scratchpad: Vec<usize> = snapshot.scratchpad.iter().map(|w| w.con_id).collect();
+    assert_eq!(scratchpad, [61]);
+}
+
+// ============================================================================
+// focus_by_window_id
+// ============================================================================
+
+#[tokio::test]
+async fn focus_by_window_id_checks_the_tree_before_sending() {
+    let mock = MockI3::start(MockState {
+        tree: two_output_tree(),
+        ..MockState::default()
+    })
+    .await;
+    let server = mock.server().await;
+    let params = |window_id| Parameters(FocusByWindowIdParams { window_id });
+
+    let missing = server.focus_by_window_id(params(4242)).await.unwrap();
+    assert!(is_error(&missing));
+    assert!(mock.state().commands.is_empty());
+
+    // window() gives con 23 the X11 id 1023
+    let found = envelope(&server.focus_by_window_id(params(1023)).await.unwrap());
+    assert_eq!(found["data"]["con_id"], 23);
+    assert_eq!(mock.state().commands, ["[id=1023] focus"]);
+
+    assert!(server.focus_by_window_id(params(0)).await.is_err());
+}
+
+// ============================================================================
+// Equalize
+// ============================================================================
+
+fn windows_from(first: usize, count: usize) -> Vec<Node> {
+    (first..first + count)
+        .map(|id| window(id, "kitty", "shell"))
+        .collect()
+}
+
+#[test]
+fn equalize_commands_per_child_count() {
+    let row = split(4, NodeLayout::SplitH, windows_from(5, 2));
+    assert_eq!(
+        equalize_commands(&row),
+        ["[con_id=5] resize set width 50 ppt"]
+    );
+
+    let column = split(4, NodeLayout::SplitV, windows_from(5, 3));
+    assert_eq!(
+        equalize_commands(&column),
+        [
+            "[con_id=5] resize set height 33 ppt",
+            "[con_id=6] resize set height 33 ppt",
+        ]
+    );
+
+    let four = split(4, NodeLayout::SplitH, windows_from(5, 4));
+    assert_eq!(
+        equalize_commands(&four),
+        [
+            "[con_id=5] resize set width 25 ppt",
+            "[con_id=6] resize set width 25 ppt",
+            "[con_id=7] resize set width 25 ppt",
+        ]
+    );
+}
+
+#[test]
+fn equalize_commands_recurses_outermost_first() {
+    let tabs = split(
+        8,
+        NodeLayout::Tabbed,
+        vec![split(9, NodeLayout::SplitV, windows_from(10, 2))],
+    );
+    let row = split(
+        4,
+        NodeLayout::SplitH,
+        vec![
+            window(5, "kitty", "shell"),
+            tabs,
+            window(6, "kitty", "shell"),
+        ],
+    );
+    // The tabbed container itself is skipped, the split inside it is not
+    assert_eq!(
+        equalize_commands(&row),
+        [
+            "[con_id=5] resize set width 33 ppt",
+            "[con_id=8] resize set width 33 ppt",
+            "[con_id=10] resize set height 50 ppt",
+        ]
+    );
+    assert!(equalize_commands(&split(4, NodeLayout::SplitH, windows_from(5, 1))).is_empty());
+}
+
+// ============================================================================
+// Offscreen windows
+// ============================================================================
+
+#[test]
+fn offscreen_windows_finds_stranded_floating_windows() {
+    let stranded = at(window(60, "Gcr-prompter", "Unlock"), 5000, 100, 400, 200);
+    let mut tree = with_scratchpad(vec![floating(70, stranded.clone())]);
+    node_mut(&mut tree, 11)
+        .floating_nodes
+        .push(floating(61, stranded));
+    // Half of it hangs off the right edge of HDMI-1
+    node_mut(&mut tree, 10).floating_nodes.push(floating(
+        62,
+        at(window(63, "mpv", "video"), 3640, 100, 400, 200),
+    ));
+    let screens = [rect(0, 0, 1920, 1080), rect(1920, 0, 1920, 1080)];
+
+    let found: Vec<_> = offscreen_windows(&tree, &screens)
+        .into_iter()
+        .map(|w| (w.window.con_id, w.offscreen, w.visible_ppt, w.recall))
+        .collect();
+    // The on-screen Pavucontrol window and the scratchpad copy are left out
+    assert_eq!(
+        found,
+        [
+            (
+                63,
+                "partial",
+                50,
+                "[con_id=63] move position center".to_string()
+            ),
+            (
+                60,
+                "full",
+                0,
+                "[con_id=60] move position center".to_string()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn offscreen_visible_ppt_counts_mirrored_outputs_once() {
+    let screen = || rect(0, 0, 1920, 1080);
+    let window = rect(1720, 0, 400, 200);
+    assert_eq!(offscreen_visible_ppt(&window, &[screen()]), Some(50));
+    assert_eq!(
+        offscreen_visible_ppt(&window, &[screen(), screen()]),
+        Some(50)
+    );
+    assert_eq!(
+        offscreen_visible_ppt(&rect(0, 0, 400, 200), &[screen()]),
+        None
+    );
+}
+
+// ============================================================================
+// move_and_split
+// ============================================================================
+
+#[test]
+fn move_and_split_command_moves_follows_and_splits() {
+    let workspaces = [
+        ws_reply("2", "DP-1", true),
+        ws_reply("3: build", "DP-1", false),
+    ];
+    assert_eq!(
+        move_and_split_command(&workspaces, 12, "3", " vertical ").unwrap(),
+        "[con_id=12] move container to workspace \"3: build\"; \
+         workspace \"3: build\"; [con_id=12] split vertical"
+    );
+    // A new workspace is used as given
+    assert_eq!(
+        move_and_split_command(&workspaces, 12, "web", "toggle").unwrap(),
+        "[con_id=12] move container to workspace \"web\"; \
+         workspace \"web\"; [con_id=12] split toggle"
+    );
+}
+
+#[test]
+fn move_and_split_command_validates_the_orientation() {
+    let workspaces = [ws_reply("2", "DP-1", true)];
+    for bad in ["", "h", "diagonal", "vertical; kill"] {
+        assert!(
+            move_and_split_command(&workspaces, 12, "2", bad).is_err(),
+            "{:?}",
+            bad
+        );
+    }
+}
+
+// ============================================================================
+// Output layout
+// ============================================================================
+
+#[test]
+fn output_layout_joins_outputs_with_their_workspaces() {
+    let workspaces = [
+        ws_reply("1:web", "HDMI-1", true),
+        ws_reply("notes", "DP-1", false),
+        ws_reply("2", "DP-1", false),
+    ];
+    let outputs = [
+        output_reply("HDMI-1", rect(1920, 0, 2560, 1440), Some("1:web")),
+        Output {
+            primary: true,
+            ..output_reply("DP-1", rect(0, 0, 1920, 1080), Some("2"))
+        },
+        Output {
+            active: false,
+            ..output_reply("VGA-1", rect(0, 0, 0, 0), None)
+        },
+    ];
+
+    let layout = serde_json::to_value(output_layout(&workspaces, &outputs)).unwrap();
+    assert_eq!(
+        layout,
+        serde_json::json!([
+            {
+                "name": "DP-1",
+                "primary": true,
+                "rect": { "x": 0, "y": 0, "width": 1920, "height": 1080 },
+                "current_workspace": "2",
+                "workspaces": ["2", "notes"],
+            },
+            {
+                "name": "HDMI-1",
+                "primary": false,
+                "rect": { "x": 1920, "y": 0, "width": 2560, "height": 1440 },
+                "current_workspace": "1:web",
+                "workspaces": ["1:web"],
+            },
+        ])
+    );
+}
+
+// ============================================================================
+// Container labels
+// ============================================================================
+
+#[test]
+fn label_mark_cleans_the_label() {
+    assert_eq!(label_mark("  build   logs ").unwrap(), "label:build logs");
+    assert_eq!(label_mark("say \"hi\"\\\u{7}").unwrap(), "label:say hi");
+    assert_eq!(
+        label_mark("tab\tand\nnewline").unwrap(),
+        "label:tab and newline"
+    );
+    assert!(label_mark(" \"\\ ").is_err());
+}
+
+#[test]
+fn label_command_replaces_the_old_label_and_keeps_other_marks() {
+    let kitty = marked(
+        window(23, "kitty", "logs"),
+        &["dock", "label:old one", "label:logs"],
+    );
+    assert_eq!(
+        label_command(&kitty, "label:logs"),
+        "[con_id=23] unmark \"label:old one\"; [con_id=23] mark --add \"label:logs\""
+    );
+    assert_eq!(
+        label_command(&window(22, "Firefox", "GitHub"), "label:web"),
+        "[con_id=22] mark --add \"label:web\""
+    );
+    assert_eq!(container_label(&kitty), Some("old one"));
+}
+
+// ============================================================================
+// Stash
+// ============================================================================
+
+#[test]
+fn stash_commands_quote_the_workspaces() {
+    assert_eq!(
+        stash_command(12, "side stash"),
+        "[con_id=12] move container to workspace \"side stash\""
+    );
+    assert_eq!(
+        unstash_command(12, "3: build"),
+        "[con_id=12] move container to workspace \"3: build\"; [con_id=12] focus"
+    );
+}
+
+#[test]
+fn record_stash_keeps_the_first_origin() {
+    let mut stashes = HashMap::new();
+    assert_eq!(record_stash(&mut stashes, 12, "2"), "2");
+    // Stashed again from the stash workspace itself
+    assert_eq!(record_stash(&mut stashes, 12, "stash"), "2");
+    assert_eq!(record_stash(&mut stashes, 14, "notes"), "notes");
+    assert_eq!(stashes.len(), 2);
+}
+
+#[tokio::test]
+async fn stash_and_unstash_round_trip() {
+    let mock = MockI3::start(MockState {
+        tree: two_output_tree(),
+        ..MockState::default()
+    })
+    .await;
+    let mut server = mock.server().await;
+    server.stash_workspace = "side stash".to_string();
+
+    let stashed = envelope(&server.stash_window().await.unwrap());
+    assert_eq!(
+        stashed["data"],
+        serde_json::json!({ "con_id": 12, "origin": "2", "stash": "side stash" })
+    );
+
+    let params = || Parameters(UnstashWindowParams { con_id: 12 });
+    let restored = server.unstash_window(params()).await.unwrap();
+    assert_eq!(envelope(&restored)["ok"], true);
+    assert_eq!(
+        mock.state().commands,
+        [
+            "[con_id=12] move container to workspace \"side stash\"",
+            "[con_id=12] move container to workspace \"2\"; [con_id=12] focus",
+        ]
+    );
+
+    // The origin is forgotten once the window is back
+    let again = server.unstash_window(params()).await.unwrap();
+    assert_eq!(envelope(&again)["ok"], false);
+    assert_eq!(mock.state().commands.len(), 2);
+}
+
+// ============================================================================
+// move_window_to_position_on_output
+// ============================================================================
+
+#[tokio::test]
+async fn move_window_to_position_on_output_targets_the_moved_window() {
+    let mock = MockI3::start(MockState {
+        tree: two_output_tree(),
+        outputs: vec![
+            output_reply("DP-1", rect(0, 0, 1920, 1080), Some("2")),
+            output_reply("HDMI-1", rect(1920, 0, 1920, 1080), Some("1:web")),
+        ],
+        ..MockState::default()
+    })
+    .await;
+    let server = mock.server().await;
+    let params = |output: &str| {
+        Parameters(MoveWindowToPositionOnOutputParams {
+            output: output.to_string(),
+            x: 2000,
+            y: 100,
+        })
+    };
+
+    let moved = server
+        .move_window_to_position_on_output(params("HDMI-1"))
+        .await
+        .unwrap();
+    assert!(!is_error(&moved));
+    assert_eq!(
+        mock.state().commands,
+        [
+            "[con_id=12] move container to output \"HDMI-1\"",
+            "[con_id=12] move position 2000 px 100 px",
+        ]
+    );
+
+    assert!(server
+        .move_window_to_position_on_output(params("VGA-1"))
+        .await
+        .is_err());
+    assert_eq!(mock.state().commands.len(), 2);
+}
+
+// ============================================================================
+// Unbracketed criteria
+// ============================================================================
+
+#[test]
+fn bracketed_criteria_adds_missing_brackets() {
+    assert_eq!(bracketed_criteria(" class=\"kitty\" "), "[class=\"kitty\"]");
+    assert_eq!(bracketed_criteria("[class=\"kitty\"]"), "[class=\"kitty\"]");
+    assert_eq!(
+        opacity_command(Some("app_id=\"foot\""), 0.8),
+        "[app_id=\"foot\"] opacity 0.8"
+    );
+}
+
+#[tokio::test]
+async fn unbracketed_criteria_are_sent_bracketed() {
+    let mock = MockI3::start(MockState {
+        tree: two_output_tree(),
+        ..MockState::default()
+    })
+    .await;
+    let server = mock.server().await;
+    let focus_params = |criteria: &str| {
+        Parameters(FocusWindowParams {
+            criteria: criteria.to_string(),
+        })
+    };
+
+    server
+        .focus_window(focus_params("class=\"kitty\""))
+        .await
+        .unwrap();
+    // Regex criteria go to i3's own focus
+    server
+        .goto_window(focus_params("class=\"^Fire.*\""))
+        .await
+        .unwrap();
+    let params = KillWindowParams {
+        criteria: "title=\"logs\"".to_string(),
+    };
+    server.kill_window(Parameters(params)).await.unwrap();
+    let params = KillAllParams {
+        criteria: "class=\"^Fire.*\"".to_string(),
+        max: None,
+        confirm: Some(true),
+    };
+    server.kill_all(Parameters(params)).await.unwrap();
+
+    assert_eq!(
+        mock.state().commands,
+        [
+            "[class=\"kitty\"] focus",
+            "[class=\"^Fire.*\"] focus",
+            "[title=\"logs\"] kill",
+            "[class=\"^Fire.*\"] kill",
+        ]
+    );
+}